@@ -0,0 +1,3 @@
+# Bash completions for ftag. Add this to your bash profile, or run:
+# eval "$(ftag completions bash)"
+complete -o default -C 'ftag --bash-complete --' ftag
@@ -0,0 +1,18 @@
+# Fish completions for ftag. Put this file in ~/.config/fish/completions, or
+# run:
+# ftag completions fish | source
+
+function __ftag_complete
+    # Ask ftag for the suggestions, the same way bash does.
+    set -l tokens (commandline -opc)
+    set -l current (commandline -ct)
+    ftag --bash-complete -- ftag "$current" "$tokens[-1]" 2>/dev/null
+end
+
+function __ftag_has_completions
+    set -l suggestions (__ftag_complete)
+    test (count $suggestions) -gt 0
+end
+
+# Files are completed when ftag has no suggestions.
+complete -c ftag -n __ftag_has_completions -f -a '(__ftag_complete)'
@@ -0,0 +1,672 @@
+use crate::{
+    config::Config,
+    core::{Error, WritePlan, FTAG_FILE},
+    load::{get_ftag_path, FileLoadingOptions, Loader, LoaderOptions},
+    vfs::FileStats,
+    walk::{DirTree, VisitedDir},
+};
+use fast_glob::glob_match;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ffi::OsStr,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/*
+A snapshot of the metadata is a tar archive that contains every .ftag file
+under the root, with its path relative to the root. The contents of the files
+being tagged are not included. The archives are written in the ustar format, so
+they can also be inspected and extracted with any tar tool. Only regular files
+named .ftag are read back from an archive, everything else is ignored.
+ */
+
+const BLOCK_SIZE: usize = 512;
+
+/// Contents of .ftag files, along with the paths of their directories.
+type Snapshot = Vec<(PathBuf, String)>;
+
+/// Names of the files in directories, by the paths of the directories.
+type DirFiles = HashMap<PathBuf, Vec<String>>;
+
+/// Read one block, or nothing if the reader is exhausted.
+fn read_block(r: &mut impl Read, block: &mut [u8; BLOCK_SIZE]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < BLOCK_SIZE {
+        match r.read(&mut block[filled..])? {
+            0 if filled == 0 => return Ok(false),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => filled += n,
+        }
+    }
+    Ok(true)
+}
+
+fn padding(size: usize) -> usize {
+    (BLOCK_SIZE - size % BLOCK_SIZE) % BLOCK_SIZE
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    let text = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..text.len()].copy_from_slice(text.as_bytes());
+}
+
+fn read_octal(field: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    u64::from_str_radix(text, 8).ok()
+}
+
+fn read_str(field: &[u8]) -> Option<&str> {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    std::str::from_utf8(&field[..end]).ok()
+}
+
+fn checksum(header: &[u8; BLOCK_SIZE]) -> u64 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, b)| match i {
+            148..156 => b' ' as u64, // The checksum field itself counts as spaces.
+            _ => *b as u64,
+        })
+        .sum()
+}
+
+/// Create the ustar header of a regular file. Paths longer than 100 bytes are
+/// split into a prefix and a name, which only works for paths up to 255 bytes.
+fn file_header(path: &str, size: u64, mtime: u64) -> Option<[u8; BLOCK_SIZE]> {
+    let (prefix, name) = if path.len() <= 100 {
+        ("", path)
+    } else {
+        path.match_indices('/')
+            .map(|(i, _)| (&path[..i], &path[(i + 1)..]))
+            .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100)?
+    };
+    let mut header = [0u8; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], 0o644); // Mode.
+    write_octal(&mut header[108..116], 0); // Owner.
+    write_octal(&mut header[116..124], 0); // Group.
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], mtime);
+    header[156] = b'0'; // Regular file.
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..(345 + prefix.len())].copy_from_slice(prefix.as_bytes());
+    let sum = checksum(&header);
+    write_octal(&mut header[148..155], sum);
+    Some(header)
+}
+
+/// Write the `files` to a tar archive. Each file is a tuple of the path inside
+/// the archive, the contents of the file, and its modification time.
+fn write_tar(w: &mut impl Write, files: &[(String, Vec<u8>, u64)]) -> Result<(), String> {
+    for (path, bytes, mtime) in files {
+        let header = file_header(path, bytes.len() as u64, *mtime)
+            .ok_or(format!("The path '{}' is too long.", path))?;
+        w.write_all(&header).map_err(|e| e.to_string())?;
+        w.write_all(bytes).map_err(|e| e.to_string())?;
+        w.write_all(&[0u8; BLOCK_SIZE][..padding(bytes.len())])
+            .map_err(|e| e.to_string())?;
+    }
+    // The end of the archive is marked by two empty blocks.
+    w.write_all(&[0u8; 2 * BLOCK_SIZE])
+        .and_then(|_| w.flush())
+        .map_err(|e| e.to_string())
+}
+
+/// Read the headers of all entries in a tar archive. `visit` is called with
+/// the path, the type flag, the size and the modification time of every
+/// entry, and must either read or skip the contents of the entry, along with
+/// the padding after them.
+fn read_headers<R: Read>(
+    r: &mut R,
+    mut visit: impl FnMut(&mut R, String, u8, usize, u64) -> io::Result<()>,
+) -> Result<(), String> {
+    let mut header = [0u8; BLOCK_SIZE];
+    while read_block(r, &mut header).map_err(|e| e.to_string())? {
+        if header.iter().all(|b| *b == 0) {
+            break; // End of the archive.
+        }
+        if read_octal(&header[148..156]) != Some(checksum(&header)) {
+            return Err(String::from("Found a header with the wrong checksum."));
+        }
+        let size = read_octal(&header[124..136])
+            .and_then(|size| usize::try_from(size).ok())
+            .filter(|size| size.checked_add(padding(*size)).is_some())
+            .ok_or(String::from("Found a header with an invalid size."))?;
+        let name = read_str(&header[..100]).ok_or(String::from("Found an invalid path."))?;
+        let path = match read_str(&header[345..500]) {
+            Some(prefix) if &header[257..262] == b"ustar" && !prefix.is_empty() => {
+                format!("{}/{}", prefix, name)
+            }
+            _ => name.to_string(),
+        };
+        let mtime = read_octal(&header[136..148]).unwrap_or(0);
+        visit(r, path, header[156], size, mtime).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn is_regular_file(typeflag: u8) -> bool {
+    matches!(typeflag, b'0' | 0)
+}
+
+/// Read exactly `size` bytes from `r` into `buf`, or skip them if `buf` is
+/// `None`. The bytes are streamed, so a corrupt size in a header can't make
+/// this allocate more than the archive actually contains.
+fn read_exact_or_skip(r: &mut impl Read, size: usize, buf: Option<&mut Vec<u8>>) -> io::Result<()> {
+    let mut r = r.take(size as u64);
+    let read = match buf {
+        Some(buf) => r.read_to_end(buf)? as u64,
+        None => io::copy(&mut r, &mut io::sink())?,
+    };
+    match read == size as u64 {
+        true => Ok(()),
+        false => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+/// Read all regular files from a tar archive, as tuples of their paths and
+/// contents.
+fn read_tar(r: &mut impl Read) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut files = Vec::new();
+    read_headers(r, |r, path, typeflag, size, _mtime| {
+        if is_regular_file(typeflag) {
+            let mut bytes = Vec::new();
+            read_exact_or_skip(r, size, Some(&mut bytes))?;
+            files.push((path, bytes));
+        } else {
+            read_exact_or_skip(r, size, None)?;
+        }
+        read_exact_or_skip(r, padding(size), None)
+    })?;
+    Ok(files)
+}
+
+/// Get the path of an entry of an archive, without any './' components. This
+/// is `None` for paths that point outside the root of the archive.
+fn normalize_entry_path(path: &str) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for c in Path::new(path).components() {
+        match c {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => continue,
+            _ => return None,
+        }
+    }
+    Some(normalized)
+}
+
+/// Where to find the contents of a file in a tar archive.
+#[derive(Clone, Copy)]
+struct TarFile {
+    offset: u64,
+    size: usize,
+    mtime: u64,
+}
+
+/// The files and directories in a tar archive, and where to find the contents
+/// of the files in the archive, so that the archive can be browsed without
+/// extracting it.
+pub(crate) struct TarIndex {
+    // The files, and `None` for directories.
+    entries: BTreeMap<PathBuf, Option<TarFile>>,
+}
+
+impl TarIndex {
+    /// Read the headers of the archive at `path`. The contents of the files
+    /// are skipped, so this is fast even for large archives.
+    pub(crate) fn read(path: &Path) -> Result<TarIndex, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        Self::from_reader(&mut BufReader::new(file))
+    }
+
+    fn from_reader(r: &mut (impl Read + Seek)) -> Result<TarIndex, String> {
+        let mut entries = BTreeMap::new();
+        read_headers(r, |r, epath, typeflag, size, mtime| {
+            let offset = r.stream_position()?;
+            let skip = i64::try_from(size + padding(size))
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+            r.seek_relative(skip)?;
+            let epath = match normalize_entry_path(&epath) {
+                Some(p) if !p.as_os_str().is_empty() => p,
+                _ => return Ok(()),
+            };
+            let entry = match typeflag {
+                b'5' => None,
+                t if is_regular_file(t) => Some(TarFile {
+                    offset,
+                    size,
+                    mtime,
+                }),
+                _ => return Ok(()), // Links and such are not supported.
+            };
+            for dir in epath.ancestors().skip(1) {
+                if dir.as_os_str().is_empty() {
+                    break;
+                }
+                entries.insert(dir.to_path_buf(), None);
+            }
+            entries.insert(epath, entry);
+            Ok(())
+        })?;
+        Ok(TarIndex { entries })
+    }
+
+    /// Call `visit` with the name of every entry in the directory `dir` of the
+    /// archive, and whether the entry is a file.
+    pub(crate) fn read_dir(&self, dir: &Path, mut visit: impl FnMut(&OsStr, bool)) {
+        for (path, entry) in self
+            .entries
+            .range(dir.to_path_buf()..)
+            .take_while(|(path, _)| path.starts_with(dir))
+            .filter(|(path, _)| path.parent() == Some(dir))
+        {
+            if let Some(name) = path.file_name() {
+                visit(name, entry.is_some());
+            }
+        }
+    }
+
+    pub(crate) fn is_dir(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty() || matches!(self.entries.get(path), Some(None))
+    }
+
+    pub(crate) fn is_file(&self, path: &Path) -> bool {
+        matches!(self.entries.get(path), Some(Some(_)))
+    }
+
+    /// Append the contents of the file at `path` in the `archive` to `buf`.
+    pub(crate) fn read_to_string(
+        &self,
+        archive: &Path,
+        path: &Path,
+        buf: &mut String,
+    ) -> io::Result<()> {
+        let TarFile { offset, size, .. } = self.file(path)?;
+        let mut file = File::open(archive)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.take(size as u64).read_to_string(buf).map(|_| ())
+    }
+
+    /// Get the size and the modification time of the file at `path` in the
+    /// archive, as recorded in its header.
+    pub(crate) fn stats(&self, path: &Path) -> io::Result<FileStats> {
+        let TarFile { size, mtime, .. } = self.file(path)?;
+        Ok(FileStats {
+            size: size as u64,
+            mtime,
+        })
+    }
+
+    fn file(&self, path: &Path) -> io::Result<TarFile> {
+        match self.entries.get(path) {
+            Some(Some(file)) => Ok(*file),
+            _ => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+/// Read the .ftag files from the archive at `path`, as tuples of the paths of
+/// their directories, and their contents. Files with paths that point outside
+/// the root of the archive are ignored.
+pub(crate) fn read_archive(path: &Path) -> Result<Snapshot, Error> {
+    let file = File::open(path).map_err(|_| Error::CannotReadStoreFile(path.to_path_buf()))?;
+    let files = read_tar(&mut BufReader::new(file))
+        .map_err(|message| Error::InvalidArchive(path.to_path_buf(), message))?;
+    Ok(files
+        .into_iter()
+        .filter_map(|(fpath, bytes)| {
+            let fpath = normalize_entry_path(&fpath)?;
+            if fpath.file_name()? != FTAG_FILE {
+                return None;
+            }
+            let text = String::from_utf8(bytes).ok()?;
+            Some((fpath.parent()?.to_path_buf(), text))
+        })
+        .collect())
+}
+
+/// Plan to write every .ftag file under `root` to a tar archive at
+/// `archive`. Returns the plan, and the number of .ftag files in the archive.
+pub fn backup(root: PathBuf, archive: &Path, config: &Config) -> Result<(WritePlan, usize), Error> {
+    let mut files = Vec::new();
+    let mut dir = DirTree::new(
+        root,
+        LoaderOptions::new(false, false, FileLoadingOptions::Skip),
+        config,
+    )?;
+    while let Some(VisitedDir {
+        abs_dir_path,
+        rel_dir_path,
+        ..
+    }) = dir.walk()
+    {
+        // The contents are copied as is, even if they can't be parsed.
+        let fpath = match get_ftag_path::<true>(abs_dir_path) {
+            Some(fpath) => fpath,
+            None => continue,
+        };
+        let bytes = std::fs::read(&fpath).map_err(|_| Error::CannotReadStoreFile(fpath.clone()))?;
+        let mtime = std::fs::metadata(&fpath)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        let mut path = String::new();
+        for name in rel_dir_path.iter() {
+            path.push_str(name.to_str().ok_or(Error::InvalidPath(fpath.clone()))?);
+            path.push('/');
+        }
+        path.push_str(FTAG_FILE);
+        files.push((path, bytes, mtime));
+    }
+    let mut bytes = Vec::new();
+    write_tar(&mut bytes, &files)
+        .map_err(|message| Error::InvalidArchive(archive.to_path_buf(), message))?;
+    let mut plan = WritePlan::default();
+    plan.write(archive.to_path_buf(), bytes);
+    Ok((plan, files.len()))
+}
+
+/// Plan to restore the .ftag files from the archive at `archive` into the
+/// directories under `root`. Existing .ftag files are backed up before they're
+/// overwritten, and .ftag files that are not in the archive are left
+/// alone. Returns the plan, and the directories that were skipped because they
+/// no longer exist.
+pub fn restore(root: PathBuf, archive: &Path) -> Result<(WritePlan, Vec<PathBuf>), Error> {
+    let mut plan = WritePlan::default();
+    let mut skipped = Vec::new();
+    for (dir, text) in read_archive(archive)? {
+        let mut dirpath = root.clone();
+        dirpath.push(&dir);
+        if !dirpath.is_dir() {
+            skipped.push(dir);
+            continue;
+        }
+        let mut fpath = dirpath;
+        fpath.push(FTAG_FILE);
+        plan.write(fpath, text);
+    }
+    Ok((plan, skipped))
+}
+
+/// Tags and description of a directory or a file in a snapshot.
+#[derive(Default, PartialEq)]
+struct Entry {
+    tags: BTreeSet<String>,
+    desc: Option<String>,
+}
+
+/// Changes to the tags and the description of a directory or a file, between
+/// two snapshots of the metadata.
+pub struct EntryDiff {
+    /// Path relative to the root. Paths of directories end with a separator.
+    pub path: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub desc_changed: bool,
+}
+
+/// Walk the directories under `root`, and collect the names of the files in
+/// every directory, and the contents of the .ftag files, by the paths of the
+/// directories relative to `root`.
+fn read_tree(root: PathBuf, config: &Config) -> Result<(DirFiles, Snapshot), Error> {
+    let mut files = HashMap::new();
+    let mut ftags = Vec::new();
+    let mut dir = DirTree::new(
+        root,
+        LoaderOptions::new(false, false, FileLoadingOptions::Skip),
+        config,
+    )?;
+    while let Some(VisitedDir {
+        abs_dir_path,
+        rel_dir_path,
+        files: dirfiles,
+        ..
+    }) = dir.walk()
+    {
+        files.insert(
+            rel_dir_path.to_path_buf(),
+            dirfiles
+                .iter()
+                .filter_map(|f| f.name().to_str().map(|n| n.to_string()))
+                .collect(),
+        );
+        if let Some(fpath) = get_ftag_path::<true>(abs_dir_path) {
+            ftags.push((
+                rel_dir_path.to_path_buf(),
+                std::fs::read_to_string(&fpath).map_err(|_| Error::CannotReadStoreFile(fpath))?,
+            ));
+        }
+    }
+    Ok((files, ftags))
+}
+
+/// Get the entries of all directories and files from the contents of the
+/// .ftag files of a snapshot, and the files currently on the disk.
+fn snapshot_entries(
+    root: &Path,
+    ftags: &[(PathBuf, String)],
+    files: &DirFiles,
+) -> Result<BTreeMap<String, Entry>, Error> {
+    let mut loader = Loader::new(LoaderOptions::new(
+        true,
+        true,
+        FileLoadingOptions::Load {
+            file_tags: true,
+            file_desc: true,
+        },
+    ));
+    let mut entries = BTreeMap::new();
+    for (dir, text) in ftags {
+        let data = loader.load_text(text, &root.join(dir).join(FTAG_FILE))?;
+        entries.insert(
+            format!(
+                "{}{}",
+                if dir.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    dir.as_path()
+                }
+                .display(),
+                std::path::MAIN_SEPARATOR
+            ),
+            Entry {
+                tags: data.tags().iter().map(|t| t.to_string()).collect(),
+                desc: data.desc.as_deref().map(str::to_string),
+            },
+        );
+        for name in files.get(dir).into_iter().flatten() {
+            let mut entry = Entry::default();
+            let mut descs = Vec::new();
+            for glob in data.globs.iter().filter(|g| glob_match(g.path, name)) {
+                entry
+                    .tags
+                    .extend(data.glob_tags(glob).iter().map(|t| t.to_string()));
+                descs.extend(data.glob_desc(glob));
+            }
+            if !descs.is_empty() {
+                entry.desc = Some(descs.join("\n"));
+            }
+            if entry != Entry::default() {
+                entries.insert(format!("{}", dir.join(name).display()), entry);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Compare the tags and descriptions of the directories and files under
+/// `root` between the snapshot in the archive at `old`, and either the
+/// snapshot in the archive at `new`, or the current .ftag files if `new` is
+/// not given. Only the tags from the entries of the files and directories
+/// themselves are compared, so a change to the tags of a directory is reported
+/// once, rather than for every file that inherits them. The files are the ones
+/// currently on the disk in both cases.
+pub fn diff(
+    root: PathBuf,
+    old: &Path,
+    new: Option<&Path>,
+    config: &Config,
+) -> Result<Vec<EntryDiff>, Error> {
+    let (files, current) = read_tree(root.clone(), config)?;
+    let old = snapshot_entries(&root, &read_archive(old)?, &files)?;
+    let new = match new {
+        Some(path) => snapshot_entries(&root, &read_archive(path)?, &files)?,
+        None => snapshot_entries(&root, &current, &files)?,
+    };
+    let empty = Entry::default();
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| {
+            let before = old.get(path).unwrap_or(&empty);
+            let after = new.get(path).unwrap_or(&empty);
+            let diff = EntryDiff {
+                path: path.clone(),
+                added: after.tags.difference(&before.tags).cloned().collect(),
+                removed: before.tags.difference(&after.tags).cloned().collect(),
+                desc_changed: before.desc != after.desc,
+            };
+            if diff.added.is_empty() && diff.removed.is_empty() && !diff.desc_changed {
+                None
+            } else {
+                Some(diff)
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn t_tar_roundtrip() {
+        let long = format!("{}/{}/.ftag", "a".repeat(120), "b".repeat(60));
+        let files = vec![
+            (String::from(".ftag"), b"[tags]\nroot\n".to_vec(), 1),
+            (String::from("x/y/.ftag"), vec![b'z'; 700], 2),
+            (long.clone(), Vec::new(), 3),
+        ];
+        let mut bytes = Vec::new();
+        write_tar(&mut bytes, &files).unwrap();
+        assert_eq!(bytes.len() % BLOCK_SIZE, 0);
+        let read = read_tar(&mut bytes.as_slice()).unwrap();
+        assert_eq!(
+            read,
+            files
+                .into_iter()
+                .map(|(path, bytes, _)| (path, bytes))
+                .collect::<Vec<_>>()
+        );
+        assert!(file_header(&"c".repeat(300), 0, 0).is_none());
+        bytes[0] = b'!'; // Corrupt the first header.
+        assert!(read_tar(&mut bytes.as_slice()).is_err());
+        // A size larger than the archive must fail without allocating it.
+        let header = file_header(".ftag", 0o77777777777, 0).unwrap();
+        assert!(read_tar(&mut &header[..]).is_err());
+    }
+
+    #[test]
+    fn t_tar_index() {
+        let files = vec![
+            (String::from("./.ftag"), b"[tags]\nroot\n".to_vec(), 1),
+            (String::from("a/b/c.txt"), b"hello".to_vec(), 2),
+            (String::from("../outside.txt"), Vec::new(), 3),
+        ];
+        let mut bytes = Vec::new();
+        write_tar(&mut bytes, &files).unwrap();
+        let index = TarIndex::from_reader(&mut io::Cursor::new(&bytes)).unwrap();
+        let mut names = Vec::new();
+        index.read_dir(Path::new(""), |name, is_file| {
+            names.push((name.to_string_lossy().to_string(), is_file))
+        });
+        assert_eq!(
+            names,
+            [(String::from(".ftag"), true), (String::from("a"), false)]
+        );
+        assert!(index.is_dir(Path::new("a/b")));
+        assert!(index.is_file(Path::new("a/b/c.txt")));
+        assert!(!index.is_file(Path::new("outside.txt")));
+        let TarFile { offset, size, .. } = index.entries[Path::new("a/b/c.txt")].unwrap();
+        assert_eq!(
+            &bytes[(offset as usize)..(offset as usize + size)],
+            b"hello"
+        );
+        assert_eq!(
+            index.stats(Path::new("a/b/c.txt")).unwrap(),
+            FileStats { size: 5, mtime: 2 }
+        );
+    }
+
+    #[test]
+    fn t_diff() {
+        let old = "[tags]\nphotos\n\n[path]\nx.jpg\n[tags]\nbeach\n\n[path]\ny.jpg\n[tags]\ncity\n\n[path]\nw.jpg\n[tags]\nforest\n";
+        let new = "[tags]\nphotos\n[desc]\nHolidays\n\n[path]\nx.jpg\n[tags]\nsea\n\n[path]\ny.jpg\n[tags]\ncity\n\n[path]\nz.jpg\n[tags]\nnight\n";
+        let dir = TempDir::new(
+            "archive_diff",
+            &[
+                ("a/.ftag", new),
+                ("a/w.jpg", ""),
+                ("a/x.jpg", ""),
+                ("a/y.jpg", ""),
+                ("a/z.jpg", ""),
+            ],
+        );
+        for (name, text) in [("old.tar", old), ("new.tar", new)] {
+            let mut bytes = Vec::new();
+            write_tar(&mut bytes, &[(String::from("a/.ftag"), text.into(), 0)]).unwrap();
+            std::fs::write(dir.join(name), bytes).unwrap();
+        }
+        let config = Config::default();
+        let summary = |diffs: Vec<EntryDiff>| {
+            diffs
+                .into_iter()
+                .map(|d| (d.path, d.added, d.removed, d.desc_changed))
+                .collect::<Vec<_>>()
+        };
+        let expected = vec![
+            (String::from("a/"), vec![], vec![], true),
+            (
+                String::from("a/w.jpg"),
+                vec![],
+                vec![String::from("forest")],
+                false,
+            ),
+            (
+                String::from("a/x.jpg"),
+                vec![String::from("sea")],
+                vec![String::from("beach")],
+                false,
+            ),
+            (
+                String::from("a/z.jpg"),
+                vec![String::from("night")],
+                vec![],
+                false,
+            ),
+        ];
+        let new_tar = dir.join("new.tar");
+        let between = diff(
+            dir.to_path_buf(),
+            &dir.join("old.tar"),
+            Some(&new_tar),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(summary(between), expected);
+        // The live tree has the same .ftag file as the new archive.
+        let live = diff(dir.to_path_buf(), &dir.join("old.tar"), None, &config).unwrap();
+        assert_eq!(summary(live), expected);
+        assert!(diff(dir.to_path_buf(), &new_tar, None, &config)
+            .unwrap()
+            .is_empty());
+    }
+}
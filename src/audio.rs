@@ -0,0 +1,545 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/*
+The artist, album, year and genre embedded in audio files are read as implicit
+tags, such as 'artist:Nina Simone' and 'genre:Jazz', and the year as a plain
+year tag like the ones inferred from the names. MP3 files have their metadata
+in ID3 tags, either ID3v2 at the start of the file or ID3v1 in the last 128
+bytes. FLAC, Ogg Vorbis and Opus files have Vorbis comments. Only what is
+needed to find these four fields is parsed, everything else is skipped. Files
+that can't be read, or have no metadata, just don't get any tags.
+ */
+
+/// Extensions of the files whose metadata is read.
+pub(crate) const AUDIO_EXTS: &[&str] = &[".mp3", ".flac", ".ogg", ".oga", ".opus"];
+
+/// Metadata larger than this is not read, to not load huge cover images.
+const MAX_METADATA_SIZE: u64 = 16 << 20;
+
+/// Genres of ID3v1, which are referred to by their index.
+const ID3V1_GENRES: &[&str] = &[
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "AlternRock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native American",
+    "Cabaret",
+    "New Wave",
+    "Psychadelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Field {
+    Artist,
+    Album,
+    Year,
+    Genre,
+}
+
+/// Collects the values of the fields, and turns them into tags.
+#[derive(Default)]
+struct Fields(Vec<(Field, String)>);
+
+impl Fields {
+    fn push(&mut self, field: Field, value: &str) {
+        let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        let value = match field {
+            Field::Year => match value.get(..4) {
+                Some(year) if year.bytes().all(|b| b.is_ascii_digit()) => year.to_string(),
+                _ => return,
+            },
+            Field::Genre => genre_name(&value).unwrap_or(value),
+            _ => value,
+        };
+        if !value.is_empty() && !self.0.iter().any(|(f, v)| *f == field && *v == value) {
+            self.0.push((field, value));
+        }
+    }
+
+    fn into_tags(self) -> Vec<String> {
+        self.0
+            .into_iter()
+            .map(|(field, value)| match field {
+                Field::Artist => format!("artist:{}", value),
+                Field::Album => format!("album:{}", value),
+                Field::Genre => format!("genre:{}", value),
+                Field::Year => value,
+            })
+            .collect()
+    }
+}
+
+/// ID3 genres can refer to the genres of ID3v1 by their index, as '17' or
+/// '(17)'. Get the name of the genre in that case.
+fn genre_name(value: &str) -> Option<String> {
+    let index = value.strip_prefix('(').and_then(|v| v.strip_suffix(')'));
+    let index: usize = index.unwrap_or(value).parse().ok()?;
+    ID3V1_GENRES.get(index).map(|g| g.to_string())
+}
+
+fn read_vec(r: &mut impl Read, size: u64) -> io::Result<Vec<u8>> {
+    if size > MAX_METADATA_SIZE {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+    let mut buf = vec![0u8; size as usize];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn syncsafe(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |acc, b| (acc << 7) | (*b & 0x7f) as u64)
+}
+
+fn big_endian(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+/// Undo the unsynchronisation of ID3v2, which inserts a zero after every 0xff.
+fn resync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, b) in data.iter().enumerate() {
+        if !(*b == 0 && i > 0 && data[i - 1] == 0xff) {
+            out.push(*b);
+        }
+    }
+    out
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+fn utf16(bytes: &[u8], big: bool) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| match big {
+            true => u16::from_be_bytes([c[0], c[1]]),
+            false => u16::from_le_bytes([c[0], c[1]]),
+        })
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Decode the values of an ID3v2 text frame. Version 2.4 allows several values
+/// separated by null characters.
+fn id3_text(frame: &[u8]) -> Vec<String> {
+    let Some((encoding, text)) = frame.split_first() else {
+        return Vec::new();
+    };
+    let text = match encoding {
+        0 => latin1(text),
+        1 => match text {
+            [0xfe, 0xff, rest @ ..] => utf16(rest, true),
+            [0xff, 0xfe, rest @ ..] => utf16(rest, false),
+            _ => utf16(text, false),
+        },
+        2 => utf16(text, true),
+        _ => String::from_utf8_lossy(text).into_owned(),
+    };
+    text.split('\0')
+        .map(|v| v.trim_start_matches('\u{feff}').to_string())
+        .collect()
+}
+
+/// Read the ID3v2 tag at the start of the reader, if there is one. The reader
+/// is left at the end of the tag.
+fn read_id3v2(r: &mut (impl Read + Seek), fields: &mut Fields) -> io::Result<()> {
+    let mut header = [0u8; 10];
+    if r.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+        r.seek(SeekFrom::Start(0))?;
+        return Ok(());
+    }
+    let (version, flags) = (header[3], header[5]);
+    let size = syncsafe(&header[6..10]);
+    let mut data = read_vec(r, size)?;
+    if flags & 0x10 != 0 {
+        r.seek(SeekFrom::Current(10))?; // Footer.
+    }
+    if flags & 0x80 != 0 && version < 4 {
+        data = resync(&data);
+    }
+    let mut pos = match (flags & 0x40 != 0, version) {
+        (true, 3) => 4 + big_endian(data.get(..4).unwrap_or_default()) as usize,
+        (true, 4) => syncsafe(data.get(..4).unwrap_or_default()) as usize,
+        _ => 0,
+    };
+    let (idlen, headlen) = match version {
+        2 => (3, 6),
+        _ => (4, 10),
+    };
+    while let Some(head) = data.get(pos..pos + headlen) {
+        if head[0] == 0 {
+            break; // Padding.
+        }
+        let len = match version {
+            2 => big_endian(&head[3..6]),
+            3 => big_endian(&head[4..8]),
+            _ => syncsafe(&head[4..8]),
+        } as usize;
+        let body = match data.get(pos + headlen..pos + headlen + len) {
+            Some(body) => body,
+            None => break,
+        };
+        pos += headlen + len;
+        let field = match &head[..idlen] {
+            b"TPE1" | b"TP1" => Field::Artist,
+            b"TALB" | b"TAL" => Field::Album,
+            b"TYER" | b"TDRC" | b"TYE" => Field::Year,
+            b"TCON" | b"TCO" => Field::Genre,
+            _ => continue,
+        };
+        let flags = match version {
+            4 => head[9],
+            _ => 0,
+        };
+        if flags & 0x0c != 0 {
+            continue; // Compressed or encrypted.
+        }
+        let body = match flags & 0x01 != 0 {
+            true => body.get(4..).unwrap_or_default(), // Data length indicator.
+            false => body,
+        };
+        let body = match flags & 0x02 != 0 {
+            true => resync(body),
+            false => body.to_vec(),
+        };
+        for value in id3_text(&body) {
+            fields.push(field, &value);
+        }
+    }
+    Ok(())
+}
+
+/// Read the ID3v1 tag in the last 128 bytes of the reader, if there is one.
+fn read_id3v1(r: &mut (impl Read + Seek), fields: &mut Fields) -> io::Result<()> {
+    if r.seek(SeekFrom::End(0))? < 128 {
+        return Ok(());
+    }
+    r.seek(SeekFrom::End(-128))?;
+    let mut tag = [0u8; 128];
+    r.read_exact(&mut tag)?;
+    if &tag[..3] != b"TAG" {
+        return Ok(());
+    }
+    let text = |bytes: &[u8]| latin1(bytes.split(|b| *b == 0).next().unwrap_or_default());
+    fields.push(Field::Artist, &text(&tag[33..63]));
+    fields.push(Field::Album, &text(&tag[63..93]));
+    fields.push(Field::Year, &text(&tag[93..97]));
+    if let Some(genre) = ID3V1_GENRES.get(tag[127] as usize) {
+        fields.push(Field::Genre, genre);
+    }
+    Ok(())
+}
+
+/// Parse a block of Vorbis comments, as found in FLAC, Ogg Vorbis and Opus.
+fn parse_vorbis_comments(data: &[u8], fields: &mut Fields) {
+    let mut pos = 0usize;
+    let next = |pos: &mut usize| -> Option<&[u8]> {
+        let len = u32::from_le_bytes(data.get(*pos..*pos + 4)?.try_into().ok()?) as usize;
+        let out = data.get(*pos + 4..*pos + 4 + len)?;
+        *pos += 4 + len;
+        Some(out)
+    };
+    let count = match next(&mut pos).and_then(|_vendor| data.get(pos..pos + 4)) {
+        Some(count) => u32::from_le_bytes(count.try_into().unwrap_or_default()),
+        None => return,
+    };
+    pos += 4;
+    for _ in 0..count {
+        let Some(comment) = next(&mut pos) else {
+            return;
+        };
+        let comment = String::from_utf8_lossy(comment);
+        let Some((key, value)) = comment.split_once('=') else {
+            continue;
+        };
+        let field = match key.to_ascii_uppercase().as_str() {
+            "ARTIST" => Field::Artist,
+            "ALBUM" => Field::Album,
+            "DATE" | "YEAR" => Field::Year,
+            "GENRE" => Field::Genre,
+            _ => continue,
+        };
+        fields.push(field, value);
+    }
+}
+
+/// Read the Vorbis comments in the metadata blocks of a FLAC stream.
+fn read_flac(r: &mut (impl Read + Seek), fields: &mut Fields) -> io::Result<()> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != b"fLaC" {
+        return Ok(());
+    }
+    loop {
+        let mut header = [0u8; 4];
+        r.read_exact(&mut header)?;
+        let len = big_endian(&header[1..]);
+        match header[0] & 0x7f {
+            4 => {
+                parse_vorbis_comments(&read_vec(r, len)?, fields);
+                return Ok(());
+            }
+            _ => r.seek(SeekFrom::Current(len as i64))?,
+        };
+        if header[0] & 0x80 != 0 {
+            return Ok(()); // Last block.
+        }
+    }
+}
+
+/// Read the Vorbis comments in the second packet of an Ogg Vorbis or Opus
+/// stream.
+fn read_ogg(r: &mut impl Read, fields: &mut Fields) -> io::Result<()> {
+    let mut packets = 0usize;
+    let mut packet: Vec<u8> = Vec::new();
+    let mut serial = None;
+    loop {
+        let mut header = [0u8; 27];
+        r.read_exact(&mut header)?;
+        if &header[..4] != b"OggS" {
+            return Ok(());
+        }
+        let mut segments = vec![0u8; header[26] as usize];
+        r.read_exact(&mut segments)?;
+        let size: u64 = segments.iter().map(|s| *s as u64).sum();
+        let data = read_vec(r, size)?;
+        if *serial.get_or_insert(header[14..18].to_vec()) != header[14..18] {
+            continue; // A different logical stream.
+        }
+        let mut pos = 0usize;
+        for len in segments.iter().map(|s| *s as usize) {
+            if packets == 1 {
+                packet.extend_from_slice(&data[pos..pos + len]);
+                if packet.len() as u64 > MAX_METADATA_SIZE {
+                    return Ok(());
+                }
+            }
+            pos += len;
+            if len < 255 {
+                packets += 1;
+                if packets == 2 {
+                    if let Some(comments) = packet
+                        .strip_prefix(b"\x03vorbis")
+                        .or_else(|| packet.strip_prefix(b"OpusTags"))
+                    {
+                        parse_vorbis_comments(comments, fields);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn read_fields(r: &mut (impl Read + Seek)) -> Fields {
+    let mut fields = Fields::default();
+    let mut magic = [0u8; 4];
+    let ok = r.read_exact(&mut magic).is_ok() && r.seek(SeekFrom::Start(0)).is_ok();
+    if !ok {
+        return fields;
+    }
+    if &magic == b"OggS" {
+        let _ = read_ogg(r, &mut fields);
+        return fields;
+    }
+    let _ = read_id3v2(r, &mut fields);
+    // FLAC files sometimes have an ID3v2 tag in front of them.
+    if fields.0.is_empty() {
+        let _ = read_flac(r, &mut fields);
+    }
+    if fields.0.is_empty() {
+        let _ = read_id3v1(r, &mut fields);
+    }
+    fields
+}
+
+/// Read the artist, album, year and genre embedded in the audio file at
+/// `path`, as tags. This is empty if the file can't be read, or has no
+/// metadata.
+pub(crate) fn read_tags(path: &Path) -> Vec<String> {
+    match File::open(path) {
+        Ok(file) => read_fields(&mut BufReader::new(file)).into_tags(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn tags(bytes: Vec<u8>) -> Vec<String> {
+        read_fields(&mut Cursor::new(bytes)).into_tags()
+    }
+
+    fn vorbis_comments(comments: &[&str]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for item in std::iter::once("vendor").chain(comments.iter().copied()) {
+            out.extend((item.len() as u32).to_le_bytes());
+            out.extend(item.as_bytes());
+            if item == "vendor" {
+                out.extend((comments.len() as u32).to_le_bytes());
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn t_read_audio_tags() {
+        // ID3v2.3 with a UTF-16 artist, and a genre referred to by index.
+        let mut frames = Vec::new();
+        for (id, body) in [
+            (
+                b"TPE1",
+                [
+                    &[1u8, 0xff, 0xfe][..],
+                    &[b'N', 0, b'i', 0, b'n', 0, b'a', 0],
+                ]
+                .concat(),
+            ),
+            (b"TALB", b"\x00Little Girl  Blue".to_vec()),
+            (b"TYER", b"\x001959".to_vec()),
+            (b"TCON", b"\x00(8)".to_vec()),
+            (b"APIC", vec![0u8; 40]),
+        ] {
+            frames.extend(id);
+            frames.extend((body.len() as u32).to_be_bytes());
+            frames.extend([0, 0]);
+            frames.extend(body);
+        }
+        frames.extend([0u8; 16]); // Padding.
+        let mut mp3 = b"ID3\x03\x00\x00".to_vec();
+        mp3.extend(
+            (0..4)
+                .rev()
+                .map(|i| ((frames.len() >> (7 * i)) & 0x7f) as u8),
+        );
+        mp3.extend(frames);
+        mp3.extend([0xffu8, 0xfb, 0x90, 0x00]);
+        assert_eq!(
+            tags(mp3),
+            [
+                "artist:Nina",
+                "album:Little Girl Blue",
+                "1959",
+                "genre:Jazz"
+            ]
+        );
+        // ID3v1 only.
+        let mut mp3 = vec![0xffu8; 200];
+        let mut v1 = [0u8; 128];
+        v1[..3].copy_from_slice(b"TAG");
+        v1[33..38].copy_from_slice(b"Queen");
+        v1[93..97].copy_from_slice(b"1975");
+        v1[127] = 17;
+        mp3.extend(v1);
+        assert_eq!(tags(mp3), ["artist:Queen", "1975", "genre:Rock"]);
+        // FLAC, with a block before the comments.
+        let comments = vorbis_comments(&["artist=Miles Davis", "DATE=1959-08-17", "TITLE=So What"]);
+        let mut flac = b"fLaC\x00\x00\x00\x02ab".to_vec();
+        flac.push(0x84);
+        flac.extend(&(comments.len() as u32).to_be_bytes()[1..]);
+        flac.extend(comments);
+        assert_eq!(tags(flac), ["artist:Miles Davis", "1959"]);
+        // Opus, with the comments in the second packet.
+        let page = |packet: &[u8]| {
+            let mut page = b"OggS\x00\x00".to_vec();
+            page.extend([0u8; 8]); // Granule position.
+            page.extend([1, 0, 0, 0]); // Serial number.
+            page.extend([0u8; 8]); // Sequence number and checksum.
+            page.push(1);
+            page.push(packet.len() as u8);
+            page.extend(packet);
+            page
+        };
+        let mut opus = page(b"OpusHead");
+        opus.extend(page(
+            &[
+                &b"OpusTags"[..],
+                &vorbis_comments(&["GENRE=Ambient", "ALBUM=Ambient 1"]),
+            ]
+            .concat(),
+        ));
+        assert_eq!(tags(opus), ["genre:Ambient", "album:Ambient 1"]);
+        assert!(tags(b"not audio".to_vec()).is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{
+    config::Config,
+    core::Error,
+    filter::Filter,
+    load::{FileLoadingOptions, GlobMatches, LoaderOptions},
+    query::TagTable,
+    vfs::{EntryKind, FileStats, FileSystem, OsFileSystem},
+    walk::{DirTree, MetaData, VisitedDir},
+};
+use std::{
+    cell::Cell,
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/*
+The benchmark times the main phases of loading the metadata of a directory
+tree, so that performance problems can be reported with numbers, and compared
+across versions. The directory walk and the loading of the .ftag files are
+interleaved in the walker, so the walker is given a file system that keeps
+track of the time spent in the file system. The time spent in the walker that
+is not spent in the file system is mostly spent parsing the .ftag files.
+ */
+
+/// The file system of the operating system, that keeps track of the time spent
+/// walking the directories, and reading files.
+struct TimedFileSystem<'a> {
+    walk: &'a Cell<Duration>,
+    read: &'a Cell<Duration>,
+}
+
+fn timed<T>(total: &Cell<Duration>, f: impl FnOnce() -> T) -> T {
+    let before = Instant::now();
+    let out = f();
+    total.set(total.get() + before.elapsed());
+    out
+}
+
+impl FileSystem for TimedFileSystem<'_> {
+    fn read_dir(&self, path: &Path, visit: &mut dyn FnMut(OsString, EntryKind)) -> io::Result<()> {
+        // The time spent in `visit` is part of the walk.
+        timed(self.walk, || OsFileSystem.read_dir(path, visit))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        timed(self.walk, || OsFileSystem.is_dir(path))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        timed(self.walk, || OsFileSystem.is_file(path))
+    }
+
+    fn read_to_string(&self, path: &Path, buf: &mut String) -> io::Result<()> {
+        timed(self.read, || OsFileSystem.read_to_string(path, buf))
+    }
+
+    fn stats(&self, path: &Path) -> io::Result<FileStats> {
+        timed(self.walk, || OsFileSystem.stats(path))
+    }
+}
+
+/// Durations of the phases of loading a directory tree, and the size of the
+/// tree.
+pub struct BenchReport {
+    pub num_dirs: usize,
+    pub num_files: usize,
+    pub num_ftag_files: usize,
+    pub num_matches: usize,
+    pub walk: Duration,
+    pub read: Duration,
+    pub parse: Duration,
+    pub glob_matching: Duration,
+    pub tag_table: Duration,
+    pub filter_eval: Duration,
+}
+
+impl BenchReport {
+    /// The phases and their durations, in the order they're run.
+    pub fn phases(&self) -> [(&'static str, Duration); 6] {
+        [
+            ("directory walk", self.walk),
+            ("reading .ftag files", self.read),
+            ("parsing .ftag files", self.parse),
+            ("glob matching", self.glob_matching),
+            ("loading tag table", self.tag_table),
+            ("filter evaluation", self.filter_eval),
+        ]
+    }
+}
+
+/// Time the phases of loading all the metadata in the directory tree at
+/// `root`, and evaluating `filter` on all the tracked files. If no `filter` is
+/// provided, a filter that matches any tag is used.
+pub fn bench(root: PathBuf, filter: Option<&str>, config: &Config) -> Result<BenchReport, Error> {
+    let (walk, read) = (Cell::new(Duration::ZERO), Cell::new(Duration::ZERO));
+    let mut dir = DirTree::with_fs(
+        root.clone(),
+        LoaderOptions::new(
+            true,
+            true,
+            FileLoadingOptions::Load {
+                file_tags: true,
+                file_desc: true,
+            },
+        ),
+        config,
+        TimedFileSystem {
+            walk: &walk,
+            read: &read,
+        },
+    )?;
+    let mut matcher = GlobMatches::new();
+    let (mut num_dirs, mut num_files, mut num_ftag_files) = (0usize, 0usize, 0usize);
+    let mut walker = Duration::ZERO;
+    let mut glob_matching = Duration::ZERO;
+    loop {
+        let before = Instant::now();
+        let VisitedDir {
+            files, metadata, ..
+        } = match dir.walk() {
+            Some(visited) => visited,
+            None => break,
+        };
+        walker += before.elapsed();
+        num_dirs += 1;
+        num_files += files.len();
+        match metadata {
+            MetaData::FailedToLoad(e) => return Err(e),
+            MetaData::NotFound => continue,
+            MetaData::Ok(data) => {
+                num_ftag_files += 1;
+                let before = Instant::now();
+                matcher.find_matches(files, &data.globs, false);
+                glob_matching += before.elapsed();
+            }
+        }
+    }
+    let before = Instant::now();
+    let table = TagTable::from_dir(root, config)?;
+    let tag_table = before.elapsed();
+    let filter = match filter {
+        Some(filter) => table.parse_filter(filter).map_err(Error::InvalidFilter)?,
+        None => (0..table.tags().len())
+            .map(Filter::Tag)
+            .reduce(|lhs, rhs| Filter::Or(Box::new(lhs), Box::new(rhs)))
+            .unwrap_or(Filter::TrueTag),
+    };
+    let before = Instant::now();
+    let num_matches = table.matching_files(&filter).len();
+    let filter_eval = before.elapsed();
+    let (walk, read) = (walk.get(), read.get());
+    Ok(BenchReport {
+        num_dirs,
+        num_files,
+        num_ftag_files,
+        num_matches,
+        walk,
+        read,
+        parse: walker.saturating_sub(walk + read),
+        glob_matching,
+        tag_table,
+        filter_eval,
+    })
+}
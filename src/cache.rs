@@ -0,0 +1,318 @@
+use crate::{
+    config::Config,
+    core::{Error, FTAG_CACHE_FILE, FTAG_FILE},
+    filter::Filter,
+    query::{query_files, query_files_and_dirs},
+    util::checksum,
+    vfs::OsFileSystem,
+    walk::{ignore_ancestors, WalkLimits, IGNORE_FILES},
+};
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/*
+The results of the most recent queries can be cached in a .ftagcache file at
+the root of the archive, so that running the same query again, say from a
+script, doesn't walk the directories. Every entry is keyed by a checksum of the
+canonical text of the filter, the limits of the walk, the effective config, and
+a stamp of the ignore files in the ancestors of the root. It records a stamp of
+every directory that was walked, which changes when the entries of the
+directory, its .ftag file or its ignore files change. The entry is only used if
+none of the stamps changed. Statting the directories is much cheaper than
+listing them and parsing their .ftag files.
+
+The file starts with a header line, followed by the entries, most recent first.
+Each entry is a line with the key in hex, the number of directories and the
+number of files, followed by a line per directory with its stamp in hex and
+its path, and a line per file with its path. The paths are relative to the
+root. Results that depend on anything other than the names of the files and
+the .ftag files, such as the sizes of the files, are never cached.
+ */
+
+const HEADER: &str = "ftag-cache 1";
+
+/// Number of queries whose results are kept in the cache.
+const MAX_ENTRIES: usize = 16;
+
+struct CacheEntry {
+    key: u64,
+    dirs: Vec<(u64, String)>, // Stamp and path of every walked directory.
+    files: Vec<String>,
+}
+
+impl CacheEntry {
+    fn is_fresh(&self, root: &Path) -> bool {
+        self.dirs
+            .iter()
+            .all(|(stamp, dir)| dir_stamp(&root.join(dir)) == *stamp)
+    }
+}
+
+/// Checksum of the modification times and the sizes of the directory, and
+/// the files in it that affect the results of queries.
+fn dir_stamp(dir: &Path) -> u64 {
+    let files = std::iter::once(FTAG_FILE).chain(IGNORE_FILES);
+    stamp(std::iter::once(dir.to_path_buf()).chain(files.map(|f| dir.join(f))))
+}
+
+/// Checksum of the ignore files in the ancestors of `root`, which apply to
+/// the walk too, but are outside the directories that are stamped.
+fn ancestors_stamp(root: &Path, config: &Config) -> u64 {
+    match config.ignore_files() {
+        true => stamp(
+            ignore_ancestors(&OsFileSystem, root)
+                .into_iter()
+                .flat_map(|dir| IGNORE_FILES.iter().map(move |f| dir.join(f))),
+        ),
+        false => 0,
+    }
+}
+
+/// Checksum of the modification times and the sizes of `paths`.
+fn stamp(paths: impl Iterator<Item = PathBuf>) -> u64 {
+    let mut text = String::new();
+    for path in paths {
+        let _ = match std::fs::metadata(&path) {
+            Ok(meta) => {
+                let mtime = meta
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default();
+                write!(
+                    text,
+                    "{}.{} {};",
+                    mtime.as_secs(),
+                    mtime.subsec_nanos(),
+                    meta.len()
+                )
+            }
+            Err(_) => write!(text, "-;"),
+        };
+    }
+    checksum(text.as_bytes()).unwrap_or_default()
+}
+
+/// Get the text of `filter` with redundant whitespace and parentheses
+/// removed, and the filter simplified, so that equivalent ways of writing a
+/// filter share the cached results.
+fn canonical_filter(filter: &str) -> Result<String, Error> {
+    let mut tags: Vec<String> = Vec::new();
+    let filter = Filter::parse(filter, |tag, quoted| {
+        // Quoted tags keep their quotes, so they don't share the results of the
+        // predicates they look like.
+        let tag = match quoted {
+            true => format!("\"{}\"", tag),
+            false => tag.to_string(),
+        };
+        Filter::Tag(match tags.iter().position(|t| *t == tag) {
+            Some(i) => i,
+            None => {
+                tags.push(tag);
+                tags.len() - 1
+            }
+        })
+    })
+    .map_err(Error::InvalidFilter)?;
+    Ok(filter.simplify().text(&tags))
+}
+
+fn cache_key(
+    root: &Path,
+    filter: &str,
+    limits: &WalkLimits,
+    config: &Config,
+) -> Result<u64, Error> {
+    let text = format!(
+        "{}\0{}\0{:?}\0{:x}\0{:x}",
+        canonical_filter(filter)?,
+        limits.subtree.display(),
+        limits.max_depth,
+        config.digest(),
+        ancestors_stamp(root, config)
+    );
+    Ok(checksum(text.as_bytes()).unwrap_or_default())
+}
+
+fn parse_entries(text: &str) -> Option<Vec<CacheEntry>> {
+    let mut lines = text.lines();
+    if lines.next()? != HEADER {
+        return None;
+    }
+    let mut entries = Vec::new();
+    while let Some(line) = lines.next() {
+        let mut words = line.split(' ');
+        let key = u64::from_str_radix(words.next()?, 16).ok()?;
+        let ndirs: usize = words.next()?.parse().ok()?;
+        let nfiles: usize = words.next()?.parse().ok()?;
+        let dirs = (0..ndirs)
+            .map(|_| {
+                let (stamp, dir) = lines.next()?.split_once(' ')?;
+                Some((u64::from_str_radix(stamp, 16).ok()?, dir.to_string()))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let files = (0..nfiles)
+            .map(|_| Some(lines.next()?.to_string()))
+            .collect::<Option<Vec<_>>>()?;
+        entries.push(CacheEntry { key, dirs, files });
+    }
+    Some(entries)
+}
+
+fn format_entries(entries: &[CacheEntry]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "{}", HEADER);
+    for CacheEntry { key, dirs, files } in entries {
+        let _ = writeln!(out, "{:016x} {} {}", key, dirs.len(), files.len());
+        for (stamp, dir) in dirs {
+            let _ = writeln!(out, "{:016x} {}", stamp, dir);
+        }
+        for file in files {
+            let _ = writeln!(out, "{}", file);
+        }
+    }
+    out
+}
+
+/// Get the path as a string that fits in a single line of the cache.
+fn path_line(path: &Path) -> Option<String> {
+    path.to_str()
+        .filter(|p| !p.contains(['\n', '\r']))
+        .map(|p| p.to_string())
+}
+
+/// Same as `query_files`, but the results are read from the cache at `dirpath`
+/// if none of the directories changed since they were cached, and the results
+/// are added to the cache otherwise. The cache is skipped unless it is enabled
+/// in the config. Failing to read or write the cache is not an error, the
+/// query is run as if there was no cache.
+pub fn cached_query_files(
+    dirpath: PathBuf,
+    filter: &str,
+    limits: &WalkLimits,
+    config: &Config,
+) -> Result<Vec<PathBuf>, Error> {
+    // Tags read from the contents of the files, and the files inside archives,
+    // can change without changing the stamps of the directories.
+    if !config.query_cache()
+        || config.read_only()
+        || config.archives()
+        || config.reads_file_contents()
+    {
+        return query_files(dirpath, filter, limits, config);
+    }
+    let key = cache_key(&dirpath, filter, limits, config)?;
+    let path = dirpath.join(FTAG_CACHE_FILE);
+    let mut entries = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| parse_entries(&text))
+        .unwrap_or_default();
+    if let Some(entry) = entries
+        .iter()
+        .find(|e| e.key == key && e.is_fresh(&dirpath))
+    {
+        return Ok(entry.files.iter().map(PathBuf::from).collect());
+    }
+    // Creating the cache changes the stamp of the root directory, so it is
+    // created before the directories are walked. Later writes replace the
+    // contents of the file, which doesn't change the stamp.
+    if !path.exists() {
+        let _ = std::fs::write(&path, format_entries(&[]));
+    }
+    let (files, dirs) = query_files_and_dirs(&dirpath, filter, limits, config)?;
+    let entry = dirs.and_then(|dirs| {
+        Some(CacheEntry {
+            key,
+            dirs: dirs
+                .iter()
+                .map(|dir| Some((dir_stamp(&dirpath.join(dir)), path_line(dir)?)))
+                .collect::<Option<Vec<_>>>()?,
+            files: files.iter().map(|f| path_line(f)).collect::<Option<_>>()?,
+        })
+    });
+    if let Some(entry) = entry {
+        entries.retain(|e| e.key != key);
+        entries.insert(0, entry);
+        entries.truncate(MAX_ENTRIES);
+        let _ = std::fs::write(&path, format_entries(&entries));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::FTAG_CONFIG_FILE;
+    use crate::util::TempDir;
+
+    #[test]
+    fn t_canonical_filter() {
+        assert_eq!(
+            canonical_filter("(foo)&  bar").unwrap(),
+            canonical_filter("foo & bar").unwrap()
+        );
+        assert_ne!(
+            canonical_filter("foo & bar").unwrap(),
+            canonical_filter("foo | bar").unwrap()
+        );
+    }
+
+    #[test]
+    fn t_cached_query_files() {
+        let dir = TempDir::new(
+            "cache",
+            &[
+                (FTAG_CONFIG_FILE, "[settings]\nquery-cache = true\n"),
+                ("a/.ftag", "[path]\nx.txt\n[tags]\nfoo\n"),
+                ("b/.ftag", "[tags]\nfoo\n[path]\ny.txt\n"),
+                ("a/x.txt", ""),
+                ("b/y.txt", ""),
+                ("b/z.txt", ""),
+            ],
+        );
+        let config = Config::load(&dir).unwrap();
+        let query = |filter| {
+            let mut files =
+                cached_query_files(dir.to_path_buf(), filter, &WalkLimits::default(), &config)
+                    .unwrap()
+                    .into_iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+        assert_eq!(query("foo"), ["a/x.txt", "b/y.txt"]);
+        // Overwrite the cached results in place, to see that they are used.
+        let path = dir.join(FTAG_CACHE_FILE);
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::io::Write::write_all(
+            &mut std::fs::OpenOptions::new().write(true).open(&path).unwrap(),
+            text.replace("\nb/y.txt\n", "\nb/w.txt\n").as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(query("(foo)"), ["a/x.txt", "b/w.txt"]);
+        // Changing a .ftag file makes the cached results stale.
+        std::fs::write(
+            dir.join("b/.ftag"),
+            "[tags]\nfoo\n[path]\ny.txt\n[path]\nz.txt\n",
+        )
+        .unwrap();
+        assert_eq!(query("foo"), ["a/x.txt", "b/y.txt", "b/z.txt"]);
+        // The results that depend on the sizes of the files are not cached.
+        assert_eq!(query("size<1"), ["a/x.txt", "b/y.txt", "b/z.txt"]);
+        assert_eq!(
+            parse_entries(&std::fs::read_to_string(&path).unwrap())
+                .unwrap()
+                .len(),
+            1
+        );
+        // Excluding more files from the command line is a different query.
+        let key = |config: &Config| cache_key(&dir, "foo", &WalkLimits::default(), config).unwrap();
+        let mut excluding = config.clone();
+        excluding.add_exclude(String::from("z.txt"));
+        assert_ne!(key(&config), key(&excluding));
+    }
+}
@@ -1,13 +1,192 @@
-use clap::{command, value_parser, Arg};
+use clap::{command, value_parser, Arg, ArgAction};
 use ftag::{
-    core::{self, get_all_tags, search, untracked_files, Error},
-    load::get_ftag_path,
-    query::{count_files_tags, run_query, TagTable},
+    archive,
+    bench::bench,
+    cache::cached_query_files,
+    config::Config,
+    core::{
+        self, add_untracked_files, get_all_tags, search, untracked_files, untracked_summary, Error,
+        FileChange, Problem, WritePlan,
+    },
+    diff,
+    doctor::{diagnose, Status},
+    filter::quote_filter_tag,
+    index::{build_index, IndexState},
+    json::Json,
+    load::{split_tags, validate_text},
+    query::{
+        count_files_tags, group_by_namespace, namespace_order, query_files, run_query, stats,
+        TagGroupItem, TagTable,
+    },
+    relink, schema,
+    vfs::{ArchiveFileSystem, FileSystem},
+    walk::{SkippedFiles, WalkLimits},
+    watch::watch,
 };
-use std::path::PathBuf;
+use std::{
+    io::{IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Suppresses the normal output of the commands when set, leaving only the
+/// exit status.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Print a line of output, unless it is suppressed with `--quiet`.
+macro_rules! output {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
 
-fn main() -> Result<(), Error> {
+/// Write `path` to `out` followed by a null byte, so that the output can be
+/// piped into `xargs -0` even when the paths contain newlines. On unix, the
+/// bytes of the path are written as they are, even if they aren't valid
+/// unicode.
+fn output_null(out: &mut impl Write, path: &Path) {
+    if QUIET.load(Ordering::Relaxed) {
+        return;
+    }
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec();
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy().into_owned().into_bytes();
+    // Like `println!`, except a closed pipe is not an error worth reporting.
+    let _ = out.write_all(&bytes).and_then(|_| out.write_all(b"\0"));
+}
+
+fn main() -> ExitCode {
     let matches = parse_args();
+    QUIET.store(matches.get_flag(arg::QUIET), Ordering::Relaxed);
+    let skipped = SkippedFiles::default();
+    let code = match run(matches, &skipped) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit::ERROR)
+        }
+    };
+    // Files skipped with --keep-going.
+    let skipped = skipped.take();
+    if skipped.is_empty() {
+        return code;
+    }
+    eprintln!("Skipped the .ftag files that failed to load:");
+    for e in skipped {
+        eprintln!("{:?}", e);
+    }
+    ExitCode::from(exit::ERROR)
+}
+
+/// Tell the user to rebuild the search index, if it was ignored because it is
+/// out of date.
+fn warn_stale_index(state: IndexState) {
+    if state == IndexState::OutOfDate {
+        eprintln!("The search index is out of date. Run 'ftag index' to update it.");
+    }
+}
+
+/// Make the changes in `plan`, or with `--dry-run`, only print them. They're
+/// printed to stderr if `to_stderr` is true, so that they don't break the
+/// output of a command that is read by other programs, such as JSON.
+fn commit(
+    plan: WritePlan,
+    dry_run: bool,
+    to_stderr: bool,
+    current_dir: &Path,
+    config: &Config,
+) -> Result<(), Error> {
+    if !dry_run {
+        return plan.apply(config);
+    }
+    for change in plan.changes() {
+        let (action, path) = match change {
+            FileChange::Write(path, _) => ("write", path),
+            FileChange::Remove(path) => ("remove", path),
+        };
+        let path = path.strip_prefix(current_dir).unwrap_or(path).display();
+        if to_stderr {
+            eprintln!("{} {}", action, path);
+        } else {
+            output!("{} {}", action, path);
+        }
+    }
+    Ok(())
+}
+
+/// Canonicalize the longest ancestor of `path` that exists on the disk, and
+/// append the rest of `path` to it.
+fn canonicalize_existing(path: &Path) -> Result<PathBuf, Error> {
+    path.ancestors()
+        .find_map(|dir| {
+            let rest = path.strip_prefix(dir).ok()?;
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            Some(dir.canonicalize().ok()?.join(rest))
+        })
+        .ok_or(Error::InvalidPath(path.to_path_buf()))
+}
+
+/// Exit code for commands that list things, depending on whether they found
+/// anything.
+fn found(count: usize) -> ExitCode {
+    if count > 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(exit::NEGATIVE)
+    }
+}
+
+/// Start the interactive mode in the TUI.
+#[cfg(feature = "tui")]
+fn interactive(current_dir: PathBuf, config: Config) -> Result<(), Error> {
+    let (first, loader) = ftag::query::TableLoader::start(current_dir, &config)?;
+    ftag::tui::start(first, loader, config).map_err(|err| Error::TUIFailure(format!("{:?}", err)))
+}
+
+#[cfg(not(feature = "tui"))]
+fn interactive(_current_dir: PathBuf, _config: Config) -> Result<(), Error> {
+    Err(tui_missing())
+}
+
+/// Go through the untracked `files` one at a time in the TUI, and return the
+/// number of files that were added.
+#[cfg(feature = "tui")]
+fn triage(
+    current_dir: PathBuf,
+    files: Vec<PathBuf>,
+    preview: bool,
+    config: Config,
+) -> Result<usize, Error> {
+    ftag::triage::start(current_dir, files, preview, config)
+        .map_err(|err| Error::TUIFailure(format!("{:?}", err)))
+}
+
+#[cfg(not(feature = "tui"))]
+fn triage(
+    _current_dir: PathBuf,
+    _files: Vec<PathBuf>,
+    _preview: bool,
+    _config: Config,
+) -> Result<usize, Error> {
+    Err(tui_missing())
+}
+
+#[cfg(not(feature = "tui"))]
+fn tui_missing() -> Error {
+    Error::TUIFailure(String::from(
+        "This build of ftag doesn't include the TUI. Build it with the 'tui' feature.",
+    ))
+}
+
+fn run(matches: clap::ArgMatches, skipped: &SkippedFiles) -> Result<ExitCode, Error> {
     let current_dir = if let Some(rootdir) = matches.get_one::<PathBuf>("path") {
         rootdir
             .canonicalize()
@@ -15,75 +194,940 @@ fn main() -> Result<(), Error> {
     } else {
         std::env::current_dir().map_err(|_| Error::InvalidWorkingDirectory)?
     };
+    let mut config = Config::load(&current_dir)?;
+    if matches.get_flag(arg::READ_ONLY) {
+        config.set_read_only();
+    }
+    if matches.get_flag(arg::KEEP_GOING) {
+        config.set_keep_going(skipped.clone());
+    }
+    for glob in matches
+        .get_many::<String>(arg::EXCLUDE)
+        .into_iter()
+        .flatten()
+    {
+        config.add_exclude(glob.clone());
+    }
+    let dry_run = matches.get_flag(arg::DRY_RUN);
+    let json = matches
+        .get_one::<String>(arg::FORMAT)
+        .is_some_and(|format| format == "json");
     // Handle tab completions first.
     if let Some(complete) = matches.subcommand_matches(cmd::BASH_COMPLETE) {
         // Bash completions can be registered with:
         // complete -o default -C 'ftag --bash-complete --' ftag
         if let Some(words) = complete.get_many::<String>(arg::BASH_COMPLETE_WORDS) {
-            handle_bash_completions(current_dir, words.map(|s| s.as_str()).collect());
+            handle_bash_completions(current_dir, &config, words.map(|s| s.as_str()).collect());
         }
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
     }
-    if let Some(_matches) = matches.subcommand_matches(cmd::COUNT) {
-        let (nfiles, ntags) = count_files_tags(current_dir)?;
-        println!("{} files; {} tags", nfiles, ntags);
-        return Ok(());
+    if let Some(matches) = matches.subcommand_matches(cmd::COMPLETIONS) {
+        print!(
+            "{}",
+            match matches.get_one::<String>(arg::SHELL).map(|s| s.as_str()) {
+                Some("zsh") => include_str!("../completions/_ftag"),
+                Some("fish") => include_str!("../completions/ftag.fish"),
+                _ => include_str!("../completions/ftag.bash"),
+            }
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(matches) = matches.subcommand_matches(cmd::COUNT) {
+        if matches.get_flag(arg::COVERAGE) {
+            let dirs = core::coverage(current_dir, &config)?;
+            for dir in &dirs {
+                output!(
+                    "{}{} {:.0}% covered; {} untracked; {} untagged; {} files",
+                    if dir.path.as_os_str().is_empty() {
+                        Path::new(".")
+                    } else {
+                        dir.path.as_path()
+                    }
+                    .display(),
+                    std::path::MAIN_SEPARATOR,
+                    dir.percent().floor(),
+                    dir.untracked,
+                    dir.untagged,
+                    dir.total
+                );
+            }
+            return Ok(found(dirs.len()));
+        }
+        let (nfiles, ntags) = count_files_tags(current_dir, &config)?;
+        output!("{} files; {} tags", nfiles, ntags);
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(matches) = matches.subcommand_matches(cmd::STATS) {
+        let top = *matches
+            .get_one::<usize>(arg::TOP)
+            .ok_or(Error::InvalidArgs)?;
+        let stats = stats(current_dir, &config)?;
+        let top_tags = &stats.tag_counts[..top.min(stats.tag_counts.len())];
+        let dir_display = |dir: &PathBuf| match dir.as_os_str().is_empty() {
+            true => String::from("."),
+            false => dir.display().to_string(),
+        };
+        if json {
+            output!(
+                "{}",
+                Json::Object(vec![
+                    ("tracked", Json::Number(stats.tracked)),
+                    ("untracked", Json::Number(stats.untracked)),
+                    ("tags", Json::Number(stats.tag_counts.len())),
+                    ("avg_tags_per_file", Json::Float(stats.avg_tags_per_file())),
+                    (
+                        "top_tags",
+                        Json::Array(
+                            top_tags
+                                .iter()
+                                .map(|(tag, count)| {
+                                    Json::Object(vec![
+                                        ("tag", Json::string(tag)),
+                                        ("count", Json::Number(*count)),
+                                    ])
+                                })
+                                .collect(),
+                        ),
+                    ),
+                    (
+                        "dirs_without_ftag",
+                        Json::strings(stats.dirs_without_ftag.iter().map(dir_display)),
+                    ),
+                ])
+            );
+            return Ok(ExitCode::SUCCESS);
+        }
+        output!("tracked files: {}", stats.tracked);
+        output!("untracked files: {}", stats.untracked);
+        output!("tags: {}", stats.tag_counts.len());
+        output!("average tags per file: {:.2}", stats.avg_tags_per_file());
+        output!("top tags:");
+        for (tag, count) in top_tags {
+            output!("  {tag}: {count}");
+        }
+        output!(
+            "directories without a .ftag file: {}",
+            stats.dirs_without_ftag.len()
+        );
+        for dir in &stats.dirs_without_ftag {
+            output!("  {}", dir_display(dir));
+        }
+        return Ok(ExitCode::SUCCESS);
     }
     if let Some(matches) = matches.subcommand_matches(cmd::QUERY) {
+        if matches.get_flag(arg::NO_IMPLICIT) {
+            config.set_no_implicit_tags();
+        }
         let filter = matches
             .get_one::<String>(arg::FILTER)
             .ok_or(Error::InvalidArgs)?;
-        run_query(current_dir, filter)
+        let null = matches.get_flag(arg::NULL);
+        let limits = WalkLimits {
+            subtree: match matches.get_one::<PathBuf>(arg::SUBDIR) {
+                Some(subdir) => current_dir
+                    .join(subdir)
+                    .canonicalize()
+                    .ok()
+                    .filter(|dir| dir.is_dir())
+                    .and_then(|dir| Some(dir.strip_prefix(&current_dir).ok()?.to_path_buf()))
+                    .ok_or(Error::InvalidPath(subdir.clone()))?,
+                None => PathBuf::new(),
+            },
+            max_depth: matches.get_one::<usize>(arg::MAX_DEPTH).copied(),
+        };
+        let cached = config.query_cache() && !matches.get_flag(arg::NO_CACHE);
+        if json && !null {
+            let paths = match cached {
+                true => cached_query_files(current_dir, filter, &limits, &config)?,
+                false => query_files(current_dir, filter, &limits, &config)?,
+            };
+            output!(
+                "{}",
+                Json::strings(paths.iter().map(|p| p.display().to_string()))
+            );
+            return Ok(found(paths.len()));
+        }
+        let mut stdout = std::io::stdout().lock();
+        let mut count = 0usize;
+        let mut visit = |path: &Path| {
+            count += 1;
+            match null {
+                true => output_null(&mut stdout, path),
+                false => output!("{}", path.display()),
+            }
+        };
+        match cached {
+            true => cached_query_files(current_dir, filter, &limits, &config)?
+                .iter()
+                .for_each(|path| visit(path)),
+            false => run_query(current_dir, filter, &limits, &config, visit)?,
+        }
+        Ok(found(count))
+    } else if let Some(matches) = matches.subcommand_matches(cmd::WATCH) {
+        let filter = matches
+            .get_one::<String>(arg::FILTER)
+            .ok_or(Error::InvalidArgs)?;
+        let show_diff = matches.get_flag(arg::DIFF);
+        watch(
+            &current_dir,
+            filter,
+            &config,
+            |results, added, removed| match (show_diff, json) {
+                (true, true) => output!(
+                    "{}",
+                    Json::Object(vec![
+                        (
+                            "added",
+                            Json::strings(added.iter().map(|p| p.display().to_string()))
+                        ),
+                        (
+                            "removed",
+                            Json::strings(removed.iter().map(|p| p.display().to_string()))
+                        ),
+                    ])
+                ),
+                (true, false) => {
+                    for path in added {
+                        output!("+ {}", path.display());
+                    }
+                    for path in removed {
+                        output!("- {}", path.display());
+                    }
+                }
+                (false, true) => output!(
+                    "{}",
+                    Json::strings(results.iter().map(|p| p.display().to_string()))
+                ),
+                (false, false) => {
+                    for path in results {
+                        output!("{}", path.display());
+                    }
+                    output!();
+                }
+            },
+            |e| eprintln!("Error: {:?}", e),
+        )?;
+        Ok(ExitCode::SUCCESS)
     } else if let Some(matches) = matches.subcommand_matches(cmd::SEARCH) {
-        return search(
+        let mut count = 0usize;
+        let state = search(
             current_dir,
             matches
                 .get_one::<String>(arg::SEARCH_STR)
                 .ok_or(Error::InvalidArgs)?,
+            &config,
+            |path| {
+                count += 1;
+                output!("{}", path);
+            },
+        )?;
+        warn_stale_index(state);
+        Ok(found(count))
+    } else if let Some(matches) = matches.subcommand_matches(cmd::GREP) {
+        let mut count = 0usize;
+        let state = core::grep(
+            current_dir,
+            matches
+                .get_one::<String>(arg::PATTERN)
+                .ok_or(Error::InvalidArgs)?,
+            std::io::stdout().is_terminal(),
+            &config,
+            |path, line| {
+                count += 1;
+                output!("{}: {}", path.display(), line);
+            },
+        )?;
+        warn_stale_index(state);
+        Ok(found(count))
+    } else if let Some(_matches) = matches.subcommand_matches(cmd::INDEX) {
+        commit(
+            build_index(current_dir.clone(), &config)?,
+            dry_run,
+            json,
+            &current_dir,
+            &config,
+        )?;
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches(cmd::BENCH) {
+        let report = bench(
+            current_dir,
+            matches.get_one::<String>(arg::FILTER).map(|f| f.as_str()),
+            &config,
+        )?;
+        output!(
+            "{} directories; {} files; {} .ftag files; {} files matched the filter",
+            report.num_dirs,
+            report.num_files,
+            report.num_ftag_files,
+            report.num_matches
         );
-    } else if let Some(_matches) = matches.subcommand_matches(cmd::INTERACTIVE) {
-        return ftag::tui::start(TagTable::from_dir(current_dir)?)
-            .map_err(|err| Error::TUIFailure(format!("{:?}", err)));
-    } else if let Some(_matches) = matches.subcommand_matches(cmd::CHECK) {
-        return core::check(current_dir);
+        for (phase, duration) in report.phases() {
+            output!("{:<20} {:>10.3} ms", phase, duration.as_secs_f64() * 1000.0);
+        }
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(_matches) = matches.subcommand_matches(cmd::DOCTOR) {
+        let diagnostics = diagnose(&current_dir, &config)?;
+        for d in &diagnostics {
+            output!("{:<8} {}: {}", d.status.label(), d.subject, d.detail);
+        }
+        Ok(if diagnostics.iter().any(|d| d.status == Status::Problem) {
+            ExitCode::from(exit::NEGATIVE)
+        } else {
+            ExitCode::SUCCESS
+        })
+    } else if let Some(matches) = matches.subcommand_matches(cmd::INTERACTIVE) {
+        if matches.get_flag(arg::NO_IMPLICIT) {
+            config.set_no_implicit_tags();
+        }
+        interactive(current_dir, config)?;
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches(cmd::UNDO) {
+        let (plan, dirs) = core::undo(current_dir.clone(), matches.get_flag(arg::PRUNE), &config)?;
+        commit(plan, dry_run, json, &current_dir, &config)?;
+        match (dry_run, json) {
+            (true, _) => {} // The planned changes were already printed.
+            (false, true) => output!(
+                "{}",
+                Json::strings(dirs.iter().map(|p| p.display().to_string()))
+            ),
+            (false, false) => {
+                for dir in dirs {
+                    output!(
+                        "{}",
+                        if dir.as_os_str().is_empty() {
+                            Path::new(".")
+                        } else {
+                            dir.as_path()
+                        }
+                        .display()
+                    );
+                }
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches(cmd::HISTORY) {
+        let history = core::history(current_dir, &config)?;
+        if let Some(id) = matches.get_one::<usize>(arg::SHOW) {
+            let entry = id
+                .checked_sub(1)
+                .and_then(|i| history.get(i))
+                .ok_or(Error::InvalidArgs)?;
+            output!("{}", entry.diff.trim_end_matches('\n'));
+            return Ok(ExitCode::SUCCESS);
+        }
+        if json {
+            output!(
+                "{}",
+                Json::Array(
+                    history
+                        .iter()
+                        .enumerate()
+                        .map(|(i, entry)| Json::Object(vec![
+                            ("id", Json::Number(i + 1)),
+                            ("time", Json::string(entry.date())),
+                            ("command", Json::string(&entry.command)),
+                            ("files", Json::Number(entry.files)),
+                        ]))
+                        .collect()
+                )
+            );
+        } else {
+            for (i, entry) in history.iter().enumerate() {
+                output!(
+                    "{:>4}  {}  {:>3} files  {}",
+                    i + 1,
+                    entry.date(),
+                    entry.files,
+                    entry.command
+                );
+            }
+        }
+        Ok(match history.is_empty() {
+            true => ExitCode::from(exit::NEGATIVE),
+            false => ExitCode::SUCCESS,
+        })
+    } else if matches.subcommand_matches(cmd::HASH).is_some() {
+        let (plan, count) = relink::record(current_dir.clone(), &config)?;
+        commit(plan, dry_run, json, &current_dir, &config)?;
+        if !dry_run {
+            output!("Recorded the checksums of {} files.", count);
+        }
+        Ok(ExitCode::SUCCESS)
+    } else if matches.subcommand_matches(cmd::RELINK).is_some() {
+        let (plan, moves) = relink::relink(current_dir.clone(), &config)?;
+        commit(plan, dry_run, json, &current_dir, &config)?;
+        if json {
+            output!(
+                "{}",
+                Json::Array(
+                    moves
+                        .iter()
+                        .map(|(from, to)| Json::Object(vec![
+                            ("from", Json::string(from.display().to_string())),
+                            ("to", Json::string(to.display().to_string())),
+                        ]))
+                        .collect()
+                )
+            );
+        } else {
+            for (from, to) in moves {
+                output!("{} -> {}", from.display(), to.display());
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches(cmd::INIT) {
+        let dir = match matches.get_one::<PathBuf>(arg::DIR) {
+            Some(dir) => current_dir.join(dir),
+            None => current_dir.clone(),
+        };
+        if !dir.is_dir() {
+            return Err(Error::InvalidPath(dir));
+        }
+        let (plan, globs) = core::init(dir, matches.get_flag(arg::FORCE), &config)?;
+        commit(plan, dry_run, json, &current_dir, &config)?;
+        match (dry_run, json) {
+            (true, _) => {} // The planned changes were already printed.
+            (false, true) => output!("{}", Json::strings(&globs)),
+            (false, false) => {
+                for glob in globs {
+                    output!("{}", glob);
+                }
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches(cmd::TRIAGE) {
+        let files = untracked_files(
+            current_dir.clone(),
+            matches.get_one::<usize>(arg::MAX_DEPTH).copied(),
+            &config,
+        )?;
+        if files.is_empty() {
+            output!("There are no untracked files.");
+            return Ok(ExitCode::SUCCESS);
+        }
+        let added = triage(
+            current_dir,
+            files,
+            !matches.get_flag(arg::NO_PREVIEW),
+            config,
+        )?;
+        output!("Added {} files.", added);
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches(cmd::CHECK) {
+        // Problems are the output of this command, not errors. They're printed
+        // to stdout, and reported through the exit status.
+        let root = match matches.get_one::<PathBuf>(arg::PATH) {
+            Some(path) => current_dir
+                .join(path)
+                .canonicalize()
+                .map_err(|_| Error::InvalidPath(path.clone()))?,
+            None => current_dir.clone(),
+        };
+        let mut problems = core::find_problems(
+            root.clone(),
+            matches.get_one::<usize>(arg::MAX_DEPTH).copied(),
+            &config,
+        )?;
+        let (rename, remove) = (
+            matches.get_flag(arg::APPLY_SUGGESTIONS),
+            matches.get_flag(arg::FIX),
+        );
+        let json = json || matches.get_flag(arg::JSON);
+        let porcelain = matches.get_flag(arg::PORCELAIN);
+        if rename || remove {
+            let plan = core::fix_unmatched_globs(&root, &problems, rename, remove)?;
+            // The planned changes must not mix with the problems.
+            commit(plan, dry_run, json || porcelain, &current_dir, &config)?;
+            if !dry_run {
+                // Only the problems that were not fixed are reported.
+                problems.retain(|problem| match problem {
+                    Problem::UnmatchedGlob { suggestion, .. } => {
+                        !(remove || (rename && suggestion.is_some()))
+                    }
+                    _ => true,
+                });
+            }
+        }
+        if json {
+            output!(
+                "{}",
+                Json::Array(
+                    problems
+                        .iter()
+                        .map(|problem| Json::Object(vec![
+                            ("code", Json::string(problem.code())),
+                            ("dir", Json::string(problem.dirpath().display().to_string())),
+                            ("subject", Json::string(problem.subject())),
+                            (
+                                "suggestion",
+                                match problem.suggestion() {
+                                    Some(s) => Json::string(s),
+                                    None => Json::Null,
+                                }
+                            ),
+                            ("message", Json::string(format!("{:?}", problem))),
+                        ]))
+                        .collect()
+                )
+            );
+        } else {
+            for problem in &problems {
+                if porcelain {
+                    output!(
+                        "{}\t{}\t{}",
+                        problem.code(),
+                        problem.dirpath().display(),
+                        problem.subject()
+                    );
+                } else {
+                    output!("{:?}", problem);
+                }
+            }
+        }
+        Ok(if problems.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(exit::NEGATIVE)
+        })
     } else if let Some(matches) = matches.subcommand_matches(cmd::WHATIS) {
+        if matches.get_flag(arg::NO_IMPLICIT) {
+            config.set_no_implicit_tags();
+        }
         match matches.get_one::<PathBuf>(arg::PATH) {
             Some(path) => {
-                let path = path
-                    .canonicalize()
-                    .map_err(|_| Error::InvalidPath(path.clone()))?;
-                println!("{}", core::what_is(&path)?);
-                return Ok(());
+                let path = match path.canonicalize() {
+                    Ok(path) => path,
+                    // Paths inside archives don't exist on the disk.
+                    Err(_) if config.archives() => canonicalize_existing(path)?,
+                    Err(_) => return Err(Error::InvalidPath(path.clone())),
+                };
+                let desc = core::what_is(&path, &config)?;
+                let table = match matches.get_flag(arg::RECURSIVE)
+                    && ArchiveFileSystem::new(config.archives()).is_dir(&path)
+                {
+                    true => Some(TagTable::from_dir(path.clone(), &config)?),
+                    false => None,
+                };
+                if json {
+                    let mut fields = vec![
+                        ("path", Json::string(path.display().to_string())),
+                        ("explicit", Json::strings(&desc.explicit)),
+                        ("inherited", Json::strings(&desc.inherited)),
+                        ("implicit", Json::strings(&desc.implicit)),
+                        (
+                            "desc",
+                            match desc.desc.is_empty() {
+                                true => Json::Null,
+                                false => Json::string(desc.desc),
+                            },
+                        ),
+                    ];
+                    if let Some(table) = table {
+                        fields.push(("tracked_files", Json::Number(table.files().len())));
+                        fields.push((
+                            "tag_counts",
+                            Json::Array(
+                                table
+                                    .tag_counts()
+                                    .into_iter()
+                                    .map(|(tag, count)| {
+                                        Json::Object(vec![
+                                            ("tag", Json::string(tag)),
+                                            ("count", Json::Number(count)),
+                                        ])
+                                    })
+                                    .collect(),
+                            ),
+                        ));
+                    }
+                    output!("{}", Json::Object(fields));
+                    return Ok(ExitCode::SUCCESS);
+                }
+                output!("{}", desc);
+                if let Some(table) = table {
+                    output!("tracked files: {}", table.files().len());
+                    for (tag, count) in table.tag_counts() {
+                        output!("  {tag}: {count}");
+                    }
+                }
+                Ok(ExitCode::SUCCESS)
             }
-            None => return Err(Error::InvalidArgs),
+            None => Err(Error::InvalidArgs),
         }
+    } else if let Some(matches) = matches.subcommand_matches(cmd::DESCRIBE_TAG) {
+        let tag = matches
+            .get_one::<String>(arg::TAG)
+            .ok_or(Error::InvalidArgs)?;
+        let desc = config
+            .describe(tag)
+            .ok_or(Error::UndescribedTag(tag.clone()))?;
+        output!("{}: {}", config.canonical(tag), desc);
+        Ok(ExitCode::SUCCESS)
     } else if let Some(matches) = matches.subcommand_matches(cmd::EDIT) {
         let path = matches
             .get_one::<PathBuf>(arg::PATH)
             .unwrap_or(&current_dir);
-        edit::edit_file(get_ftag_path::<false>(path).ok_or(Error::InvalidPath(path.clone()))?)
-            .map_err(|e| Error::EditCommandFailed(format!("{:?}", e)))?;
-        return Ok(());
-    } else if let Some(_matches) = matches.subcommand_matches(cmd::CLEAN) {
-        core::clean(current_dir)
-    } else if let Some(_matches) = matches.subcommand_matches(cmd::UNTRACKED) {
-        for path in untracked_files(current_dir)? {
-            println!("{}", path.display());
-        }
-        return Ok(());
-    } else if let Some(_matches) = matches.subcommand_matches(cmd::TAGS) {
-        let mut tags: Box<[String]> = get_all_tags(current_dir)?.collect();
-        tags.sort_unstable();
-        for tag in tags {
-            println!("{}", tag);
-        }
-        return Ok(());
+        core::edit(path, &config)?;
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches(cmd::CLEAN) {
+        let plan = core::clean(
+            current_dir.clone(),
+            matches.get_flag(arg::CANONICALIZE),
+            &config,
+        )?;
+        if !dry_run {
+            plan.apply(&config)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        for change in plan.changes() {
+            let (path, new) = match change {
+                FileChange::Write(path, bytes) => (path, Some(String::from_utf8_lossy(bytes))),
+                FileChange::Remove(path) => (path, None),
+            };
+            let old = std::fs::read(path).ok();
+            let old = old.as_deref().map(String::from_utf8_lossy);
+            let patch = diff::unified(
+                &path
+                    .strip_prefix(&current_dir)
+                    .unwrap_or(path)
+                    .display()
+                    .to_string(),
+                old.as_deref(),
+                new.as_deref(),
+            );
+            if !patch.is_empty() {
+                output!("{}", patch.trim_end_matches('\n'));
+            }
+        }
+        Ok(match plan.changes().is_empty() {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::from(exit::NEGATIVE),
+        })
+    } else if let Some(matches) = matches.subcommand_matches(cmd::VALIDATE) {
+        let (name, text) = match matches.get_one::<PathBuf>(arg::FILE) {
+            Some(path) if path.as_os_str() != "-" => (
+                path.display().to_string(),
+                std::fs::read_to_string(path)
+                    .map_err(|_| Error::CannotReadStoreFile(path.clone()))?,
+            ),
+            _ => {
+                let mut text = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut text)
+                    .map_err(|_| Error::CannotReadStoreFile(PathBuf::from("-")))?;
+                (String::from("<stdin>"), text)
+            }
+        };
+        let errors = validate_text(&text);
+        for (line, message) in &errors {
+            output!("{}:{}: {}", name, line, message);
+        }
+        Ok(if errors.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(exit::NEGATIVE)
+        })
+    } else if let Some(matches) = matches.subcommand_matches(cmd::VALIDATE_EXPORT) {
+        if matches.get_flag(arg::PRINT_SCHEMA) {
+            output!("{}", schema::SCHEMA.trim_end());
+            return Ok(ExitCode::SUCCESS);
+        }
+        let (name, text) = match matches.get_one::<PathBuf>(arg::FILE) {
+            Some(path) if path.as_os_str() != "-" => (
+                path.display().to_string(),
+                std::fs::read_to_string(path).map_err(|_| Error::InvalidPath(path.clone()))?,
+            ),
+            _ => {
+                let mut text = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut text)
+                    .map_err(|_| Error::InvalidPath(PathBuf::from("-")))?;
+                (String::from("<stdin>"), text)
+            }
+        };
+        let kind = matches.get_one::<String>(arg::KIND).map(String::as_str);
+        match schema::validate_output(&text, kind) {
+            Ok(kinds) => {
+                output!("{}: {}", name, kinds.join(", "));
+                Ok(ExitCode::SUCCESS)
+            }
+            Err(errors) => {
+                for message in &errors {
+                    output!("{}: {}", name, message);
+                }
+                Ok(ExitCode::from(exit::NEGATIVE))
+            }
+        }
+    } else if matches.subcommand_matches(cmd::VERIFY).is_some() {
+        let errors = core::verify(current_dir, &config)?;
+        for (path, line, message) in &errors {
+            output!("{}:{}: {}", path.display(), line, message);
+        }
+        Ok(if errors.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(exit::NEGATIVE)
+        })
+    } else if let Some(matches) = matches.subcommand_matches(cmd::FMT) {
+        let sort_tags = matches.get_flag(arg::SORT_TAGS);
+        let (plan, errors) = match matches.get_one::<PathBuf>(arg::FILE) {
+            Some(file) => (core::format_file(file, sort_tags)?, Vec::new()),
+            None => core::format(current_dir.clone(), sort_tags, &config)?,
+        };
+        commit(plan, dry_run, json, &current_dir, &config)?;
+        for e in &errors {
+            eprintln!("{:?}", e);
+        }
+        Ok(if errors.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(exit::NEGATIVE)
+        })
+    } else if let Some(matches) = matches.subcommand_matches(cmd::MV) {
+        let src = matches
+            .get_one::<PathBuf>(arg::SOURCE)
+            .ok_or(Error::InvalidArgs)?;
+        let dst = matches
+            .get_one::<PathBuf>(arg::DESTINATION)
+            .ok_or(Error::InvalidArgs)?;
+        let (plan, dst) = core::plan_move(src, dst)?;
+        if dry_run {
+            output!("move {} {}", src.display(), dst.display());
+            commit(plan, dry_run, json, &current_dir, &config)?;
+        } else {
+            core::move_file(src, &dst, plan, &config)?;
+        }
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches(cmd::BACKUP) {
+        let path = matches
+            .get_one::<PathBuf>(arg::ARCHIVE)
+            .ok_or(Error::InvalidArgs)?;
+        let (plan, count) = archive::backup(current_dir.clone(), path, &config)?;
+        commit(plan, dry_run, json, &current_dir, &config)?;
+        if !dry_run {
+            output!("{} .ftag files written to '{}'", count, path.display());
+        }
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches(cmd::RESTORE) {
+        let path = matches
+            .get_one::<PathBuf>(arg::ARCHIVE)
+            .ok_or(Error::InvalidArgs)?;
+        let (plan, skipped) = archive::restore(current_dir.clone(), path)?;
+        for dir in &skipped {
+            eprintln!(
+                "Warning: Skipped the .ftag file of '{}', because the directory doesn't exist.",
+                dir.display()
+            );
+        }
+        let restored: Vec<PathBuf> = plan
+            .changes()
+            .iter()
+            .filter_map(|change| match change {
+                FileChange::Write(path, _) => Some(
+                    path.strip_prefix(&current_dir)
+                        .unwrap_or(path)
+                        .to_path_buf(),
+                ),
+                FileChange::Remove(_) => None,
+            })
+            .collect();
+        commit(plan, dry_run, json, &current_dir, &config)?;
+        if !dry_run {
+            for path in &restored {
+                output!("{}", path.display());
+            }
+        }
+        Ok(ExitCode::SUCCESS)
+    } else if let Some(matches) = matches.subcommand_matches(cmd::DIFF) {
+        let old = matches
+            .get_one::<PathBuf>(arg::OLD)
+            .ok_or(Error::InvalidArgs)?;
+        let new = matches.get_one::<PathBuf>(arg::NEW);
+        let diffs = archive::diff(current_dir, old, new.map(|p| p.as_path()), &config)?;
+        for diff in &diffs {
+            let mut changes: Vec<String> = diff
+                .added
+                .iter()
+                .map(|t| format!("+{}", t))
+                .chain(diff.removed.iter().map(|t| format!("-{}", t)))
+                .collect();
+            if diff.desc_changed {
+                changes.push(String::from("(description changed)"));
+            }
+            output!("{}: {}", diff.path, changes.join(" "));
+        }
+        Ok(if diffs.is_empty() {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(exit::NEGATIVE)
+        })
+    } else if let Some(matches) = matches.subcommand_matches(cmd::UNTRACKED) {
+        let max_depth = matches.get_one::<usize>(arg::MAX_DEPTH).copied();
+        if matches.get_flag(arg::ADD) {
+            let tags: Vec<String> = matches
+                .get_many::<String>(arg::TAGS)
+                .into_iter()
+                .flatten()
+                .flat_map(|t| split_tags(t))
+                .map(|t| t.to_string())
+                .collect();
+            for tag in core::check_deprecated_tags(&tags, matches.get_flag(arg::STRICT), &config)? {
+                match config.replacement(tag) {
+                    Some(r) => eprintln!(
+                        "Warning: The tag '{}' is deprecated, use '{}' instead.",
+                        tag, r
+                    ),
+                    None => eprintln!("Warning: The tag '{}' is deprecated.", tag),
+                }
+            }
+            let (plan, added) =
+                add_untracked_files(current_dir.clone(), &tags, max_depth, &config)?;
+            commit(plan, dry_run, json, &current_dir, &config)?;
+            match (dry_run, json) {
+                (true, _) => {} // The planned changes were already printed.
+                (false, true) => output!(
+                    "{}",
+                    Json::strings(added.iter().map(|p| p.display().to_string()))
+                ),
+                (false, false) => {
+                    for path in added {
+                        output!("{}", path.display());
+                    }
+                }
+            }
+            Ok(ExitCode::SUCCESS)
+        } else if matches.get_flag(arg::SUMMARY) {
+            let summary = untracked_summary(current_dir, max_depth, &config)?;
+            if json {
+                output!(
+                    "{}",
+                    Json::Array(
+                        summary
+                            .iter()
+                            .map(|(dir, count)| Json::Object(vec![
+                                ("dir", Json::string(dir.display().to_string())),
+                                ("untracked", Json::Number(*count)),
+                            ]))
+                            .collect()
+                    )
+                );
+                return Ok(found(summary.len()));
+            }
+            for (dir, count) in &summary {
+                output!(
+                    "{}{} {} untracked",
+                    if dir.as_os_str().is_empty() {
+                        Path::new(".")
+                    } else {
+                        dir.as_path()
+                    }
+                    .display(),
+                    std::path::MAIN_SEPARATOR,
+                    count
+                );
+            }
+            Ok(found(summary.len()))
+        } else {
+            let files = untracked_files(current_dir, max_depth, &config)?;
+            if matches.get_flag(arg::NULL) {
+                let mut stdout = std::io::stdout().lock();
+                for path in &files {
+                    output_null(&mut stdout, path);
+                }
+            } else if json {
+                output!(
+                    "{}",
+                    Json::strings(files.iter().map(|p| p.display().to_string()))
+                );
+            } else {
+                for path in &files {
+                    output!("{}", path.display());
+                }
+            }
+            Ok(found(files.len()))
+        }
+    } else if let Some(matches) = matches.subcommand_matches(cmd::TAGS) {
+        if matches.get_flag(arg::NO_IMPLICIT) {
+            config.set_no_implicit_tags();
+        }
+        if let Some(tag) = matches.get_one::<String>(arg::WHERE) {
+            let origins = core::tag_origins(current_dir, tag, &config)?;
+            if json {
+                output!(
+                    "{}",
+                    Json::Array(
+                        origins
+                            .iter()
+                            .map(|origin| Json::Object(vec![
+                                ("path", Json::string(origin.path.display().to_string())),
+                                ("directory", Json::Bool(origin.directory)),
+                                ("globs", Json::Number(origin.globs)),
+                            ]))
+                            .collect()
+                    )
+                );
+                return Ok(found(origins.len()));
+            }
+            for origin in &origins {
+                output!(
+                    "{}: {}{} {}",
+                    origin.path.display(),
+                    if origin.directory { "directory, " } else { "" },
+                    origin.globs,
+                    if origin.globs == 1 { "glob" } else { "globs" }
+                );
+            }
+            return Ok(found(origins.len()));
+        }
+        let max_depth = matches.get_one::<usize>(arg::MAX_DEPTH).copied();
+        let mut tags: Box<[String]> = get_all_tags(current_dir, max_depth, &config)?
+            .filter(|t| !config.is_virtual(t))
+            .chain(config.virtual_tags().map(|(t, _)| t.to_string()))
+            .collect();
+        let marker = |tag: &str| {
+            if config.is_virtual(tag) {
+                " (virtual)"
+            } else {
+                ""
+            }
+        };
+        if json {
+            tags.sort_unstable();
+            output!(
+                "{}",
+                Json::Array(
+                    tags.iter()
+                        .map(|tag| Json::Object(vec![
+                            ("tag", Json::string(tag)),
+                            ("virtual", Json::Bool(config.is_virtual(tag))),
+                        ]))
+                        .collect()
+                )
+            );
+        } else if matches.get_flag(arg::GROUP) {
+            tags.sort_unstable_by(|a, b| namespace_order(a).cmp(&namespace_order(b)));
+            for item in group_by_namespace(&tags) {
+                match item {
+                    TagGroupItem::Namespace(ns) => output!("{}:", ns),
+                    TagGroupItem::Tag(tag, text) if text.len() < tag.len() => {
+                        output!("  {}{}", text, marker(tag))
+                    }
+                    TagGroupItem::Tag(tag, _text) => output!("{}{}", tag, marker(tag)),
+                }
+            }
+        } else {
+            tags.sort_unstable();
+            for tag in &tags {
+                output!("{}{}", tag, marker(tag));
+            }
+        }
+        Ok(found(tags.len()))
     } else {
-        return Err(Error::InvalidArgs);
+        Err(Error::InvalidArgs)
     }
 }
 
-fn handle_bash_completions(current_dir: PathBuf, mut words: Vec<&str>) {
+fn handle_bash_completions(current_dir: PathBuf, config: &Config, mut words: Vec<&str>) {
     /*
     Bash completion always passes in 3 words. The first word will be the main
     binary: ftag. The second word will be an empty string, and the third word
@@ -96,16 +1140,20 @@ fn handle_bash_completions(current_dir: PathBuf, mut words: Vec<&str>) {
     if words[0] != "ftag" {
         return;
     }
-    const PREV_WORDS: [&str; 11] = [
+    const PREV_WORDS: [&str; 15] = [
         "query",
         "-q",
+        "watch",
+        "grep",
         "interactive",
         "check",
         "whatis",
+        "describe-tag",
         "edit",
         "untracked",
         "tags",
         "clean",
+        "completions",
         "--path",
         "-p",
     ];
@@ -117,13 +1165,13 @@ fn handle_bash_completions(current_dir: PathBuf, mut words: Vec<&str>) {
                 }
             }
         }
-        Some(cmd::QUERY) | Some(cmd::QUERY_SHORT) => {
-            if let (Some(word), Ok(tags)) = (words.pop(), get_all_tags(current_dir)) {
+        Some(cmd::QUERY) | Some(cmd::QUERY_SHORT) | Some(cmd::WATCH) => {
+            if let (Some(word), Ok(tags)) = (words.pop(), get_all_tags(current_dir, None, config)) {
                 let (left, right) = {
                     let mut last = 0usize;
                     for (i, c) in word.char_indices() {
                         match c {
-                            '|' | '(' | ')' | '&' | '!' => last = i,
+                            '|' | '^' | '(' | ')' | '&' | '!' => last = i,
                             _ if c.is_whitespace() => last = i,
                             _ => {} // Do nothing.
                         }
@@ -131,8 +1179,31 @@ fn handle_bash_completions(current_dir: PathBuf, mut words: Vec<&str>) {
                     let last = if last == 0 { last } else { last + 1 };
                     (&word[..last], &word[last..])
                 };
-                for tag in tags.filter(|t| t.starts_with(right)) {
-                    println!("{left}{}", tag);
+                // The quote of a tag that is being typed is added back if needed.
+                let right = right.strip_prefix('"').unwrap_or(right);
+                // Aliases are completed to their canonical form.
+                for tag in tags.filter(|t| t.starts_with(right) || config.alias_matches(t, right)) {
+                    println!("{left}{}", shell_escape(word, &quote_filter_tag(&tag)));
+                }
+                for (tag, _query) in config.virtual_tags().filter(|(t, _)| t.starts_with(right)) {
+                    println!("{left}{}", shell_escape(word, &quote_filter_tag(tag)));
+                }
+            }
+        }
+        Some(cmd::DESCRIBE_TAG) => {
+            if let (Some(word), Ok(tags)) = (words.pop(), get_all_tags(current_dir, None, config)) {
+                for tag in tags.filter(|t| t.starts_with(word) && config.describe(t).is_some()) {
+                    println!("{}", shell_escape(word, &tag));
+                }
+            }
+        }
+        Some(cmd::COMPLETIONS) => {
+            if let Some(word) = words.pop() {
+                for shell in ["bash", "zsh", "fish"]
+                    .iter()
+                    .filter(|s| s.starts_with(word))
+                {
+                    println!("{}", shell);
                 }
             }
         }
@@ -140,6 +1211,25 @@ fn handle_bash_completions(current_dir: PathBuf, mut words: Vec<&str>) {
     }
 }
 
+/// Escape the whitespace, quotes and other characters special to bash in `text`,
+/// so that bash reads it back as is, when it replaces the partial `word` that
+/// starts with the same kind of quote, if any.
+fn shell_escape(word: &str, text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        let escape = match word.chars().next() {
+            Some('\'') => false, // Nothing is special in single quotes.
+            Some('"') => c == '"',
+            _ => c.is_whitespace() || "\"'\\$`&|;()<>".contains(c),
+        };
+        if escape {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
 fn parse_args() -> clap::ArgMatches {
     command!()
         .arg(
@@ -149,7 +1239,74 @@ fn parse_args() -> clap::ArgMatches {
                 .required(false)
                 .value_parser(value_parser!(PathBuf)),
         )
-        .subcommand(clap::Command::new(cmd::COUNT).about(about::COUNT))
+        .arg(
+            Arg::new(arg::QUIET)
+                .long("quiet")
+                .required(false)
+                .action(ArgAction::SetTrue)
+                .global(true)
+                .help(about::QUIET)
+                .long_help(about::QUIET_LONG),
+        )
+        .arg(
+            Arg::new(arg::READ_ONLY)
+                .long("read-only")
+                .required(false)
+                .action(ArgAction::SetTrue)
+                .global(true)
+                .help(about::READ_ONLY),
+        )
+        .arg(
+            Arg::new(arg::KEEP_GOING)
+                .long("keep-going")
+                .required(false)
+                .action(ArgAction::SetTrue)
+                .global(true)
+                .help(about::KEEP_GOING),
+        )
+        .arg(
+            Arg::new(arg::EXCLUDE)
+                .long("exclude")
+                .value_name("GLOB")
+                .required(false)
+                .action(ArgAction::Append)
+                .global(true)
+                .help(about::EXCLUDE),
+        )
+        .arg(
+            Arg::new(arg::FORMAT)
+                .long("format")
+                .required(false)
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .global(true)
+                .help(about::FORMAT),
+        )
+        .arg(
+            Arg::new(arg::DRY_RUN)
+                .long("dry-run")
+                .required(false)
+                .action(ArgAction::SetTrue)
+                .global(true)
+                .help(about::DRY_RUN),
+        )
+        .subcommand(
+            clap::Command::new(cmd::COUNT).about(about::COUNT).arg(
+                Arg::new(arg::COVERAGE)
+                    .long("coverage")
+                    .action(ArgAction::SetTrue)
+                    .help(about::COUNT_COVERAGE),
+            ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::STATS).about(about::STATS).arg(
+                Arg::new(arg::TOP)
+                    .long("top")
+                    .value_parser(value_parser!(usize))
+                    .default_value("10")
+                    .help(about::STATS_TOP),
+            ),
+        )
         .subcommand(
             clap::Command::new(cmd::QUERY)
                 .alias(cmd::QUERY_SHORT)
@@ -159,6 +1316,53 @@ fn parse_args() -> clap::ArgMatches {
                         .required(true)
                         .help(about::QUERY_FILTER)
                         .long_help(about::QUERY_FILTER_LONG),
+                )
+                .arg(
+                    Arg::new(arg::SUBDIR)
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf))
+                        .help(about::QUERY_SUBDIR),
+                )
+                .arg(
+                    Arg::new(arg::NULL)
+                        .long("null")
+                        .short('0')
+                        .action(ArgAction::SetTrue)
+                        .help(about::NULL),
+                )
+                .arg(
+                    Arg::new(arg::MAX_DEPTH)
+                        .long("max-depth")
+                        .value_parser(value_parser!(usize))
+                        .help(about::MAX_DEPTH),
+                )
+                .arg(
+                    Arg::new(arg::NO_IMPLICIT)
+                        .long("no-implicit")
+                        .action(ArgAction::SetTrue)
+                        .help(about::NO_IMPLICIT),
+                )
+                .arg(
+                    Arg::new(arg::NO_CACHE)
+                        .long("no-cache")
+                        .action(ArgAction::SetTrue)
+                        .help(about::NO_CACHE),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::WATCH)
+                .about(about::WATCH)
+                .arg(
+                    Arg::new(arg::FILTER)
+                        .required(true)
+                        .help(about::QUERY_FILTER)
+                        .long_help(about::QUERY_FILTER_LONG),
+                )
+                .arg(
+                    Arg::new(arg::DIFF)
+                        .long("diff")
+                        .action(ArgAction::SetTrue)
+                        .help(about::WATCH_DIFF),
                 ),
         )
         .subcommand(
@@ -172,39 +1376,356 @@ fn parse_args() -> clap::ArgMatches {
                         .long_help(about::SEARCH_STR_LONG),
                 ),
         )
+        .subcommand(
+            clap::Command::new(cmd::GREP).about(about::GREP).arg(
+                Arg::new(arg::PATTERN)
+                    .required(true)
+                    .help(about::GREP_PATTERN),
+            ),
+        )
+        .subcommand(clap::Command::new(cmd::INDEX).about(about::INDEX))
+        .subcommand(clap::Command::new(cmd::DOCTOR).about(about::DOCTOR))
+        .subcommand(
+            clap::Command::new(cmd::BENCH).about(about::BENCH).arg(
+                Arg::new(arg::FILTER)
+                    .required(false)
+                    .help(about::BENCH_FILTER),
+            ),
+        )
         .subcommand(
             clap::Command::new(cmd::INTERACTIVE)
                 .alias("-i")
-                .about(about::INTERACTIVE),
+                .about(about::INTERACTIVE)
+                .arg(
+                    Arg::new(arg::NO_IMPLICIT)
+                        .long("no-implicit")
+                        .action(ArgAction::SetTrue)
+                        .help(about::NO_IMPLICIT),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::CHECK)
+                .about(about::CHECK)
+                .arg(
+                    Arg::new(arg::PATH)
+                        .help(about::CHECK_PATH)
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new(arg::PORCELAIN)
+                        .long("porcelain")
+                        .action(ArgAction::SetTrue)
+                        .help(about::CHECK_PORCELAIN)
+                        .long_help(about::CHECK_PORCELAIN_LONG),
+                )
+                .arg(
+                    Arg::new(arg::FIX)
+                        .long("fix")
+                        .action(ArgAction::SetTrue)
+                        .help(about::CHECK_FIX),
+                )
+                .arg(
+                    Arg::new(arg::APPLY_SUGGESTIONS)
+                        .long("apply-suggestions")
+                        .action(ArgAction::SetTrue)
+                        .help(about::CHECK_APPLY_SUGGESTIONS),
+                )
+                .arg(
+                    Arg::new(arg::JSON)
+                        .long("json")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with(arg::PORCELAIN)
+                        .help(about::CHECK_JSON),
+                )
+                .arg(
+                    Arg::new(arg::MAX_DEPTH)
+                        .long("max-depth")
+                        .value_parser(value_parser!(usize))
+                        .help(about::MAX_DEPTH),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::WHATIS)
+                .about(about::WHATIS)
+                .arg(
+                    Arg::new(arg::PATH)
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .help(about::WHATIS_PATH),
+                )
+                .arg(
+                    Arg::new(arg::RECURSIVE)
+                        .long("recursive")
+                        .action(ArgAction::SetTrue)
+                        .help(about::WHATIS_RECURSIVE),
+                )
+                .arg(
+                    Arg::new(arg::NO_IMPLICIT)
+                        .long("no-implicit")
+                        .action(ArgAction::SetTrue)
+                        .help(about::NO_IMPLICIT),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::DESCRIBE_TAG)
+                .about(about::DESCRIBE_TAG)
+                .arg(
+                    Arg::new(arg::TAG)
+                        .required(true)
+                        .help(about::DESCRIBE_TAG_TAG),
+                ),
         )
         .subcommand(
-            clap::Command::new(cmd::CHECK).about(about::CHECK).arg(
+            clap::Command::new(cmd::EDIT).about(about::EDIT).arg(
                 Arg::new(arg::PATH)
-                    .help(about::CHECK_PATH)
+                    .help(about::EDIT_PATH)
                     .required(false)
-                    .value_parser(value_parser!(PathBuf)),
+                    .value_parser(value_parser!(PathBuf))
+                    .default_value("."),
             ),
         )
         .subcommand(
-            clap::Command::new(cmd::WHATIS).about(about::WHATIS).arg(
-                Arg::new(arg::PATH)
+            clap::Command::new(cmd::CLEAN).about(about::CLEAN).arg(
+                Arg::new(arg::CANONICALIZE)
+                    .long("canonicalize")
+                    .action(ArgAction::SetTrue)
+                    .help(about::CLEAN_CANONICALIZE),
+            ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::VALIDATE)
+                .about(about::VALIDATE)
+                .arg(
+                    Arg::new(arg::FILE)
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf))
+                        .help(about::VALIDATE_FILE),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::VALIDATE_EXPORT)
+                .about(about::VALIDATE_EXPORT)
+                .arg(
+                    Arg::new(arg::FILE)
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf))
+                        .help(about::VALIDATE_EXPORT_FILE),
+                )
+                .arg(
+                    Arg::new(arg::KIND)
+                        .long(arg::KIND)
+                        .help(about::VALIDATE_EXPORT_KIND),
+                )
+                .arg(
+                    Arg::new(arg::PRINT_SCHEMA)
+                        .long(arg::PRINT_SCHEMA)
+                        .action(ArgAction::SetTrue)
+                        .help(about::VALIDATE_EXPORT_PRINT_SCHEMA),
+                ),
+        )
+        .subcommand(clap::Command::new(cmd::VERIFY).about(about::VERIFY))
+        .subcommand(
+            clap::Command::new(cmd::FMT)
+                .about(about::FMT)
+                .arg(
+                    Arg::new(arg::FILE)
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf))
+                        .help(about::FMT_FILE),
+                )
+                .arg(
+                    Arg::new(arg::SORT_TAGS)
+                        .long("sort-tags")
+                        .action(ArgAction::SetTrue)
+                        .help(about::FMT_SORT_TAGS),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::MV)
+                .about(about::MV)
+                .arg(
+                    Arg::new(arg::SOURCE)
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .help(about::MV_SOURCE),
+                )
+                .arg(
+                    Arg::new(arg::DESTINATION)
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .help(about::MV_DESTINATION),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::BACKUP).about(about::BACKUP).arg(
+                Arg::new(arg::ARCHIVE)
+                    .long("archive")
                     .required(true)
                     .value_parser(value_parser!(PathBuf))
-                    .help(about::WHATIS_PATH),
+                    .help(about::BACKUP_ARCHIVE),
             ),
         )
         .subcommand(
-            clap::Command::new(cmd::EDIT).about(about::EDIT).arg(
-                Arg::new(arg::PATH)
-                    .help(about::EDIT_PATH)
-                    .required(false)
+            clap::Command::new(cmd::RESTORE).about(about::RESTORE).arg(
+                Arg::new(arg::ARCHIVE)
+                    .long("archive")
+                    .required(true)
                     .value_parser(value_parser!(PathBuf))
-                    .default_value("."),
+                    .help(about::RESTORE_ARCHIVE),
             ),
         )
-        .subcommand(clap::Command::new(cmd::CLEAN).about(about::CLEAN))
-        .subcommand(clap::Command::new(cmd::UNTRACKED).about(about::UNTRACKED))
-        .subcommand(clap::Command::new(cmd::TAGS).about(about::TAGS))
+        .subcommand(
+            clap::Command::new(cmd::DIFF)
+                .about(about::DIFF)
+                .arg(
+                    Arg::new(arg::OLD)
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .help(about::DIFF_OLD),
+                )
+                .arg(
+                    Arg::new(arg::NEW)
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf))
+                        .help(about::DIFF_NEW),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::UNTRACKED)
+                .about(about::UNTRACKED)
+                .arg(
+                    Arg::new(arg::SUMMARY)
+                        .long("summary")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with(arg::ADD)
+                        .help(about::UNTRACKED_SUMMARY),
+                )
+                .arg(
+                    Arg::new(arg::NULL)
+                        .long("null")
+                        .short('0')
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all([arg::ADD, arg::SUMMARY])
+                        .help(about::NULL),
+                )
+                .arg(
+                    Arg::new(arg::ADD)
+                        .long("add")
+                        .action(ArgAction::SetTrue)
+                        .help(about::UNTRACKED_ADD),
+                )
+                .arg(
+                    Arg::new(arg::TAGS)
+                        .long("tags")
+                        .num_args(1..)
+                        .requires(arg::ADD)
+                        .help(about::UNTRACKED_TAGS),
+                )
+                .arg(
+                    Arg::new(arg::STRICT)
+                        .long("strict")
+                        .action(ArgAction::SetTrue)
+                        .requires(arg::ADD)
+                        .help(about::UNTRACKED_STRICT),
+                )
+                .arg(
+                    Arg::new(arg::MAX_DEPTH)
+                        .long("max-depth")
+                        .value_parser(value_parser!(usize))
+                        .help(about::MAX_DEPTH),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::UNDO).about(about::UNDO).arg(
+                Arg::new(arg::PRUNE)
+                    .long("prune")
+                    .action(ArgAction::SetTrue)
+                    .help(about::UNDO_PRUNE),
+            ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::HISTORY).about(about::HISTORY).arg(
+                Arg::new(arg::SHOW)
+                    .long("show")
+                    .value_name("ID")
+                    .value_parser(value_parser!(usize))
+                    .help(about::HISTORY_SHOW),
+            ),
+        )
+        .subcommand(clap::Command::new(cmd::HASH).about(about::HASH))
+        .subcommand(clap::Command::new(cmd::RELINK).about(about::RELINK))
+        .subcommand(
+            clap::Command::new(cmd::INIT)
+                .about(about::INIT)
+                .arg(
+                    Arg::new(arg::DIR)
+                        .required(false)
+                        .value_parser(value_parser!(PathBuf))
+                        .help(about::INIT_DIR),
+                )
+                .arg(
+                    Arg::new(arg::FORCE)
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .help(about::INIT_FORCE),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::TRIAGE)
+                .about(about::TRIAGE)
+                .arg(
+                    Arg::new(arg::NO_PREVIEW)
+                        .long("no-preview")
+                        .action(ArgAction::SetTrue)
+                        .help(about::TRIAGE_NO_PREVIEW),
+                )
+                .arg(
+                    Arg::new(arg::MAX_DEPTH)
+                        .long("max-depth")
+                        .value_parser(value_parser!(usize))
+                        .help(about::MAX_DEPTH),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::TAGS)
+                .about(about::TAGS)
+                .arg(
+                    Arg::new(arg::GROUP)
+                        .long("group")
+                        .action(ArgAction::SetTrue)
+                        .help(about::TAGS_GROUP),
+                )
+                .arg(
+                    Arg::new(arg::WHERE)
+                        .long("where")
+                        .conflicts_with(arg::GROUP)
+                        .help(about::TAGS_WHERE),
+                )
+                .arg(
+                    Arg::new(arg::MAX_DEPTH)
+                        .long("max-depth")
+                        .value_parser(value_parser!(usize))
+                        .conflicts_with(arg::WHERE)
+                        .help(about::MAX_DEPTH),
+                )
+                .arg(
+                    Arg::new(arg::NO_IMPLICIT)
+                        .long("no-implicit")
+                        .action(ArgAction::SetTrue)
+                        .help(about::NO_IMPLICIT),
+                ),
+        )
+        .subcommand(
+            clap::Command::new(cmd::COMPLETIONS)
+                .about(about::COMPLETIONS)
+                .arg(
+                    Arg::new(arg::SHELL)
+                        .required(true)
+                        .value_parser(["bash", "zsh", "fish"])
+                        .help(about::COMPLETIONS_SHELL),
+                ),
+        )
         .subcommand(
             clap::Command::new(cmd::BASH_COMPLETE)
                 .arg(Arg::new(arg::BASH_COMPLETE_WORDS).num_args(3)),
@@ -214,54 +1735,229 @@ fn parse_args() -> clap::ArgMatches {
 
 mod cmd {
     pub const COUNT: &str = "count";
+    pub const STATS: &str = "stats";
     pub const QUERY: &str = "query";
     pub const QUERY_SHORT: &str = "-q";
+    pub const WATCH: &str = "watch";
     pub const SEARCH: &str = "search";
     pub const SEARCH_SHORT: &str = "-s";
+    pub const GREP: &str = "grep";
+    pub const INDEX: &str = "index";
+    pub const DOCTOR: &str = "doctor";
+    pub const BENCH: &str = "bench";
     pub const INTERACTIVE: &str = "interactive";
     pub const CHECK: &str = "check";
     pub const WHATIS: &str = "whatis";
+    pub const DESCRIBE_TAG: &str = "describe-tag";
     pub const EDIT: &str = "edit";
     pub const CLEAN: &str = "clean";
+    pub const FMT: &str = "fmt";
+    pub const VALIDATE: &str = "validate";
+    pub const VALIDATE_EXPORT: &str = "validate-export";
+    pub const VERIFY: &str = "verify";
+    pub const MV: &str = "mv";
     pub const UNTRACKED: &str = "untracked";
+    pub const TRIAGE: &str = "triage";
+    pub const INIT: &str = "init";
+    pub const UNDO: &str = "undo";
+    pub const HISTORY: &str = "history";
+    pub const HASH: &str = "hash";
+    pub const RELINK: &str = "relink";
+    pub const BACKUP: &str = "backup";
+    pub const RESTORE: &str = "restore";
+    pub const DIFF: &str = "diff";
     pub const TAGS: &str = "tags";
+    pub const COMPLETIONS: &str = "completions";
     pub const BASH_COMPLETE: &str = "--bash-complete";
 }
 
+/// Exit codes other than success.
+mod exit {
+    /// Problems were found, or the command found nothing to list.
+    pub const NEGATIVE: u8 = 1;
+    /// The command failed.
+    pub const ERROR: u8 = 2;
+}
+
 mod arg {
     pub const FILTER: &str = "filter"; // Query command.
     pub const PATH: &str = "path"; // --path flag to run in a different path than cwd.
     pub const SEARCH_STR: &str = "search string";
+    pub const PATTERN: &str = "pattern";
     pub const BASH_COMPLETE_WORDS: &str = "bash-complete-words";
+    pub const PORCELAIN: &str = "porcelain";
+    pub const SUMMARY: &str = "summary";
+    pub const RECURSIVE: &str = "recursive";
+    pub const FILE: &str = "file";
+    pub const KIND: &str = "kind";
+    pub const PRINT_SCHEMA: &str = "print-schema";
+    pub const SORT_TAGS: &str = "sort-tags";
+    pub const SOURCE: &str = "source";
+    pub const DESTINATION: &str = "destination";
+    pub const ADD: &str = "add";
+    pub const TAGS: &str = "tags";
+    pub const CANONICALIZE: &str = "canonicalize";
+    pub const GROUP: &str = "group";
+    pub const STRICT: &str = "strict";
+    pub const TAG: &str = "tag";
+    pub const WHERE: &str = "where";
+    pub const QUIET: &str = "quiet";
+    pub const DRY_RUN: &str = "dry-run";
+    pub const READ_ONLY: &str = "read-only";
+    pub const KEEP_GOING: &str = "keep-going";
+    pub const EXCLUDE: &str = "exclude";
+    pub const FORMAT: &str = "format";
+    pub const JSON: &str = "json";
+    pub const FIX: &str = "fix";
+    pub const NO_IMPLICIT: &str = "no-implicit";
+    pub const APPLY_SUGGESTIONS: &str = "apply-suggestions";
+    pub const NULL: &str = "null";
+    pub const DIFF: &str = "diff";
+    pub const SHELL: &str = "shell";
+    pub const TOP: &str = "top";
+    pub const MAX_DEPTH: &str = "max-depth";
+    pub const NO_PREVIEW: &str = "no-preview";
+    pub const DIR: &str = "dir";
+    pub const FORCE: &str = "force";
+    pub const PRUNE: &str = "prune";
+    pub const SHOW: &str = "show";
+    pub const SUBDIR: &str = "subdir";
+    pub const COVERAGE: &str = "coverage";
+    pub const ARCHIVE: &str = "archive";
+    pub const OLD: &str = "old";
+    pub const NEW: &str = "new";
+    pub const NO_CACHE: &str = "no-cache";
 }
 
 mod about {
+    pub const QUIET: &str =
+        "Suppress the normal output, and only report the outcome through the exit status.";
+    pub const QUIET_LONG: &str = "Suppress the normal output, and only report the outcome through the exit status. This is useful in shell conditionals. The exit status is one of:
+0: Success.
+1: 'check' found problems, or a command that lists things, such as 'query', 'search', 'grep', 'untracked' or 'tags', found nothing to list.
+2: The command failed. Errors are printed to stderr even with --quiet.";
+    pub const READ_ONLY: &str = "Refuse to write, remove or edit any .ftag files, and explain why, instead of changing them. This can also be enabled with the 'read-only' setting in the config.";
+    pub const EXCLUDE: &str = "Skip the files and directories whose names match the glob, in addition to the ones excluded in the config, and the ones ignored by .gitignore and .ignore files. Can be used more than once.";
+    pub const KEEP_GOING: &str = "Skip the .ftag files that fail to load instead of stopping at the first one, and list them at the end. The files in the skipped directories are treated as untracked. Exits with 2 if any .ftag files were skipped.";
+    pub const FORMAT: &str = "Format of the output. With 'json', the 'query', 'watch', 'stats', 'tags', 'untracked', 'whatis', 'check' and 'history' commands print their output as a single JSON value, for scripts and editors to consume.";
+    pub const NULL: &str = "Separate the paths with a null character instead of a newline, so that they can be piped into 'xargs -0' even when they contain spaces or newlines. This takes precedence over '--format'.";
+    pub const MAX_DEPTH: &str = "Only descend this many levels of subdirectories below the working directory. With 0, only the files in the working directory are considered.";
+    pub const NO_CACHE: &str = "Run the query on the directories, even if the 'query-cache' setting is enabled and the results are in the cache. The cache is left as it is.";
+    pub const QUERY_SUBDIR: &str = "Only list the files in this subdirectory of the working directory. The paths are still relative to the working directory, and the files still inherit the tags of the directories above the subdirectory. With '--max-depth', the depth is counted from the subdirectory.";
+    pub const DRY_RUN: &str = "Instead of writing or removing any files, print the files that a command would write or remove. This applies to every command that changes files on the disk, such as 'clean', 'index', 'untracked --add', 'backup' and 'restore'. With '--format json', the files are printed to stderr instead, so that stdout is valid JSON.";
     pub const COUNT: &str = "Output the number of tracked files.";
+    pub const COUNT_COVERAGE: &str = "Instead of the totals, list the directories that contain untracked files, or tracked files without any tags, along with the percentage of their files that are tagged. The worst covered directories are listed first. Files that only inherit tags from their directories count as tagged, but implicit tags are ignored.";
+    pub const STATS: &str = "Print a report of the files and tags in the working directory: the number of tracked and untracked files, the number of tags, the average number of tags per file, the tags with the most files, and the directories without a .ftag file. Inherited and implicit tags are counted.";
+    pub const STATS_TOP: &str = "Number of tags with the most files to list.";
     pub const QUERY: &str = "List all files that match the given query string.";
     pub const QUERY_FILTER: &str = "The query string to compare the files against.";
     pub const QUERY_FILTER_LONG: &str =
         "The query string must be composed of tags and supported boolean operations:
-& (for and), | (for or) and ! (for not).  An example query
+& (for and), | (for or), ^ (for exclusive or) and ! (for not). A
+lone '*' matches all files, and 'none' matches no files. An example query
 string is 'foo & bar'. Using this will list all files that have both
 tags 'foo' and 'bar'.  More complex queries can be delimited using
 parentheses. For example: '(foo & bar) | !baz' will list all files
 that either have both 'foo' and 'bar' tags, or don't have the 'baz'
-tag.";
+tag. Tags separated only by whitespace are combined with an implicit
+and, so 'foo bar' is the same as 'foo & bar'. A wildcard such as
+'person:*' matches every tag in the 'person' namespace, such as
+'person:alice', and a pattern such as 'camera:fuji*' matches the
+tags in the namespace whose names match it. 'name:' followed by a
+glob, such as 'name:*.rs', matches the files whose names match the
+glob. Years can be compared
+with 'year>=2015', 'year<2000' and so on, or '2010..2015' for a
+range that includes both ends. Dates such as 'date:2021-07-14' can
+be compared with 'date>=2021-01-01' and so on, or with
+'2021-01-01..2021-06-30'. The size and the modification time of
+files can be compared with 'size>10M', 'mtime<30d' or
+'mtime<2021-01-01'. 'untagged' matches the files without any
+explicit tags. Tags that contain whitespace are
+wrapped in double quotes, such as '\"new york\" & photo'.";
+    pub const WATCH: &str = "List all files that match the given query string, and list them again every time the results change, until interrupted. The directories are watched for changes to the .ftag files, and for files that are added, removed or renamed. Every list of results is followed by an empty line, or with '--format json', printed as a JSON array on a single line.";
+    pub const WATCH_DIFF: &str = "Instead of listing all the results every time, print the files that started matching the query prefixed with '+ ', and the files that stopped matching prefixed with '- '. With '--format json', each change is printed as an object with 'added' and 'removed' arrays on a single line.";
     pub const SEARCH: &str = "Search all tags and descriptions for the given keywords";
     pub const SEARCH_STR: &str = "A string of keywords to search for.";
     pub const SEARCH_STR_LONG: &str = "Any file that contains any of the keywords in this string in either it's tags or description will included in the output.";
+    pub const GREP: &str = "Search the descriptions of all files and directories for lines that match the given regular expression. Each matching line is printed along with the path of the file or directory it describes.";
+    pub const GREP_PATTERN: &str = "The regular expression to search for. Use '(?i)' at the start of the expression for case insensitive search.";
+    pub const INDEX: &str = "Build an index of all the tags and descriptions, to speed up the 'search' and 'grep' commands. The index is stored in a .ftagindex file in the working directory, and is only used when the commands are run from the same directory. The index also holds a snapshot of the directories, which speeds up 'query', 'interactive' and 'gui'. If any .ftag files are changed, or any files are added, removed or renamed in the subdirectories, the index is ignored until it is rebuilt.";
+    pub const DOCTOR: &str = "Check the environment and report problems: whether an editor is installed, whether the bash completions are registered, whether all the .ftag files can be parsed, whether the search index is up to date, and which optional programs such as ffmpeg and exiftool are installed. Exits with 1 if any problems were found.";
+    pub const BENCH: &str = "Time the main phases of loading the metadata in the working directory, such as walking the directories, reading and parsing the .ftag files, matching the globs and evaluating a filter, and print a breakdown. Use this to report performance problems.";
+    pub const BENCH_FILTER: &str = "Filter to evaluate on all the tracked files. A filter that matches files with any tag is used if none is provided.";
     pub const INTERACTIVE: &str = "\
 Launch interactive mode in the working directory. Interactive mode loads all the files and tags, and let's you incrementally refine your search criteria inside a TUI. More documentation on the interactive mode can be found here: https://github.com/ranjeethmahankali/ftag/blob/no-table/README.md";
-    pub const CHECK: &str = "Recursively traverse directories starting from the working directory and check to see if all the files listed in every .ftag file is exists.";
+    pub const CHECK: &str = "Recursively traverse directories starting from the working directory and check to see if all the files listed in every .ftag file is exists. Exits with 0 if no problems were found, 1 if there were problems such as globs that don't match any files, and 2 if a .ftag file failed to load or the directories couldn't be read.";
+    pub const CHECK_FIX: &str = "Remove the globs that don't match any files from the .ftag files, along with the entries that are left without any globs. Unlike 'clean', nothing else is changed. The .ftag files are backed up before they're modified, and only the problems that were not fixed are reported.";
+    pub const NO_IMPLICIT: &str = "Don't infer any implicit tags, such as years and formats, from the names of the files. Only the tags in the .ftag files are used, which is also faster on trees with many files.";
+    pub const CHECK_JSON: &str = "Print the problems as a JSON array of objects with the 'code', 'dir', 'subject', 'suggestion' and 'message' of each problem. This is the same as '--format json'.";
+    pub const CHECK_APPLY_SUGGESTIONS: &str = "Replace the globs that don't match any files with the suggested file names, when a file was likely renamed. A file is suggested when it is not tracked, is in the same directory, has the same extension and a similar name as the glob, and is the only such file for the glob. Combine with '--fix' to remove the other globs that don't match any files. The .ftag files are backed up before they're modified.";
+    pub const CHECK_PORCELAIN: &str =
+        "Print one machine-parsable line per problem and nothing else.";
+    pub const CHECK_PORCELAIN_LONG: &str = "Print one line per problem in the format '<code>\\t<dir>\\t<subject>' and nothing else. <dir> is relative to the directory being checked, and <subject> is the glob or tag with the problem. The codes are stable and will not change in future versions:
+unmatched-glob: The glob does not match any file in the directory.
+invalid-tag: The tag does not match the 'tag-pattern' in the config.
+deprecated-tag: The tag is deprecated in the config.
+shadowed-tag: The tag starts with 'name:', so it can only be queried in quotes.";
     pub const CHECK_PATH:&str = "The directory path where to start checking recursively. If ommitted, the workind directory is assumed.";
     pub const WHATIS: &str = "Get the tags and description (if found) of the given file.";
     pub const WHATIS_PATH: &str = "Path of the file to describe.";
-    pub const EDIT: &str = "Edit the .ftag file of the given (optional) directory.
-If the environment variable EDITOR is set, it will be used to open the file. If it is not set, ftag can try to guess your default editor, but this is not guaranteed to work. Setting the EDITOR environment variable is recommended.";
-    pub const EDIT_PATH: &str = "Path to the directory whose .ftag file you wish to edit. If no path is specified, the current working
+    pub const WHATIS_RECURSIVE: &str = "If the path is a directory, also print the number of tracked files beneath it, and how many of them have each tag.";
+    pub const DESCRIBE_TAG: &str =
+        "Print the description of the given tag from the glossary in the config.";
+    pub const DESCRIBE_TAG_TAG: &str = "The tag to describe.";
+    pub const EDIT: &str = "Edit the .ftag file of the given (optional) directory or file.
+The editors listed in the [editors] section of the config are tried in order, followed by the VISUAL and EDITOR environment variables. The first editor that is installed is used. If none are installed, the file is only opened in the default application of your system if 'gui-edit-fallback' is enabled in the config.";
+    pub const EDIT_PATH: &str = "Path to the directory whose .ftag file you wish to edit. If the path of a file is given, the .ftag file of its directory is opened at the entry of the file. If no path is specified, the current working
 directory is used as default.";
-    pub const CLEAN: &str = "This commands cleans all the tag data. This includes deleting globs that don't match to any files on the disk, and merging globs that share the same tags and description into the same entry.";
+    pub const CLEAN: &str = "This commands cleans all the tag data. This includes deleting globs that don't match to any files on the disk, and merging globs that share the same tags and description into the same entry. The .ftag files that are left with no tags, description or entries are removed. With '--dry-run', a unified diff of the changes is printed instead, and the exit status is 1 if any changes are needed.";
+    pub const VALIDATE: &str = "Check a single .ftag file for errors that would make it fail to load, without walking the directories. All the errors are reported with their line numbers. Exits with 1 if any errors were found.";
+    pub const VALIDATE_FILE: &str = "Path of the .ftag file to check. The contents are read from stdin if this is '-' or omitted.";
+    pub const VALIDATE_EXPORT: &str = "Check a file of JSON against the schema of the output of the commands with '--format json', so that scripts can tell if the output they rely on has changed. The schema is versioned and embedded in the binary. The outputs the file matches are printed, or the reasons it doesn't match. Exits with 1 if it doesn't match.";
+    pub const VALIDATE_EXPORT_FILE: &str = "Path of the JSON file to check. The contents are read from stdin if this is '-' or omitted.";
+    pub const VALIDATE_EXPORT_KIND: &str =
+        "Check the file against the output of one command only, to see why it doesn't match.";
+    pub const VALIDATE_EXPORT_PRINT_SCHEMA: &str = "Print the schema instead of checking a file.";
+    pub const VERIFY: &str = "Recursively parse all the .ftag files starting from the working directory, and list all the errors that would make them fail to load, instead of stopping at the first one. Every error is reported as 'file:line: message'. Exits with 1 if any errors were found.";
+    pub const FMT: &str = "Reformat the .ftag files without changing what they mean. Every header is put on its own line, the tags are wrapped, and stray whitespace is removed. Unlike 'clean', the entries keep their order, and globs that don't match any files are kept. Files that fail to parse are not changed, and their errors are reported after formatting the rest.";
+    pub const FMT_FILE: &str = "Path of a single .ftag file to format, instead of all the .ftag files in the working directory.";
+    pub const FMT_SORT_TAGS: &str = "Also sort the tags in each tags header.";
+    pub const MV: &str = "Move or rename a file, and update the .ftag files so that the file keeps its tags and description. The file is removed from the entries in the .ftag file of its directory, and a new entry is added to the .ftag file of the destination directory.";
+    pub const MV_SOURCE: &str = "Path of the file to move.";
+    pub const MV_DESTINATION: &str =
+        "New path of the file, or an existing directory to move the file into.";
+    pub const CLEAN_CANONICALIZE: &str =
+        "Also replace all tags declared as aliases in the config with their canonical form.";
+    pub const BACKUP: &str = "Write all the .ftag files found by traversing the directories recursively into a single archive, to take a snapshot of the metadata. The files being tagged are not included.";
+    pub const BACKUP_ARCHIVE: &str = "Path of the tar archive to create.";
+    pub const RESTORE: &str = "Restore the .ftag files from an archive created with the 'backup' command. Existing .ftag files are backed up before they are overwritten, and .ftag files that are not in the archive are left alone. The paths of the restored .ftag files are printed.";
+    pub const RESTORE_ARCHIVE: &str = "Path of the tar archive to restore from.";
+    pub const DIFF: &str = "Compare the tags and descriptions of the files and directories between two archives created with the 'backup' command, or between an archive and the current .ftag files. Every file or directory that changed is printed with the tags it gained and lost. Changes to the tags of a directory are reported for the directory, rather than every file that inherits them. The exit status is 0 if nothing changed, and 1 otherwise.";
+    pub const DIFF_OLD: &str = "Path of the archive with the old snapshot.";
+    pub const DIFF_NEW: &str =
+        "Path of the archive with the new snapshot. If omitted, the current .ftag files are used.";
     pub const UNTRACKED: &str =
         "List all files that are not tracked by ftag, recursively from the current directory.";
+    pub const UNTRACKED_SUMMARY: &str = "Instead of listing the untracked files, print the number of untracked files in each directory, sorted in descending order.";
+    pub const UNTRACKED_ADD: &str = "Append entries for all the untracked files to the .ftag files of their directories, so they become tracked. Untracked files that share an extension are added as a single glob such as '*.jpg', when there are many of them. The .ftag files are created if they don't exist, and existing .ftag files are backed up before they are modified.";
+    pub const UNTRACKED_TAGS: &str = "Tags to assign to the entries added with '--add'.";
+    pub const TRIAGE: &str = "Open a TUI to go through the untracked files one at a time. Each file is shown with the tags inferred from its name, and a preview of its contents if it is a text file. The tags and description you type are appended as an entry to the .ftag file in the directory of the file.";
+    pub const INIT: &str = "Create a .ftag file in a directory, with an entry for every file in it, ready for you to fill in the tags. Files that share an extension are added as a single glob such as '*.jpg', when there are many of them. The paths of the entries are printed.";
+    pub const UNDO: &str = "Undo the last command that changed .ftag files in this directory tree, such as 'clean', using the .ftagjournal it recorded. The changed .ftag files are restored from the .ftagbak backups next to them, and the created ones are removed. The .ftag files are backed up before they're restored, so running this again redoes the change. The directories of the restored files are printed.";
+    pub const HASH: &str = "Recursively record the size and checksum of every tracked file in a .ftaghash file next to the .ftag file, so that 'relink' can find the files after they're moved or renamed. Files that haven't changed since they were last recorded are not read again.";
+    pub const RELINK: &str = "Find the tracked files that were moved or renamed since their checksums were recorded with 'hash', and move their entries, with the tags and descriptions, to the .ftag files in their new directories. An entry is moved when its glob doesn't match any files, and exactly one untracked file has the recorded size and checksum. The old and new paths of the files are printed.";
+    pub const UNDO_PRUNE: &str = "Instead of restoring the .ftag files, remove all the .ftagbak backups and .ftagjournal files, once you're happy with the changes.";
+    pub const HISTORY: &str = "List the past commands that changed .ftag files in this directory tree, oldest first, as recorded in the .ftagjournal files. Each command is printed with its id, the time it ran in UTC, the number of .ftag files it changed, and its command line. The exit status is 1 if there are none.";
+    pub const HISTORY_SHOW: &str = "Print the unified diff of the changes made by the command with this id, instead of listing the commands.";
+    pub const INIT_DIR: &str =
+        "The directory in which to create the .ftag file. This defaults to the working directory.";
+    pub const INIT_FORCE: &str =
+        "Overwrite the .ftag file if it already exists. The existing file is backed up.";
+    pub const TRIAGE_NO_PREVIEW: &str = "Don't show a preview of the contents of the files.";
+    pub const UNTRACKED_STRICT: &str = "Refuse to add the entries if any of the tags are deprecated in the config, instead of printing a warning.";
     pub const TAGS: &str = "List all tags found by traversing the directories recursively from the current directory. The output list of tags will not contain duplicates.";
+    pub const TAGS_WHERE: &str = "Instead of listing all tags, list the .ftag files in which the given tag appears, along with whether it is assigned to the directory, and the number of globs it is assigned to. This is useful to know what is affected before renaming or deleting a tag.";
+    pub const COMPLETIONS: &str = "Print the script that enables tab completion of the commands and tags in the given shell. The script can be sourced in the profile of the shell, or saved in the directory where the shell looks for completions.";
+    pub const COMPLETIONS_SHELL: &str = "The shell to print the completion script for.";
+    pub const TAGS_GROUP: &str = "Group the tags by their namespaces. For example, 'person:alice' and 'person:bob' are listed as 'alice' and 'bob' under 'person:'.";
 }
@@ -0,0 +1,997 @@
+use crate::{
+    audio::{self, AUDIO_EXTS},
+    core::{Error, FTAG_CONFIG_FILE},
+    filter::Filter,
+    load::{has_any_extension, Inference, Tag},
+    util::checksum,
+    walk::SkippedFiles,
+};
+use regex::Regex;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+/*
+The configuration is read from a .ftagconfig file at the root of the archive,
+which is meant to be checked into version control along with the data, and
+from a config file of the user, which overrides the former locally. Both files
+are made up of sections, each starting with a header in its own line, wrapped
+in [] brackets, similar to the .ftag files. Lines starting with '#' are
+comments.
+
+The [settings] section contains 'key = value' lines. The [formats] section
+contains lines such as 'audio = .mp3 .flac', mapping a format tag to the
+extensions of the files it is inferred from, in addition to the built-in
+formats. The [exclude] section contains one glob per line, in addition to the
+default globs of junk files created by operating systems and editors, unless
+the 'default-exclude' setting is false. The [aliases] section contains lines
+such as 'pic = photo = image', declaring tags that mean the same thing. The
+first tag in the line is the canonical form of the others. The [implications]
+section contains lines such as 'golden-retriever => dog => animal', meaning
+every file tagged with a tag in the line also has all the tags to its right.
+The [deprecated] section contains one tag per line, optionally followed by its
+replacement, such as 'old-tag = new-tag'. The [glossary] section contains
+lines such as 'tag = description', explaining what the tags mean. The
+[virtual] section contains lines such as 'inbox = photo & !reviewed',
+declaring virtual tags that files have when they match the query on the right.
+A query can only use the virtual tags declared above it.
+
+The [openers] section contains lines such as '.pdf = zathura' or 'video = mpv
+--fs {}', mapping a file extension or an inferred format tag to the command
+used to open such files. The [editors] section contains one command per line,
+such as 'nvim +{line}', which are tried in order when editing .ftag files. The
+[actions] section contains lines such as 'Convert to PDF = convert {} {}.pdf',
+naming commands that can be run on files from the GUI. These three sections
+run commands, so they are only read from the config file of the user, and
+ignored in the .ftagconfig file.
+
+Settings from the user's config override the same settings from the
+.ftagconfig file. Every other section in the user's config replaces the
+section with the same name in the .ftagconfig file.
+ */
+
+/// Globs of junk files created by operating systems and editors, that are
+/// excluded by default.
+const DEFAULT_EXCLUDE: &[&str] = &[
+    ".DS_Store",
+    "._*",
+    "Thumbs.db",
+    "desktop.ini",
+    "*~",
+    "*.swp",
+    "*.swo",
+    ".git",
+];
+
+const SETTINGS: &str = "settings";
+const EXCLUDE: &str = "exclude";
+const ALIASES: &str = "aliases";
+const IMPLICATIONS: &str = "implications";
+const DEPRECATED: &str = "deprecated";
+const GLOSSARY: &str = "glossary";
+const OPENERS: &str = "openers";
+const EDITORS: &str = "editors";
+const ACTIONS: &str = "actions";
+const VIRTUAL: &str = "virtual";
+const FORMATS: &str = "formats";
+
+/// A line from a config file, along with where it came from for error
+/// reporting.
+struct Line {
+    file: PathBuf,
+    number: usize,
+    text: String,
+}
+
+impl Line {
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error::InvalidConfig(self.file.clone(), self.number, message.into())
+    }
+
+    /// Split a 'key = value' line.
+    fn key_value(&self) -> Result<(&str, &str), Error> {
+        self.text
+            .split_once('=')
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .ok_or(self.error("Expected a line of the form 'key = value'."))
+    }
+}
+
+struct Section {
+    name: String,
+    header: Line,
+    lines: Vec<Line>,
+}
+
+fn parse_sections(path: &Path) -> Result<Vec<Section>, Error> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|_| Error::CannotReadStoreFile(path.to_path_buf()))?;
+    // Tolerate the byte order mark added by some editors on Windows.
+    parse_text(path, text.strip_prefix('\u{feff}').unwrap_or(&text))
+}
+
+fn parse_text(path: &Path, text: &str) -> Result<Vec<Section>, Error> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (i, text) in text.lines().enumerate() {
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let line = Line {
+            file: path.to_path_buf(),
+            number: i + 1,
+            text: text.to_string(),
+        };
+        if let Some(name) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            sections.push(Section {
+                name: name.trim().to_string(),
+                header: line,
+                lines: Vec::new(),
+            });
+        } else {
+            match sections.last_mut() {
+                Some(section) => section.lines.push(line),
+                None => return Err(line.error("Expected a section header such as '[settings]'.")),
+            }
+        }
+    }
+    Ok(sections)
+}
+
+/// Sections that run commands on the machine of the user. A .ftagconfig file
+/// comes with the data, possibly from someone else, so these sections are
+/// only read from the config file of the user.
+const COMMAND_SECTIONS: &[&str] = &[OPENERS, EDITORS, ACTIONS];
+
+/// Combine the sections of the .ftagconfig file of the archive with the
+/// sections of the config file of the user, which take precedence.
+fn merge_sections(mut repo: Vec<Section>, user: Vec<Section>) -> Vec<Section> {
+    repo.retain(|s| {
+        !COMMAND_SECTIONS.contains(&s.name.as_str())
+            && (s.name == SETTINGS || !user.iter().any(|u| u.name == s.name))
+    });
+    repo.extend(user);
+    repo
+}
+
+/// Find the .ftagconfig file in `root` or the closest of its ancestors.
+fn find_repo_config(root: &Path) -> Option<PathBuf> {
+    root.ancestors()
+        .map(|dir| dir.join(FTAG_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Get the path of the config file of the user.
+fn user_config_path() -> Option<PathBuf> {
+    let mut path = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        PathBuf::from(dir)
+    } else {
+        let mut dir = PathBuf::from(std::env::var_os("HOME")?);
+        dir.push(".config");
+        dir
+    };
+    path.push("ftag");
+    path.push("config");
+    Some(path)
+}
+
+/// Project wide settings, shared by everyone working with the archive.
+#[derive(Clone)]
+pub struct Config {
+    exclude: Vec<String>,
+    implicit_tags: bool,
+    inference: Inference,
+    audio_tags: bool,
+    tag_pattern: Option<Regex>,
+    aliases: HashMap<String, String>,      // Alias -> canonical tag.
+    implied: HashMap<String, Vec<String>>, // Tag -> all tags it implies.
+    deprecated: HashMap<String, Option<String>>, // Tag -> replacement.
+    glossary: HashMap<String, String>,     // Tag -> description.
+    openers: HashMap<String, String>,      // Extension or format tag -> command.
+    editors: Vec<String>,
+    gui_edit_fallback: bool,
+    gui_image_cache: usize, // In megabytes.
+    archives: bool,
+    ignore_files: bool,
+    read_only: bool,
+    keep_going: Option<SkippedFiles>,
+    actions: Vec<(String, String)>,      // Name -> command.
+    virtual_tags: Vec<(String, String)>, // Tag -> query.
+    query_cache: bool,
+    live_filter: bool,
+    tui_mouse: bool,
+    digest: u64, // Checksum of the text of the config files.
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            exclude: Vec::new(),
+            implicit_tags: true,
+            inference: Inference::default(),
+            audio_tags: false,
+            tag_pattern: None,
+            aliases: HashMap::new(),
+            implied: HashMap::new(),
+            deprecated: HashMap::new(),
+            glossary: HashMap::new(),
+            openers: HashMap::new(),
+            editors: Vec::new(),
+            gui_edit_fallback: false,
+            gui_image_cache: 512,
+            archives: false,
+            ignore_files: true,
+            read_only: false,
+            keep_going: None,
+            actions: Vec::new(),
+            virtual_tags: Vec::new(),
+            query_cache: false,
+            live_filter: true,
+            tui_mouse: true,
+            digest: 0,
+        }
+    }
+}
+
+/// Parse a range of years such as '1900..2100'. Both ends are included.
+fn parse_year_range(line: &Line, value: &str) -> Result<RangeInclusive<u16>, Error> {
+    value
+        .split_once("..")
+        .and_then(|(first, last)| Some((first.trim().parse().ok()?, last.trim().parse().ok()?)))
+        .filter(|(first, last)| first <= last)
+        .map(|(first, last)| first..=last)
+        .ok_or(line.error(format!(
+            "Expected a range of years such as '1900..2100', found '{}'.",
+            value
+        )))
+}
+
+fn parse_bool(line: &Line, value: &str) -> Result<bool, Error> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(line.error(format!("Expected 'true' or 'false', found '{}'.", value))),
+    }
+}
+
+impl Config {
+    /// Load the configuration for the archive at `root`. This reads the
+    /// .ftagconfig file from `root` or the closest of its ancestors, and the
+    /// config file of the user. The default configuration is returned if
+    /// neither of them exist.
+    pub fn load(root: &Path) -> Result<Config, Error> {
+        let repo = find_repo_config(root);
+        let user = user_config_path().filter(|p| p.is_file());
+        let repo_sections = match &repo {
+            Some(path) => parse_sections(path)?,
+            None => Vec::new(),
+        };
+        let user_sections = match &user {
+            Some(path) => parse_sections(path)?,
+            None => Vec::new(),
+        };
+        let mut config = Self::from_sections(merge_sections(repo_sections, user_sections))?;
+        let mut text = Vec::new();
+        for path in repo.iter().chain(user.iter()) {
+            text.extend(std::fs::read(path).unwrap_or_default());
+            text.push(0); // So that moving text between the files is a change.
+        }
+        config.digest = checksum(&text[..]).unwrap_or_default();
+        Ok(config)
+    }
+
+    fn from_sections(sections: Vec<Section>) -> Result<Config, Error> {
+        let mut config = Config::default();
+        let mut implications = Vec::new();
+        let mut default_exclude = true;
+        let (mut year_tags, mut format_tags) = (true, true);
+        let mut virtual_lines = Vec::new();
+        for section in sections {
+            match section.name.as_str() {
+                SETTINGS => {
+                    for line in &section.lines {
+                        match line.key_value()? {
+                            ("implicit-tags", value) => {
+                                config.implicit_tags = parse_bool(line, value)?
+                            }
+                            ("year-tags", value) => year_tags = parse_bool(line, value)?,
+                            ("format-tags", value) => format_tags = parse_bool(line, value)?,
+                            ("date-tags", value) => {
+                                config.inference.dates = parse_bool(line, value)?
+                            }
+                            ("year-range", value) => {
+                                config.inference.years = Some(parse_year_range(line, value)?)
+                            }
+                            ("default-exclude", value) => {
+                                default_exclude = parse_bool(line, value)?
+                            }
+                            ("audio-tags", value) => config.audio_tags = parse_bool(line, value)?,
+                            ("query-cache", value) => config.query_cache = parse_bool(line, value)?,
+                            ("live-filter", value) => config.live_filter = parse_bool(line, value)?,
+                            ("tui-mouse", value) => config.tui_mouse = parse_bool(line, value)?,
+                            ("archives", value) => config.archives = parse_bool(line, value)?,
+                            ("ignore-files", value) => {
+                                config.ignore_files = parse_bool(line, value)?
+                            }
+                            ("read-only", value) => config.read_only = parse_bool(line, value)?,
+                            ("gui-edit-fallback", value) => {
+                                config.gui_edit_fallback = parse_bool(line, value)?
+                            }
+                            ("gui-image-cache", value) => {
+                                config.gui_image_cache = value.parse().map_err(|_| {
+                                    line.error(format!(
+                                        "Expected the size of the cache in megabytes, found '{}'.",
+                                        value
+                                    ))
+                                })?
+                            }
+                            ("tag-pattern", value) => {
+                                config.tag_pattern =
+                                    Some(Regex::new(value).map_err(|e| line.error(e.to_string()))?)
+                            }
+                            (key, _) => {
+                                return Err(line.error(format!("Unknown setting '{}'.", key)))
+                            }
+                        }
+                    }
+                }
+                ALIASES => {
+                    for line in &section.lines {
+                        config.add_aliases(line)?;
+                    }
+                }
+                IMPLICATIONS => {
+                    for line in &section.lines {
+                        let tags: Vec<&str> = line.text.split("=>").map(|t| t.trim()).collect();
+                        if tags.len() < 2 || tags.iter().any(|t| t.is_empty()) {
+                            return Err(
+                                line.error("Expected a line of the form 'tag => implied => ...'.")
+                            );
+                        }
+                        implications.extend(
+                            tags.windows(2)
+                                .map(|pair| (pair[0].to_string(), pair[1].to_string())),
+                        );
+                    }
+                }
+                DEPRECATED => config.deprecated.extend(section.lines.iter().map(|line| {
+                    match line.text.split_once('=') {
+                        Some((tag, replacement)) => {
+                            (tag.trim().to_string(), Some(replacement.trim().to_string()))
+                        }
+                        None => (line.text.clone(), None),
+                    }
+                })),
+                GLOSSARY => {
+                    for line in &section.lines {
+                        let (tag, desc) = line.key_value()?;
+                        config.glossary.insert(tag.to_string(), desc.to_string());
+                    }
+                }
+                OPENERS => {
+                    for line in &section.lines {
+                        let (key, command) = line.key_value()?;
+                        if command.is_empty() {
+                            return Err(line.error("Expected a command to open the files with."));
+                        }
+                        config
+                            .openers
+                            .insert(key.to_lowercase(), command.to_string());
+                    }
+                }
+                ACTIONS => {
+                    for line in &section.lines {
+                        let (name, command) = line.key_value()?;
+                        if name.is_empty() || command.is_empty() {
+                            return Err(line.error("Expected a line of the form 'name = command'."));
+                        }
+                        config.actions.push((name.to_string(), command.to_string()));
+                    }
+                }
+                FORMATS => {
+                    for line in &section.lines {
+                        config.add_format(line)?;
+                    }
+                }
+                VIRTUAL => virtual_lines.extend(section.lines),
+                EDITORS => config
+                    .editors
+                    .extend(section.lines.into_iter().map(|l| l.text)),
+                EXCLUDE => config
+                    .exclude
+                    .extend(section.lines.into_iter().map(|l| l.text)),
+                name => return Err(section.header.error(format!("Unknown section '{}'.", name))),
+            }
+        }
+        if default_exclude {
+            config
+                .exclude
+                .extend(DEFAULT_EXCLUDE.iter().map(|g| g.to_string()));
+        }
+        if !year_tags {
+            config.inference.years = None;
+        }
+        if !format_tags {
+            config.inference.formats.clear();
+        }
+        config.resolve_implications(implications);
+        config.add_virtual_tags(&virtual_lines)?;
+        Ok(config)
+    }
+
+    fn add_format(&mut self, line: &Line) -> Result<(), Error> {
+        let (tag, exts) = line.key_value()?;
+        let exts: Vec<String> = exts
+            .split_whitespace()
+            .map(|ext| match ext.starts_with('.') {
+                true => ext.to_string(),
+                false => format!(".{}", ext),
+            })
+            .collect();
+        if tag.is_empty() || exts.is_empty() {
+            return Err(line.error("Expected a line of the form 'tag = .ext .ext ...'."));
+        }
+        match self.inference.formats.iter_mut().find(|(t, _)| t == tag) {
+            Some((_, known)) => known.extend(exts),
+            None => self.inference.formats.push((tag.to_string(), exts)),
+        }
+        Ok(())
+    }
+
+    /// Compute the transitive closure of the implications, after replacing
+    /// all aliases with their canonical tags.
+    fn resolve_implications(&mut self, implications: Vec<(String, String)>) {
+        let mut direct = HashMap::<&str, Vec<&str>>::new();
+        for (tag, implied) in implications.iter() {
+            direct
+                .entry(self.canonical(tag))
+                .or_default()
+                .push(self.canonical(implied));
+        }
+        let mut resolved = HashMap::new();
+        let mut stack = Vec::new();
+        let mut visited = HashSet::new();
+        for tag in direct.keys() {
+            visited.clear();
+            stack.clear();
+            stack.push(*tag);
+            while let Some(current) = stack.pop() {
+                for next in direct.get(current).into_iter().flatten() {
+                    // Cycles are harmless, the tags just imply each other.
+                    if next != tag && visited.insert(*next) {
+                        stack.push(next);
+                    }
+                }
+            }
+            let mut implied: Vec<String> = visited.iter().map(|t| t.to_string()).collect();
+            implied.sort_unstable();
+            resolved.insert(tag.to_string(), implied);
+        }
+        self.implied = resolved;
+    }
+
+    fn add_aliases(&mut self, line: &Line) -> Result<(), Error> {
+        let tags: Vec<&str> = line.text.split('=').map(|t| t.trim()).collect();
+        let (canonical, aliases) = match tags.split_first() {
+            Some((canonical, aliases))
+                if !aliases.is_empty() && tags.iter().all(|t| !t.is_empty()) =>
+            {
+                (canonical, aliases)
+            }
+            _ => return Err(line.error("Expected a line of the form 'tag = alias = ...'.")),
+        };
+        if let Some(other) = self.aliases.get(*canonical) {
+            return Err(line.error(format!(
+                "'{}' is already declared as an alias of '{}'.",
+                canonical, other
+            )));
+        }
+        for alias in aliases {
+            if let Some(other) = self.aliases.get(*alias).filter(|other| other != canonical) {
+                return Err(line.error(format!(
+                    "'{}' is already declared as an alias of '{}'.",
+                    alias, other
+                )));
+            }
+            if self.aliases.values().any(|c| c == alias) {
+                return Err(line.error(format!(
+                    "'{}' is already declared as a canonical tag.",
+                    alias
+                )));
+            }
+            self.aliases
+                .insert(alias.to_string(), canonical.to_string());
+        }
+        Ok(())
+    }
+
+    fn add_virtual_tags(&mut self, lines: &[Line]) -> Result<(), Error> {
+        for line in lines {
+            let (tag, query) = line.key_value()?;
+            if tag.is_empty()
+                || tag
+                    .chars()
+                    .any(|c| c.is_whitespace() || "()&|!".contains(c))
+            {
+                return Err(line.error("Expected a line of the form 'tag = query'."));
+            }
+            if self.is_virtual(tag) {
+                return Err(line.error(format!("'{}' is already declared.", tag)));
+            }
+            self.virtual_tags.push((tag.to_string(), query.to_string()));
+        }
+        // Only the virtual tags declared before a query can be used in it,
+        // which rules out cycles.
+        for (i, line) in lines.iter().enumerate() {
+            let mut later = None;
+            Filter::parse(&self.virtual_tags[i].1, |t, _| {
+                if self.virtual_tags[i..].iter().any(|(vtag, _)| vtag == t) {
+                    later = Some(t.to_string());
+                }
+                Filter::FalseTag
+            })
+            .map_err(|e| line.error(format!("Invalid query: {:?}", e)))?;
+            if let Some(t) = later {
+                return Err(line.error(format!(
+                    "'{}' must be declared above the virtual tags that use it.",
+                    t
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Globs of the names of files and directories that should be ignored when
+    /// traversing the directories.
+    pub fn exclude(&self) -> &[String] {
+        &self.exclude
+    }
+
+    /// Get an iterator over the implicit tags of a file or directory with the
+    /// given name. This is empty if implicit tags are disabled.
+    pub(crate) fn implicit_tags<'a>(&'a self, name: &'a str) -> impl Iterator<Item = Tag<'a>> {
+        self.implicit_tags
+            .then(|| self.inference.tags(name))
+            .into_iter()
+            .flatten()
+    }
+
+    /// Get the tags read from the metadata embedded in the audio file at
+    /// `path`, such as 'artist:Nina Simone'. This is empty unless reading the
+    /// metadata is enabled, and the file is an audio file.
+    pub(crate) fn audio_tags(&self, path: &Path) -> Vec<String> {
+        let is_audio = path
+            .file_name()
+            .is_some_and(|name| has_any_extension(name, AUDIO_EXTS));
+        match self.implicit_tags && self.audio_tags && is_audio {
+            true => audio::read_tags(path),
+            false => Vec::new(),
+        }
+    }
+
+    /// Get the extensions of the files from which the format tag `tag` is
+    /// inferred. This is `None` if `tag` is not a format tag.
+    pub(crate) fn format_extensions(&self, tag: &str) -> Option<&[String]> {
+        self.inference.format_extensions(tag)
+    }
+
+    /// Get the canonical form of the tag. This is the tag itself, unless it is
+    /// declared as an alias of another tag.
+    pub fn canonical<'a>(&'a self, tag: &'a str) -> &'a str {
+        self.aliases.get(tag).map(|t| t.as_str()).unwrap_or(tag)
+    }
+
+    /// Get an iterator over all declared aliases, and their canonical tags.
+    pub fn aliases(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases.iter().map(|(a, c)| (a.as_str(), c.as_str()))
+    }
+
+    /// Get an iterator over the canonical form of the tag, followed by all the
+    /// tags it implies.
+    pub fn expand<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a str> {
+        let tag = self.canonical(tag);
+        std::iter::once(tag).chain(
+            self.implied
+                .get(tag)
+                .into_iter()
+                .flatten()
+                .map(|t| t.as_str()),
+        )
+    }
+
+    /// Same as `expand`, but for tags that were either inferred or read from
+    /// the .ftag files.
+    pub(crate) fn expand_tag<'a>(&'a self, tag: Tag<'a>) -> impl Iterator<Item = Cow<'a, str>> {
+        let (text, year) = match tag {
+            Tag::Text(t) | Tag::Format(t) => (Some(t), None),
+            Tag::Year(_) | Tag::Month(_) | Tag::Date(..) => {
+                (None, Some(Cow::Owned(tag.to_string())))
+            }
+        };
+        text.into_iter()
+            .flat_map(|t| self.expand(t))
+            .map(Cow::Borrowed)
+            .chain(year)
+    }
+
+    /// Check if any of the aliases of the canonical tag start with `prefix`.
+    pub fn alias_matches(&self, canonical: &str, prefix: &str) -> bool {
+        self.aliases()
+            .any(|(alias, c)| c == canonical && alias.starts_with(prefix))
+    }
+
+    /// Check if the tag is valid according to the rules in the config.
+    pub fn is_valid_tag(&self, tag: &str) -> bool {
+        match &self.tag_pattern {
+            Some(pattern) => pattern.is_match(tag),
+            None => true,
+        }
+    }
+
+    /// Get the description of the tag from the glossary. Aliases share the
+    /// description of their canonical tag.
+    pub fn describe(&self, tag: &str) -> Option<&str> {
+        self.glossary
+            .get(tag)
+            .or_else(|| self.glossary.get(self.canonical(tag)))
+            .map(|d| d.as_str())
+    }
+
+    /// Check if the tag is deprecated.
+    pub fn is_deprecated(&self, tag: &str) -> bool {
+        self.deprecated.contains_key(tag)
+    }
+
+    /// Get the suggested replacement of a deprecated tag.
+    pub fn replacement(&self, tag: &str) -> Option<&str> {
+        self.deprecated.get(tag).and_then(|r| r.as_deref())
+    }
+
+    /// Get the command to open the file at `path` with. The extension of the
+    /// file takes precedence over its inferred format tag. `None` means the
+    /// default application of the system should be used.
+    pub fn opener(&self, path: &Path) -> Option<&str> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        let ext = name.rfind('.').map(|i| &name[i..]);
+        ext.and_then(|ext| self.openers.get(ext))
+            .or_else(|| {
+                self.inference.tags(&name).find_map(|tag| match tag {
+                    Tag::Format(format) => self.openers.get(format),
+                    Tag::Text(_) | Tag::Year(_) | Tag::Month(_) | Tag::Date(..) => None,
+                })
+            })
+            .map(|c| c.as_str())
+    }
+
+    /// Commands of the editors to try in order, when editing .ftag files.
+    pub fn editors(&self) -> &[String] {
+        &self.editors
+    }
+
+    /// Check if .ftag files may be opened in the default application of the
+    /// system, when no editor is found.
+    pub fn gui_edit_fallback(&self) -> bool {
+        self.gui_edit_fallback
+    }
+
+    /// Maximum number of bytes of memory the GUI may use for caching images.
+    pub fn gui_image_cache(&self) -> usize {
+        self.gui_image_cache * 1024 * 1024
+    }
+
+    /// Exclude the files and directories whose names match `glob`, in addition
+    /// to the ones excluded by the config files.
+    pub fn add_exclude(&mut self, glob: String) {
+        self.exclude.push(glob);
+    }
+
+    /// Check if the patterns in the .gitignore and .ignore files should be
+    /// honored when walking the directories.
+    pub fn ignore_files(&self) -> bool {
+        self.ignore_files
+    }
+
+    /// Check if tar archives should be browsed like directories, when looking
+    /// for tagged files.
+    pub fn archives(&self) -> bool {
+        self.archives
+    }
+
+    /// Check if the results of queries should be cached at the root of the
+    /// archive.
+    pub fn query_cache(&self) -> bool {
+        self.query_cache
+    }
+
+    /// Check if the TUI should update the lists of files and tags as the
+    /// filter is typed, instead of when it is entered.
+    pub fn live_filter(&self) -> bool {
+        self.live_filter
+    }
+
+    /// Check if the TUI should capture the mouse, for scrolling and selecting
+    /// files.
+    pub fn tui_mouse(&self) -> bool {
+        self.tui_mouse
+    }
+
+    /// Checksum of everything in the config that can change the results of a
+    /// query, so cached results can be told apart from results with another
+    /// config. This includes the changes made after loading the config, such
+    /// as the globs from `add_exclude`.
+    pub(crate) fn digest(&self) -> u64 {
+        let mut text = format!(
+            "{:x}\0{}\0{}\0{}",
+            self.digest,
+            self.implicit_tags,
+            self.ignore_files,
+            self.keep_going.is_some()
+        );
+        for glob in &self.exclude {
+            text.push('\0');
+            text.push_str(glob);
+        }
+        checksum(text.as_bytes()).unwrap_or_default()
+    }
+
+    /// Check if the tags of some files are read from their contents, rather
+    /// than from their names and the .ftag files.
+    pub(crate) fn reads_file_contents(&self) -> bool {
+        self.implicit_tags && self.audio_tags
+    }
+
+    /// Check if commands must refuse to write any .ftag files.
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Forbid writing any .ftag files, regardless of the setting in the config
+    /// files.
+    pub fn set_read_only(&mut self) {
+        self.read_only = true;
+    }
+
+    /// Don't infer any implicit tags from the names of the files, regardless of
+    /// the settings in the config files.
+    pub fn set_no_implicit_tags(&mut self) {
+        self.implicit_tags = false;
+    }
+
+    /// Check if the .ftag files that fail to load should be skipped, instead of
+    /// failing the whole command.
+    pub fn keep_going(&self) -> bool {
+        self.keep_going.is_some()
+    }
+
+    /// Skip the .ftag files that fail to load, and carry on. The walks record
+    /// the skipped files in `skipped`, so the caller can report them when the
+    /// command is done.
+    pub fn set_keep_going(&mut self, skipped: SkippedFiles) {
+        self.keep_going = Some(skipped);
+    }
+
+    /// Where the walks record the .ftag files they skip in keep-going mode.
+    pub(crate) fn skipped_files(&self) -> Option<&SkippedFiles> {
+        self.keep_going.as_ref()
+    }
+
+    /// Named commands that can be run on files, in the order they're declared.
+    pub fn actions(&self) -> &[(String, String)] {
+        &self.actions
+    }
+
+    /// Virtual tags, and the queries that define them, in the order they're
+    /// declared.
+    pub fn virtual_tags(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.virtual_tags
+            .iter()
+            .map(|(tag, query)| (tag.as_str(), query.as_str()))
+    }
+
+    /// Get the query that defines the virtual tag.
+    pub fn virtual_query(&self, tag: &str) -> Option<&str> {
+        self.virtual_tags()
+            .find_map(|(t, query)| (t == tag).then_some(query))
+    }
+
+    /// Check if the tag is a virtual tag.
+    pub fn is_virtual(&self, tag: &str) -> bool {
+        self.virtual_query(tag).is_some()
+    }
+
+    /// Check if the config has any rules for validating tags.
+    pub fn has_tag_rules(&self) -> bool {
+        self.tag_pattern.is_some() || !self.deprecated.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Config, Error> {
+        Config::from_sections(parse_text(Path::new(FTAG_CONFIG_FILE), text)?)
+    }
+
+    #[test]
+    fn t_aliases() {
+        let config = parse(
+            "
+[aliases]
+image = pic = photo
+# Comment
+doc = document
+",
+        )
+        .unwrap();
+        assert_eq!(config.canonical("pic"), "image");
+        assert_eq!(config.canonical("photo"), "image");
+        assert_eq!(config.canonical("image"), "image");
+        assert_eq!(config.canonical("document"), "doc");
+        assert_eq!(config.canonical("other"), "other");
+        assert!(parse("[aliases]\nimage = pic\nphoto = pic").is_err());
+        assert!(parse("[aliases]\nimage = pic\npic = photo").is_err());
+        assert!(parse("[aliases]\nimage\n").is_err());
+        assert!(parse("[aliases]\nimage = = pic\n").is_err());
+    }
+
+    #[test]
+    fn t_implications() {
+        let config = parse(
+            "
+[aliases]
+dog = doggo
+[implications]
+golden-retriever => doggo => animal
+cat => animal => animal
+",
+        )
+        .unwrap();
+        let expand = |tag| config.expand(tag).collect::<Vec<_>>();
+        assert_eq!(
+            expand("golden-retriever"),
+            ["golden-retriever", "animal", "dog"]
+        );
+        assert_eq!(expand("doggo"), ["dog", "animal"]);
+        assert_eq!(expand("cat"), ["cat", "animal"]);
+        assert_eq!(expand("animal"), ["animal"]);
+        assert!(parse("[implications]\ndog =>\n").is_err());
+    }
+
+    #[test]
+    fn t_inference() {
+        let tags = |config: &Config, name: &'static str| {
+            config
+                .implicit_tags(name)
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+        };
+        let config = parse(
+            "
+[settings]
+year-range = 1900..2100
+[formats]
+audio = .mp3 flac
+image = .heic
+",
+        )
+        .unwrap();
+        assert_eq!(tags(&config, "0000_test.mp3"), ["audio"]);
+        assert_eq!(
+            tags(&config, "1999_to_2001.FLAC"),
+            ["1999", "2000", "2001", "audio"]
+        );
+        assert_eq!(tags(&config, "beach.heic"), ["image"]);
+        assert_eq!(tags(&config, "beach.jpg"), ["image"]);
+        assert_eq!(tags(&config, "1850-07-14.mp3"), ["audio"]);
+        assert_eq!(
+            config.format_extensions("audio"),
+            Some(&[".mp3".to_string(), ".flac".to_string()][..])
+        );
+        let config = parse("[settings]\nyear-tags = false\n").unwrap();
+        assert_eq!(tags(&config, "2019_trip.jpg"), ["image"]);
+        let config = parse("[settings]\nformat-tags = false\n[formats]\naudio = .mp3").unwrap();
+        assert_eq!(tags(&config, "2019_trip.mp3"), ["2019"]);
+        let config = parse("[settings]\ndate-tags = false\n").unwrap();
+        assert_eq!(tags(&config, "2019-07-14.txt"), ["2019"]);
+        assert!(parse("[settings]\nyear-range = 2100..1900\n").is_err());
+        assert!(parse("[formats]\naudio =\n").is_err());
+    }
+
+    #[test]
+    fn t_openers() {
+        let config = parse(
+            "
+[openers]
+.pdf = zathura
+video = mpv --fs {}
+.MKV = vlc
+",
+        )
+        .unwrap();
+        assert_eq!(config.opener(Path::new("docs/paper.PDF")), Some("zathura"));
+        assert_eq!(config.opener(Path::new("clip.mp4")), Some("mpv --fs {}"));
+        assert_eq!(config.opener(Path::new("movie.mkv")), Some("vlc"));
+        assert_eq!(config.opener(Path::new("notes.txt")), None);
+        assert!(parse("[openers]\n.pdf =\n").is_err());
+    }
+
+    #[test]
+    fn t_repo_commands_ignored() {
+        let path = Path::new(FTAG_CONFIG_FILE);
+        let repo = "[openers]\n.pdf = rm -rf ~\n[editors]\nevil\n[actions]\nRun = evil {}\n";
+        let merged = |user: &str| {
+            Config::from_sections(merge_sections(
+                parse_text(path, repo).unwrap(),
+                parse_text(path, user).unwrap(),
+            ))
+            .unwrap()
+        };
+        let config = merged("");
+        assert_eq!(config.opener(Path::new("paper.pdf")), None);
+        assert!(config.editors().is_empty());
+        assert!(config.actions().is_empty());
+        let config = merged("[openers]\n.pdf = zathura\n");
+        assert_eq!(config.opener(Path::new("paper.pdf")), Some("zathura"));
+    }
+
+    #[test]
+    fn t_default_exclude() {
+        let config = parse("[exclude]\nnode_modules\n").unwrap();
+        assert!(config.exclude().iter().any(|g| g == "node_modules"));
+        assert!(config.exclude().iter().any(|g| g == ".DS_Store"));
+        let config = parse("[settings]\ndefault-exclude = false\n[exclude]\n*~\n").unwrap();
+        assert_eq!(config.exclude(), ["*~"]);
+    }
+
+    #[test]
+    fn t_virtual_tags() {
+        let config = parse(
+            "[virtual]
+inbox = photo & !reviewed
+later = inbox | todo
+",
+        )
+        .unwrap();
+        assert_eq!(config.virtual_query("inbox"), Some("photo & !reviewed"));
+        assert!(config.is_virtual("later"));
+        assert!(!config.is_virtual("photo"));
+        assert!(parse(
+            "[virtual]
+later = inbox | todo
+inbox = photo
+"
+        )
+        .is_err());
+        assert!(parse(
+            "[virtual]
+inbox = inbox | photo
+"
+        )
+        .is_err());
+        assert!(parse(
+            "[virtual]
+inbox = photo
+inbox = video
+"
+        )
+        .is_err());
+        assert!(parse(
+            "[virtual]
+in box = photo
+"
+        )
+        .is_err());
+        assert!(parse(
+            "[virtual]
+inbox = (photo
+"
+        )
+        .is_err());
+    }
+}
@@ -1,42 +1,175 @@
 use crate::{
-    filter::FilterParseError,
+    config::Config,
+    filter::{FilterParseError, NAME_PREFIX},
+    index::{IndexState, SearchIndex},
     load::{
-        get_filename_str, get_ftag_backup_path, get_ftag_path, infer_implicit_tags, DirData,
-        FileLoadingOptions, GlobMatches, Loader, LoaderOptions,
+        find_comments, format_text, get_filename_str, get_ftag_backup_path, get_ftag_path, is_url,
+        normalize_text, quote_tag, remove_glob_in_text, rename_glob_in_text, rename_tag_in_text,
+        validate_text, DirData, FileLoadingOptions, GlobMatches, Loader, LoaderOptions,
     },
-    walk::{DirTree, MetaData, VisitedDir},
+    query::SECONDS_PER_DAY,
+    vfs::{ArchiveFileSystem, FileSystem, OsFileSystem},
+    walk::{walk_parallel, DirEntry, DirTree, MetaData, VisitedDir, WalkLimits},
 };
+use fast_glob::glob_match;
+use regex::Regex;
 use std::{
-    collections::HashSet,
-    fmt::Debug,
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fmt::{Debug, Display},
     fs::OpenOptions,
-    io,
+    io::{self, Write},
     path::{Path, PathBuf},
+    process::{self, Stdio},
 };
 
 pub(crate) const FTAG_FILE: &str = ".ftag";
 pub(crate) const FTAG_BACKUP_FILE: &str = ".ftagbak";
+pub(crate) const FTAG_INDEX_FILE: &str = ".ftagindex";
+pub(crate) const FTAG_CONFIG_FILE: &str = ".ftagconfig";
+pub(crate) const FTAG_LOCK_FILE: &str = ".ftaglock";
+pub(crate) const FTAG_HASH_FILE: &str = ".ftaghash";
+pub(crate) const FTAG_CACHE_FILE: &str = ".ftagcache";
+pub(crate) const FTAG_JOURNAL_FILE: &str = ".ftagjournal";
 
-/// The data related to a glob in an ftag file. This is meant to be used in
-/// error reporting.
-pub struct GlobInfo {
-    glob: String,
-    dirpath: PathBuf, // The store file where the glob was found.
+/// A problem found in the metadata by `check`.
+pub enum Problem {
+    /// A glob that doesn't match any files. `suggestion` is an untracked file
+    /// in the same directory that the glob likely refers to, if the file was
+    /// renamed.
+    UnmatchedGlob {
+        dirpath: PathBuf,
+        glob: String,
+        suggestion: Option<String>,
+    },
+    /// A tag that violates the rules in the config.
+    InvalidTag { dirpath: PathBuf, tag: String },
+    /// A tag that is deprecated in the config.
+    DeprecatedTag {
+        dirpath: PathBuf,
+        tag: String,
+        replacement: Option<String>,
+    },
+    /// A tag that starts with `name:`, which can only be queried in quotes,
+    /// because it is read as a glob that matches the names of the files
+    /// otherwise.
+    ShadowedTag { dirpath: PathBuf, tag: String },
+}
+
+impl Problem {
+    /// Code used to identify the kind of problem in the porcelain output of
+    /// `check`. Other tools parse these, so they must never change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Problem::UnmatchedGlob { .. } => "unmatched-glob",
+            Problem::InvalidTag { .. } => "invalid-tag",
+            Problem::DeprecatedTag { .. } => "deprecated-tag",
+            Problem::ShadowedTag { .. } => "shadowed-tag",
+        }
+    }
+
+    /// The path of the directory, relative to the root of the check, whose
+    /// .ftag file has the problem.
+    pub fn dirpath(&self) -> &Path {
+        match self {
+            Problem::UnmatchedGlob { dirpath, .. }
+            | Problem::InvalidTag { dirpath, .. }
+            | Problem::DeprecatedTag { dirpath, .. }
+            | Problem::ShadowedTag { dirpath, .. } => dirpath,
+        }
+    }
+
+    /// A fix for the problem, such as the new name of a renamed file.
+    pub fn suggestion(&self) -> Option<&str> {
+        match self {
+            Problem::UnmatchedGlob { suggestion, .. } => suggestion.as_deref(),
+            Problem::InvalidTag { .. } | Problem::ShadowedTag { .. } => None,
+            Problem::DeprecatedTag { replacement, .. } => replacement.as_deref(),
+        }
+    }
+
+    /// The glob or tag that has the problem.
+    pub fn subject(&self) -> &str {
+        match self {
+            Problem::UnmatchedGlob { glob, .. } => glob,
+            Problem::InvalidTag { tag, .. }
+            | Problem::DeprecatedTag { tag, .. }
+            | Problem::ShadowedTag { tag, .. } => tag,
+        }
+    }
+}
+
+impl Debug for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::UnmatchedGlob {
+                dirpath,
+                glob,
+                suggestion,
+            } => {
+                write!(f, "No files in '{}' matching '{}'", dirpath.display(), glob)?;
+                match suggestion {
+                    Some(s) => write!(f, ", did you mean '{}'?", s),
+                    None => Ok(()),
+                }
+            }
+            Problem::InvalidTag { dirpath, tag } => write!(
+                f,
+                "The tag '{}' in '{}' is not allowed by the config",
+                tag,
+                dirpath.display()
+            ),
+            Problem::DeprecatedTag {
+                dirpath,
+                tag,
+                replacement,
+            } => write!(
+                f,
+                "The tag '{}' in '{}' is deprecated{}",
+                tag,
+                dirpath.display(),
+                match replacement {
+                    Some(r) => format!(", use '{}' instead", r),
+                    None => String::new(),
+                }
+            ),
+            Problem::ShadowedTag { dirpath, tag } => write!(
+                f,
+                "The tag '{}' in '{}' can only be queried in quotes, because '{}' matches the names of files",
+                tag,
+                dirpath.display(),
+                NAME_PREFIX
+            ),
+        }
+    }
 }
 
 pub enum Error {
     TUIFailure(String),
-    GUIFailure(eframe::Error),
+    GUIFailure(String),
     EditCommandFailed(String),
-    UnmatchedGlobs(Vec<GlobInfo>),
+    OpenCommandFailed(String),
+    CheckFailed(Vec<Problem>),
     InvalidArgs,
     InvalidWorkingDirectory,
     InvalidPath(PathBuf),
     CannotReadStoreFile(PathBuf),
-    CannotParseFtagFile(PathBuf, String),
+    CannotParseFtagFile(PathBuf, usize, String),
     CannotWriteFile(PathBuf),
+    CannotMoveFile(PathBuf, String),
     InvalidFilter(FilterParseError),
+    InvalidRegex(String),
+    InvalidConfig(PathBuf, usize, String),
+    CorruptIndex(PathBuf),
+    InvalidArchive(PathBuf, String),
+    Locked(PathBuf),
+    ReadOnly(PathBuf),
+    AlreadyExists(PathBuf),
+    ModifiedConcurrently(PathBuf),
+    DeprecatedTags(Vec<String>),
+    UndescribedTag(String),
     DirectoryTraversalFailed,
+    WatchFailed(String),
 }
 
 impl Debug for Error {
@@ -47,15 +180,11 @@ impl Debug for Error {
             }
             Self::GUIFailure(e) => write!(f, "Failure in the GUI:\n{}", e),
             Self::EditCommandFailed(message) => write!(f, "Unable to edit file:\n{}", message),
-            Self::UnmatchedGlobs(infos) => {
+            Self::OpenCommandFailed(message) => write!(f, "Unable to open file:\n{}", message),
+            Self::CheckFailed(problems) => {
                 writeln!(f)?;
-                for info in infos {
-                    writeln!(
-                        f,
-                        "No files in '{}' matching '{}'",
-                        info.dirpath.display(),
-                        info.glob
-                    )?;
+                for problem in problems {
+                    writeln!(f, "{:?}", problem)?;
                 }
                 Ok(())
             }
@@ -65,78 +194,708 @@ impl Debug for Error {
             Self::CannotReadStoreFile(path) => {
                 write!(f, "Unable to read file: '{}'", path.display())
             }
-            Self::CannotParseFtagFile(path, message) => {
-                writeln!(f, "While parsing file '{}'", path.display())?;
-                write!(f, "{}", message)
+            Self::CannotParseFtagFile(path, line, message) => {
+                write!(f, "{}:{}: {}", path.display(), line, message)
             }
             Self::CannotWriteFile(path) => writeln!(f, "Cannot write to file {}", path.display()),
+            Self::CannotMoveFile(path, message) => {
+                write!(f, "Cannot move file {}:\n{}", path.display(), message)
+            }
             Self::InvalidFilter(err) => write!(f, "Unable to parse filter:\n{:?}", err),
+            Self::InvalidRegex(message) => {
+                write!(f, "Unable to parse regular expression:\n{}", message)
+            }
+            Self::InvalidConfig(path, line, message) => {
+                write!(f, "{}:{}: {}", path.display(), line, message)
+            }
+            Self::CorruptIndex(path) => write!(
+                f,
+                "The search index '{}' is corrupt. Run 'ftag index' to rebuild it.",
+                path.display()
+            ),
+            Self::InvalidArchive(path, message) => {
+                write!(f, "Invalid archive '{}': {}", path.display(), message)
+            }
+            Self::Locked(path) => write!(
+                f,
+                "The directory '{}' is locked by another ftag process. If no other ftag process is running, the lock was left behind by an interrupted process, and '{}' can be removed.",
+                path.display(),
+                path.join(FTAG_LOCK_FILE).display()
+            ),
+            Self::ReadOnly(path) => write!(
+                f,
+                "Refusing to change '{}' in read-only mode. Remove the --read-only flag, or the read-only setting from the config, to allow changes.",
+                path.display()
+            ),
+            Self::AlreadyExists(path) => write!(
+                f,
+                "'{}' already exists. Use --force to overwrite it.",
+                path.display()
+            ),
+            Self::ModifiedConcurrently(path) => write!(
+                f,
+                "'{}' was modified by another process while this command was running. Nothing was written, run the command again.",
+                path.display()
+            ),
+            Self::DeprecatedTags(tags) => {
+                write!(f, "Refusing to use deprecated tags: {}", tags.join(", "))
+            }
+            Self::UndescribedTag(tag) => {
+                write!(f, "The tag '{}' has no description in the glossary.", tag)
+            }
             Self::DirectoryTraversalFailed => {
                 write!(f, "Something went wrong when traversing directories.")
             }
+            Self::WatchFailed(message) => {
+                write!(f, "Unable to watch the directories for changes:\n{}", message)
+            }
         }
     }
 }
 
 /// Recursively check all directories. This will read all .ftag
 /// files, and make sure every listed glob / path matches at least one
-/// file on disk.
-pub fn check(path: PathBuf) -> Result<(), Error> {
-    let mut matcher = GlobMatches::new();
-    let mut missing = Vec::new();
-    let mut dir = DirTree::new(
-        path.clone(),
-        LoaderOptions::new(
-            false,
-            false,
-            FileLoadingOptions::Load {
-                file_tags: false,
-                file_desc: false,
-            },
-        ),
-    )?;
-    while let Some(VisitedDir {
+/// file on disk, and that all tags are valid according to the config.
+pub fn check(path: PathBuf, config: &Config) -> Result<(), Error> {
+    let problems = find_problems(path, None, config)?;
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::CheckFailed(problems))
+    }
+}
+
+/// Find the problems in the metadata of the directory visited by the walker,
+/// i.e. globs that don't match any file on disk, and tags that are not valid
+/// according to the config. `prefix` is prepended to the paths of the
+/// directories.
+fn check_dir(
+    visited: VisitedDir,
+    prefix: &Path,
+    matcher: &mut GlobMatches,
+    config: &Config,
+    problems: &mut Vec<Problem>,
+) -> Result<(), Error> {
+    let VisitedDir {
         rel_dir_path,
         files,
         metadata,
         ..
-    }) = dir.walk()
-    {
-        match metadata {
-            MetaData::FailedToLoad(e) => return Err(e),
-            MetaData::NotFound => continue, // No metadata.
-            MetaData::Ok(DirData { globs, .. }) => {
-                matcher.find_matches(files, globs, true);
-                missing.extend(globs.iter().enumerate().filter_map(|(i, f)| {
-                    if !matcher.is_glob_matched(i) {
-                        Some(GlobInfo {
-                            glob: f.path.to_string(),
-                            dirpath: rel_dir_path.to_path_buf(),
-                        })
-                    } else {
-                        None
-                    }
+    } = visited;
+    match metadata {
+        MetaData::FailedToLoad(e) => return Err(e),
+        MetaData::NotFound => {} // No metadata.
+        MetaData::Ok(DirData { globs, alltags, .. }) => {
+            let dirpath = prefix.join(rel_dir_path);
+            matcher.find_matches(files, &globs, true);
+            // URLs don't point to files on the disk.
+            let unmatched: Vec<&str> = globs
+                .iter()
+                .enumerate()
+                .filter(|(i, g)| !matcher.is_glob_matched(*i) && !is_url(g.path))
+                .map(|(_, g)| g.path)
+                .collect();
+            let untracked: Vec<&str> = files
+                .iter()
+                .enumerate()
+                .filter(|(fi, _)| !matcher.is_file_matched(*fi))
+                .filter_map(|(_, f)| f.name().to_str())
+                .collect();
+            let suggestions = suggest_renames(&unmatched, &untracked);
+            problems.extend(
+                unmatched
+                    .into_iter()
+                    .zip(suggestions)
+                    .map(|(glob, suggestion)| Problem::UnmatchedGlob {
+                        glob: glob.to_string(),
+                        dirpath: dirpath.clone(),
+                        suggestion: suggestion.map(|s| s.to_string()),
+                    }),
+            );
+            let mut tags: Vec<&str> = alltags.clone();
+            tags.sort_unstable();
+            tags.dedup();
+            problems.extend(tags.into_iter().filter_map(|tag| {
+                if !config.is_valid_tag(tag) {
+                    Some(Problem::InvalidTag {
+                        dirpath: dirpath.clone(),
+                        tag: tag.to_string(),
+                    })
+                } else if config.is_deprecated(tag) {
+                    Some(Problem::DeprecatedTag {
+                        dirpath: dirpath.clone(),
+                        tag: tag.to_string(),
+                        replacement: config.replacement(tag).map(|r| r.to_string()),
+                    })
+                } else if tag.starts_with(NAME_PREFIX) {
+                    Some(Problem::ShadowedTag {
+                        dirpath: dirpath.clone(),
+                        tag: tag.to_string(),
+                    })
+                } else {
+                    None
+                }
+            }));
+        }
+    }
+    Ok(())
+}
+
+/// Recursively traverse the directories starting from `path`, up to `max_depth`
+/// levels of subdirectories, and find all problems in the metadata, i.e. globs
+/// that don't match any file on disk, and tags that are not valid according to
+/// the config. The directories are walked on multiple threads, and the problems
+/// are sorted by the paths of their directories.
+pub fn find_problems(
+    path: PathBuf,
+    max_depth: Option<usize>,
+    config: &Config,
+) -> Result<Vec<Problem>, Error> {
+    let limits = WalkLimits::depth(max_depth);
+    // Everything is loaded, so that all the errors that would make the .ftag
+    // files fail to load in other commands are reported.
+    let options = || {
+        LoaderOptions::new(
+            true,
+            true,
+            FileLoadingOptions::Load {
+                file_tags: true,
+                file_desc: true,
+            },
+        )
+    };
+    let mut matcher = GlobMatches::new();
+    let mut problems = Vec::new();
+    // Check the root directory, then walk its subdirectories on other threads.
+    let mut dir = DirTree::new(path.clone(), options(), config)?.limit(&limits);
+    if let Some(visited) = dir.walk() {
+        check_dir(visited, Path::new(""), &mut matcher, config, &mut problems)?;
+    }
+    let tasks = dir.take_subdirs().into_iter().map(|d| (d, ())).collect();
+    let mut found: Vec<(PathBuf, Vec<Problem>)> = Vec::new();
+    walk_parallel(
+        tasks,
+        |subdir, (), queue, send| {
+            let mut matcher = GlobMatches::new();
+            let mut dir =
+                DirTree::new(path.join(subdir), options(), config)?.limit(&limits.below(subdir));
+            while let Some(visited) = dir.walk() {
+                let dirpath = subdir.join(visited.rel_dir_path);
+                let mut problems = Vec::new();
+                check_dir(visited, subdir, &mut matcher, config, &mut problems)?;
+                if !problems.is_empty() {
+                    send((dirpath, problems));
+                }
+                if !queue.share(&mut dir, subdir, &()) {
+                    break;
+                }
+            }
+            Ok(())
+        },
+        |result| found.push(result),
+    )?;
+    // Stable, so the problems of each directory stay in order.
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    problems.extend(found.into_iter().flat_map(|(_, problems)| problems));
+    Ok(problems)
+}
+
+/// Get the number of single character insertions, deletions and substitutions
+/// needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let next = (diag + usize::from(ca != *cb))
+                .min(row[j] + 1)
+                .min(row[j + 1] + 1);
+            diag = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+/// Get the file extension of `name` in lower case, or an empty string.
+fn extension_lowercase(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => ext.to_lowercase(),
+        _ => String::new(),
+    }
+}
+
+/// For each of the `globs` that don't match any files, suggest one of the
+/// `untracked` files in the same directory, that the glob likely referred to
+/// before the file was renamed. Only globs that are plain file names are
+/// considered, and a file is only suggested if it has the same extension and
+/// a similar name. The suggestions are one-to-one: a file is not suggested if
+/// it is equally similar to another file, or is the best match for more than
+/// one glob.
+fn suggest_renames<'a>(globs: &[&str], untracked: &[&'a str]) -> Vec<Option<&'a str>> {
+    // Half the characters of the longer name can be different.
+    const MIN_SIMILARITY: f64 = 0.5;
+    let is_plain = |name: &str| !name.contains(['*', '?', '[', ']', '{', '}', '/', '\\']);
+    let best: Vec<Option<usize>> = globs
+        .iter()
+        .map(|glob| {
+            if !is_plain(glob) {
+                return None;
+            }
+            let ext = extension_lowercase(glob);
+            let mut scores: Vec<(usize, f64)> = untracked
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| is_plain(f) && extension_lowercase(f) == ext)
+                .map(|(fi, f)| {
+                    let len = glob.chars().count().max(f.chars().count()).max(1);
+                    (fi, 1. - edit_distance(glob, f) as f64 / len as f64)
+                })
+                .filter(|(_, score)| *score >= MIN_SIMILARITY)
+                .collect();
+            scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+            match scores.as_slice() {
+                [(fi, _)] => Some(*fi),
+                [(fi, first), (_, second), ..] if first > second => Some(*fi),
+                _ => None,
+            }
+        })
+        .collect();
+    best.iter()
+        .map(|fi| {
+            fi.filter(|fi| best.iter().filter(|b| **b == Some(*fi)).count() == 1)
+                .map(|fi| untracked[fi])
+        })
+        .collect()
+}
+
+/// Plan to fix the globs that don't match any files, found by `find_problems`
+/// in the directory tree at `root`, in the .ftag files. If `rename` is true,
+/// the globs with a suggestion are replaced by the suggested file name. If
+/// `remove` is true, the other globs are removed, along with the entries that
+/// are left without any globs. Everything else is kept as it is. Unlike
+/// `clean`, the entries are not merged. The .ftag files that are left empty are
+/// removed.
+pub fn fix_unmatched_globs(
+    root: &Path,
+    problems: &[Problem],
+    rename: bool,
+    remove: bool,
+) -> Result<WritePlan, Error> {
+    let mut dirs: BTreeMap<&Path, Vec<(&str, Option<&str>)>> = BTreeMap::new();
+    for problem in problems {
+        if let Problem::UnmatchedGlob {
+            dirpath,
+            glob,
+            suggestion,
+        } = problem
+        {
+            match suggestion.as_deref().filter(|_| rename) {
+                Some(s) => dirs.entry(dirpath).or_default().push((glob, Some(s))),
+                None if remove => dirs.entry(dirpath).or_default().push((glob, None)),
+                None => {}
+            }
+        }
+    }
+    let mut plan = WritePlan::default();
+    for (dir, globs) in dirs {
+        let fpath = root.join(dir).join(FTAG_FILE);
+        let mut text = std::fs::read_to_string(&fpath)
+            .map_err(|_| Error::CannotReadStoreFile(fpath.clone()))?;
+        normalize_text(&mut text);
+        for (glob, suggestion) in globs {
+            text = match suggestion {
+                Some(s) => rename_glob_in_text(&text, glob, s).0,
+                None => remove_glob_in_text(&text, glob).0,
+            };
+        }
+        match text.trim().is_empty() {
+            true => plan.remove(fpath),
+            false => plan.write(fpath, text),
+        }
+    }
+    Ok(plan)
+}
+
+/// A change to a file on the disk.
+pub enum FileChange {
+    /// Create the file, or overwrite it with new contents.
+    Write(PathBuf, Vec<u8>),
+    Remove(PathBuf),
+}
+
+impl FileChange {
+    fn path(&self) -> &Path {
+        match self {
+            FileChange::Write(path, _) | FileChange::Remove(path) => path,
+        }
+    }
+}
+
+/// Advisory lock on a directory, held while its .ftag files are being
+/// changed. The lock is a file in the directory, that is created when the lock
+/// is acquired and removed when it is dropped. This only keeps ftag processes
+/// from clobbering each other's changes, other programs ignore it.
+pub struct DirLock {
+    path: PathBuf,
+}
+
+impl DirLock {
+    /// Lock the directory at `dirpath`, or fail if it is already locked.
+    pub fn acquire(dirpath: &Path) -> Result<DirLock, Error> {
+        let path = dirpath.join(FTAG_LOCK_FILE);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                // The id of the process is only for the curious user.
+                let _ = writeln!(file, "{}", process::id());
+                Ok(DirLock { path })
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                Err(Error::Locked(dirpath.to_path_buf()))
+            }
+            Err(_) => Err(Error::CannotWriteFile(path)),
+        }
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// The changes a command intends to make to the files on the disk. Commands
+/// that modify files return a plan instead of writing the files themselves, so
+/// that the changes can be reported without writing anything, for example with
+/// `--dry-run`. Existing .ftag files are backed up before they are overwritten
+/// or removed.
+#[derive(Default)]
+pub struct WritePlan {
+    changes: Vec<FileChange>,
+    // Contents of the files when the plan was made, to detect changes made by
+    // other processes in the meantime.
+    original: Vec<Option<Vec<u8>>>,
+}
+
+impl WritePlan {
+    /// Plan to write `bytes` to the file at `path`, unless the file already
+    /// has the same contents.
+    pub(crate) fn write(&mut self, path: PathBuf, bytes: impl Into<Vec<u8>>) {
+        let bytes = bytes.into();
+        let original = std::fs::read(&path).ok();
+        if original.as_ref().is_some_and(|current| *current == bytes) {
+            return;
+        }
+        self.changes.push(FileChange::Write(path, bytes));
+        self.original.push(original);
+    }
+
+    /// Plan to remove the file at `path`.
+    pub(crate) fn remove(&mut self, path: PathBuf) {
+        self.original.push(std::fs::read(&path).ok());
+        self.changes.push(FileChange::Remove(path));
+    }
+
+    pub fn changes(&self) -> &[FileChange] {
+        &self.changes
+    }
+
+    /// Make the planned changes to the files. The directories whose ftag files
+    /// change are locked for the duration, and nothing is written if any of
+    /// them is already locked, or if any of the files changed after the plan
+    /// was made. In read-only mode, nothing is written if any of the ftag
+    /// files would change.
+    pub fn apply(self, config: &Config) -> Result<(), Error> {
+        let ftag_files: Vec<&Path> = self
+            .changes
+            .iter()
+            .map(|change| change.path())
+            .filter(|path| {
+                path.file_name().is_some_and(|name| {
+                    name == FTAG_FILE || name == FTAG_INDEX_FILE || name == FTAG_HASH_FILE
+                })
+            })
+            .collect();
+        if let (true, Some(path)) = (config.read_only(), ftag_files.first()) {
+            return Err(Error::ReadOnly(path.to_path_buf()));
+        }
+        let mut dirs: Vec<&Path> = ftag_files
+            .into_iter()
+            .filter_map(|path| path.parent())
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        let _locks = dirs
+            .into_iter()
+            .map(DirLock::acquire)
+            .collect::<Result<Vec<_>, _>>()?;
+        for (change, original) in self.changes.iter().zip(self.original.iter()) {
+            if std::fs::read(change.path()).ok() != *original {
+                return Err(Error::ModifiedConcurrently(change.path().to_path_buf()));
+            }
+        }
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        let journal: Vec<(PathBuf, Option<String>, Option<String>)> = self
+            .changes
+            .iter()
+            .zip(self.original.iter())
+            .filter(|(change, _)| change.path().file_name() == Some(OsStr::new(FTAG_FILE)))
+            .map(|(change, original)| {
+                let new = match change {
+                    FileChange::Write(_, bytes) => Some(text(bytes)),
+                    FileChange::Remove(_) => None,
+                };
+                (
+                    change.path().to_path_buf(),
+                    original.as_deref().map(text),
+                    new,
+                )
+            })
+            .collect();
+        for change in self.changes {
+            match change {
+                FileChange::Write(path, bytes) => {
+                    backup_ftag_file(&path)?;
+                    std::fs::write(&path, bytes).map_err(|_| Error::CannotWriteFile(path))?;
+                }
+                FileChange::Remove(path) => {
+                    backup_ftag_file(&path)?;
+                    std::fs::remove_file(&path).map_err(|_| Error::CannotWriteFile(path))?;
+                }
+            }
+        }
+        Journal::write(&journal)
+    }
+}
+
+/*
+Every command that changes .ftag files records them in a journal, so that
+`undo` restores exactly the files changed by the last command, and not every
+file that was ever backed up, and `history` can list the past commands. The
+record of a command is appended to the journal in the deepest directory that
+contains all the changed files. It starts with a line that has the time it was
+written and the command line, followed by a line for each changed .ftag file:
+'~' and the path of its directory if it was backed up, or '+' and the path if
+it was created. Then comes the unified diff of the changes, with every line
+prefixed by '|'.
+ */
+
+/// The .ftag files changed by a command.
+struct Journal {
+    /// Nanoseconds since the Unix epoch when the journal was written.
+    time: u128,
+    /// The command line of the command.
+    command: String,
+    /// Directory of the journal.
+    dir: PathBuf,
+    /// Directories of the changed .ftag files, relative to `dir`, and whether
+    /// the .ftag files existed before the command, i.e. were backed up.
+    entries: Vec<(PathBuf, bool)>,
+    /// Unified diff of the changes, with the paths relative to `dir`.
+    diff: String,
+}
+
+impl Journal {
+    /// Record the changes to the .ftag files in `changed` in a journal. Each
+    /// change is the path of the .ftag file, its text before the change if it
+    /// existed, and its text after the change unless it was removed.
+    fn write(changed: &[(PathBuf, Option<String>, Option<String>)]) -> Result<(), Error> {
+        let mut dir = match changed.first().and_then(|(path, ..)| path.parent()) {
+            Some(dir) => dir.to_path_buf(),
+            None => return Ok(()),
+        };
+        for (path, ..) in changed {
+            while !path.starts_with(&dir) && dir.pop() {}
+        }
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        let command: Vec<String> = std::env::args()
+            .enumerate()
+            .map(|(i, arg)| match i {
+                0 => Path::new(&arg)
+                    .file_stem()
+                    .map_or(arg.clone(), |s| s.to_string_lossy().into_owned()),
+                _ => arg.replace('\n', " "),
+            })
+            .collect();
+        let mut text = format!("{} {}\n", time, command.join(" "));
+        let mut diff = String::new();
+        for (path, old, new) in changed {
+            let rel = path.strip_prefix(&dir).unwrap_or(path);
+            text.push_str(if old.is_some() { "~ " } else { "+ " });
+            text.push_str(&rel.parent().unwrap_or(Path::new("")).to_string_lossy());
+            text.push('\n');
+            diff.push_str(&crate::diff::unified(
+                &rel.to_string_lossy(),
+                old.as_deref(),
+                new.as_deref(),
+            ));
+        }
+        for line in diff.lines() {
+            text.push_str("| ");
+            text.push_str(line);
+            text.push('\n');
+        }
+        let path = dir.join(FTAG_JOURNAL_FILE);
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|_| Error::CannotWriteFile(path))
+    }
+
+    /// Check if any of the changed .ftag files are in the directory tree at
+    /// `path`.
+    fn touches(&self, path: &Path) -> bool {
+        self.entries
+            .iter()
+            .any(|(rel, _)| self.dir.join(rel).starts_with(path))
+    }
+
+    /// Read the records of all the commands in the journal in `dir`, oldest
+    /// first. Records that can't be parsed are skipped.
+    fn read_all(dir: &Path) -> Vec<Journal> {
+        let Ok(text) = std::fs::read_to_string(dir.join(FTAG_JOURNAL_FILE)) else {
+            return Vec::new();
+        };
+        let mut journals = Vec::new();
+        // The record being read, unless it is invalid.
+        let mut current: Option<Option<Journal>> = None;
+        for line in text.lines() {
+            if line.starts_with(|c: char| c.is_ascii_digit()) {
+                journals.extend(current.take().flatten());
+                let (time, command) = line.split_once(' ').unwrap_or((line, ""));
+                current = Some(time.parse().ok().map(|time| Journal {
+                    time,
+                    command: command.to_string(),
+                    dir: dir.to_path_buf(),
+                    entries: Vec::new(),
+                    diff: String::new(),
                 }));
+                continue;
+            }
+            let Some(Some(journal)) = current.as_mut() else {
+                continue;
+            };
+            match line.split_at_checked(2) {
+                Some(("~ ", rel)) => journal.entries.push((PathBuf::from(rel), true)),
+                Some(("+ ", rel)) => journal.entries.push((PathBuf::from(rel), false)),
+                Some(("| ", diff)) => {
+                    journal.diff.push_str(diff);
+                    journal.diff.push('\n');
+                }
+                _ => current = Some(None),
             }
         }
+        journals.extend(current.flatten());
+        journals
     }
-    if missing.is_empty() {
-        Ok(())
-    } else {
-        Err(Error::UnmatchedGlobs(missing))
+}
+
+/// Find the records of the commands that changed any .ftag files in the
+/// directory tree at `path`, oldest first.
+fn find_journals(path: &Path, config: &Config) -> Result<Vec<Journal>, Error> {
+    // The journal of a command could be in any directory of the tree, or above
+    // it if the command changed files outside the tree too.
+    let mut journals: Vec<Journal> = path
+        .ancestors()
+        .skip(1)
+        .flat_map(Journal::read_all)
+        .collect();
+    let mut dir = DirTree::new(
+        path.to_path_buf(),
+        LoaderOptions::new(false, false, FileLoadingOptions::Skip),
+        config,
+    )?
+    .report_failures();
+    while let Some(VisitedDir { abs_dir_path, .. }) = dir.walk() {
+        journals.extend(Journal::read_all(abs_dir_path));
     }
+    journals.retain(|journal| journal.touches(path));
+    journals.sort_by_key(|journal| journal.time);
+    Ok(journals)
+}
+
+/// A command that changed .ftag files, as recorded in the journal.
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch when the command ran.
+    pub time: u64,
+    /// The command line of the command.
+    pub command: String,
+    /// Number of .ftag files the command changed.
+    pub files: usize,
+    /// Unified diff of the changes, with the paths relative to the directory
+    /// that contains all the changed files.
+    pub diff: String,
+}
+
+impl HistoryEntry {
+    /// The time the command ran, such as '2024-05-01 13:45:00', in UTC.
+    pub fn date(&self) -> String {
+        let (days, secs) = (self.time / SECONDS_PER_DAY, self.time % SECONDS_PER_DAY);
+        // Civil date from the days since the epoch, from the algorithm of
+        // Howard Hinnant.
+        let z = days as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+}
+
+/// Get the commands that changed any .ftag files in the directory tree at
+/// `path`, oldest first, from the journals they recorded.
+pub fn history(path: PathBuf, config: &Config) -> Result<Vec<HistoryEntry>, Error> {
+    Ok(find_journals(&path, config)?
+        .into_iter()
+        .map(|journal| HistoryEntry {
+            time: (journal.time / 1_000_000_000) as u64,
+            command: journal.command,
+            files: journal.entries.len(),
+            diff: journal.diff,
+        })
+        .collect())
+}
+
+/// Copy the file at `path` to the backup file next to it, if it is an existing
+/// .ftag file.
+fn backup_ftag_file(path: &Path) -> Result<(), Error> {
+    if path.file_name().is_some_and(|name| name == FTAG_FILE) && path.is_file() {
+        std::fs::copy(path, get_ftag_backup_path(path))
+            .map_err(|_| Error::CannotWriteFile(path.to_path_buf()))?;
+    }
+    Ok(())
 }
 
 struct FileDataOwned {
     glob: String,
     tags: Vec<String>,
     desc: Option<String>,
+    comments: Vec<String>,
 }
 
 struct FileDataMultiple {
     globs: Vec<String>,
     tags: Vec<String>,
     desc: Option<String>,
+    comments: Vec<String>,
 }
 
 fn write_globs<T: AsRef<str>>(globs: &[T], w: &mut impl io::Write) -> Result<(), io::Error> {
@@ -150,6 +909,26 @@ fn write_globs<T: AsRef<str>>(globs: &[T], w: &mut impl io::Write) -> Result<(),
     Ok(())
 }
 
+fn write_comments<T: AsRef<str>>(comments: &[T], w: &mut impl io::Write) -> Result<(), io::Error> {
+    for comment in comments {
+        writeln!(w, "{}", comment.as_ref())?;
+    }
+    Ok(())
+}
+
+/// Write an entry for a group of files, with its comments directly before its
+/// path header, where they belong to it.
+fn write_group(group: &FileDataMultiple, w: &mut impl io::Write) -> Result<(), io::Error> {
+    writeln!(w)?;
+    write_comments(&group.comments, w)?;
+    writeln!(w, "[path]")?;
+    for glob in &group.globs {
+        writeln!(w, "{}", glob)?;
+    }
+    write_tags(&group.tags, w)?;
+    write_desc(group.desc.as_ref(), w)
+}
+
 fn write_tags<T: AsRef<str>>(tags: &[T], w: &mut impl io::Write) -> Result<(), io::Error> {
     if tags.is_empty() {
         return Ok(());
@@ -158,7 +937,7 @@ fn write_tags<T: AsRef<str>>(tags: &[T], w: &mut impl io::Write) -> Result<(), i
     if tags
         .iter()
         .try_fold(0usize, |len, tag| -> Result<usize, io::Error> {
-            let tag = tag.as_ref();
+            let tag = quote_tag(tag.as_ref());
             Ok(if len > 80 {
                 writeln!(w, "{}", tag)?;
                 0usize
@@ -181,7 +960,23 @@ fn write_desc<T: AsRef<str>>(desc: Option<&T>, w: &mut impl io::Write) -> Result
     }
 }
 
-pub fn clean(path: PathBuf) -> Result<(), Error> {
+/// Check if the .ftag file in `dirpath` has nothing but whitespace.
+fn is_blank_ftag_file(dirpath: &Path) -> bool {
+    get_ftag_path::<true>(dirpath)
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .is_some_and(|text| text.trim_start_matches('\u{feff}').trim().is_empty())
+}
+
+/// Recursively clean the metadata of all directories starting from
+/// `path`. The .ftag files that are left with no tags, description or entries
+/// are removed. If `canonicalize` is true, all aliased tags are replaced with their
+/// canonical form. Nothing is written until the returned plan is applied.
+pub fn clean(path: PathBuf, canonicalize: bool, config: &Config) -> Result<WritePlan, Error> {
+    let mut plan = WritePlan::default();
+    let rewrite = |tag: &str| match canonicalize {
+        true => config.canonical(tag).to_string(),
+        false => tag.to_string(),
+    };
     let mut matcher = GlobMatches::new();
     let mut valid: Vec<FileDataOwned> = Vec::new();
     let mut dir = DirTree::new(
@@ -194,6 +989,7 @@ pub fn clean(path: PathBuf) -> Result<(), Error> {
                 file_desc: true,
             },
         ),
+        config,
     )?;
     while let Some(VisitedDir {
         abs_dir_path,
@@ -205,50 +1001,68 @@ pub fn clean(path: PathBuf) -> Result<(), Error> {
         let data = match metadata {
             MetaData::Ok(d) => d,
             MetaData::NotFound => continue,
+            // Blank files don't have any headers, so they fail to load.
+            MetaData::FailedToLoad(_) if is_blank_ftag_file(abs_dir_path) => {
+                plan.remove(abs_dir_path.join(FTAG_FILE));
+                continue;
+            }
             MetaData::FailedToLoad(e) => return Err(e),
         };
+        let fpath = get_ftag_path::<true>(abs_dir_path)
+            .ok_or(Error::CannotReadStoreFile(abs_dir_path.to_path_buf()))?;
+        // The comments are not part of the data, so they're read from the text.
+        let mut text = std::fs::read_to_string(&fpath)
+            .map_err(|_| Error::CannotReadStoreFile(fpath.clone()))?;
+        normalize_text(&mut text);
+        let comments = find_comments(&text);
         matcher.find_matches(files, &data.globs, true);
         valid.clear();
         valid.extend(data.globs.iter().enumerate().filter_map(|(gi, g)| {
-            if matcher.is_glob_matched(gi) {
-                let mut tags: Vec<String> = g
-                    .tags(&data.alltags)
-                    .iter()
-                    .map(|t| t.to_string())
-                    .collect();
+            if matcher.is_glob_matched(gi) || is_url(g.path) {
+                let mut tags: Vec<String> = data.glob_tags(g).iter().map(|t| rewrite(t)).collect();
                 tags.sort_unstable();
                 tags.dedup();
                 Some(FileDataOwned {
                     glob: g.path.to_string(),
                     tags,
-                    desc: g.desc.map(|d| d.to_string()),
+                    desc: data.glob_desc(g).map(str::to_string),
+                    comments: comments
+                        .globs
+                        .get(g.path)
+                        .into_iter()
+                        .flatten()
+                        .map(|c| c.to_string())
+                        .collect(),
                 })
             } else {
                 None
             }
         }));
+        if valid.is_empty()
+            && data.tags().is_empty()
+            && data.desc.as_deref().is_none_or(str::is_empty)
+            && comments.dir.is_empty()
+        {
+            // Nothing left worth keeping.
+            plan.remove(abs_dir_path.join(FTAG_FILE));
+            continue;
+        }
         // This should group files that share the same tags and desc
         valid.sort_unstable_by(|a, b| match a.tags.cmp(&b.tags) {
             std::cmp::Ordering::Less => std::cmp::Ordering::Less,
             std::cmp::Ordering::Equal => a.desc.cmp(&b.desc),
             std::cmp::Ordering::Greater => std::cmp::Ordering::Greater,
         });
-
-        let fpath = get_ftag_path::<true>(abs_dir_path)
-            .ok_or(Error::CannotReadStoreFile(abs_dir_path.to_path_buf()))?;
-        // Backup existing data.
-        std::fs::copy(&fpath, get_ftag_backup_path(abs_dir_path))
-            .map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
-        let mut writer = io::BufWriter::new(
-            OpenOptions::new()
-                .write(true)
-                .truncate(true)
-                .create(true)
-                .open(&fpath)
-                .map_err(|_| Error::CannotWriteFile(fpath.clone()))?,
-        );
+        let mut writer: Vec<u8> = Vec::new();
         // Write directory data.
-        write_tags(data.tags(), &mut writer).map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
+        write_comments(&comments.dir, &mut writer)
+            .map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
+        let mut dirtags: Vec<String> = data.tags().iter().map(|t| rewrite(t)).collect();
+        if canonicalize {
+            dirtags.sort_unstable();
+            dirtags.dedup();
+        }
+        write_tags(&dirtags, &mut writer).map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
         write_desc(data.desc.as_ref(), &mut writer)
             .map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
         // Write out the file data in groups that share the same tags and description.
@@ -264,22 +1078,27 @@ pub fn clean(path: PathBuf) -> Result<(), Error> {
                             if current.tags == file.tags && current.desc == file.desc =>
                         {
                             current.globs.push(file.glob);
+                            for comment in file.comments {
+                                if !current.comments.contains(&comment) {
+                                    current.comments.push(comment);
+                                }
+                            }
                             Some(current)
                         }
                         Some(current) => {
-                            write_globs(&current.globs, &mut writer)?;
-                            write_tags(&current.tags, &mut writer)?;
-                            write_desc(current.desc.as_ref(), &mut writer)?;
+                            write_group(&current, &mut writer)?;
                             Some(FileDataMultiple {
                                 globs: vec![file.glob],
                                 tags: file.tags,
                                 desc: file.desc,
+                                comments: file.comments,
                             })
                         }
                         None => Some(FileDataMultiple {
                             globs: vec![file.glob],
                             tags: file.tags,
                             desc: file.desc,
+                            comments: file.comments,
                         }),
                     })
                 },
@@ -287,170 +1106,1052 @@ pub fn clean(path: PathBuf) -> Result<(), Error> {
             .map_err(|_| Error::CannotWriteFile(fpath.clone()))?
         {
             // This is the last entry.
-            write_globs(&last.globs, &mut writer)
-                .map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
-            write_tags(&last.tags, &mut writer)
-                .map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
-            write_desc(last.desc.as_ref(), &mut writer)
-                .map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
-        }
-    }
-    Ok(())
-}
-
-/// Get a description string from the tags and description of a file.
-fn full_description(tags: Vec<String>, desc: String) -> String {
-    let tagstr = {
-        let mut tags = tags.into_iter();
-        let first = tags.next().unwrap_or_default();
-        tags.fold(first, |acc, t| format!("{}, {}", acc, t))
-    };
-    format!(
-        "tags: [{}]{}",
-        tagstr,
-        if desc.is_empty() {
-            desc
-        } else {
-            format!("\n{}", desc)
+            write_group(&last, &mut writer).map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
         }
-    )
-}
-
-/// Get the description of a file or a directory.
-pub fn what_is(path: &Path) -> Result<String, Error> {
-    if path.is_file() {
-        what_is_file(path)
-    } else if path.is_dir() {
-        what_is_dir(path)
-    } else {
-        Err(Error::InvalidPath(path.to_path_buf()))
+        plan.write(fpath, writer);
     }
+    Ok(plan)
 }
 
-/// Get a full description of the file that includes the tags and the
-/// description of said file.
-fn what_is_file(path: &Path) -> Result<String, Error> {
-    use fast_glob::glob_match;
-    let mut loader = Loader::new(LoaderOptions::new(
+/// Plan to reformat the .ftag file at `path`, without changing what it
+/// means. Files that fail to load are not changed, and the error is returned
+/// instead.
+fn format_into(path: &Path, sort_tags: bool, plan: &mut WritePlan) -> Result<(), Error> {
+    let mut text = std::fs::read_to_string(path)
+        .map_err(|_| Error::CannotReadStoreFile(path.to_path_buf()))?;
+    normalize_text(&mut text);
+    Loader::new(LoaderOptions::new(
         true,
         true,
         FileLoadingOptions::Load {
             file_tags: true,
             file_desc: true,
         },
-    ));
-    let data = match get_ftag_path::<true>(path) {
-        Some(storepath) => loader.load(&storepath)?,
-        None => return Err(Error::InvalidPath(path.to_path_buf())),
-    };
-    let mut outdesc = data.desc.unwrap_or("").to_string();
-    let mut outtags = data
-        .tags()
-        .iter()
-        .map(|t| t.to_string())
-        .collect::<Vec<_>>();
-    if let Some(parent) = path.parent() {
-        outtags.extend(infer_implicit_tags(get_filename_str(parent)?).map(|t| t.to_string()));
-    }
-    let filenamestr = path
-        .file_name()
-        .ok_or(Error::InvalidPath(path.to_path_buf()))?
-        .to_str()
-        .ok_or(Error::InvalidPath(path.to_path_buf()))?;
-    for g in data.globs.iter() {
-        if glob_match(g.path, filenamestr) {
-            outtags.extend(
-                g.tags(&data.alltags)
-                    .iter()
-                    .map(|t| t.to_string())
-                    .chain(infer_implicit_tags(filenamestr).map(|t| t.to_string())),
-            );
-            if let Some(fdesc) = g.desc {
-                outdesc = format!("{}\n{}", fdesc, outdesc);
-            }
-        }
-    }
-    // Remove duplicate tags.
-    outtags.sort_unstable();
-    outtags.dedup();
-    Ok(full_description(outtags, outdesc))
+    ))
+    .load_text(&text, path)?;
+    plan.write(path.to_path_buf(), format_text(&text, sort_tags));
+    Ok(())
 }
 
-/// Get the full description of a directory that includes it's tags and
-/// description.
-fn what_is_dir(path: &Path) -> Result<String, Error> {
-    let mut loader = Loader::new(LoaderOptions::new(true, true, FileLoadingOptions::Skip));
-    let data = match get_ftag_path::<true>(path) {
-        Some(storepath) => loader.load(&storepath)?,
-        None => return Err(Error::InvalidPath(path.to_path_buf())),
-    };
-    let desc = data.desc.unwrap_or("").to_string();
-    let tags = data
-        .tags()
-        .iter()
-        .map(|t| t.to_string())
-        .chain(infer_implicit_tags(get_filename_str(path)?).map(|t| t.to_string()))
-        .collect::<Vec<_>>();
-    Ok(full_description(tags, desc))
+/// Reformat the .ftag file at `path`. Nothing is written until the returned
+/// plan is applied. Paths of files not named .ftag are rejected, so that other
+/// files can't be overwritten by mistake.
+pub fn format_file(path: &Path, sort_tags: bool) -> Result<WritePlan, Error> {
+    if path.file_name() != Some(OsStr::new(FTAG_FILE)) {
+        return Err(Error::InvalidPath(path.to_path_buf()));
+    }
+    let mut plan = WritePlan::default();
+    format_into(path, sort_tags, &mut plan)?;
+    Ok(plan)
 }
 
-/// Recursively traverse the directories starting from `root` and
-/// return all files that are not tracked.
-pub fn untracked_files(root: PathBuf) -> Result<Vec<PathBuf>, Error> {
-    let mut matcher = GlobMatches::new();
+/// Recursively reformat all the .ftag files starting from `path`. Unlike
+/// `clean`, this only changes the whitespace, and optionally the order of the
+/// tags, so the entries keep their order, and the globs that don't match any
+/// files are kept. Nothing is written until the returned plan is applied. The
+/// .ftag files that fail to load are left unchanged, and their errors are
+/// returned along with the plan to reformat the rest.
+pub fn format(
+    path: PathBuf,
+    sort_tags: bool,
+    config: &Config,
+) -> Result<(WritePlan, Vec<Error>), Error> {
+    let mut plan = WritePlan::default();
+    let mut errors = Vec::new();
     let mut dir = DirTree::new(
-        root.clone(),
-        LoaderOptions::new(
-            false,
-            false,
+        path,
+        LoaderOptions::new(true, false, FileLoadingOptions::Skip),
+        config,
+    )?;
+    while let Some(VisitedDir {
+        abs_dir_path,
+        metadata,
+        ..
+    }) = dir.walk()
+    {
+        match metadata {
+            MetaData::Ok(_) | MetaData::FailedToLoad(_) => {
+                if let Err(e) = format_into(&abs_dir_path.join(FTAG_FILE), sort_tags, &mut plan) {
+                    errors.push(e);
+                }
+            }
+            MetaData::NotFound => continue,
+        }
+    }
+    Ok((plan, errors))
+}
+
+/// Plan to undo the last command that changed any .ftag files in the directory
+/// tree at `path`, according to the journal of that command. The .ftag files
+/// it changed are restored from their backups, and the ones it created are
+/// removed. Restoring is a command like any other, so undoing again redoes the
+/// changes. If `prune` is true, all the backups and journals are removed
+/// instead. Returns the plan, and the directories in which .ftag files are
+/// restored or removed, or backups are removed, relative to `path`.
+pub fn undo(
+    path: PathBuf,
+    prune: bool,
+    config: &Config,
+) -> Result<(WritePlan, Vec<PathBuf>), Error> {
+    let mut plan = WritePlan::default();
+    let mut dirs = Vec::new();
+    if prune {
+        let mut dir = DirTree::new(
+            path.clone(),
+            LoaderOptions::new(false, false, FileLoadingOptions::Skip),
+            config,
+        )?
+        .report_failures();
+        while let Some(VisitedDir {
+            abs_dir_path,
+            rel_dir_path,
+            ..
+        }) = dir.walk()
+        {
+            let before = plan.changes().len();
+            for name in [FTAG_BACKUP_FILE, FTAG_JOURNAL_FILE] {
+                let file = abs_dir_path.join(name);
+                if file.is_file() {
+                    plan.remove(file);
+                }
+            }
+            if plan.changes().len() > before {
+                dirs.push(rel_dir_path.to_path_buf());
+            }
+        }
+        return Ok((plan, dirs));
+    }
+    let Some(journal) = find_journals(&path, config)?.pop() else {
+        return Ok((plan, dirs));
+    };
+    // The .ftag files that fail to load are restored too, as they're likely
+    // the reason for undoing.
+    for (rel, existed) in &journal.entries {
+        let dirpath = journal.dir.join(rel);
+        let Ok(rel) = dirpath.strip_prefix(&path) else {
+            continue; // Outside the tree.
+        };
+        let before = plan.changes().len();
+        let fpath = dirpath.join(FTAG_FILE);
+        match existed {
+            true => {
+                let backup = dirpath.join(FTAG_BACKUP_FILE);
+                plan.write(
+                    fpath,
+                    std::fs::read(&backup).map_err(|_| Error::CannotReadStoreFile(backup))?,
+                );
+            }
+            false if fpath.is_file() => plan.remove(fpath),
+            false => {}
+        }
+        if plan.changes().len() > before {
+            dirs.push(rel.to_path_buf());
+        }
+    }
+    Ok((plan, dirs))
+}
+
+/// Parse every .ftag file in the directory tree at `path`, and find all the
+/// errors in them, instead of stopping at the first error. Each error is
+/// reported with the path of the .ftag file relative to `path`, and the line
+/// number it was found at.
+pub fn verify(path: PathBuf, config: &Config) -> Result<Vec<(PathBuf, usize, String)>, Error> {
+    let mut errors = Vec::new();
+    let mut dir = DirTree::new(
+        path,
+        LoaderOptions::new(
+            true,
+            true,
+            FileLoadingOptions::Load {
+                file_tags: true,
+                file_desc: true,
+            },
+        ),
+        config,
+    )?
+    .report_failures();
+    while let Some(VisitedDir {
+        abs_dir_path,
+        rel_dir_path,
+        metadata,
+        ..
+    }) = dir.walk()
+    {
+        let err = match metadata {
+            MetaData::FailedToLoad(e) => e,
+            MetaData::Ok(_) | MetaData::NotFound => continue,
+        };
+        let relpath = rel_dir_path.join(FTAG_FILE);
+        let found = match std::fs::read_to_string(abs_dir_path.join(FTAG_FILE)) {
+            Ok(text) => validate_text(&text),
+            Err(_) => Vec::new(),
+        };
+        if found.is_empty() {
+            // The loader found an error that the validator doesn't know about.
+            match err {
+                Error::CannotParseFtagFile(_, line, message) => {
+                    errors.push((relpath, line, message))
+                }
+                e => return Err(e),
+            }
+        } else {
+            errors.extend(
+                found
+                    .into_iter()
+                    .map(|(line, message)| (relpath.clone(), line, message)),
+            );
+        }
+    }
+    Ok(errors)
+}
+
+/// Replace aliases with their canonical tags, add the implied tags, and remove
+/// duplicates.
+fn expand_tags(tags: Vec<String>, config: &Config) -> Vec<String> {
+    let mut expanded: Vec<String> = tags
+        .iter()
+        .flat_map(|t| config.expand(t))
+        .map(|t| t.to_string())
+        .collect();
+    expanded.sort_unstable();
+    expanded.dedup();
+    expanded
+}
+
+/// The tags and description of a file or a directory. The tags are listed in
+/// three groups: the tags from the file's own entries, the tags inherited from
+/// its directories, and the implicit tags. A tag is only listed in the first
+/// group it appears in.
+pub struct Description {
+    pub explicit: Vec<String>,
+    pub inherited: Vec<String>,
+    pub implicit: Vec<String>,
+    pub desc: String,
+}
+
+impl Display for Description {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "explicit: [{}]\ninherited: [{}]\nimplicit: [{}]",
+            self.explicit.join(", "),
+            self.inherited.join(", "),
+            self.implicit.join(", "),
+        )?;
+        if !self.desc.is_empty() {
+            write!(f, "\n{}", self.desc)?;
+        }
+        Ok(())
+    }
+}
+
+fn full_description(
+    explicit: Vec<String>,
+    inherited: Vec<String>,
+    implicit: Vec<String>,
+    desc: String,
+    config: &Config,
+) -> Description {
+    let explicit = expand_tags(explicit, config);
+    let mut inherited = expand_tags(inherited, config);
+    inherited.retain(|t| explicit.binary_search(t).is_err());
+    let mut implicit = expand_tags(implicit, config);
+    implicit.retain(|t| explicit.binary_search(t).is_err() && inherited.binary_search(t).is_err());
+    Description {
+        explicit,
+        inherited,
+        implicit,
+        desc,
+    }
+}
+
+/// Build a command to run from a command template, such as one from the
+/// config. A `{}` in the template is replaced with `path`, otherwise the path
+/// is appended to the command. A `{line}` in the template is replaced with
+/// `line`, if it is provided.
+pub(crate) fn build_command(
+    template: &str,
+    path: &Path,
+    line: Option<usize>,
+) -> Option<process::Command> {
+    let mut words = template.split_whitespace();
+    let mut cmd = process::Command::new(words.next()?);
+    let mut has_path = false;
+    for word in words.map(|w| match line {
+        Some(line) => w.replace("{line}", &line.to_string()),
+        None => w.to_string(),
+    }) {
+        match word.split_once("{}") {
+            Some((before, after)) => {
+                let mut arg = OsString::from(before);
+                arg.push(path);
+                arg.push(after);
+                cmd.arg(arg);
+                has_path = true;
+            }
+            None => {
+                cmd.arg(word);
+            }
+        }
+    }
+    if !has_path {
+        cmd.arg(path);
+    }
+    Some(cmd)
+}
+
+/// Open the file at `path` with the opener from the config, or the default
+/// application of the system if the config doesn't have an opener for it.
+pub fn open_file(path: &Path, config: &Config) -> Result<(), Error> {
+    // URLs are opened in the browser, regardless of the configured openers.
+    let url = path.to_str().is_some_and(is_url);
+    let command = match config.opener(path) {
+        Some(command) if !url => command,
+        _ => return opener::open(path).map_err(|e| Error::OpenCommandFailed(e.to_string())),
+    };
+    // Detach the output of the opener, so it doesn't mess up the interactive
+    // mode.
+    build_command(command, path, None)
+        .ok_or(Error::OpenCommandFailed(command.to_string()))?
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| Error::OpenCommandFailed(format!("{}: {}", command, e)))
+}
+
+/// Get the line number of the entry of the file named `name` in the .ftag
+/// file at `ftag_path`. This is the first line of the file, if the file has
+/// no entry.
+fn entry_line(ftag_path: &Path, name: &str) -> usize {
+    let text = match std::fs::read_to_string(ftag_path) {
+        Ok(text) => text,
+        Err(_) => return 1,
+    };
+    let mut in_path = false;
+    for (i, line) in text.lines().enumerate() {
+        match line.trim() {
+            "[path]" => in_path = true,
+            "[tags]" | "[desc]" => in_path = false,
+            line if in_path && line == name => return i + 1,
+            _ => {}
+        }
+    }
+    1
+}
+
+/// Open the .ftag file of the file or directory at `path` in an editor. If
+/// `path` is a file, the editor jumps to the entry of the file, if the
+/// command of the editor has a `{line}` placeholder. The editors from the
+/// config are tried in order, followed by the VISUAL and EDITOR environment
+/// variables, and the first one that is installed is used. The file is only
+/// opened in the default application of the system if none of them are
+/// installed, and the config allows it.
+pub fn edit(path: &Path, config: &Config) -> Result<(), Error> {
+    let ftag_path = get_ftag_path::<false>(path).ok_or(Error::InvalidPath(path.to_path_buf()))?;
+    if config.read_only() {
+        return Err(Error::ReadOnly(ftag_path));
+    }
+    let line = if path.is_file() {
+        entry_line(&ftag_path, get_filename_str(path)?)
+    } else {
+        1
+    };
+    let env: Vec<String> = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .collect();
+    // Keep other ftag processes from changing the file while it is open in a
+    // terminal editor.
+    let _lock = DirLock::acquire(ftag_path.parent().unwrap_or(Path::new("")))?;
+    for command in config.editors().iter().chain(env.iter()) {
+        let mut cmd = match build_command(command, &ftag_path, Some(line)) {
+            Some(cmd) => cmd,
+            None => continue, // Empty command.
+        };
+        match cmd.status() {
+            Ok(status) if status.success() => return Ok(()),
+            Ok(status) => {
+                return Err(Error::EditCommandFailed(format!(
+                    "'{}' exited with {}",
+                    command, status
+                )))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue, // Not installed.
+            Err(e) => return Err(Error::EditCommandFailed(format!("{}: {}", command, e))),
+        }
+    }
+    if config.gui_edit_fallback() {
+        return opener::open(&ftag_path).map_err(|e| Error::EditCommandFailed(e.to_string()));
+    }
+    Err(Error::EditCommandFailed(String::from(
+        "No editor was found. Set the EDITOR environment variable, or list your editors in the [editors] section of the config.",
+    )))
+}
+
+/// Get the description of a file or a directory.
+pub fn what_is(path: &Path, config: &Config) -> Result<Description, Error> {
+    // The path can be inside an archive.
+    let fs = ArchiveFileSystem::new(config.archives());
+    if fs.is_file(path) {
+        what_is_file(path, &fs, config)
+    } else if fs.is_dir(path) {
+        what_is_dir(path, &fs, config)
+    } else {
+        Err(Error::InvalidPath(path.to_path_buf()))
+    }
+}
+
+/// Get the tags inherited by `path` from the .ftag files of its ancestor
+/// directories, and the implicit tags of the names of those directories.
+fn ancestor_tags(
+    path: &Path,
+    fs: &impl FileSystem,
+    config: &Config,
+) -> Result<(Vec<String>, Vec<String>), Error> {
+    let mut loader = Loader::new(LoaderOptions::new(true, false, FileLoadingOptions::Skip));
+    let mut inherited = Vec::new();
+    let mut implicit = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        let storepath = dir.join(FTAG_FILE);
+        if fs.is_file(&storepath) {
+            inherited.extend(
+                loader
+                    .load_from(fs, &storepath)?
+                    .tags()
+                    .iter()
+                    .map(|t| t.to_string()),
+            );
+            implicit.extend(
+                config
+                    .implicit_tags(get_filename_str(dir)?)
+                    .map(|t| t.to_string()),
+            );
+        }
+    }
+    Ok((inherited, implicit))
+}
+
+/// Get a full description of the file that includes the tags and the
+/// description of said file.
+fn what_is_file(path: &Path, fs: &impl FileSystem, config: &Config) -> Result<Description, Error> {
+    let mut loader = Loader::new(LoaderOptions::new(
+        false,
+        true,
+        FileLoadingOptions::Load {
+            file_tags: true,
+            file_desc: true,
+        },
+    ));
+    let storepath = path
+        .parent()
+        .map(|dir| dir.join(FTAG_FILE))
+        .filter(|storepath| fs.is_file(storepath))
+        .ok_or(Error::InvalidPath(path.to_path_buf()))?;
+    let data = loader.load_from(fs, &storepath)?;
+    let mut outdesc = data.desc.as_deref().unwrap_or("").to_string();
+    let mut explicit = Vec::new();
+    let (inherited, mut implicit) = ancestor_tags(path, fs, config)?;
+    let filenamestr = path
+        .file_name()
+        .ok_or(Error::InvalidPath(path.to_path_buf()))?
+        .to_str()
+        .ok_or(Error::InvalidPath(path.to_path_buf()))?;
+    let mut matched = false;
+    for g in data.globs.iter() {
+        if glob_match(g.path, filenamestr) {
+            matched = true;
+            explicit.extend(data.glob_tags(g).iter().map(|t| t.to_string()));
+            if let Some(fdesc) = data.glob_desc(g) {
+                outdesc = format!("{}\n{}", fdesc, outdesc);
+            }
+        }
+    }
+    if matched {
+        implicit.extend(config.implicit_tags(filenamestr).map(|t| t.to_string()));
+        implicit.extend(config.audio_tags(path));
+    }
+    Ok(full_description(
+        explicit, inherited, implicit, outdesc, config,
+    ))
+}
+
+/// Get the full description of a directory that includes it's tags and
+/// description.
+fn what_is_dir(path: &Path, fs: &impl FileSystem, config: &Config) -> Result<Description, Error> {
+    let mut loader = Loader::new(LoaderOptions::new(true, true, FileLoadingOptions::Skip));
+    let storepath = path.join(FTAG_FILE);
+    if !fs.is_file(&storepath) {
+        return Err(Error::InvalidPath(path.to_path_buf()));
+    }
+    let data = loader.load_from(fs, &storepath)?;
+    let desc = data.desc.as_deref().unwrap_or("").to_string();
+    let explicit = data.tags().iter().map(|t| t.to_string()).collect();
+    let (inherited, mut implicit) = ancestor_tags(path, fs, config)?;
+    implicit.extend(
+        config
+            .implicit_tags(get_filename_str(path)?)
+            .map(|t| t.to_string()),
+    );
+    Ok(full_description(
+        explicit, inherited, implicit, desc, config,
+    ))
+}
+
+/// Recursively traverse the directories starting from `root` and call `visit`
+/// for every directory that contains untracked files. `visit` is called with
+/// the absolute path of the directory, the path of the directory relative to
+/// `root`, all the files in the directory, and the indices of the untracked
+/// files.
+fn visit_untracked_files<F>(
+    root: PathBuf,
+    max_depth: Option<usize>,
+    config: &Config,
+    mut visit: F,
+) -> Result<(), Error>
+where
+    F: FnMut(&Path, &Path, &[DirEntry], &[usize]) -> Result<(), Error>,
+{
+    let mut matcher = GlobMatches::new();
+    let mut untracked: Vec<usize> = Vec::new();
+    let mut dir = DirTree::new(
+        root,
+        LoaderOptions::new(
+            false,
+            false,
             FileLoadingOptions::Load {
                 file_tags: false,
                 file_desc: false,
             },
         ),
-    )?;
-    let mut untracked = Vec::new();
+        config,
+    )?
+    .limit(&WalkLimits::depth(max_depth));
     while let Some(VisitedDir {
+        abs_dir_path,
         rel_dir_path,
         files,
         metadata,
         ..
     }) = dir.walk()
     {
+        untracked.clear();
         match metadata {
             MetaData::FailedToLoad(e) => return Err(e),
             MetaData::Ok(DirData { globs, .. }) => {
-                matcher.find_matches(files, globs, false);
-                untracked.extend(files.iter().enumerate().filter_map(|(fi, file)| {
-                    // Skip the files that matched with at least one glob. Copy the
-                    // paths of files that didn't match with any glob.
-                    match matcher.is_file_matched(fi) {
-                        true => None,
-                        false => {
-                            let mut relpath = rel_dir_path.to_path_buf();
-                            relpath.push(file.name());
-                            Some(relpath)
-                        }
-                    }
-                }));
+                matcher.find_matches(files, &globs, false);
+                // Skip the files that matched with at least one glob.
+                untracked.extend((0..files.len()).filter(|fi| !matcher.is_file_matched(*fi)));
             }
             MetaData::NotFound => {
                 // Metadata doesn't exist so everything is untracked.
-                untracked.extend(files.iter().map(|ch| {
-                    let mut relpath = rel_dir_path.to_path_buf();
-                    relpath.push(ch.name());
-                    relpath
-                }));
+                untracked.extend(0..files.len());
             }
         }
+        if !untracked.is_empty() {
+            visit(abs_dir_path, rel_dir_path, files, &untracked)?;
+        }
     }
+    Ok(())
+}
+
+/// Recursively traverse the directories starting from `root`, up to
+/// `max_depth` levels of subdirectories, and return all files that are not
+/// tracked.
+pub fn untracked_files(
+    root: PathBuf,
+    max_depth: Option<usize>,
+    config: &Config,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut untracked = Vec::new();
+    visit_untracked_files(
+        root,
+        max_depth,
+        config,
+        |_abs_dir_path, rel_dir_path, files, indices| {
+            untracked.extend(indices.iter().map(|fi| {
+                let mut relpath = rel_dir_path.to_path_buf();
+                relpath.push(files[*fi].name());
+                relpath
+            }));
+            Ok(())
+        },
+    )?;
     Ok(untracked)
 }
 
-/// Recursively traverse the directories from `path` and get all tags.
-pub fn get_all_tags(path: PathBuf) -> Result<impl Iterator<Item = String>, Error> {
-    let mut alltags = HashSet::new();
+/// Recursively traverse the directories starting from `root`, up to
+/// `max_depth` levels of subdirectories, and count the untracked files in each
+/// directory. Directories without untracked files are omitted. The output is
+/// sorted in descending order of the counts.
+pub fn untracked_summary(
+    root: PathBuf,
+    max_depth: Option<usize>,
+    config: &Config,
+) -> Result<Vec<(PathBuf, usize)>, Error> {
+    let mut counts: Vec<(PathBuf, usize)> = Vec::new();
+    visit_untracked_files(
+        root,
+        max_depth,
+        config,
+        |_abs_dir_path, rel_dir_path, _files, indices| {
+            counts.push((rel_dir_path.to_path_buf(), indices.len()));
+            Ok(())
+        },
+    )?;
+    counts.sort_by(|(adir, acount), (bdir, bcount)| bcount.cmp(acount).then(adir.cmp(bdir)));
+    Ok(counts)
+}
+
+/// How well the files directly inside a directory are tagged.
+pub struct DirCoverage {
+    /// Path of the directory relative to the root of the traversal.
+    pub path: PathBuf,
+    /// Number of files in the directory.
+    pub total: usize,
+    /// Number of files that are not tracked by any .ftag file.
+    pub untracked: usize,
+    /// Number of tracked files that neither have tags of their own, nor
+    /// inherit any tags from their directories. Implicit tags are not counted.
+    pub untagged: usize,
+}
+
+impl DirCoverage {
+    /// Percentage of the files in the directory that are tracked and tagged.
+    pub fn percent(&self) -> f64 {
+        100.0 * (self.total - self.untracked - self.untagged) as f64 / self.total as f64
+    }
+}
+
+/// Recursively traverse the directories starting from `root` and find the
+/// number of untracked and untagged files in each directory. Directories in
+/// which all files are tagged are omitted. The output is sorted such that the
+/// worst covered directories come first.
+pub fn coverage(root: PathBuf, config: &Config) -> Result<Vec<DirCoverage>, Error> {
     let mut matcher = GlobMatches::new();
+    let mut out: Vec<DirCoverage> = Vec::new();
+    // Whether each directory in the current depth-first chain has any tags of
+    // its own or inherited from its ancestors.
+    let mut has_dir_tags: Vec<bool> = Vec::new();
+    let mut dir = DirTree::new(
+        root,
+        LoaderOptions::new(
+            true,
+            false,
+            FileLoadingOptions::Load {
+                file_tags: true,
+                file_desc: false,
+            },
+        ),
+        config,
+    )?;
+    while let Some(VisitedDir {
+        traverse_depth,
+        rel_dir_path,
+        files,
+        metadata,
+        ..
+    }) = dir.walk()
+    {
+        has_dir_tags.truncate(traverse_depth - 1);
+        let inherited = has_dir_tags.last().copied().unwrap_or(false);
+        let (untracked, untagged) = match metadata {
+            MetaData::FailedToLoad(e) => return Err(e),
+            MetaData::Ok(data) => {
+                let inherited = inherited || !data.tags().is_empty();
+                has_dir_tags.push(inherited);
+                matcher.find_matches(files, &data.globs, false);
+                let untracked = (0..files.len())
+                    .filter(|fi| !matcher.is_file_matched(*fi))
+                    .count();
+                let untagged = if inherited {
+                    0
+                } else {
+                    (0..files.len())
+                        .filter(|fi| {
+                            matcher.is_file_matched(*fi)
+                                && matcher
+                                    .matched_globs(*fi)
+                                    .all(|gi| data.glob_tags(&data.globs[gi]).is_empty())
+                        })
+                        .count()
+                };
+                (untracked, untagged)
+            }
+            MetaData::NotFound => {
+                has_dir_tags.push(inherited);
+                (files.len(), 0)
+            }
+        };
+        if untracked + untagged > 0 {
+            out.push(DirCoverage {
+                path: rel_dir_path.to_path_buf(),
+                total: files.len(),
+                untracked,
+                untagged,
+            });
+        }
+    }
+    out.sort_by(|a, b| {
+        (b.untracked + b.untagged)
+            .cmp(&(a.untracked + a.untagged))
+            .then(a.percent().total_cmp(&b.percent()))
+            .then(a.path.cmp(&b.path))
+    });
+    Ok(out)
+}
+
+/// Get the globs for stub entries that will track the `untracked` files in a
+/// directory containing `files`. Untracked files that share an extension are
+/// grouped into a single glob such as `*.jpg`, as long as there are enough of
+/// them and none of the other files with that extension are already
+/// tracked. Files with names that are not valid unicode are skipped, because
+/// they cannot be tracked.
+fn stub_globs(files: &[DirEntry], untracked: &[usize]) -> Vec<String> {
+    const MIN_FILES_PER_GLOB: usize = 4;
+    let extension = |name: &str| -> Option<String> {
+        Path::new(name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_string())
+    };
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new(); // (untracked, total)
+    for (fi, file) in files.iter().enumerate() {
+        if let Some(ext) = file.name().to_str().and_then(extension) {
+            let (nuntracked, ntotal) = counts.entry(ext).or_default();
+            *ntotal += 1;
+            if untracked.binary_search(&fi).is_ok() {
+                *nuntracked += 1;
+            }
+        }
+    }
+    let mut globs: Vec<String> = Vec::new();
+    for name in untracked.iter().filter_map(|fi| files[*fi].name().to_str()) {
+        match extension(name) {
+            Some(ext) => match counts.get(&ext) {
+                Some((nuntracked, ntotal))
+                    if *nuntracked == *ntotal && *ntotal >= MIN_FILES_PER_GLOB =>
+                {
+                    let glob = format!("*.{}", ext);
+                    if !globs.contains(&glob) {
+                        globs.push(glob);
+                    }
+                }
+                _ => globs.push(name.to_string()),
+            },
+            None => globs.push(name.to_string()),
+        }
+    }
+    globs
+}
+
+/// Recursively traverse the directories starting from `root`, up to `max_depth`
+/// levels of subdirectories, and append stub entries with the given `tags` to
+/// the .ftag files, so that all untracked files
+/// become tracked. .ftag files are created where necessary, and existing .ftag
+/// files are backed up before they are modified. Returns the paths of the files
+/// that were added, relative to `root`.
+pub fn add_untracked_files(
+    root: PathBuf,
+    tags: &[String],
+    max_depth: Option<usize>,
+    config: &Config,
+) -> Result<(WritePlan, Vec<PathBuf>), Error> {
+    let mut plan = WritePlan::default();
+    let mut added = Vec::new();
+    visit_untracked_files(
+        root,
+        max_depth,
+        config,
+        |abs_dir_path, rel_dir_path, files, indices| {
+            let globs = stub_globs(files, indices);
+            if globs.is_empty() {
+                return Ok(());
+            }
+            let mut fpath = abs_dir_path.to_path_buf();
+            fpath.push(FTAG_FILE);
+            // The entries are appended to the existing data.
+            let mut writer = match fpath.exists() {
+                true => {
+                    std::fs::read(&fpath).map_err(|_| Error::CannotReadStoreFile(fpath.clone()))?
+                }
+                false => Vec::new(),
+            };
+            write_globs(&globs, &mut writer).map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
+            write_tags(tags, &mut writer).map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
+            plan.write(fpath, writer);
+            added.extend(
+                indices
+                    .iter()
+                    .map(|fi| files[*fi].name())
+                    .filter(|name| name.to_str().is_some())
+                    .map(|name| {
+                        let mut relpath = rel_dir_path.to_path_buf();
+                        relpath.push(name);
+                        relpath
+                    }),
+            );
+            Ok(())
+        },
+    )?;
+    Ok((plan, added))
+}
+
+/// Plan to create a .ftag file in `dir`, with a directory `[tags]` header and
+/// an entry with an empty `[tags]` header for every file in `dir`, ready to be
+/// filled in. Files that share an extension are grouped into a single glob, just
+/// like in `add_untracked_files`. An existing .ftag file is only overwritten if
+/// `force` is true. Returns the plan, and the globs of the entries.
+pub fn init(dir: PathBuf, force: bool, config: &Config) -> Result<(WritePlan, Vec<String>), Error> {
+    let fpath = dir.join(FTAG_FILE);
+    if !force && fpath.exists() {
+        return Err(Error::AlreadyExists(fpath));
+    }
+    let mut walker = DirTree::new(
+        dir.clone(),
+        LoaderOptions::new(false, false, FileLoadingOptions::Skip),
+        config,
+    )?
+    .limit(&WalkLimits::depth(Some(0)));
+    // The existing .ftag file is replaced, so it doesn't matter if it fails to
+    // load.
+    let globs = match walker.walk() {
+        Some(VisitedDir { files, .. }) => stub_globs(files, &(0..files.len()).collect::<Vec<_>>()),
+        None => return Err(Error::InvalidPath(dir)),
+    };
+    let mut writer: Vec<u8> = b"[tags]\n".to_vec();
+    for glob in &globs {
+        writeln!(writer, "\n[path]\n{}\n[tags]", glob)
+            .map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
+    }
+    let mut plan = WritePlan::default();
+    plan.write(fpath, writer);
+    Ok((plan, globs))
+}
+
+/// Append entries to the .ftag files that add `tags` to the files at `paths`,
+/// which are relative to `root`. The new entries are appended to the .ftag
+/// files in the directories of the files, and can be merged with the existing
+/// entries with `clean`.
+pub fn add_tags(root: &Path, paths: &[PathBuf], tags: &[String]) -> Result<WritePlan, Error> {
+    let mut dirs: BTreeMap<PathBuf, Vec<&str>> = BTreeMap::new();
+    for path in paths {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or(Error::InvalidPath(path.clone()))?;
+        dirs.entry(root.join(path.parent().unwrap_or(Path::new(""))))
+            .or_default()
+            .push(name);
+    }
+    let mut plan = WritePlan::default();
+    for (dir, names) in dirs {
+        let fpath = dir.join(FTAG_FILE);
+        let mut writer = match fpath.exists() {
+            true => std::fs::read(&fpath).map_err(|_| Error::CannotReadStoreFile(fpath.clone()))?,
+            false => Vec::new(),
+        };
+        write_globs(&names, &mut writer).map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
+        write_tags(tags, &mut writer).map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
+        plan.write(fpath, writer);
+    }
+    Ok(plan)
+}
+
+/// Append an entry for the file at `path`, which is relative to `root`, with
+/// `tags` and `desc` to the .ftag file in the directory of the file. The .ftag
+/// file is created if it doesn't exist.
+pub fn add_entry(
+    root: &Path,
+    path: &Path,
+    tags: &[String],
+    desc: Option<&str>,
+) -> Result<WritePlan, Error> {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or(Error::InvalidPath(path.to_path_buf()))?;
+    let fpath = root
+        .join(path.parent().unwrap_or(Path::new("")))
+        .join(FTAG_FILE);
+    let mut writer = match fpath.exists() {
+        true => std::fs::read(&fpath).map_err(|_| Error::CannotReadStoreFile(fpath.clone()))?,
+        false => Vec::new(),
+    };
+    write_globs(&[name], &mut writer)
+        .and_then(|_| write_tags(tags, &mut writer))
+        .and_then(|_| write_desc(desc.as_ref(), &mut writer))
+        .map_err(|_| Error::CannotWriteFile(fpath.clone()))?;
+    let mut plan = WritePlan::default();
+    plan.write(fpath, writer);
+    Ok(plan)
+}
+
+/// Plan to move the file at `src` to `dst`, which can be a new path or an
+/// existing directory. The file is removed from the entries of the .ftag file
+/// in its directory, and a new entry with the same tags and description is
+/// appended to the .ftag file in the destination directory. Returns the plan
+/// for the .ftag files, and the path the file will be moved to.
+pub fn plan_move(src: &Path, dst: &Path) -> Result<(WritePlan, PathBuf), Error> {
+    if !src.is_file() {
+        return Err(Error::InvalidPath(src.to_path_buf()));
+    }
+    let dst = match dst.is_dir() {
+        true => dst.join(
+            src.file_name()
+                .ok_or(Error::InvalidPath(src.to_path_buf()))?,
+        ),
+        false => dst.to_path_buf(),
+    };
+    if dst.exists() {
+        return Err(Error::InvalidPath(dst));
+    }
+    let srcname = get_filename_str(src)?;
+    let dstname = get_filename_str(&dst)?;
+    let dir_of = |path: &Path| match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let (srcstore, dststore) = (dir_of(src).join(FTAG_FILE), dir_of(&dst).join(FTAG_FILE));
+    let mut plan = WritePlan::default();
+    // Collect the tags and description of the file, and remove its globs.
+    let mut tags: Vec<String> = Vec::new();
+    let mut desc: Option<String> = None;
+    let mut srctext = None;
+    let mut removed = 0usize;
+    if srcstore.is_file() {
+        (tags, desc) = file_entry(&srcstore, srcname)?;
+        let mut text = std::fs::read_to_string(&srcstore)
+            .map_err(|_| Error::CannotReadStoreFile(srcstore.clone()))?;
+        normalize_text(&mut text);
+        let (text, count) = remove_glob_in_text(&text, srcname);
+        (srctext, removed) = (Some(text), count);
+    }
+    if removed == 0 && tags.is_empty() && desc.is_none() {
+        // The file is not tracked, so there is nothing to carry along.
+        return Ok((plan, dst));
+    }
+    let mut writer: Vec<u8> = match (&srctext, srcstore == dststore) {
+        (Some(text), true) => format!("{}\n", text.trim_end()).into_bytes(),
+        _ if dststore.is_file() => {
+            std::fs::read(&dststore).map_err(|_| Error::CannotReadStoreFile(dststore.clone()))?
+        }
+        _ => Vec::new(),
+    };
+    write_entry(dstname, &tags, desc.as_ref(), &mut writer)
+        .map_err(|_| Error::CannotWriteFile(dststore.clone()))?;
+    if let (Some(text), false) = (srctext, srcstore == dststore) {
+        plan.write(srcstore, text);
+    }
+    plan.write(dststore, writer);
+    Ok((plan, dst))
+}
+
+/// Get the tags and the description of the file named `name` from all the
+/// entries that match it in the .ftag file at `store`. The tags are sorted,
+/// and the different descriptions are joined with blank lines, in the order
+/// they appear in the file.
+pub(crate) fn file_entry(store: &Path, name: &str) -> Result<(Vec<String>, Option<String>), Error> {
+    let mut loader = Loader::new(LoaderOptions::new(
+        false,
+        false,
+        FileLoadingOptions::Load {
+            file_tags: true,
+            file_desc: true,
+        },
+    ));
+    let data = loader.load_from(&OsFileSystem, store)?;
+    let mut tags: Vec<String> = Vec::new();
+    let mut descs: Vec<&str> = Vec::new();
+    for g in data.globs.iter().filter(|g| glob_match(g.path, name)) {
+        tags.extend(data.glob_tags(g).iter().map(|t| t.to_string()));
+        if let Some(desc) = data.glob_desc(g).map(str::trim) {
+            if !descs.contains(&desc) {
+                descs.push(desc);
+            }
+        }
+    }
+    tags.sort_unstable();
+    tags.dedup();
+    let desc = (!descs.is_empty()).then(|| descs.join("\n\n"));
+    Ok((tags, desc))
+}
+
+/// Write an entry for the file named `name` with the given tags and
+/// description.
+pub(crate) fn write_entry<T: AsRef<str>>(
+    name: &str,
+    tags: &[String],
+    desc: Option<&T>,
+    w: &mut impl io::Write,
+) -> Result<(), io::Error> {
+    write_globs(&[name], w)?;
+    write_tags(tags, w)?;
+    write_desc(desc, w)
+}
+
+/// Rename the file at `src` to `dst`. A file can't be renamed to another file
+/// system, so in that case it is copied and the original is removed.
+fn rename_file(src: &Path, dst: &Path) -> io::Result<()> {
+    match std::fs::rename(src, dst) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            std::fs::copy(src, dst)?;
+            std::fs::remove_file(src).inspect_err(|_| {
+                let _ = std::fs::remove_file(dst);
+            })
+        }
+        result => result,
+    }
+}
+
+/// Move the file at `src` to `dst`, and apply the `plan` made for the move by
+/// `plan_move`. If the plan cannot be applied, the file is moved back.
+pub fn move_file(src: &Path, dst: &Path, plan: WritePlan, config: &Config) -> Result<(), Error> {
+    if let (true, Some(change)) = (config.read_only(), plan.changes().first()) {
+        return Err(Error::ReadOnly(change.path().to_path_buf()));
+    }
+    rename_file(src, dst).map_err(|e| Error::CannotMoveFile(src.to_path_buf(), e.to_string()))?;
+    plan.apply(config).inspect_err(|_| {
+        let _ = rename_file(dst, src);
+    })
+}
+
+/// Get the deprecated tags in `tags`, for the caller to warn about. If `strict`
+/// is true and there are any, an error is returned instead.
+pub fn check_deprecated_tags<'a, T: AsRef<str>>(
+    tags: &'a [T],
+    strict: bool,
+    config: &Config,
+) -> Result<Vec<&'a str>, Error> {
+    let deprecated: Vec<&str> = tags
+        .iter()
+        .map(|t| t.as_ref())
+        .filter(|t| config.is_deprecated(t))
+        .collect();
+    if strict && !deprecated.is_empty() {
+        return Err(Error::DeprecatedTags(
+            deprecated.iter().map(|t| t.to_string()).collect(),
+        ));
+    }
+    Ok(deprecated)
+}
+
+/// Occurrences of a tag in one .ftag file.
+pub struct TagOrigin {
+    /// Path of the .ftag file, relative to the root of the traversal.
+    pub path: PathBuf,
+    /// Whether the tag is assigned to the directory itself.
+    pub directory: bool,
+    /// Number of globs that the tag is assigned to.
+    pub globs: usize,
+}
+
+/// Recursively traverse the directories from `path` and find all .ftag files
+/// in which `tag` appears. This doesn't account for aliases, implications or
+/// implicit tags, because it is meant to report where the tag is written.
+pub fn tag_origins(path: PathBuf, tag: &str, config: &Config) -> Result<Vec<TagOrigin>, Error> {
+    let mut origins = Vec::new();
     let mut dir = DirTree::new(
         path,
         LoaderOptions::new(
@@ -461,8 +2162,125 @@ pub fn get_all_tags(path: PathBuf) -> Result<impl Iterator<Item = String>, Error
                 file_desc: false,
             },
         ),
+        config,
     )?;
     while let Some(VisitedDir {
+        rel_dir_path,
+        metadata,
+        ..
+    }) = dir.walk()
+    {
+        let data = match metadata {
+            MetaData::Ok(d) => d,
+            MetaData::NotFound => continue,
+            MetaData::FailedToLoad(e) => return Err(e),
+        };
+        let directory = data.tags().contains(&tag);
+        let globs = data
+            .globs
+            .iter()
+            .filter(|g| data.glob_tags(g).contains(&tag))
+            .count();
+        if directory || globs > 0 {
+            origins.push(TagOrigin {
+                path: rel_dir_path.join(FTAG_FILE),
+                directory,
+                globs,
+            });
+        }
+    }
+    Ok(origins)
+}
+
+/// Changes to the .ftag files required to rename a tag across the archive.
+pub struct TagRename {
+    plan: WritePlan,
+    entries: usize,
+}
+
+impl TagRename {
+    /// Number of .ftag files that will change.
+    pub fn num_files(&self) -> usize {
+        self.plan.changes().len()
+    }
+
+    /// Number of entries, i.e. tags headers, that will change.
+    pub fn num_entries(&self) -> usize {
+        self.entries
+    }
+
+    /// Write the changes to the .ftag files, after backing them up.
+    pub fn apply(self, config: &Config) -> Result<(), Error> {
+        self.plan.apply(config)
+    }
+}
+
+/// Recursively traverse the directories from `path` and find the changes
+/// required to rename the tag `from` to `to`. If `to` already exists, this
+/// merges the two tags. Nothing is written until the returned changes are
+/// applied.
+pub fn plan_tag_rename(
+    path: PathBuf,
+    from: &str,
+    to: &str,
+    config: &Config,
+) -> Result<TagRename, Error> {
+    let mut rename = TagRename {
+        plan: WritePlan::default(),
+        entries: 0,
+    };
+    let mut dir = DirTree::new(
+        path,
+        LoaderOptions::new(false, false, FileLoadingOptions::Skip),
+        config,
+    )?;
+    while let Some(VisitedDir {
+        abs_dir_path,
+        metadata,
+        ..
+    }) = dir.walk()
+    {
+        match metadata {
+            MetaData::Ok(_) => {}
+            MetaData::NotFound => continue,
+            MetaData::FailedToLoad(e) => return Err(e),
+        }
+        let fpath = abs_dir_path.join(FTAG_FILE);
+        let text = std::fs::read_to_string(&fpath)
+            .map_err(|_| Error::CannotReadStoreFile(fpath.clone()))?;
+        let (text, changed) = rename_tag_in_text(&text, from, to);
+        if changed > 0 {
+            rename.entries += changed;
+            rename.plan.write(fpath, text);
+        }
+    }
+    Ok(rename)
+}
+
+/// Recursively traverse the directories from `path`, up to `max_depth` levels of
+/// subdirectories, and get all tags.
+pub fn get_all_tags(
+    path: PathBuf,
+    max_depth: Option<usize>,
+    config: &Config,
+) -> Result<impl Iterator<Item = String>, Error> {
+    let mut alltags = HashSet::new();
+    let mut matcher = GlobMatches::new();
+    let mut dir = DirTree::with_archives(
+        path,
+        LoaderOptions::new(
+            true,
+            false,
+            FileLoadingOptions::Load {
+                file_tags: true,
+                file_desc: false,
+            },
+        ),
+        config,
+    )?
+    .limit(&WalkLimits::depth(max_depth));
+    while let Some(VisitedDir {
+        abs_dir_path,
         rel_dir_path,
         metadata,
         files,
@@ -476,22 +2294,37 @@ pub fn get_all_tags(path: PathBuf) -> Result<impl Iterator<Item = String>, Error
                 globs,
                 ..
             }) => {
-                alltags.extend(tags.iter().map(|t| t.to_string()).chain(
-                    infer_implicit_tags(get_filename_str(rel_dir_path)?).map(|t| t.to_string()),
-                ));
-                matcher.find_matches(files, globs, false);
+                alltags.extend(
+                    tags.iter().map(|t| t.to_string()).chain(
+                        config
+                            .implicit_tags(get_filename_str(rel_dir_path)?)
+                            .map(|t| t.to_string()),
+                    ),
+                );
+                matcher.find_matches(files, &globs, false);
                 alltags.extend(
                     files
                         .iter()
                         .enumerate()
                         .filter(|(fi, _f)| matcher.is_file_matched(*fi))
                         .filter_map(|(_fi, f)| f.name().to_str())
-                        .flat_map(|t| infer_implicit_tags(t).map(|t| t.to_string())),
+                        .flat_map(|t| {
+                            config
+                                .implicit_tags(t)
+                                .map(|t| t.to_string())
+                                .chain(config.audio_tags(&abs_dir_path.join(t)))
+                        }),
                 );
             }
             MetaData::NotFound => continue, // No metadata, just pass on the tags to the next dir.
         }
     }
+    // Only list the canonical forms of aliased tags, and include implied tags.
+    let alltags: HashSet<String> = alltags
+        .iter()
+        .flat_map(|t| config.expand(t))
+        .map(|t| t.to_string())
+        .collect();
     Ok(alltags.into_iter())
 }
 
@@ -512,12 +2345,32 @@ fn match_desc(words: &[String], tags: &[&str], desc: Option<&str>) -> bool {
     }
 }
 
-pub fn search(path: PathBuf, needle: &str) -> Result<(), Error> {
+/// Recursively traverse the directories from `path`, and call `visit` with
+/// every file whose tags or description contain any of the words in `needle`.
+/// The search index is used if it is up to date. Returns the state of the
+/// index, so the caller can tell the user to rebuild an index that is out of
+/// date.
+pub fn search<F>(
+    path: PathBuf,
+    needle: &str,
+    config: &Config,
+    mut visit: F,
+) -> Result<IndexState, Error>
+where
+    F: FnMut(&str),
+{
     let words: Vec<_> = needle
         .trim()
         .split(|c: char| !c.is_alphanumeric())
         .map(|word| word.trim().to_lowercase())
         .collect();
+    let state = match SearchIndex::load(&path)? {
+        (state, Some(index)) => {
+            index.search(&words, visit);
+            return Ok(state);
+        }
+        (state, None) => state,
+    };
     let mut dir = DirTree::new(
         path,
         LoaderOptions::new(
@@ -528,24 +2381,391 @@ pub fn search(path: PathBuf, needle: &str) -> Result<(), Error> {
                 file_desc: true,
             },
         ),
+        config,
     )?;
     while let Some(VisitedDir { metadata, .. }) = dir.walk() {
         match metadata {
             MetaData::FailedToLoad(e) => return Err(e),
             MetaData::Ok(data) => {
-                let dirmatch = match_desc(&words, data.tags(), data.desc);
+                let dirmatch = match_desc(&words, data.tags(), data.desc.as_deref());
                 for filepath in data.globs.iter().filter_map(|g| {
-                    if dirmatch || match_desc(&words, g.tags(&data.alltags), g.desc) {
+                    if dirmatch || match_desc(&words, data.glob_tags(g), data.glob_desc(g)) {
                         Some(g.path)
                     } else {
                         None
                     }
                 }) {
-                    println!("{}", filepath);
+                    visit(filepath);
                 }
             }
             MetaData::NotFound => continue, // No metadata, just keep going.
         }
     }
-    Ok(())
+    Ok(state)
+}
+
+/// Call `visit` with `path` and every line of `desc` that matches `regex`. If
+/// `highlight` is true, the matches are highlighted with ANSI colors.
+fn grep_desc<F>(regex: &Regex, path: &Path, desc: &str, highlight: bool, visit: &mut F)
+where
+    F: FnMut(&Path, &str),
+{
+    const HIGHLIGHT_START: &str = "\x1b[1;31m";
+    const HIGHLIGHT_END: &str = "\x1b[0m";
+    for line in desc.lines() {
+        if !highlight {
+            if regex.is_match(line) {
+                visit(path, line);
+            }
+            continue;
+        }
+        let mut out = String::new();
+        let mut last = 0usize;
+        for mat in regex.find_iter(line).filter(|m| !m.is_empty()) {
+            out.push_str(&line[last..mat.start()]);
+            out.push_str(HIGHLIGHT_START);
+            out.push_str(mat.as_str());
+            out.push_str(HIGHLIGHT_END);
+            last = mat.end();
+        }
+        if last > 0 {
+            out.push_str(&line[last..]);
+            visit(path, &out);
+        } else if regex.is_match(line) {
+            // Only empty matches.
+            visit(path, line);
+        }
+    }
+}
+
+/// Recursively traverse the directories from `path`, and call `visit` with
+/// every line of the descriptions of files and directories that matches the
+/// regular expression `pattern`, along with the path it belongs to. If
+/// `highlight` is true, the matches are highlighted with ANSI colors. Like
+/// `search`, this uses the search index if it is up to date, and returns the
+/// state of the index.
+pub fn grep<F>(
+    path: PathBuf,
+    pattern: &str,
+    highlight: bool,
+    config: &Config,
+    mut visit: F,
+) -> Result<IndexState, Error>
+where
+    F: FnMut(&Path, &str),
+{
+    let regex = Regex::new(pattern).map_err(|e| Error::InvalidRegex(e.to_string()))?;
+    let state = match SearchIndex::load(&path)? {
+        (state, Some(index)) => {
+            index.descriptions(|path, desc| grep_desc(&regex, path, desc, highlight, &mut visit));
+            return Ok(state);
+        }
+        (state, None) => state,
+    };
+    let mut dir = DirTree::new(
+        path,
+        LoaderOptions::new(
+            false,
+            true,
+            FileLoadingOptions::Load {
+                file_tags: false,
+                file_desc: true,
+            },
+        ),
+        config,
+    )?;
+    let mut filepath = PathBuf::new();
+    while let Some(VisitedDir {
+        rel_dir_path,
+        metadata,
+        ..
+    }) = dir.walk()
+    {
+        match metadata {
+            MetaData::FailedToLoad(e) => return Err(e),
+            MetaData::Ok(data) => {
+                if let Some(desc) = data.desc.as_deref() {
+                    grep_desc(
+                        &regex,
+                        if rel_dir_path.as_os_str().is_empty() {
+                            Path::new(".")
+                        } else {
+                            rel_dir_path
+                        },
+                        desc,
+                        highlight,
+                        &mut visit,
+                    );
+                }
+                for g in data.globs.iter() {
+                    if let Some(desc) = data.glob_desc(g) {
+                        filepath.clear();
+                        filepath.push(rel_dir_path);
+                        filepath.push(g.path);
+                        grep_desc(&regex, &filepath, desc, highlight, &mut visit);
+                    }
+                }
+            }
+            MetaData::NotFound => continue, // No metadata, just keep going.
+        }
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn t_undo() {
+        let dir = TempDir::new(
+            "undo",
+            &[
+                ("a/.ftag", "[tags]\na\n"),
+                ("b/.ftag", "[tags]\nb\n"),
+                ("c/x.txt", ""),
+            ],
+        );
+        let config = Config::default();
+        let read = |path: &str| std::fs::read_to_string(dir.join(path)).ok();
+        let mut plan = WritePlan::default();
+        plan.write(dir.join("a/.ftag"), "[tags]\na2\n");
+        plan.apply(&config).unwrap();
+        // The last command changes one file and creates another.
+        let mut plan = WritePlan::default();
+        plan.write(dir.join("b/.ftag"), "[tags]\nb2\n");
+        plan.write(dir.join("c/.ftag"), "[tags]\nc\n");
+        plan.apply(&config).unwrap();
+        let undo_in = |path: &Path| {
+            let (plan, mut dirs) = undo(path.to_path_buf(), false, &config).unwrap();
+            plan.apply(&config).unwrap();
+            dirs.sort();
+            dirs
+        };
+        assert_eq!(undo_in(&dir), [PathBuf::from("b"), PathBuf::from("c")]);
+        assert_eq!(read("a/.ftag").as_deref(), Some("[tags]\na2\n"));
+        assert_eq!(read("b/.ftag").as_deref(), Some("[tags]\nb\n"));
+        assert_eq!(read("c/.ftag"), None);
+        // Undoing again redoes the last command.
+        assert_eq!(undo_in(&dir), [PathBuf::from("b"), PathBuf::from("c")]);
+        assert_eq!(read("b/.ftag").as_deref(), Some("[tags]\nb2\n"));
+        assert_eq!(read("c/.ftag").as_deref(), Some("[tags]\nc\n"));
+        // In a subtree, the last command that changed anything in it is undone.
+        assert_eq!(undo_in(&dir.join("a")), [PathBuf::new()]);
+        assert_eq!(read("a/.ftag").as_deref(), Some("[tags]\na\n"));
+        assert_eq!(read("b/.ftag").as_deref(), Some("[tags]\nb2\n"));
+        let (plan, _) = undo(dir.to_path_buf(), true, &config).unwrap();
+        plan.apply(&config).unwrap();
+        for path in ["a/.ftagbak", "b/.ftagbak", ".ftagjournal", "a/.ftagjournal"] {
+            assert_eq!(read(path), None);
+        }
+        assert!(undo(dir.to_path_buf(), false, &config)
+            .unwrap()
+            .0
+            .changes()
+            .is_empty());
+    }
+
+    #[test]
+    fn t_history() {
+        let dir = TempDir::new("history", &[("a/.ftag", "[tags]\na\n"), ("c/x.txt", "")]);
+        let config = Config::default();
+        let mut plan = WritePlan::default();
+        plan.write(dir.join("a/.ftag"), "[tags]\nb\n");
+        plan.write(dir.join("c/.ftag"), "[tags]\nc\n");
+        plan.apply(&config).unwrap();
+        let mut plan = WritePlan::default();
+        plan.remove(dir.join("a/.ftag"));
+        plan.apply(&config).unwrap();
+        let history = history(dir.to_path_buf(), &config).unwrap();
+        assert_eq!(history.iter().map(|h| h.files).collect::<Vec<_>>(), [2, 1]);
+        assert_eq!(
+            history[0].diff,
+            "--- a/a/.ftag\n+++ b/a/.ftag\n@@ -1,2 +1,2 @@\n [tags]\n-a\n+b\n--- /dev/null\n+++ b/c/.ftag\n@@ -0,0 +1,2 @@\n+[tags]\n+c\n"
+        );
+        assert_eq!(
+            history[1].diff,
+            "--- a/.ftag\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-[tags]\n-b\n"
+        );
+        // Only the commands that changed files in the subtree are listed.
+        assert_eq!(super::history(dir.join("c"), &config).unwrap().len(), 1);
+        // Undoing keeps the history, and is a command too.
+        undo(dir.to_path_buf(), false, &config)
+            .unwrap()
+            .0
+            .apply(&config)
+            .unwrap();
+        assert_eq!(super::history(dir.to_path_buf(), &config).unwrap().len(), 3);
+        let entry = HistoryEntry {
+            time: 1_700_000_000,
+            command: String::new(),
+            files: 0,
+            diff: String::new(),
+        };
+        assert_eq!(entry.date(), "2023-11-14 22:13:20");
+    }
+
+    #[test]
+    fn t_init() {
+        let dir = TempDir::new(
+            "init",
+            &[
+                (".ftagconfig", "[implicit]\nfalse\n"),
+                ("a.jpg", ""),
+                ("b.jpg", ""),
+                ("c.jpg", ""),
+                ("d.jpg", ""),
+                ("notes.txt", ""),
+            ],
+        );
+        let config = Config::default();
+        let (plan, globs) = init(dir.to_path_buf(), false, &config).unwrap();
+        assert_eq!(globs, ["*.jpg", "notes.txt"]);
+        plan.apply(&config).unwrap();
+        let read = |path: &str| std::fs::read_to_string(dir.join(path)).unwrap();
+        let scaffold = "[tags]\n\n[path]\n*.jpg\n[tags]\n\n[path]\nnotes.txt\n[tags]\n";
+        assert_eq!(read(FTAG_FILE), scaffold);
+        assert_eq!(read(".ftagconfig"), "[implicit]\nfalse\n");
+        // Running it again doesn't overwrite either file.
+        dir.write(FTAG_FILE, "[tags]\nedited\n");
+        assert!(matches!(
+            init(dir.to_path_buf(), false, &config),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert_eq!(read(FTAG_FILE), "[tags]\nedited\n");
+        assert_eq!(read(".ftagconfig"), "[implicit]\nfalse\n");
+        let (plan, _) = init(dir.to_path_buf(), true, &config).unwrap();
+        plan.apply(&config).unwrap();
+        assert_eq!(read(FTAG_FILE), scaffold);
+    }
+
+    #[test]
+    fn t_ignored_tagged_file() {
+        let dir = TempDir::new(
+            "ignored_tagged_file",
+            &[
+                (".git/HEAD", ""),
+                (".gitignore", "*.log\n"),
+                (".ftag", "[path]\nbuild.log\n[tags]\nimportant\n"),
+                ("build.log", ""),
+                ("other.log", ""),
+            ],
+        );
+        let config = Config::default();
+        assert!(find_problems(dir.to_path_buf(), None, &config)
+            .unwrap()
+            .is_empty());
+        // Cleaning doesn't drop the entry of the ignored file.
+        clean(dir.to_path_buf(), false, &config)
+            .unwrap()
+            .apply(&config)
+            .unwrap();
+        let text = std::fs::read_to_string(dir.join(FTAG_FILE)).unwrap();
+        assert!(text.contains("[path]\nbuild.log\n[tags]\nimportant"));
+    }
+
+    #[test]
+    fn t_clean_comments() {
+        let dir = TempDir::new(
+            "clean_comments",
+            &[
+                (
+                    ".ftag",
+                    "# dir\n[path]\nx.txt\n[tags]\nfoo\n\n# About y\n[path]\ny.txt\n# y\n",
+                ),
+                ("y.txt", ""),
+            ],
+        );
+        let config = Config::default();
+        clean(dir.to_path_buf(), false, &config)
+            .unwrap()
+            .apply(&config)
+            .unwrap();
+        // The comment before the entry of y.txt is not removed with x.txt.
+        assert_eq!(
+            std::fs::read_to_string(dir.join(FTAG_FILE)).unwrap(),
+            "# dir\n\n# About y\n# y\n[path]\ny.txt\n"
+        );
+    }
+
+    #[test]
+    fn t_fix_after_format() {
+        let dir = TempDir::new(
+            "fix_after_format",
+            &[
+                (
+                    ".ftag",
+                    "[tags]\nroot\n[path]\nx.txt\n[tags]\nfoo\n# About y\n[path]\ny.txt\n[tags]\nbar\n",
+                ),
+                ("y.txt", ""),
+            ],
+        );
+        let config = Config::default();
+        let (plan, errors) = format(dir.to_path_buf(), false, &config).unwrap();
+        assert!(errors.is_empty());
+        plan.apply(&config).unwrap();
+        let read = || std::fs::read_to_string(dir.join(FTAG_FILE)).unwrap();
+        assert_eq!(
+            read(),
+            "[tags]\nroot\n\n[path]\nx.txt\n[tags]\nfoo\n\n# About y\n[path]\ny.txt\n[tags]\nbar\n"
+        );
+        // The comment that fmt put before the entry of y.txt stays with it.
+        let problems = find_problems(dir.to_path_buf(), None, &config).unwrap();
+        fix_unmatched_globs(&dir, &problems, false, true)
+            .unwrap()
+            .apply(&config)
+            .unwrap();
+        assert_eq!(
+            read(),
+            "[tags]\nroot\n\n# About y\n[path]\ny.txt\n[tags]\nbar\n"
+        );
+    }
+
+    #[test]
+    fn t_shadowed_tag() {
+        let dir = TempDir::new(
+            "shadowed_tag",
+            &[
+                (
+                    "a/.ftag",
+                    "[path]\nx.txt\n[tags]\nname:alice \"name:bob\" names\n",
+                ),
+                ("a/x.txt", ""),
+            ],
+        );
+        let problems = find_problems(dir.to_path_buf(), None, &Config::default()).unwrap();
+        assert_eq!(
+            problems
+                .iter()
+                .map(|p| (p.code(), p.dirpath(), p.subject()))
+                .collect::<Vec<_>>(),
+            [
+                ("shadowed-tag", Path::new("a"), "name:alice"),
+                ("shadowed-tag", Path::new("a"), "name:bob")
+            ]
+        );
+    }
+
+    #[test]
+    fn t_add_entry() {
+        let dir = TempDir::new(
+            "add_entry",
+            &[("a/.ftag", "[tags]\na\n"), ("a/x.txt", ""), ("b/y.txt", "")],
+        );
+        let config = Config::default();
+        let tags = [String::from("new york"), String::from("trip")];
+        let plan = add_entry(&dir, Path::new("a/x.txt"), &tags, Some("Notes.")).unwrap();
+        plan.apply(&config).unwrap();
+        let plan = add_entry(&dir, Path::new("b/y.txt"), &tags[1..], None).unwrap();
+        plan.apply(&config).unwrap();
+        let read = |path: &str| std::fs::read_to_string(dir.join(path)).unwrap();
+        // The entry is appended to the existing file, or the file is created.
+        assert_eq!(
+            read("a/.ftag"),
+            "[tags]\na\n\n[path]\nx.txt\n[tags]\n\"new york\" trip \n[desc]\nNotes.\n"
+        );
+        assert_eq!(read("b/.ftag"), "\n[path]\ny.txt\n[tags]\ntrip \n");
+        assert!(add_entry(&dir, Path::new(""), &tags, None).is_err());
+    }
 }
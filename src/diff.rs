@@ -0,0 +1,296 @@
+use std::fmt::Write;
+
+/*
+Changes to the .ftag files are shown as unified diffs, the same format as `diff
+-u` and `git diff`, so they can be read by people and applied with `patch`. The
+shortest diff is found with the linear space variant of Myers' algorithm, so
+it works for large files too. Lines that are not followed by a newline are
+compared as if they were. A file that doesn't exist on one side of the diff is
+shown as /dev/null, like `diff -N` does.
+ */
+
+/// Number of unchanged lines shown around the changes.
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Furthest reaching x coordinates of the paths along each diagonal k = x - y,
+/// for k in -max..=max.
+struct Frontier {
+    max: isize,
+    xs: Vec<usize>,
+}
+
+impl Frontier {
+    fn new(max: usize) -> Self {
+        Frontier {
+            max: max as isize,
+            xs: vec![0; 2 * max + 1],
+        }
+    }
+
+    fn get(&self, k: isize) -> usize {
+        self.xs[(k + self.max) as usize]
+    }
+
+    fn set(&mut self, k: isize, x: usize) {
+        self.xs[(k + self.max) as usize] = x;
+    }
+}
+
+fn common_prefix(a: &[&str], b: &[&str]) -> usize {
+    a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
+}
+
+fn common_suffix(a: &[&str], b: &[&str]) -> usize {
+    a.iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count()
+}
+
+/// Find the middle snake of the shortest edit script that turns `a` into `b`,
+/// by extending the paths from both ends until they overlap. Returns where
+/// the snake starts, which splits the edit script into two halves that are
+/// both shorter. `a` and `b` must not be empty, and must not start or end with
+/// the same line.
+fn middle_snake(a: &[&str], b: &[&str], fwd: &mut Frontier, bwd: &mut Frontier) -> (usize, usize) {
+    let (n, m) = (a.len(), b.len());
+    let delta = n as isize - m as isize;
+    let odd = delta & 1 == 1;
+    fwd.set(1, 0);
+    bwd.set(1, 0);
+    for d in 0..=((n + m).div_ceil(2) as isize) {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = match k == -d || (k != d && fwd.get(k - 1) < fwd.get(k + 1)) {
+                true => fwd.get(k + 1),
+                false => fwd.get(k - 1) + 1,
+            };
+            let (x0, y0) = (x, (x as isize - k) as usize);
+            if x < n && y0 < m {
+                x += common_prefix(&a[x..], &b[y0..]);
+            }
+            fwd.set(k, x);
+            if odd && (k - delta).abs() < d && fwd.get(k) + bwd.get(delta - k) >= n {
+                return (x0, y0);
+            }
+        }
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = match k == -d || (k != d && bwd.get(k - 1) < bwd.get(k + 1)) {
+                true => bwd.get(k + 1),
+                false => bwd.get(k - 1) + 1,
+            };
+            let mut y = (x as isize - k) as usize;
+            if x < n && y < m {
+                let advance = common_suffix(&a[..(n - x)], &b[..(m - y)]);
+                x += advance;
+                y += advance;
+            }
+            bwd.set(k, x);
+            if !odd && (k - delta).abs() <= d && bwd.get(k) + fwd.get(delta - k) >= n {
+                return (n - x, m - y);
+            }
+        }
+    }
+    unreachable!("The paths from both ends always overlap.")
+}
+
+/// Append the edit script that turns `a` into `b` to `ops`, by splitting it at
+/// the middle snake and recursing into both halves. This is the linear space
+/// variant of Myers' algorithm, so large files don't need a table of the size
+/// of the product of their lengths.
+fn diff_into(a: &[&str], b: &[&str], fwd: &mut Frontier, bwd: &mut Frontier, ops: &mut Vec<Op>) {
+    let prefix = common_prefix(a, b);
+    ops.extend(std::iter::repeat_n(Op::Equal, prefix));
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = common_suffix(a, b);
+    let (a, b) = (&a[..(a.len() - suffix)], &b[..(b.len() - suffix)]);
+    if a.is_empty() {
+        ops.extend(std::iter::repeat_n(Op::Insert, b.len()));
+    } else if b.is_empty() {
+        ops.extend(std::iter::repeat_n(Op::Delete, a.len()));
+    } else {
+        let (x, y) = middle_snake(a, b, fwd, bwd);
+        diff_into(&a[..x], &b[..y], fwd, bwd, ops);
+        diff_into(&a[x..], &b[y..], fwd, bwd, ops);
+    }
+    ops.extend(std::iter::repeat_n(Op::Equal, suffix));
+}
+
+/// Get the shortest edit script that turns `old` into `new`, as a list of
+/// operations on the lines.
+fn edits(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let max = (old.len() + new.len()) / 2 + 2;
+    let (mut fwd, mut bwd) = (Frontier::new(max), Frontier::new(max));
+    let mut ops = Vec::with_capacity(old.len() + new.len());
+    diff_into(old, new, &mut fwd, &mut bwd, &mut ops);
+    // Show the removed lines before the added lines in every change.
+    for run in ops.chunk_by_mut(|a, b| (*a == Op::Equal) == (*b == Op::Equal)) {
+        run.sort_by_key(|op| *op == Op::Insert);
+    }
+    ops
+}
+
+/// Get the position of a range of `count` lines starting at the 0-based line
+/// `start`, as written in the hunk headers.
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// Write the names of the old and the new file, or /dev/null if they don't
+/// exist.
+fn write_header(out: &mut String, name: &str, old: bool, new: bool) {
+    let side = |exists: bool, prefix: &str| match exists {
+        true => format!("{}/{}", prefix, name),
+        false => String::from("/dev/null"),
+    };
+    let _ = write!(out, "--- {}\n+++ {}\n", side(old, "a"), side(new, "b"));
+}
+
+/// Get the unified diff that turns the text `old` into the text `new`, with
+/// `name` in the headers. A text is `None` if the file doesn't exist. The
+/// diff is empty if the texts have the same lines, and the file exists on
+/// both sides or neither.
+pub fn unified(name: &str, old: Option<&str>, new: Option<&str>) -> String {
+    let mut out = String::new();
+    if old.is_some() != new.is_some() {
+        write_header(&mut out, name, old.is_some(), new.is_some());
+    }
+    let (old, new): (Vec<&str>, Vec<&str>) = (
+        old.unwrap_or_default().lines().collect(),
+        new.unwrap_or_default().lines().collect(),
+    );
+    let ops = edits(&old, &new);
+    // Positions of the operations in the old and the new text.
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut i, mut j) = (0usize, 0usize);
+    for op in &ops {
+        positions.push((i, j));
+        match op {
+            Op::Equal => (i, j) = (i + 1, j + 1),
+            Op::Delete => i += 1,
+            Op::Insert => j += 1,
+        }
+    }
+    positions.push((i, j));
+    let changes: Vec<usize> = (0..ops.len()).filter(|k| ops[*k] != Op::Equal).collect();
+    let mut k = 0usize;
+    while k < changes.len() {
+        // Changes that are close enough are shown in the same hunk.
+        let mut last = k;
+        while last + 1 < changes.len() && changes[last + 1] - changes[last] <= 2 * CONTEXT {
+            last += 1;
+        }
+        let begin = changes[k].saturating_sub(CONTEXT);
+        let end = (changes[last] + CONTEXT + 1).min(ops.len());
+        if out.is_empty() {
+            write_header(&mut out, name, true, true);
+        }
+        let ((i0, j0), (i1, j1)) = (positions[begin], positions[end]);
+        let _ = writeln!(
+            out,
+            "@@ -{} +{} @@",
+            hunk_range(i0, i1 - i0),
+            hunk_range(j0, j1 - j0)
+        );
+        for (op, (i, j)) in ops[begin..end].iter().zip(positions[begin..end].iter()) {
+            let _ = match op {
+                Op::Equal => writeln!(out, " {}", old[*i]),
+                Op::Delete => writeln!(out, "-{}", old[*i]),
+                Op::Insert => writeln!(out, "+{}", new[*j]),
+            };
+        }
+        k = last + 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_unified() {
+        assert_eq!(unified("x", Some("a\nb\n"), Some("a\nb")), "");
+        assert_eq!(
+            unified(
+                ".ftag",
+                Some("[tags]\nfoo bar\n"),
+                Some("[tags]\nbar foo\n")
+            ),
+            "--- a/.ftag\n+++ b/.ftag\n@@ -1,2 +1,2 @@\n [tags]\n-foo bar\n+bar foo\n"
+        );
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n";
+        assert_eq!(
+            unified("f", Some(old), Some(new)),
+            "--- a/f\n+++ b/f\n@@ -1,6 +1,6 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n@@ -9,4 +9,3 @@\n 9\n 10\n 11\n-12\n"
+        );
+        assert_eq!(
+            unified("f", None, Some("[path]\na.txt\n")),
+            "--- /dev/null\n+++ b/f\n@@ -0,0 +1,2 @@\n+[path]\n+a.txt\n"
+        );
+        assert_eq!(
+            unified("f", Some("[path]\na.txt\n"), None),
+            "--- a/f\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-[path]\n-a.txt\n"
+        );
+        assert_eq!(unified("f", Some(""), None), "--- a/f\n+++ /dev/null\n");
+    }
+
+    #[test]
+    fn t_edits() {
+        // The edit scripts are the shortest, and turn the old lines into the new.
+        let (mut seed, mut next) = (7u64, 0u64);
+        let mut random = |n: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            next = (seed >> 33) % n;
+            next as usize
+        };
+        let letters = ["a", "b", "c"];
+        for _ in 0..500 {
+            let old: Vec<&str> = (0..random(12)).map(|_| letters[random(3)]).collect();
+            let new: Vec<&str> = (0..random(12)).map(|_| letters[random(3)]).collect();
+            let ops = edits(&old, &new);
+            let (mut i, mut j, mut out) = (0, 0, Vec::new());
+            for op in &ops {
+                match op {
+                    Op::Equal => {
+                        assert_eq!(old[i], new[j]);
+                        out.push(old[i]);
+                        (i, j) = (i + 1, j + 1);
+                    }
+                    Op::Delete => i += 1,
+                    Op::Insert => {
+                        out.push(new[j]);
+                        j += 1;
+                    }
+                }
+            }
+            assert_eq!((i, j, &out), (old.len(), new.len(), &new));
+            // Length of the longest common subsequence.
+            let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+            for i in 0..old.len() {
+                for j in 0..new.len() {
+                    lcs[i + 1][j + 1] = match old[i] == new[j] {
+                        true => lcs[i][j] + 1,
+                        false => lcs[i][j + 1].max(lcs[i + 1][j]),
+                    };
+                }
+            }
+            let nequal = ops.iter().filter(|op| **op == Op::Equal).count();
+            assert_eq!(nequal, lcs[old.len()][new.len()]);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{
+    config::Config,
+    core::Error,
+    index::{index_state, IndexState},
+    load::{FileLoadingOptions, LoaderOptions},
+    walk::{DirTree, MetaData, VisitedDir},
+};
+use std::path::{Path, PathBuf};
+
+/*
+The doctor checks the environment ftag runs in, and reports the things that
+are likely to go wrong, such as a missing editor or .ftag files that fail to
+parse. Each check produces a diagnostic. Only the diagnostics with the
+`Problem` status mean something is broken. `Missing` means something optional
+is not available, which may be fine.
+ */
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Status {
+    Ok,
+    Missing,
+    Problem,
+}
+
+impl Status {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Missing => "missing",
+            Status::Problem => "problem",
+        }
+    }
+}
+
+pub struct Diagnostic {
+    pub subject: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Diagnostic {
+    fn new(subject: &'static str, status: Status, detail: String) -> Self {
+        Diagnostic {
+            subject,
+            status,
+            detail,
+        }
+    }
+}
+
+/// Find the executable of the program that `command` runs. The program is the
+/// first word of the command, and is looked up in the directories in the
+/// `PATH` environment variable, unless it is a path.
+fn find_executable(command: &str) -> Option<PathBuf> {
+    let program = Path::new(command.split_whitespace().next()?);
+    if program.components().count() > 1 {
+        return Some(program.to_path_buf()).filter(|p| p.is_file());
+    }
+    let mut name = program.as_os_str().to_os_string();
+    name.push(std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+}
+
+/// Check that one of the editors `ftag edit` tries is installed.
+fn check_editor(config: &Config) -> Diagnostic {
+    const SUBJECT: &str = "editor";
+    let env: Vec<String> = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .collect();
+    let found = config
+        .editors()
+        .iter()
+        .chain(env.iter())
+        .find_map(|command| Some((command, find_executable(command)?)));
+    match found {
+        Some((command, path)) => Diagnostic::new(
+            SUBJECT,
+            Status::Ok,
+            format!("'{}' ({})", command, path.display()),
+        ),
+        None if config.gui_edit_fallback() => Diagnostic::new(
+            SUBJECT,
+            Status::Missing,
+            String::from("No editor was found. Files will be edited in the default application of the system."),
+        ),
+        None => Diagnostic::new(
+            SUBJECT,
+            Status::Problem,
+            String::from("No editor was found. Set the EDITOR environment variable, or list your editors in the [editors] section of the config."),
+        ),
+    }
+}
+
+/// Check whether the completions are registered for bash, zsh or fish in any
+/// of the usual places.
+fn check_completions() -> Diagnostic {
+    const SUBJECT: &str = "completions";
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let profiles = [".bashrc", ".bash_profile", ".profile", ".zshrc"]
+        .iter()
+        .filter_map(|name| Some(home.as_ref()?.join(name)));
+    let registered = profiles.into_iter().find(|path| {
+        std::fs::read_to_string(path).is_ok_and(|text| {
+            text.contains("ftag --bash-complete") || text.contains("ftag completions")
+        })
+    });
+    let files = [
+        home.as_ref()
+            .map(|h| h.join(".local/share/bash-completion/completions/ftag")),
+        Some(PathBuf::from("/usr/share/bash-completion/completions/ftag")),
+        Some(PathBuf::from("/etc/bash_completion.d/ftag")),
+        home.as_ref()
+            .map(|h| h.join(".config/fish/completions/ftag.fish")),
+        Some(PathBuf::from(
+            "/usr/share/fish/vendor_completions.d/ftag.fish",
+        )),
+        Some(PathBuf::from("/usr/share/zsh/site-functions/_ftag")),
+    ];
+    match registered.or(files.into_iter().flatten().find(|path| path.is_file())) {
+        Some(path) => Diagnostic::new(SUBJECT, Status::Ok, format!("{}", path.display())),
+        None => Diagnostic::new(
+            SUBJECT,
+            Status::Missing,
+            String::from("Run 'ftag completions <shell>' to get the completion script for bash, zsh or fish."),
+        ),
+    }
+}
+
+/// Check that all the .ftag files in `root` can be parsed.
+fn check_ftag_files(root: &Path, config: &Config) -> Result<Diagnostic, Error> {
+    const SUBJECT: &str = "ftag files";
+    let mut dir = DirTree::new(
+        root.to_path_buf(),
+        LoaderOptions::new(
+            true,
+            true,
+            FileLoadingOptions::Load {
+                file_tags: true,
+                file_desc: true,
+            },
+        ),
+        config,
+    )?
+    .report_failures();
+    let mut nfiles = 0usize;
+    let mut errors = Vec::new();
+    while let Some(VisitedDir { metadata, .. }) = dir.walk() {
+        match metadata {
+            MetaData::Ok(_) => nfiles += 1,
+            MetaData::NotFound => continue,
+            MetaData::FailedToLoad(e) => errors.push(format!("{:?}", e)),
+        }
+    }
+    Ok(match errors.first() {
+        None => Diagnostic::new(SUBJECT, Status::Ok, format!("{} files parsed", nfiles)),
+        Some(first) => Diagnostic::new(
+            SUBJECT,
+            Status::Problem,
+            format!("{} files failed to parse. {}", errors.len(), first),
+        ),
+    })
+}
+
+/// Check whether the search index exists, and is up to date.
+fn check_index(root: &Path) -> Diagnostic {
+    const SUBJECT: &str = "index";
+    match index_state(root) {
+        Ok(IndexState::UpToDate) => {
+            Diagnostic::new(SUBJECT, Status::Ok, String::from("up to date"))
+        }
+        Ok(IndexState::OutOfDate) => Diagnostic::new(
+            SUBJECT,
+            Status::Problem,
+            String::from("The search index is out of date. Run 'ftag index' to update it."),
+        ),
+        Ok(IndexState::Missing) => Diagnostic::new(
+            SUBJECT,
+            Status::Missing,
+            String::from("No search index. Run 'ftag index' to speed up 'search' and 'grep'."),
+        ),
+        Err(e) => Diagnostic::new(SUBJECT, Status::Problem, format!("{:?}", e)),
+    }
+}
+
+/// Check whether an optional external program is installed.
+fn check_program(subject: &'static str) -> Diagnostic {
+    match find_executable(subject) {
+        Some(path) => Diagnostic::new(subject, Status::Ok, format!("{}", path.display())),
+        None => Diagnostic::new(subject, Status::Missing, String::from("not installed")),
+    }
+}
+
+/// Run all the checks on the environment, and the archive at `root`.
+pub fn diagnose(root: &Path, config: &Config) -> Result<Vec<Diagnostic>, Error> {
+    Ok(vec![
+        check_editor(config),
+        check_completions(),
+        check_ftag_files(root, config)?,
+        check_index(root),
+        check_program("ffmpeg"),
+        check_program("exiftool"),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn t_find_executable() {
+        let dir = TempDir::new("doctor", &[("program", "")]);
+        let program = dir.join("program");
+        let command = format!("{} --wait {{}}", program.display());
+        assert_eq!(find_executable(&command), Some(program.clone()));
+        assert_eq!(
+            find_executable(&format!("{}", dir.join("missing").display())),
+            None
+        );
+        assert_eq!(find_executable(""), None);
+    }
+}
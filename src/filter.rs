@@ -1,11 +1,45 @@
-use std::fmt::{Debug, Display};
+use fast_glob::glob_match;
+use std::{
+    borrow::Cow,
+    fmt::{Debug, Display},
+};
+
+/// Prefix of the predicates that match the names of the files against a glob,
+/// such as `name:*.rs`, instead of matching a tag.
+pub(crate) const NAME_PREFIX: &str = "name:";
+/// Matches every file.
+const ALL: &str = "*";
+/// Matches no files.
+const NONE: &str = "none";
+/// Tags that contain these characters, or `..`, may be read as wildcards or
+/// comparisons, so they're quoted when a filter is written back as text.
+const PREDICATE_CHARS: [char; 7] = ['*', '?', '[', '{', '<', '>', '='];
+/// Characters that are operators in a filter, even in the middle of a word.
+const OPERATOR_CHARS: [char; 6] = ['&', '|', '^', '!', '(', ')'];
+
+/// Quote `tag` if it would otherwise be read as something other than that tag
+/// in a filter, such as an operator, a predicate or more than one tag.
+pub fn quote_filter_tag(tag: &str) -> Cow<'_, str> {
+    match tag.starts_with(NAME_PREFIX)
+        || tag == ALL
+        || tag == NONE
+        || tag.contains(PREDICATE_CHARS)
+        || tag.contains(OPERATOR_CHARS)
+        || tag.contains("..")
+        || tag.contains(char::is_whitespace)
+    {
+        true => Cow::Owned(format!("\"{}\"", tag)),
+        false => Cow::Borrowed(tag),
+    }
+}
 
 pub enum FilterParseError {
     EmptyQuery,
     MalformedParens,
-    ExpectedBinaryOperator,
+    MalformedQuotes,
     UnexpectedBinaryOperator(String),
     EndOfTokens,
+    Unsupported(String),
 }
 
 impl Debug for FilterParseError {
@@ -13,57 +47,171 @@ impl Debug for FilterParseError {
         match self {
             FilterParseError::EmptyQuery => write!(f, "The filter string is empty."),
             FilterParseError::MalformedParens => write!(f, "Parentheses are unbalanced."),
-            FilterParseError::ExpectedBinaryOperator => write!(f, "A binary operator is missing."),
+            FilterParseError::MalformedQuotes => write!(f, "Quotes are unbalanced."),
             FilterParseError::UnexpectedBinaryOperator(t) => write!(f, "'{}' was not expected.", t),
             FilterParseError::EndOfTokens => write!(f, "Unexpected end of tokens."),
+            FilterParseError::Unsupported(t) => {
+                write!(f, "'{}' is only supported by the query command.", t)
+            }
         }
     }
 }
 
+#[derive(PartialEq)]
 pub enum Filter {
     Tag(usize),
+    Name(String), // Glob the name of the file must match.
     And(Box<Filter>, Box<Filter>),
     Or(Box<Filter>, Box<Filter>),
+    Xor(Box<Filter>, Box<Filter>),
     Not(Box<Filter>),
     FalseTag, // always false.
     TrueTag,  // Always true.
 }
 use Filter::*;
 
-fn eval_impl<F>(filter: &Filter, checker: &F) -> bool
+fn eval_impl<F>(filter: &Filter, name: &str, checker: &F) -> bool
 where
     F: Fn(usize) -> bool,
 {
     match filter {
         Tag(ti) => checker(*ti),
-        And(lhs, rhs) => eval_impl(lhs, checker) && eval_impl(rhs, checker),
-        Or(lhs, rhs) => eval_impl(lhs, checker) || eval_impl(rhs, checker),
-        Not(input) => !eval_impl(input, checker),
+        Name(glob) => glob_match(glob, name),
+        And(lhs, rhs) => eval_impl(lhs, name, checker) && eval_impl(rhs, name, checker),
+        Or(lhs, rhs) => eval_impl(lhs, name, checker) || eval_impl(rhs, name, checker),
+        Xor(lhs, rhs) => eval_impl(lhs, name, checker) != eval_impl(rhs, name, checker),
+        Not(input) => !eval_impl(input, name, checker),
         FalseTag => false,
         TrueTag => true,
     }
 }
 
+fn eval_partial_impl<F>(filter: &Filter, checker: &F) -> Option<bool>
+where
+    F: Fn(usize) -> Option<bool>,
+{
+    match filter {
+        Tag(ti) => checker(*ti),
+        Name(_) => None,
+        And(lhs, rhs) => match (
+            eval_partial_impl(lhs, checker),
+            eval_partial_impl(rhs, checker),
+        ) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        },
+        Or(lhs, rhs) => match (
+            eval_partial_impl(lhs, checker),
+            eval_partial_impl(rhs, checker),
+        ) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        },
+        Xor(lhs, rhs) => Some(eval_partial_impl(lhs, checker)? != eval_partial_impl(rhs, checker)?),
+        Not(input) => eval_partial_impl(input, checker).map(|v| !v),
+        FalseTag => Some(false),
+        TrueTag => Some(true),
+    }
+}
+
 impl Filter {
+    /// Parse a filter from `input`. `tagmaker` is called with each tag, and
+    /// whether it was quoted. Quoted tags are always plain tags, never
+    /// wildcards, ranges or any other predicate.
     pub fn parse<F>(input: &str, mut tagmaker: F) -> Result<Self, FilterParseError>
     where
-        F: FnMut(&str) -> Filter,
+        F: FnMut(&str, bool) -> Filter,
     {
         parse_filter(input, &mut tagmaker)
     }
 
     fn maybe_parens(parent: &Filter, child: &Filter, childstr: String) -> String {
         match (child, parent) {
-            (Tag(_), _) | (Not(_), _) | (And(_, _), And(_, _)) | (Or(_, _), Or(_, _)) => childstr,
+            (Tag(_), _)
+            | (Name(_), _)
+            | (Not(_), _)
+            | (FalseTag, _)
+            | (TrueTag, _)
+            | (And(_, _), And(_, _))
+            | (Or(_, _), Or(_, _))
+            | (Xor(_, _), Xor(_, _)) => childstr,
             _ => format!("({})", childstr),
         }
     }
 
-    pub fn eval<F>(&self, checker: F) -> bool
+    /// Evaluate the filter for a file with the given `name`. `checker` tells
+    /// whether the file has the tag with the given index.
+    pub fn eval<F>(&self, name: &str, checker: F) -> bool
     where
         F: Fn(usize) -> bool,
     {
-        eval_impl(self, &checker)
+        eval_impl(self, name, &checker)
+    }
+
+    /// Evaluate the filter when only some of the tags are known. `checker`
+    /// tells whether the file has the tag with the given index, or returns
+    /// `None` if that is not known. The result is `None` if it depends on the
+    /// unknown tags, or on the name of the file.
+    pub fn eval_partial<F>(&self, checker: F) -> Option<bool>
+    where
+        F: Fn(usize) -> Option<bool>,
+    {
+        eval_partial_impl(self, &checker)
+    }
+
+    /// Simplify the filter without changing which files pass it. Constants are
+    /// folded, negations are cancelled out, and repeated terms in a chain of
+    /// the same operator are removed, along with the terms absorbed by others,
+    /// such as 'b' in 'a & (a | b)'.
+    pub fn simplify(self) -> Filter {
+        match self {
+            Tag(_) | Name(_) | FalseTag | TrueTag => self,
+            Not(inner) => match inner.simplify() {
+                // De Morgan's laws, where both operands are negated.
+                And(lhs, rhs) if is_not(&lhs) && is_not(&rhs) => {
+                    simplify_chain(Or(Box::new(not_filter(*lhs)), Box::new(not_filter(*rhs))))
+                }
+                Or(lhs, rhs) if is_not(&lhs) && is_not(&rhs) => {
+                    simplify_chain(And(Box::new(not_filter(*lhs)), Box::new(not_filter(*rhs))))
+                }
+                inner => not_filter(inner),
+            },
+            And(lhs, rhs) => {
+                simplify_chain(And(Box::new(lhs.simplify()), Box::new(rhs.simplify())))
+            }
+            Or(lhs, rhs) => simplify_chain(Or(Box::new(lhs.simplify()), Box::new(rhs.simplify()))),
+            Xor(lhs, rhs) => match (lhs.simplify(), rhs.simplify()) {
+                (FalseTag, other) | (other, FalseTag) => other,
+                (TrueTag, other) | (other, TrueTag) => not_filter(other),
+                (lhs, rhs) if lhs == rhs => FalseTag,
+                (lhs, rhs) if complementary(&lhs, &rhs) => TrueTag,
+                (lhs, rhs) => Xor(Box::new(lhs), Box::new(rhs)),
+            },
+        }
+    }
+
+    /// Get the tags that every item must have to pass this filter. The
+    /// returned indices are sorted.
+    pub fn required_tags(&self) -> Vec<usize> {
+        match self {
+            Tag(ti) => vec![*ti],
+            And(lhs, rhs) => {
+                let mut tags = lhs.required_tags();
+                tags.extend(rhs.required_tags());
+                tags.sort_unstable();
+                tags.dedup();
+                tags
+            }
+            Or(lhs, rhs) => {
+                let rtags = rhs.required_tags();
+                let mut tags = lhs.required_tags();
+                tags.retain(|t| rtags.binary_search(t).is_ok());
+                tags
+            }
+            Name(_) | Xor(_, _) | Not(_) | FalseTag | TrueTag => Vec::new(),
+        }
     }
 
     pub fn text<T>(&self, tagnames: &[T]) -> String
@@ -71,7 +219,8 @@ impl Filter {
         T: Display,
     {
         match self {
-            Tag(i) => tagnames[*i].to_string(),
+            Tag(i) => quote_filter_tag(&tagnames[*i].to_string()).into_owned(),
+            Name(glob) => format!("{}{}", NAME_PREFIX, glob),
             And(lhs, rhs) => format!(
                 "{} & {}",
                 Self::maybe_parens(self, lhs, lhs.text(tagnames)),
@@ -82,12 +231,17 @@ impl Filter {
                 Self::maybe_parens(self, lhs, lhs.text(tagnames)),
                 Self::maybe_parens(self, rhs, rhs.text(tagnames))
             ),
+            Xor(lhs, rhs) => format!(
+                "{} ^ {}",
+                Self::maybe_parens(self, lhs, lhs.text(tagnames)),
+                Self::maybe_parens(self, rhs, rhs.text(tagnames))
+            ),
             Not(filter) => format!(
                 "!{}",
                 Self::maybe_parens(self, filter, filter.text(tagnames))
             ),
-            FalseTag => String::from("NOT_A_TAG"),
-            TrueTag => String::from("ALL_TAGS"),
+            FalseTag => String::from(NONE),
+            TrueTag => String::from(ALL),
         }
     }
 }
@@ -96,6 +250,7 @@ impl Display for Filter {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Tag(tag) => write!(f, "{}", tag),
+            Name(glob) => write!(f, "{}{}", NAME_PREFIX, glob),
             And(lhs, rhs) => write!(
                 f,
                 "{} & {}",
@@ -108,13 +263,19 @@ impl Display for Filter {
                 Self::maybe_parens(self, lhs, lhs.to_string()),
                 Self::maybe_parens(self, rhs, rhs.to_string()),
             ),
+            Xor(lhs, rhs) => write!(
+                f,
+                "{} ^ {}",
+                Self::maybe_parens(self, lhs, lhs.to_string()),
+                Self::maybe_parens(self, rhs, rhs.to_string()),
+            ),
             Not(filter) => write!(
                 f,
                 "!{}",
                 Self::maybe_parens(self, filter, filter.to_string())
             ),
-            FalseTag => write!(f, "FALSE_TAG"),
-            TrueTag => write!(f, "TRUE_TAG"),
+            FalseTag => write!(f, "{}", NONE),
+            TrueTag => write!(f, "{}", ALL),
         }
     }
 }
@@ -122,6 +283,7 @@ impl Display for Filter {
 enum Token {
     And,
     Or,
+    Xor,
     Not,
     Parsed(Filter),
 }
@@ -131,6 +293,7 @@ impl Display for Token {
         match self {
             Token::And => write!(f, "&"),
             Token::Or => write!(f, "|"),
+            Token::Xor => write!(f, "^"),
             Token::Not => write!(f, "!"),
             Token::Parsed(p) => write!(f, "{}", p),
         }
@@ -141,7 +304,7 @@ impl Display for Token {
 /// strings corresponding to the tags.
 fn parse_filter<F>(input: &str, tagmaker: &mut F) -> Result<Filter, FilterParseError>
 where
-    F: FnMut(&str) -> Filter,
+    F: FnMut(&str, bool) -> Filter,
 {
     if input.is_empty() {
         return Err(FilterParseError::EmptyQuery);
@@ -150,9 +313,27 @@ where
     let mut parens: Vec<usize> = Vec::new();
     let mut begin: usize = 0;
     let mut end = 0;
+    let mut quoted = false;
     for (i, c) in input.char_indices() {
         end = i;
+        if quoted {
+            if c == '"' {
+                // Quoted tags are never predicates.
+                let tag = input[begin..i].trim();
+                if !tag.is_empty() {
+                    stack.push(Token::Parsed(tagmaker(tag, true)));
+                }
+                begin = i + 1;
+                quoted = false;
+            }
+            continue;
+        }
         match c {
+            '"' => {
+                push_tag(input, begin, i, &mut stack, tagmaker);
+                begin = i + 1;
+                quoted = true;
+            }
             '(' => {
                 parens.push(stack.len());
                 begin = i + 1;
@@ -184,6 +365,11 @@ where
                 begin = i + 1;
                 stack.push(Token::Or);
             }
+            '^' => {
+                push_tag(input, begin, i, &mut stack, tagmaker);
+                begin = i + 1;
+                stack.push(Token::Xor);
+            }
             _ if c.is_whitespace() => {
                 push_tag(input, begin, i, &mut stack, tagmaker);
                 begin = i + 1;
@@ -194,18 +380,28 @@ where
     if !parens.is_empty() {
         return Err(FilterParseError::MalformedParens);
     }
+    if quoted {
+        return Err(FilterParseError::MalformedQuotes);
+    }
     push_tag(input, begin, end + 1, &mut stack, tagmaker);
     parse_tokens(stack.into_iter())
 }
 
-/// Reduce the iterator of tokens into a filter.
+/// Reduce the iterator of tokens into a filter. Consecutive filters without
+/// a binary operator between them are combined with an implicit and. All the
+/// binary operators have the same precedence, and are applied from left to
+/// right.
 fn parse_tokens<I: Iterator<Item = Token>>(mut iter: I) -> Result<Filter, FilterParseError> {
     let mut filter = next_filter(&mut iter)?;
     while let Some(t) = iter.next() {
         filter = match t {
             Token::And => Filter::And(Box::new(filter), Box::new(next_filter(&mut iter)?)),
             Token::Or => Filter::Or(Box::new(filter), Box::new(next_filter(&mut iter)?)),
-            Token::Not | Token::Parsed(_) => return Err(FilterParseError::ExpectedBinaryOperator),
+            Token::Xor => Filter::Xor(Box::new(filter), Box::new(next_filter(&mut iter)?)),
+            Token::Not | Token::Parsed(_) => Filter::And(
+                Box::new(filter),
+                Box::new(next_filter(&mut std::iter::once(t).chain(&mut iter))?),
+            ),
         };
     }
     Ok(filter)
@@ -215,7 +411,7 @@ fn parse_tokens<I: Iterator<Item = Token>>(mut iter: I) -> Result<Filter, Filter
 fn next_filter<I: Iterator<Item = Token>>(iter: &mut I) -> Result<Filter, FilterParseError> {
     match iter.next() {
         Some(t) => match t {
-            Token::And | Token::Or => {
+            Token::And | Token::Or | Token::Xor => {
                 Err(FilterParseError::UnexpectedBinaryOperator(t.to_string()))
             }
             Token::Not => Ok(not_filter(next_filter(iter)?)),
@@ -230,21 +426,108 @@ fn next_filter<I: Iterator<Item = Token>>(iter: &mut I) -> Result<Filter, Filter
 /// `!!something` into `something`.
 fn not_filter(filter: Filter) -> Filter {
     match filter {
-        Tag(_) | And(_, _) | Or(_, _) => Filter::Not(Box::new(filter)),
+        Tag(_) | Name(_) | And(_, _) | Or(_, _) | Xor(_, _) => Filter::Not(Box::new(filter)),
         Not(inner) => *inner,
         FalseTag => TrueTag,
         TrueTag => FalseTag,
     }
 }
 
+fn is_not(filter: &Filter) -> bool {
+    matches!(filter, Not(_))
+}
+
+/// Check if one of the filters is the negation of the other.
+fn complementary(a: &Filter, b: &Filter) -> bool {
+    match (a, b) {
+        (Not(inner), other) | (other, Not(inner)) => **inner == *other,
+        _ => false,
+    }
+}
+
+/// Get the operands of a chain of the same operator, such as 'a', 'b' and 'c'
+/// in '(a & b) & c'. Anything else is a chain of one operand.
+fn operands(filter: &Filter, and: bool) -> Vec<&Filter> {
+    match (filter, and) {
+        (And(lhs, rhs), true) | (Or(lhs, rhs), false) => {
+            let mut out = operands(lhs, and);
+            out.extend(operands(rhs, and));
+            out
+        }
+        _ => vec![filter],
+    }
+}
+
+fn take_operands(filter: Filter, and: bool, out: &mut Vec<Filter>) {
+    match (filter, and) {
+        (And(lhs, rhs), true) | (Or(lhs, rhs), false) => {
+            take_operands(*lhs, and, out);
+            take_operands(*rhs, and, out);
+        }
+        (filter, _) => out.push(filter),
+    }
+}
+
+/// Simplify a chain of and or or operators, whose operands are already
+/// simplified.
+fn simplify_chain(filter: Filter) -> Filter {
+    let and = matches!(filter, And(_, _));
+    let (identity, absorbing) = match and {
+        true => (TrueTag, FalseTag),
+        false => (FalseTag, TrueTag),
+    };
+    let mut terms = Vec::new();
+    take_operands(filter, and, &mut terms);
+    let mut unique: Vec<Filter> = Vec::with_capacity(terms.len());
+    for term in terms {
+        if term == absorbing || unique.iter().any(|t| complementary(t, &term)) {
+            return absorbing;
+        }
+        if term != identity && !unique.contains(&term) {
+            unique.push(term);
+        }
+    }
+    // A term of the other operator is absorbed by any of its operands, such as
+    // 'a | b' in 'a & (a | b)'.
+    let absorbed: Vec<bool> = unique
+        .iter()
+        .enumerate()
+        .map(|(i, term)| {
+            let inner = operands(term, !and);
+            inner.len() > 1
+                && unique
+                    .iter()
+                    .enumerate()
+                    .any(|(j, other)| i != j && inner.contains(&other))
+        })
+        .collect();
+    unique
+        .into_iter()
+        .zip(absorbed)
+        .filter(|(_, absorbed)| !absorbed)
+        .map(|(term, _)| term)
+        .reduce(|lhs, rhs| match and {
+            true => And(Box::new(lhs), Box::new(rhs)),
+            false => Or(Box::new(lhs), Box::new(rhs)),
+        })
+        .unwrap_or(identity)
+}
+
 /// Push the tag into the vector of tokens. The tag-data is created using the
-/// tag maker.
+/// tag maker, unless the tag is a predicate on the names of the files, or one
+/// of the terminals that match all or none of the files.
 fn push_tag<F>(input: &str, from: usize, to: usize, tokens: &mut Vec<Token>, tagmaker: &mut F)
 where
-    F: FnMut(&str) -> Filter,
+    F: FnMut(&str, bool) -> Filter,
 {
     if to > from {
-        tokens.push(Token::Parsed(tagmaker(&input[from..to])));
+        let tag = &input[from..to];
+        tokens.push(Token::Parsed(match tag.strip_prefix(NAME_PREFIX) {
+            Some(glob) if !glob.is_empty() => Filter::Name(glob.to_string()),
+            _ if tag == ALL => Filter::TrueTag,
+            _ if tag == NONE => Filter::FalseTag,
+            _ => tagmaker(tag, false),
+        }));
     }
 }
 
@@ -260,9 +543,15 @@ mod test {
             "(apple & mango) | banana",
             "(apple & mango) | !banana",
             "(apple & pear) | !(banana & !pear) | (fig & grape)",
+            "\"new york\" & (photo | !\"san francisco\")",
+            "apple ^ banana ^ mango",
+            "(apple ^ banana) | !(mango ^ pear)",
+            "* & !apple",
+            "none | (apple ^ *)",
+            "\"none\" & \"*\"",
         ] {
             let mut map = BTreeMap::<String, usize>::new();
-            let filter = Filter::parse(fstr, |tag| {
+            let filter = Filter::parse(fstr, |tag, _| {
                 let size = map.len();
                 let idx = *map.entry(tag.to_string()).or_insert(size);
                 Filter::Tag(idx)
@@ -270,13 +559,234 @@ mod test {
             .unwrap();
             let tagnames: Box<[_]> = {
                 let mut pairs: Vec<_> = map.into_iter().collect();
-                pairs.sort_by(|(_ta, ia), (_tb, ib)| ia.cmp(ib));
+                pairs.sort_by_key(|(_t, i)| *i);
                 pairs.into_iter().map(|(t, _i)| t).collect()
             };
             assert_eq!(filter.text(&tagnames), fstr);
         }
     }
 
+    #[test]
+    fn t_implicit_and() {
+        for (before, after) in [
+            ("apple banana", "apple & banana"),
+            ("apple banana mango", "apple & banana & mango"),
+            ("apple !banana", "apple & !banana"),
+            ("apple (banana | mango)", "apple & (banana | mango)"),
+            ("(apple | pear) banana", "(apple | pear) & banana"),
+            ("apple banana | mango", "(apple & banana) | mango"),
+            ("apple | banana mango", "(apple | banana) & mango"),
+            ("apple ^ banana mango", "(apple ^ banana) & mango"),
+            ("apple & banana ^ mango", "(apple & banana) ^ mango"),
+            ("apple ^ banana | mango", "(apple ^ banana) | mango"),
+            ("apple | banana ^ mango", "(apple | banana) ^ mango"),
+        ] {
+            let mut map = BTreeMap::<String, usize>::new();
+            let filter = Filter::parse(before, |tag, _| {
+                let size = map.len();
+                Filter::Tag(*map.entry(tag.to_string()).or_insert(size))
+            })
+            .unwrap();
+            let tagnames: Box<[_]> = {
+                let mut pairs: Vec<_> = map.into_iter().collect();
+                pairs.sort_by_key(|(_t, i)| *i);
+                pairs.into_iter().map(|(t, _i)| t).collect()
+            };
+            assert_eq!(filter.text(&tagnames), after);
+        }
+    }
+
+    #[test]
+    fn t_required_tags() {
+        for (fstr, required) in [
+            ("apple", vec!["apple"]),
+            ("apple & banana", vec!["apple", "banana"]),
+            ("apple | banana", vec![]),
+            ("(apple & banana) | (apple & mango)", vec!["apple"]),
+            ("apple & !banana", vec!["apple"]),
+            ("!(apple & banana)", vec![]),
+        ] {
+            let mut map = BTreeMap::<String, usize>::new();
+            let filter = Filter::parse(fstr, |tag, _| {
+                let size = map.len();
+                Filter::Tag(*map.entry(tag.to_string()).or_insert(size))
+            })
+            .unwrap();
+            let expected: Vec<usize> = required.iter().map(|t| map[*t]).collect();
+            assert_eq!(filter.required_tags(), expected);
+        }
+    }
+
+    #[test]
+    fn t_eval_partial() {
+        for (fstr, expected) in [
+            ("apple", Some(true)),
+            ("banana", None),
+            ("!apple", Some(false)),
+            ("!apple & banana", Some(false)),
+            ("apple | banana", Some(true)),
+            ("apple & banana", None),
+            ("apple ^ banana", None),
+            ("apple ^ *", Some(false)),
+            ("!apple | name:*.rs", None),
+            ("none | (banana & !apple)", Some(false)),
+        ] {
+            let mut map = BTreeMap::<String, usize>::new();
+            let filter = Filter::parse(fstr, |tag, _| {
+                let size = map.len();
+                Filter::Tag(*map.entry(tag.to_string()).or_insert(size))
+            })
+            .unwrap();
+            let apple = map.get("apple").copied();
+            assert_eq!(
+                filter.eval_partial(|ti| (Some(ti) == apple).then_some(true)),
+                expected,
+                "{fstr}"
+            );
+        }
+    }
+
+    #[test]
+    fn t_name_filter() {
+        let mut map = BTreeMap::<String, usize>::new();
+        let filter = Filter::parse("rust & name:*.rs | !name:main.*", |tag, _| {
+            let size = map.len();
+            Filter::Tag(*map.entry(tag.to_string()).or_insert(size))
+        })
+        .unwrap();
+        assert_eq!(filter.text(&["rust"]), "(rust & name:*.rs) | !name:main.*");
+        assert!(filter.eval("lib.rs", |_| true));
+        assert!(!filter.eval("main.rs", |_| false));
+        assert!(filter.eval("main.rs", |_| true));
+        assert!(filter.eval("notes.txt", |_| true));
+        assert!(!filter.eval("main.txt", |_| true));
+    }
+
+    #[test]
+    fn t_quoted_tags() {
+        let mut map = BTreeMap::<String, usize>::new();
+        let filter = Filter::parse("\"new york\"photo|\"name:x\"", |tag, _| {
+            let size = map.len();
+            Filter::Tag(*map.entry(tag.to_string()).or_insert(size))
+        })
+        .unwrap();
+        assert_eq!(
+            map.keys().collect::<Vec<_>>(),
+            vec!["name:x", "new york", "photo"]
+        );
+        assert_eq!(
+            filter.text(&["new york", "photo", "name:x"]),
+            "(\"new york\" & photo) | \"name:x\""
+        );
+        assert!(matches!(
+            Filter::parse("\"new york & photo", |_, _| Filter::TrueTag),
+            Err(FilterParseError::MalformedQuotes)
+        ));
+        // The tagmaker is told which tags were quoted, so they're never read
+        // as predicates, and they stay quoted when written back.
+        let mut tags = Vec::new();
+        let filter = Filter::parse("person:* \"person:*\" \"2010..2015\"", |tag, quoted| {
+            tags.push((tag.to_string(), quoted));
+            Filter::Tag(tags.len() - 1)
+        })
+        .unwrap();
+        assert_eq!(
+            tags,
+            [
+                (String::from("person:*"), false),
+                (String::from("person:*"), true),
+                (String::from("2010..2015"), true)
+            ]
+        );
+        assert_eq!(
+            filter.text(&["a", "person:*", "2010..2015"]),
+            "a & \"person:*\" & \"2010..2015\""
+        );
+        // Tags with operators in them are quoted too.
+        let filter = Filter::parse("\"x^2\" \"r&d\"", |tag, _| {
+            Filter::Tag(usize::from(tag == "r&d"))
+        })
+        .unwrap();
+        assert_eq!(filter.text(&["x^2", "r&d"]), "\"x^2\" & \"r&d\"");
+        assert_eq!(quote_filter_tag("photo"), "photo");
+    }
+
+    #[test]
+    fn t_xor_all_none() {
+        let filter = Filter::parse("a ^ b", |tag, _| Filter::Tag((tag == "b") as usize)).unwrap();
+        assert!(!filter.eval("", |_| false));
+        assert!(filter.eval("", |ti| ti == 0));
+        assert!(filter.eval("", |ti| ti == 1));
+        assert!(!filter.eval("", |_| true));
+        assert!(filter.required_tags().is_empty());
+        let all = Filter::parse("*", |_, _| Filter::FalseTag).unwrap();
+        assert!(all.eval("", |_| false));
+        let none = Filter::parse("none", |_, _| Filter::TrueTag).unwrap();
+        assert!(!none.eval("", |_| true));
+        assert!(!Filter::parse("!*", |_, _| Filter::TrueTag)
+            .unwrap()
+            .eval("", |_| true));
+        // Only a bare '*' is a terminal.
+        let mut tags = Vec::new();
+        Filter::parse("person:* name:*.rs \"none\"", |tag, _| {
+            tags.push(tag.to_string());
+            Filter::TrueTag
+        })
+        .unwrap();
+        assert_eq!(tags, ["person:*", "none"]);
+        assert!(matches!(
+            Filter::parse("^ apple", |_, _| Filter::TrueTag),
+            Err(FilterParseError::UnexpectedBinaryOperator(_))
+        ));
+    }
+
+    #[test]
+    fn t_simplify() {
+        for (before, after) in [
+            ("apple & apple", "apple"),
+            ("(apple & banana) & (banana & apple)", "apple & banana"),
+            (
+                "(apple | banana) & (apple | banana) & mango",
+                "(apple | banana) & mango",
+            ),
+            ("apple & !apple", "none"),
+            ("apple | banana | !apple", "*"),
+            ("apple & * & banana", "apple & banana"),
+            ("apple & (banana | none)", "apple & banana"),
+            ("apple | (banana & none)", "apple"),
+            ("apple & (apple | banana)", "apple"),
+            ("(apple & banana) | apple | mango", "apple | mango"),
+            ("!(!apple & !banana)", "apple | banana"),
+            ("!(!apple | !banana)", "apple & banana"),
+            ("!(!apple | banana)", "!(!apple | banana)"),
+            ("!!(apple & apple)", "apple"),
+            ("apple ^ apple", "none"),
+            ("apple ^ !apple", "*"),
+            ("apple ^ * ^ banana", "!apple ^ banana"),
+            (
+                "(apple & banana) | (apple & mango)",
+                "(apple & banana) | (apple & mango)",
+            ),
+            (
+                "(((apple) & banana) & (apple)) & ((apple & banana) & apple)",
+                "apple & banana",
+            ),
+        ] {
+            let mut map = BTreeMap::<String, usize>::new();
+            let filter = Filter::parse(before, |tag, _| {
+                let size = map.len();
+                Filter::Tag(*map.entry(tag.to_string()).or_insert(size))
+            })
+            .unwrap();
+            let tagnames: Box<[_]> = {
+                let mut pairs: Vec<_> = map.into_iter().collect();
+                pairs.sort_by_key(|(_t, i)| *i);
+                pairs.into_iter().map(|(t, _i)| t).collect()
+            };
+            assert_eq!(filter.simplify().text(&tagnames), after);
+        }
+    }
+
     #[test]
     fn t_not_not_filter() {
         for (before, after) in [
@@ -289,14 +799,14 @@ mod test {
             ("!!(!!(!(!apple)))", "apple"),
         ] {
             let mut map = BTreeMap::<String, usize>::new();
-            let filter = Filter::parse(before, |tag| {
+            let filter = Filter::parse(before, |tag, _| {
                 let size = map.len();
                 Filter::Tag(*map.entry(tag.to_string()).or_insert(size))
             })
             .unwrap();
             let tagnames: Box<[_]> = {
                 let mut pairs: Vec<_> = map.into_iter().collect();
-                pairs.sort_by(|(_ta, ia), (_tb, ib)| ia.cmp(ib));
+                pairs.sort_by_key(|(_t, i)| *i);
                 pairs.into_iter().map(|(t, _i)| t).collect()
             };
             assert_eq!(filter.text(&tagnames), after);
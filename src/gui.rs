@@ -1,11 +1,16 @@
-use clap::{command, value_parser, Arg};
+use clap::{command, value_parser, Arg, ArgAction};
 use egui::text::{CCursor, CCursorRange};
 use ftag::{
+    config::Config,
     core::Error,
     interactive::{InteractiveSession, State},
-    query::TagTable,
+    query::{group_by_namespace, TableLoader, TagGroupItem},
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
 };
-use std::path::{Path, PathBuf};
 
 fn main() -> Result<(), Error> {
     let matches = command!()
@@ -16,6 +21,12 @@ fn main() -> Result<(), Error> {
                 .required(false)
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(
+            Arg::new("no-implicit")
+                .long("no-implicit")
+                .action(ArgAction::SetTrue)
+                .help("Don't infer any implicit tags, such as years and formats, from the names of the files."),
+        )
         .get_matches();
     let current_dir = if let Some(rootdir) = matches.get_one::<PathBuf>("path") {
         rootdir
@@ -24,7 +35,11 @@ fn main() -> Result<(), Error> {
     } else {
         std::env::current_dir().map_err(|_| Error::InvalidWorkingDirectory)?
     };
-    let table = TagTable::from_dir(current_dir)?;
+    let mut config = Config::load(&current_dir)?;
+    if matches.get_flag("no-implicit") {
+        config.set_no_implicit_tags();
+    }
+    let (first, loader) = TableLoader::start(current_dir, &config)?;
     let options = eframe::NativeOptions {
         follow_system_theme: true,
         viewport: egui::ViewportBuilder::default().with_maximized(true),
@@ -37,22 +52,198 @@ fn main() -> Result<(), Error> {
             let ctx = &cc.egui_ctx;
             ctx.set_pixels_per_point(1.2);
             egui_extras::install_image_loaders(ctx);
+            // Added last, so that it takes precedence over the default loaders.
+            ctx.add_image_loader(Arc::new(ThumbnailLoader::new(ctx.clone())));
             Ok(Box::from(GuiApp {
-                session: InteractiveSession::init(table),
-                page_index: 0,
-                num_pages: 1,
+                loader: (!first.is_done()).then_some(loader),
+                progress: (first.loaded, first.total),
+                panes: vec![Pane::new(InteractiveSession::init(first.table, config))],
+                active: 0,
+                frame: 0,
+                last_visible: HashMap::new(),
             }))
         }),
     )
-    .map_err(Error::GUIFailure)
+    .map_err(|e| Error::GUIFailure(e.to_string()))
 }
 
-struct GuiApp {
+/// A grid of files with its own filter. The view can be split into two panes
+/// to compare the results of two filters side by side.
+struct Pane {
     session: InteractiveSession,
     page_index: usize,
     num_pages: usize,
 }
 
+impl Pane {
+    fn new(session: InteractiveSession) -> Self {
+        Pane {
+            session,
+            page_index: 0,
+            num_pages: 1,
+        }
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "{}: {} results, page {} of {}",
+            if self.session.filter_str().is_empty() {
+                "*"
+            } else {
+                self.session.filter_str()
+            },
+            self.session.filelist().len(),
+            self.page_index + 1,
+            self.num_pages
+        )
+    }
+}
+
+struct GuiApp {
+    panes: Vec<Pane>,
+    active: usize, // Index of the pane that receives the commands.
+    frame: usize,
+    last_visible: HashMap<String, usize>, // Image uri -> last frame it was visible in.
+    loader: Option<TableLoader>,          // Loads the rest of the table in the background.
+    progress: (usize, usize),             // Top level directories loaded, and the total.
+}
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "bmp", "webp"];
+const THUMBNAIL_SIZE: u32 = 512;
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+fn image_uri(path: &Path) -> String {
+    format!("file://{}", path.display())
+}
+
+enum Thumbnail {
+    Pending,
+    Ready(Arc<egui::ColorImage>),
+    Failed(String),
+}
+
+/// Image loader that decodes the images on background threads and scales
+/// them down to thumbnails, so the GUI doesn't freeze while the images of a
+/// page are loading.
+struct ThumbnailLoader {
+    cache: Arc<Mutex<HashMap<String, Thumbnail>>>,
+    sender: mpsc::Sender<String>,
+}
+
+impl ThumbnailLoader {
+    fn new(ctx: egui::Context) -> Self {
+        let cache = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, receiver) = mpsc::channel::<String>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let nthreads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        for _ in 0..nthreads {
+            let (cache, receiver, ctx) = (cache.clone(), receiver.clone(), ctx.clone());
+            std::thread::spawn(move || loop {
+                // The lock is released before decoding, so the threads can
+                // decode images concurrently.
+                let uri = match receiver.lock().map(|r| r.recv()) {
+                    Ok(Ok(uri)) => uri,
+                    _ => return, // The loader was dropped.
+                };
+                let thumbnail = match Self::decode(Path::new(&uri["file://".len()..])) {
+                    Ok(image) => Thumbnail::Ready(Arc::new(image)),
+                    Err(e) => Thumbnail::Failed(e.to_string()),
+                };
+                // The image may have been forgotten while it was decoded.
+                if let Some(entry @ Thumbnail::Pending) =
+                    cache.lock().as_mut().ok().and_then(|c| c.get_mut(&uri))
+                {
+                    *entry = thumbnail;
+                }
+                ctx.request_repaint();
+            });
+        }
+        ThumbnailLoader { cache, sender }
+    }
+
+    fn decode(path: &Path) -> Result<egui::ColorImage, image::ImageError> {
+        let mut image = image::ImageReader::open(path)?
+            .with_guessed_format()?
+            .decode()?;
+        if image.width() > THUMBNAIL_SIZE || image.height() > THUMBNAIL_SIZE {
+            image = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+        }
+        let image = image.into_rgba8();
+        Ok(egui::ColorImage::from_rgba_unmultiplied(
+            [image.width() as usize, image.height() as usize],
+            image.as_raw(),
+        ))
+    }
+}
+
+impl egui::load::ImageLoader for ThumbnailLoader {
+    fn id(&self) -> &str {
+        "ftag::ThumbnailLoader"
+    }
+
+    fn load(
+        &self,
+        _ctx: &egui::Context,
+        uri: &str,
+        _size_hint: egui::load::SizeHint,
+    ) -> egui::load::ImageLoadResult {
+        use egui::load::{ImagePoll, LoadError};
+        match uri.strip_prefix("file://") {
+            Some(path) if is_image(Path::new(path)) => {}
+            _ => return Err(LoadError::NotSupported),
+        }
+        let mut cache = self
+            .cache
+            .lock()
+            .map_err(|e| LoadError::Loading(e.to_string()))?;
+        match cache.get(uri) {
+            Some(Thumbnail::Ready(image)) => Ok(ImagePoll::Ready {
+                image: image.clone(),
+            }),
+            Some(Thumbnail::Failed(e)) => Err(LoadError::Loading(e.clone())),
+            Some(Thumbnail::Pending) => Ok(ImagePoll::Pending { size: None }),
+            None => {
+                self.sender
+                    .send(uri.to_string())
+                    .map_err(|e| LoadError::Loading(e.to_string()))?;
+                cache.insert(uri.to_string(), Thumbnail::Pending);
+                Ok(ImagePoll::Pending { size: None })
+            }
+        }
+    }
+
+    fn forget(&self, uri: &str) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.remove(uri);
+        }
+    }
+
+    fn forget_all(&self) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.clear();
+        }
+    }
+
+    fn byte_size(&self) -> usize {
+        self.cache.lock().map_or(0, |cache| {
+            cache
+                .values()
+                .map(|t| match t {
+                    Thumbnail::Ready(image) => {
+                        image.pixels.len() * std::mem::size_of::<egui::Color32>()
+                    }
+                    Thumbnail::Pending | Thumbnail::Failed(_) => 0,
+                })
+                .sum()
+        })
+    }
+}
+
 const DESIRED_ROW_HEIGHT: f32 = 200.;
 const DESIRED_COL_WIDTH: f32 = 200.;
 const ICON_MAX_HEIGHT: f32 = DESIRED_ROW_HEIGHT * 0.5;
@@ -69,9 +260,9 @@ impl GuiApp {
             Other,
         }
         let ftype = match abspath.extension() {
+            _ if is_image(abspath) => FileType::Image,
             Some(ext) => match ext.to_ascii_lowercase().to_str() {
                 Some(ext) => match ext {
-                    "png" | "jpg" | "jpeg" | "bmp" | "webp" => FileType::Image,
                     "pdf" => FileType::PdfDocument,
                     "mov" | "flv" | "mp4" | "3gp" => FileType::Video,
                     _ => FileType::Other,
@@ -82,7 +273,7 @@ impl GuiApp {
         };
         match ftype {
             FileType::Image => ui.add(
-                egui::Image::from_uri(format!("file://{}", abspath.display()))
+                egui::Image::from_uri(image_uri(abspath))
                     .rounding(10.)
                     .show_loading_spinner(true)
                     .maintain_aspect_ratio(true)
@@ -142,7 +333,67 @@ impl GuiApp {
         }
     }
 
-    fn render_grid_preview(&mut self, ui: &mut egui::Ui) {
+    fn session(&self) -> &InteractiveSession {
+        &self.panes[self.active].session
+    }
+
+    /// Show the latest snapshot of the table that is loaded in the background
+    /// in all the panes, if there is a new one.
+    fn poll_loader(&mut self) {
+        let progress = match self.loader.as_ref().and_then(TableLoader::poll) {
+            None => return,
+            Some(Ok(progress)) => progress,
+            Some(Err(e)) => {
+                self.loader = None;
+                self.panes[self.active]
+                    .session
+                    .set_echo(&format!("{:?}", e));
+                return;
+            }
+        };
+        self.progress = (progress.loaded, progress.total);
+        let done = progress.is_done();
+        if done {
+            self.loader = None;
+        }
+        let pane = &mut self.panes[self.active];
+        let result = pane.session.set_table(progress.table);
+        // Catch the changes made to the files while they were loading.
+        let result = match done {
+            true => result.and_then(|_| pane.session.refresh()),
+            false => result,
+        };
+        if let Err(e) = result {
+            pane.session.set_echo(&format!("{:?}", e));
+        }
+        if let State::ListsUpdated = pane.session.state() {
+            pane.session.set_state(State::Default);
+        }
+        self.share_table(self.active);
+    }
+
+    /// Split the view into two panes, or go back to a single pane. The new
+    /// pane starts with all the files, and the active pane is kept when the
+    /// view is merged.
+    fn toggle_split(&mut self) {
+        if self.panes.len() > 1 {
+            let pane = self.panes.swap_remove(self.active);
+            self.panes = vec![pane];
+            self.active = 0;
+        } else {
+            let session = self.session();
+            let session = InteractiveSession::init(
+                Arc::clone(session.shared_table()),
+                session.config().clone(),
+            );
+            self.panes.push(Pane::new(session));
+            self.active = 1;
+        }
+    }
+
+    fn render_grid_preview(&mut self, index: usize, ui: &mut egui::Ui) {
+        let (frame, last_visible) = (self.frame, &mut self.last_visible);
+        let pane = &mut self.panes[index];
         let (ncols, ncells, row_height, col_width) = {
             let ncols = f32::ceil(ui.available_width() / (DESIRED_COL_WIDTH + COL_SPACING));
             let nrows = f32::ceil(ui.available_height() / (DESIRED_ROW_HEIGHT + ROW_SPACING));
@@ -156,36 +407,74 @@ impl GuiApp {
             )
         };
         // This takes the ceil of integer division.
-        self.num_pages = usize::max(self.session.filelist().len().div_ceil(ncells), 1);
+        pane.num_pages = usize::max(pane.session.filelist().len().div_ceil(ncells), 1);
+        // The list of files can shrink when the view is refreshed.
+        pane.page_index = usize::min(pane.page_index, pane.num_pages - 1);
+        // Start loading the thumbnails of the next page in the background, so
+        // flipping to the next page is instant.
+        for file in pane
+            .session
+            .filelist()
+            .iter()
+            .skip((pane.page_index + 1) * ncells)
+            .take(ncells)
+        {
+            let path = pane.session.table().abs_path(file);
+            if is_image(&path) {
+                let uri = image_uri(&path);
+                let _ = ui.ctx().try_load_texture(
+                    &uri,
+                    Default::default(),
+                    egui::load::SizeHint::default(),
+                );
+                last_visible.insert(uri, frame);
+            }
+        }
         let mut echo = None;
-        egui::Grid::new("image_grid")
+        let mut action = None;
+        egui::Grid::new(("image_grid", index))
             .min_row_height(row_height)
             .max_col_width(col_width)
             .striped(true)
             .spacing(egui::Vec2::new(COL_SPACING, ROW_SPACING))
             .show(ui, |ui| {
-                for (counter, (relpath, path)) in self
+                for (counter, (relpath, path)) in pane
                     .session
                     .filelist()
                     .iter()
-                    .map(|file| {
-                        let mut path = self.session.table().path().to_path_buf();
-                        path.push(file);
-                        (file, path)
-                    })
-                    .skip(self.page_index * ncells)
+                    .map(|file| (file, pane.session.table().abs_path(file)))
+                    .skip(pane.page_index * ncells)
                     .take(ncells)
                     .enumerate()
                 {
+                    if is_image(&path) {
+                        last_visible.insert(image_uri(&path), frame);
+                    }
                     ui.vertical_centered(|ui| {
                         let response = Self::render_file_preview(relpath, &path, ui);
-                        if response.double_clicked() && opener::open(&path).is_err() {
+                        if response.double_clicked()
+                            && ftag::core::open_file(&path, pane.session.config()).is_err()
+                        {
                             echo = Some("Unable to open the file.");
                         } else if response.hovered() {
                             response.show_tooltip_ui(|ui| {
-                                ui.monospace(ftag::core::what_is(&path).unwrap_or(String::from(
-                                    "Unable to fetch the description of this file.",
-                                )));
+                                ui.monospace(
+                                    ftag::core::what_is(&path, pane.session.config())
+                                        .map(|d| d.to_string())
+                                        .unwrap_or(String::from(
+                                            "Unable to fetch the description of this file.",
+                                        )),
+                                );
+                            });
+                        }
+                        if !pane.session.config().actions().is_empty() {
+                            response.context_menu(|ui| {
+                                for (name, command) in pane.session.config().actions() {
+                                    if ui.button(name).clicked() {
+                                        action = Some((PathBuf::from(relpath), command.clone()));
+                                        ui.close_menu();
+                                    }
+                                }
                             });
                         }
                     });
@@ -194,9 +483,83 @@ impl GuiApp {
                     }
                 }
                 if let Some(message) = echo {
-                    self.session.set_echo(message);
+                    pane.session.set_echo(message);
                 }
             });
+        if let Some((path, command)) = action {
+            // The action may have modified the files, so the view is refreshed.
+            let output = pane.session.exec(&[path], &command);
+            match pane.session.refresh() {
+                Ok(()) => pane.session.set_echo(&output),
+                Err(e) => pane.session.set_echo(&format!("{:?}", e)),
+            }
+            pane.session.set_state(State::Default);
+            self.share_table(index);
+        }
+    }
+
+    /// Show the table of the pane at `index` in the other panes, if it was
+    /// replaced or modified, such as by `/reload`. The panes show the same
+    /// files, so they share one table, and keep their own filters.
+    fn share_table(&mut self, index: usize) {
+        let table = Arc::clone(self.panes[index].session.shared_table());
+        for (i, other) in self.panes.iter_mut().enumerate() {
+            if i == index || Arc::ptr_eq(other.session.shared_table(), &table) {
+                continue;
+            }
+            if let Err(e) = other.session.set_table(Arc::clone(&table)) {
+                other.session.set_echo(&format!("{:?}", e));
+            }
+            if let State::ListsUpdated = other.session.state() {
+                other.session.set_state(State::Default);
+            }
+        }
+    }
+
+    /// Forget the least recently visible images, until the images and their
+    /// textures fit in the cache size from the config. The images visible in
+    /// the current frame are never forgotten.
+    fn evict_images(&mut self, ctx: &egui::Context) {
+        let loaders = ctx.loaders();
+        let mut used = loaders
+            .bytes
+            .lock()
+            .iter()
+            .map(|l| l.byte_size())
+            .sum::<usize>()
+            + loaders
+                .image
+                .lock()
+                .iter()
+                .map(|l| l.byte_size())
+                .sum::<usize>()
+            + loaders
+                .texture
+                .lock()
+                .iter()
+                .map(|l| l.byte_size())
+                .sum::<usize>();
+        let limit = self.session().config().gui_image_cache();
+        if used <= limit || self.last_visible.is_empty() {
+            return;
+        }
+        let mut uris: Vec<(usize, String)> = self
+            .last_visible
+            .iter()
+            .map(|(uri, frame)| (*frame, uri.clone()))
+            .collect();
+        uris.sort_unstable();
+        // Recomputing the size after forgetting each image is expensive, so
+        // the average size of an image is used instead.
+        let average = used / uris.len();
+        for (frame, uri) in uris {
+            if used <= limit || frame == self.frame {
+                break;
+            }
+            ctx.forget_image(&uri);
+            self.last_visible.remove(&uri);
+            used = used.saturating_sub(average);
+        }
     }
 
     fn invert_color(color: &egui::Color32) -> egui::Color32 {
@@ -208,13 +571,13 @@ impl GuiApp {
     }
 
     fn parse_suggestion_string(&self) -> Option<(&str, &str, &str)> {
-        let (left, rest) = self.session.echo().split_once('[')?;
+        let (left, rest) = self.session().echo().split_once('[')?;
         let (middle, right) = rest.split_once(']')?;
         Some((left, middle, right))
     }
 
     fn render_echo(&self, ui: &mut egui::Ui) {
-        let tryparse = if let State::Autocomplete = self.session.state() {
+        let tryparse = if let State::Autocomplete = self.session().state() {
             self.parse_suggestion_string()
         } else {
             None
@@ -247,7 +610,7 @@ impl GuiApp {
             None => {
                 ui.add(
                     egui::Label::new(
-                        egui::widget_text::RichText::new(self.session.echo())
+                        egui::widget_text::RichText::new(self.session().echo())
                             .text_style(egui::TextStyle::Monospace),
                     )
                     .selectable(false)
@@ -260,40 +623,73 @@ impl GuiApp {
 
 impl eframe::App for GuiApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.frame += 1;
+        self.poll_loader();
+        if self.loader.is_some() {
+            // Keep showing the progress, even if there is no input.
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
         // Tags panel.
         egui::SidePanel::left("tags_panel").show(ctx, |ui| {
             egui::ScrollArea::vertical().show(ui, |ui| {
-                for tag in self.session.taglist() {
-                    ui.add(
-                        egui::Label::new(
-                            egui::widget_text::RichText::new(tag)
-                                .text_style(egui::TextStyle::Monospace),
-                        )
-                        .selectable(false),
+                for item in group_by_namespace(self.session().taglist()) {
+                    let text = match item {
+                        TagGroupItem::Namespace(ns) => {
+                            egui::widget_text::RichText::new(format!("{}:", ns)).strong()
+                        }
+                        TagGroupItem::Tag(tag, text) if text.len() < tag.len() => {
+                            egui::widget_text::RichText::new(format!("  {}", text))
+                        }
+                        TagGroupItem::Tag(_tag, text) => egui::widget_text::RichText::new(text),
+                    };
+                    // Virtual tags are in italics.
+                    let text = match item {
+                        TagGroupItem::Tag(tag, _) if self.session().config().is_virtual(tag) => {
+                            text.italics()
+                        }
+                        _ => text,
+                    };
+                    let response = ui.add(
+                        egui::Label::new(text.text_style(egui::TextStyle::Monospace))
+                            .selectable(false),
                     );
+                    if let TagGroupItem::Tag(tag, _text) = item {
+                        let config = self.session().config();
+                        if let Some(desc) = config.describe(tag) {
+                            response.on_hover_text(desc);
+                        } else if let Some(query) = config.virtual_query(tag) {
+                            response.on_hover_text(format!("Virtual tag: {}", query));
+                        }
+                    }
                 }
             });
         });
-        // Current filter string.
+        // Current filter string of each pane. The active pane is highlighted.
         egui::TopBottomPanel::top("top_bar").show(ctx, |ui| {
-            ui.centered_and_justified(|ui| {
-                ui.add(
-                    egui::Label::new(
-                        egui::widget_text::RichText::new(format!(
-                            "{}: {} results, page {} of {}",
-                            if self.session.filter_str().is_empty() {
-                                "ALL_TAGS"
+            ui.columns(self.panes.len(), |columns| {
+                for (i, (pane, ui)) in self.panes.iter().zip(columns.iter_mut()).enumerate() {
+                    let summary = match self.loader {
+                        Some(_) => format!(
+                            "{} (loading {} of {} directories)",
+                            pane.summary(),
+                            self.progress.0,
+                            self.progress.1
+                        ),
+                        None => pane.summary(),
+                    };
+                    let text = egui::widget_text::RichText::new(summary)
+                        .text_style(egui::TextStyle::Monospace);
+                    ui.centered_and_justified(|ui| {
+                        ui.add(
+                            egui::Label::new(if i == self.active && self.panes.len() > 1 {
+                                text.strong().underline()
                             } else {
-                                self.session.filter_str()
-                            },
-                            self.session.filelist().len(),
-                            self.page_index + 1,
-                            self.num_pages
-                        ))
-                        .text_style(egui::TextStyle::Monospace),
-                    )
-                    .selectable(false),
-                );
+                                text
+                            })
+                            .selectable(false),
+                        );
+                    });
+                }
             });
         });
         // Input field and echo string.
@@ -303,7 +699,8 @@ impl eframe::App for GuiApp {
                     self.render_echo(ui);
                 });
                 ui.separator();
-                let mut output = egui::TextEdit::singleline(self.session.command_mut())
+                let pane = &mut self.panes[self.active];
+                let mut output = egui::TextEdit::singleline(pane.session.command_mut())
                     .frame(false)
                     .desired_width(f32::INFINITY)
                     .min_size(egui::Vec2::new(100., 24.))
@@ -316,12 +713,12 @@ impl eframe::App for GuiApp {
                 if query_response.lost_focus() {
                     if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
                         // User hit return with a query.
-                        self.session.process_input();
-                        match self.session.state() {
+                        pane.session.process_input();
+                        match pane.session.state() {
                             State::Default | State::Autocomplete => {} // Do nothing.
                             State::ListsUpdated => {
-                                self.page_index = 0;
-                                self.session.set_state(State::Default);
+                                pane.page_index = 0;
+                                pane.session.set_state(State::Default);
                             }
                             State::Exit => {
                                 ctx.send_viewport_cmd(egui::ViewportCommand::Close);
@@ -329,27 +726,45 @@ impl eframe::App for GuiApp {
                         }
                         // Move the cursor to the end of the line, say, after autocomplete.
                         output.state.cursor.set_char_range(Some(CCursorRange::two(
-                            CCursor::new(self.session.command().len()),
-                            CCursor::new(self.session.command().len()),
+                            CCursor::new(pane.session.command().len()),
+                            CCursor::new(pane.session.command().len()),
                         )));
                         output.state.store(ctx, query_response.id);
+                        self.share_table(self.active);
                     } else if ui.input(|i| i.key_pressed(egui::Key::Tab)) {
-                        self.session.autocomplete();
+                        pane.session.autocomplete();
                     }
                 } else if query_response.changed() {
-                    self.session.stop_autocomplete();
+                    pane.session.stop_autocomplete();
                 } else if ui.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::N)) {
-                    self.page_index = usize::clamp(self.page_index + 1, 0, self.num_pages - 1);
+                    pane.page_index = usize::clamp(pane.page_index + 1, 0, pane.num_pages - 1);
                 } else if ui.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::P)) {
-                    self.page_index =
-                        usize::clamp(self.page_index.saturating_sub(1), 0, self.num_pages - 1);
+                    pane.page_index =
+                        usize::clamp(pane.page_index.saturating_sub(1), 0, pane.num_pages - 1);
+                } else if ui.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::D)) {
+                    self.toggle_split();
+                } else if ui.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::W)) {
+                    self.active = (self.active + 1) % self.panes.len();
                 }
                 query_response.request_focus();
             });
         });
-        // Files previews.
+        // Files previews, side by side when the view is split.
         egui::CentralPanel::default().show(ctx, |ui| {
-            self.render_grid_preview(ui);
+            let npanes = self.panes.len();
+            ui.columns(npanes, |columns| {
+                for (i, ui) in columns.iter_mut().enumerate() {
+                    // Clicking anywhere in a pane makes it the active pane.
+                    if npanes > 1
+                        && ui.rect_contains_pointer(ui.max_rect())
+                        && ui.input(|i| i.pointer.primary_clicked())
+                    {
+                        self.active = i;
+                    }
+                    self.render_grid_preview(i, ui);
+                }
+            });
+            self.evict_images(ui.ctx());
         });
     }
 }
@@ -0,0 +1,718 @@
+use crate::{
+    config::Config,
+    core::{Error, WritePlan, FTAG_FILE, FTAG_INDEX_FILE},
+    load::{FileLoadingOptions, LoaderOptions},
+    vfs::{EntryKind, FileStats, FileSystem, OsFileSystem},
+    walk::{DirTree, MetaData, VisitedDir},
+};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/*
+The search index is a single binary file at the root of the archive, that
+contains the tags and descriptions from all the .ftag files, and an inverted
+index that maps tokens to the entries they appear in. This lets `search` and
+`grep` run without walking the directories and parsing every .ftag file. The
+index also records the modification times of all the .ftag files, and is only
+used if none of them have changed since the index was built.
+
+The index also keeps a snapshot of the directory tree, i.e. the entries of
+every directory that was walked except the root, and the text of its .ftag
+file. Queries and tag tables walk the snapshot instead of listing the
+directories, as long as the modification times of the directories and the .ftag
+files haven't changed. Adding, removing or renaming an entry in a directory
+changes the modification time of the directory, so the snapshot is never used
+when it is stale.
+ */
+
+const MAGIC: &[u8; 8] = b"FTAGIDX1";
+
+/// Tags and description of a directory, or a glob in a .ftag file.
+struct IndexedEntry {
+    path: String,
+    tags: Vec<String>,
+    desc: Option<String>,
+}
+
+/// Data from a single .ftag file.
+struct IndexedDir {
+    /// Path of the directory relative to the root of the index.
+    rel_path: String,
+    /// Modification time of the .ftag file as (seconds, nanoseconds).
+    mtime: (u64, u32),
+    dir: IndexedEntry,
+    globs: Vec<IndexedEntry>,
+}
+
+/// Entries of a directory, recorded while building the index.
+struct IndexedListing {
+    /// Path of the directory relative to the root of the index.
+    rel_path: String,
+    /// Modification time of the directory as (seconds, nanoseconds).
+    mtime: (u64, u32),
+    entries: Vec<(String, EntryKind)>,
+    /// Text of the .ftag file in the directory, if it has one.
+    ftag: Option<String>,
+}
+
+pub(crate) struct SearchIndex {
+    dirs: Vec<IndexedDir>,
+    /// Sorted list of tokens, and the ids of the entries they appear in. The
+    /// entries are numbered in the order they appear in `dirs`, with every
+    /// directory followed by its globs.
+    tokens: Vec<(String, Vec<u32>)>,
+    /// Snapshot of the directory tree. This is `None` for indices written by
+    /// older versions, that don't have a snapshot.
+    tree: Option<Vec<IndexedListing>>,
+}
+
+/// The file system of the operating system, that records the entries of the
+/// directories that are listed, and the text of the .ftag files that are read.
+struct RecordingFileSystem<'a> {
+    root: &'a Path,
+    listings: RefCell<BTreeMap<PathBuf, IndexedListing>>,
+}
+
+impl FileSystem for RecordingFileSystem<'_> {
+    fn read_dir(&self, path: &Path, visit: &mut dyn FnMut(OsString, EntryKind)) -> io::Result<()> {
+        // Read the modification time first, so that any change made while
+        // listing the directory makes the snapshot out of date.
+        let mtime = modified_time(path);
+        let mut entries = Vec::new();
+        let mut valid = true;
+        OsFileSystem.read_dir(path, &mut |name, kind| {
+            match name.to_str() {
+                Some(name) => entries.push((name.to_string(), kind)),
+                None => valid = false,
+            }
+            visit(name, kind);
+        })?;
+        let rel_path = path
+            .strip_prefix(self.root)
+            .ok()
+            .and_then(|p| p.to_str())
+            .filter(|p| !p.is_empty())
+            .map(|p| p.to_string());
+        // Directories that can't be stored in the index are left out, and
+        // listed from the file system when the snapshot is used. So is the
+        // root, because writing the index changes its modification time.
+        if let (true, Some(rel_path), Some(mtime)) = (valid, rel_path, mtime) {
+            self.listings.borrow_mut().insert(
+                path.to_path_buf(),
+                IndexedListing {
+                    rel_path,
+                    mtime,
+                    entries,
+                    ftag: None,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        OsFileSystem.is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        OsFileSystem.is_file(path)
+    }
+
+    fn read_to_string(&self, path: &Path, buf: &mut String) -> io::Result<()> {
+        let start = buf.len();
+        OsFileSystem.read_to_string(path, buf)?;
+        if path.file_name().is_some_and(|name| name == FTAG_FILE) {
+            let mut listings = self.listings.borrow_mut();
+            if let Some(listing) = path.parent().and_then(|dir| listings.get_mut(dir)) {
+                listing.ftag = Some(buf[start..].to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn stats(&self, path: &Path) -> io::Result<FileStats> {
+        OsFileSystem.stats(path)
+    }
+}
+
+/// Directory tree recorded in the index. Directories that are not in the
+/// snapshot are read from the file system of the operating system.
+pub(crate) struct IndexedFileSystem {
+    /// Listings of the directories, by their absolute paths.
+    dirs: HashMap<PathBuf, IndexedListing>,
+}
+
+impl IndexedFileSystem {
+    /// Get the listing of the directory that contains `path`, and the name of
+    /// `path`.
+    fn parent_listing<'a>(&self, path: &'a Path) -> Option<(&IndexedListing, &'a str)> {
+        Some((self.dirs.get(path.parent()?)?, path.file_name()?.to_str()?))
+    }
+}
+
+impl FileSystem for IndexedFileSystem {
+    fn read_dir(&self, path: &Path, visit: &mut dyn FnMut(OsString, EntryKind)) -> io::Result<()> {
+        match self.dirs.get(path) {
+            Some(listing) => {
+                for (name, kind) in &listing.entries {
+                    visit(OsString::from(name), *kind);
+                }
+                Ok(())
+            }
+            None => OsFileSystem.read_dir(path, visit),
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.dirs.contains_key(path) || OsFileSystem.is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        match self.parent_listing(path) {
+            Some((listing, name)) => listing
+                .entries
+                .iter()
+                .any(|(n, kind)| n == name && *kind == EntryKind::File),
+            None => OsFileSystem.is_file(path),
+        }
+    }
+
+    fn read_to_string(&self, path: &Path, buf: &mut String) -> io::Result<()> {
+        match self.parent_listing(path) {
+            Some((
+                IndexedListing {
+                    ftag: Some(text), ..
+                },
+                FTAG_FILE,
+            )) => {
+                buf.push_str(text);
+                Ok(())
+            }
+            _ => OsFileSystem.read_to_string(path, buf),
+        }
+    }
+
+    /// Modifying a file doesn't change the modification time of its directory,
+    /// so the sizes and modification times of files are not in the snapshot.
+    fn stats(&self, path: &Path) -> io::Result<FileStats> {
+        OsFileSystem.stats(path)
+    }
+}
+
+/// Split `text` into lower case alphanumeric tokens.
+fn tokenize(text: &str) -> impl Iterator<Item = String> + use<'_> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+}
+
+fn modified_time(path: &Path) -> Option<(u64, u32)> {
+    let duration = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?;
+    Some((duration.as_secs(), duration.subsec_nanos()))
+}
+
+fn index_path(root: &Path) -> PathBuf {
+    let mut path = root.to_path_buf();
+    path.push(FTAG_INDEX_FILE);
+    path
+}
+
+fn write_u32(w: &mut impl Write, val: u32) -> io::Result<()> {
+    w.write_all(&val.to_le_bytes())
+}
+
+fn write_str(w: &mut impl Write, val: &str) -> io::Result<()> {
+    write_u32(w, val.len() as u32)?;
+    w.write_all(val.as_bytes())
+}
+
+fn write_entry(w: &mut impl Write, entry: &IndexedEntry) -> io::Result<()> {
+    write_str(w, &entry.path)?;
+    write_u32(w, entry.tags.len() as u32)?;
+    for tag in &entry.tags {
+        write_str(w, tag)?;
+    }
+    match &entry.desc {
+        Some(desc) => {
+            w.write_all(&[1u8])?;
+            write_str(w, desc)
+        }
+        None => w.write_all(&[0u8]),
+    }
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_str(r: &mut impl Read) -> io::Result<String> {
+    let mut bytes = vec![0u8; read_u32(r)? as usize];
+    r.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+}
+
+fn read_entry(r: &mut impl Read) -> io::Result<IndexedEntry> {
+    let path = read_str(r)?;
+    let ntags = read_u32(r)?;
+    let tags = (0..ntags).map(|_| read_str(r)).collect::<io::Result<_>>()?;
+    let mut flag = [0u8; 1];
+    r.read_exact(&mut flag)?;
+    let desc = match flag[0] {
+        0 => None,
+        _ => Some(read_str(r)?),
+    };
+    Ok(IndexedEntry { path, tags, desc })
+}
+
+fn write_listing(w: &mut impl Write, listing: &IndexedListing) -> io::Result<()> {
+    write_str(w, &listing.rel_path)?;
+    w.write_all(&listing.mtime.0.to_le_bytes())?;
+    write_u32(w, listing.mtime.1)?;
+    write_u32(w, listing.entries.len() as u32)?;
+    for (name, kind) in &listing.entries {
+        write_str(w, name)?;
+        w.write_all(&[match kind {
+            EntryKind::File => 0u8,
+            EntryKind::Dir => 1u8,
+        }])?;
+    }
+    match &listing.ftag {
+        Some(text) => {
+            w.write_all(&[1u8])?;
+            write_str(w, text)
+        }
+        None => w.write_all(&[0u8]),
+    }
+}
+
+fn read_listing(r: &mut impl Read) -> io::Result<IndexedListing> {
+    let rel_path = read_str(r)?;
+    let mut secs = [0u8; 8];
+    r.read_exact(&mut secs)?;
+    let nanos = read_u32(r)?;
+    let nentries = read_u32(r)?;
+    let mut entries = Vec::with_capacity(nentries as usize);
+    let mut flag = [0u8; 1];
+    for _ in 0..nentries {
+        let name = read_str(r)?;
+        r.read_exact(&mut flag)?;
+        entries.push((
+            name,
+            match flag[0] {
+                0 => EntryKind::File,
+                _ => EntryKind::Dir,
+            },
+        ));
+    }
+    r.read_exact(&mut flag)?;
+    let ftag = match flag[0] {
+        0 => None,
+        _ => Some(read_str(r)?),
+    };
+    Ok(IndexedListing {
+        rel_path,
+        mtime: (u64::from_le_bytes(secs), nanos),
+        entries,
+        ftag,
+    })
+}
+
+impl SearchIndex {
+    /// Recursively traverse the directories from `root` and build the index.
+    fn build(root: &Path, config: &Config) -> Result<SearchIndex, Error> {
+        let mut dirs = Vec::new();
+        let mut tokens = BTreeMap::<String, Vec<u32>>::new();
+        let recorder = RecordingFileSystem {
+            root,
+            listings: RefCell::new(BTreeMap::new()),
+        };
+        let mut dir = DirTree::with_fs(
+            root.to_path_buf(),
+            LoaderOptions::new(
+                true,
+                true,
+                FileLoadingOptions::Load {
+                    file_tags: true,
+                    file_desc: true,
+                },
+            ),
+            config,
+            &recorder,
+        )?;
+        let mut docid = 0u32;
+        let mut add_tokens = |entry: &IndexedEntry, docid: u32| {
+            for token in entry
+                .tags
+                .iter()
+                .flat_map(|t| tokenize(t))
+                .chain(entry.desc.iter().flat_map(|d| tokenize(d)))
+            {
+                let docs = tokens.entry(token).or_default();
+                if docs.last() != Some(&docid) {
+                    docs.push(docid);
+                }
+            }
+        };
+        while let Some(VisitedDir {
+            abs_dir_path,
+            rel_dir_path,
+            metadata,
+            ..
+        }) = dir.walk()
+        {
+            let data = match metadata {
+                MetaData::Ok(data) => data,
+                MetaData::NotFound => continue,
+                MetaData::FailedToLoad(e) => return Err(e),
+            };
+            let mut fpath = abs_dir_path.to_path_buf();
+            fpath.push(FTAG_FILE);
+            let indexed = IndexedDir {
+                rel_path: rel_dir_path
+                    .to_str()
+                    .ok_or(Error::InvalidPath(rel_dir_path.to_path_buf()))?
+                    .to_string(),
+                mtime: modified_time(&fpath).ok_or(Error::CannotReadStoreFile(fpath))?,
+                dir: IndexedEntry {
+                    path: String::new(),
+                    tags: data.tags().iter().map(|t| t.to_string()).collect(),
+                    desc: data.desc.as_deref().map(str::to_string),
+                },
+                globs: data
+                    .globs
+                    .iter()
+                    .map(|g| IndexedEntry {
+                        path: g.path.to_string(),
+                        tags: data.glob_tags(g).iter().map(|t| t.to_string()).collect(),
+                        desc: data.glob_desc(g).map(str::to_string),
+                    })
+                    .collect(),
+            };
+            for entry in std::iter::once(&indexed.dir).chain(indexed.globs.iter()) {
+                add_tokens(entry, docid);
+                docid += 1;
+            }
+            dirs.push(indexed);
+        }
+        Ok(SearchIndex {
+            dirs,
+            tokens: tokens.into_iter().collect(),
+            tree: Some(recorder.listings.into_inner().into_values().collect()),
+        })
+    }
+
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        write_u32(w, self.dirs.len() as u32)?;
+        for dir in &self.dirs {
+            write_str(w, &dir.rel_path)?;
+            w.write_all(&dir.mtime.0.to_le_bytes())?;
+            write_u32(w, dir.mtime.1)?;
+            write_entry(w, &dir.dir)?;
+            write_u32(w, dir.globs.len() as u32)?;
+            for glob in &dir.globs {
+                write_entry(w, glob)?;
+            }
+        }
+        write_u32(w, self.tokens.len() as u32)?;
+        for (token, docs) in &self.tokens {
+            write_str(w, token)?;
+            write_u32(w, docs.len() as u32)?;
+            for doc in docs {
+                write_u32(w, *doc)?;
+            }
+        }
+        if let Some(tree) = &self.tree {
+            write_u32(w, tree.len() as u32)?;
+            for listing in tree {
+                write_listing(w, listing)?;
+            }
+        }
+        w.flush()
+    }
+
+    fn read(r: &mut impl Read) -> io::Result<SearchIndex> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::from(io::ErrorKind::InvalidData));
+        }
+        let ndirs = read_u32(r)?;
+        let mut dirs = Vec::with_capacity(ndirs as usize);
+        for _ in 0..ndirs {
+            let rel_path = read_str(r)?;
+            let mut secs = [0u8; 8];
+            r.read_exact(&mut secs)?;
+            let nanos = read_u32(r)?;
+            let dir = read_entry(r)?;
+            let nglobs = read_u32(r)?;
+            let globs = (0..nglobs)
+                .map(|_| read_entry(r))
+                .collect::<io::Result<_>>()?;
+            dirs.push(IndexedDir {
+                rel_path,
+                mtime: (u64::from_le_bytes(secs), nanos),
+                dir,
+                globs,
+            });
+        }
+        let ntokens = read_u32(r)?;
+        let mut tokens = Vec::with_capacity(ntokens as usize);
+        for _ in 0..ntokens {
+            let token = read_str(r)?;
+            let ndocs = read_u32(r)?;
+            let docs = (0..ndocs).map(|_| read_u32(r)).collect::<io::Result<_>>()?;
+            tokens.push((token, docs));
+        }
+        let tree = match read_u32(r) {
+            Ok(nlistings) => Some(
+                (0..nlistings)
+                    .map(|_| read_listing(r))
+                    .collect::<io::Result<_>>()?,
+            ),
+            // Indices written by older versions end after the tokens.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => return Err(e),
+        };
+        Ok(SearchIndex { dirs, tokens, tree })
+    }
+
+    /// Read the index from `root`, regardless of whether it is up to date.
+    /// Returns `None` if the index doesn't exist.
+    fn read_from(root: &Path) -> Result<Option<SearchIndex>, Error> {
+        let path = index_path(root);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(_) => return Ok(None),
+        };
+        Self::read(&mut BufReader::new(file))
+            .map(Some)
+            .map_err(|_| Error::CorruptIndex(path))
+    }
+
+    /// Check if none of the indexed .ftag files were modified or deleted,
+    /// and none of the directories in the snapshot were changed since the
+    /// index was built.
+    fn is_up_to_date(&self, root: &Path) -> bool {
+        let mut fpath = PathBuf::new();
+        let tree = match &self.tree {
+            Some(tree) => tree,
+            None => return false,
+        };
+        self.dirs.iter().all(|dir| {
+            fpath.clear();
+            fpath.push(root);
+            fpath.push(&dir.rel_path);
+            fpath.push(FTAG_FILE);
+            modified_time(&fpath) == Some(dir.mtime)
+        }) && tree.iter().all(|listing| {
+            fpath.clear();
+            fpath.push(root);
+            fpath.push(&listing.rel_path);
+            modified_time(&fpath) == Some(listing.mtime)
+        })
+    }
+
+    /// Load the index from `root`, along with its state. The index is only
+    /// returned if it is up to date, i.e. none of the indexed .ftag files was
+    /// modified or deleted since the index was built.
+    pub fn load(root: &Path) -> Result<(IndexState, Option<SearchIndex>), Error> {
+        Ok(match Self::read_from(root)? {
+            Some(index) if index.is_up_to_date(root) => (IndexState::UpToDate, Some(index)),
+            Some(_) => (IndexState::OutOfDate, None),
+            None => (IndexState::Missing, None),
+        })
+    }
+
+    /// Call `visit` for every directory in the index with the path of the
+    /// directory relative to the root, the directory entry and the entries of
+    /// the globs. Each entry is accompanied by its id.
+    fn visit_entries<F>(&self, mut visit: F)
+    where
+        F: FnMut(&Path, (u32, &IndexedEntry), &mut dyn Iterator<Item = (u32, &IndexedEntry)>),
+    {
+        let mut docid = 0u32;
+        for dir in &self.dirs {
+            let first = docid + 1;
+            visit(
+                Path::new(&dir.rel_path),
+                (docid, &dir.dir),
+                &mut (first..).zip(dir.globs.iter()),
+            );
+            docid = first + dir.globs.len() as u32;
+        }
+    }
+
+    fn num_entries(&self) -> usize {
+        self.dirs.iter().map(|d| d.globs.len() + 1).sum()
+    }
+
+    /// Same as `core::search`, but using the index.
+    pub fn search<F>(&self, words: &[String], mut visit: F)
+    where
+        F: FnMut(&str),
+    {
+        let mut matched = vec![false; self.num_entries()];
+        // Words don't contain any delimiters, so a word is a substring of a
+        // tag or a description if and only if it is a substring of one of
+        // their tokens.
+        for (_token, docs) in self
+            .tokens
+            .iter()
+            .filter(|(token, _docs)| words.iter().any(|w| token.contains(w.as_str())))
+        {
+            for doc in docs {
+                matched[*doc as usize] = true;
+            }
+        }
+        self.visit_entries(|_dirpath, (dirid, _dir), globs| {
+            let dirmatch = matched[dirid as usize];
+            for (_id, glob) in globs.filter(|(id, _glob)| dirmatch || matched[*id as usize]) {
+                visit(&glob.path);
+            }
+        });
+    }
+
+    /// Call `visit` with the path and the description of every file and
+    /// directory in the index that has a description.
+    pub fn descriptions<F>(&self, mut visit: F)
+    where
+        F: FnMut(&Path, &str),
+    {
+        let mut path = PathBuf::new();
+        self.visit_entries(|dirpath, (_dirid, dir), globs| {
+            if let Some(desc) = &dir.desc {
+                visit(
+                    if dirpath.as_os_str().is_empty() {
+                        Path::new(".")
+                    } else {
+                        dirpath
+                    },
+                    desc,
+                );
+            }
+            for (_id, glob) in globs {
+                if let Some(desc) = &glob.desc {
+                    path.clear();
+                    path.push(dirpath);
+                    path.push(&glob.path);
+                    visit(&path, desc);
+                }
+            }
+        });
+    }
+}
+
+/// Get the snapshot of the directory tree at `root` from the index, to walk
+/// the directories without listing them. Returns `None` if the index is
+/// missing, unreadable or out of date, or if archives are enabled, because the
+/// snapshot doesn't look inside archives.
+pub(crate) fn indexed_file_system(root: &Path, config: &Config) -> Option<IndexedFileSystem> {
+    if config.archives() {
+        return None;
+    }
+    let index = SearchIndex::read_from(root)
+        .ok()?
+        .filter(|index| index.is_up_to_date(root))?;
+    Some(IndexedFileSystem {
+        dirs: index
+            .tree?
+            .into_iter()
+            .map(|listing| (root.join(&listing.rel_path), listing))
+            .collect(),
+    })
+}
+
+/// State of the search index in a directory.
+#[derive(PartialEq, Eq, Debug)]
+pub enum IndexState {
+    Missing,
+    OutOfDate,
+    UpToDate,
+}
+
+/// Check whether the search index at `root` exists, and whether it is up to
+/// date with the .ftag files.
+pub fn index_state(root: &Path) -> Result<IndexState, Error> {
+    Ok(match SearchIndex::read_from(root)? {
+        Some(index) if index.is_up_to_date(root) => IndexState::UpToDate,
+        Some(_) => IndexState::OutOfDate,
+        None => IndexState::Missing,
+    })
+}
+
+/// Build the search index for the directory tree at `root`, and plan to write
+/// it to disk.
+pub fn build_index(root: PathBuf, config: &Config) -> Result<WritePlan, Error> {
+    let index = SearchIndex::build(&root, config)?;
+    let path = index_path(&root);
+    let mut bytes = Vec::new();
+    index
+        .write(&mut bytes)
+        .map_err(|_| Error::CannotWriteFile(path.clone()))?;
+    let mut plan = WritePlan::default();
+    plan.write(path, bytes);
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn t_indexed_file_system() {
+        let ftag = "[tags]\nfoo\n[path]\nb.txt\n[tags]\nbar\n";
+        let dir = TempDir::new(
+            "index",
+            &[
+                (FTAG_FILE, "[path]\na.txt\n[tags]\nfoo\n"),
+                ("a.txt", ""),
+                ("sub/.ftag", ftag),
+                ("sub/b.txt", ""),
+            ],
+        );
+        let config = Config::default();
+        assert!(indexed_file_system(&dir, &config).is_none());
+        build_index(dir.to_path_buf(), &config)
+            .unwrap()
+            .apply(&config)
+            .unwrap();
+        let fs = indexed_file_system(&dir, &config).unwrap();
+        let mut entries = Vec::new();
+        fs.read_dir(&dir.join("sub"), &mut |name, kind| {
+            entries.push((name, kind))
+        })
+        .unwrap();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            entries,
+            [
+                (OsString::from(FTAG_FILE), EntryKind::File),
+                (OsString::from("b.txt"), EntryKind::File)
+            ]
+        );
+        let mut text = String::new();
+        fs.read_to_string(&dir.join("sub").join(FTAG_FILE), &mut text)
+            .unwrap();
+        assert_eq!(text, ftag);
+        assert!(fs.is_file(&dir.join("sub").join("b.txt")));
+        assert!(!fs.is_file(&dir.join("sub").join("c.txt")));
+        // New files make the snapshot out of date.
+        std::fs::write(dir.join("sub").join("c.txt"), "").unwrap();
+        assert!(indexed_file_system(&dir, &config).is_none());
+    }
+}
@@ -1,9 +1,16 @@
 use crate::{
-    core::what_is,
+    config::Config,
+    core::{self, open_file, what_is},
     filter::{Filter, FilterParseError},
-    query::TagTable,
+    load::{is_url, split_tags},
+    query::{namespace_order, TagTable},
+};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
-use std::{fmt::Debug, path::PathBuf};
 
 /// State of the app.
 pub enum State {
@@ -16,9 +23,15 @@ pub enum State {
 enum Command {
     Exit,
     Reset,
-    Filter(Filter),
+    Filter(Filter, String),
     WhatIs(PathBuf),
-    Open(PathBuf),
+    Open(Vec<PathBuf>),
+    Exec(Vec<PathBuf>, String),
+    Reload(Option<PathBuf>),
+    Mark(Vec<PathBuf>),
+    Unmark(Option<Vec<PathBuf>>),
+    Marks,
+    AddTags(Vec<PathBuf>, Vec<String>),
 }
 
 enum Error {
@@ -36,7 +49,9 @@ impl Debug for Error {
 }
 
 pub struct InteractiveSession {
-    table: TagTable,
+    // Shared with other sessions showing the same table, and copied on write.
+    table: Arc<TagTable>,
+    config: Config,
     // State management.
     command: String,
     echo: String,
@@ -44,8 +59,14 @@ pub struct InteractiveSession {
     tag_active: Vec<bool>,
     filtered_indices: Vec<usize>,
     filter_str: String,
+    // The filter as it was entered, which can refer to tags that are not
+    // loaded yet. This is parsed again when the table changes.
+    filter_input: String,
     taglist: Vec<String>,
     filelist: Vec<String>,
+    // Files marked by the user, relative to the root of the table. These are
+    // kept across filters.
+    marks: BTreeSet<PathBuf>,
     // Autocomplete
     command_completions: Box<[String]>,
     suggestions: Vec<String>,
@@ -53,12 +74,14 @@ pub struct InteractiveSession {
 }
 
 impl InteractiveSession {
-    pub fn init(table: TagTable) -> InteractiveSession {
-        let taglist = table.tags().to_vec();
+    pub fn init(table: Arc<TagTable>, config: Config) -> InteractiveSession {
+        let mut taglist = table.tags().to_vec();
+        taglist.sort_unstable_by(|a, b| namespace_order(a).cmp(&namespace_order(b)));
         let ntags = table.tags().len();
         let nfiles = table.files().len();
         let mut app = InteractiveSession {
             table,
+            config,
             command: String::new(),
             echo: String::new(),
             state: State::Default,
@@ -67,10 +90,15 @@ impl InteractiveSession {
             filelist: Vec::with_capacity(nfiles),
             filtered_indices: (0..nfiles).collect(),
             filter_str: String::new(),
-            command_completions: ["exit", "quit", "reset", "whatis", "open"]
-                .iter()
-                .map(|s| s.to_string())
-                .collect(),
+            filter_input: String::new(),
+            marks: BTreeSet::new(),
+            command_completions: [
+                "exit", "quit", "reset", "whatis", "open", "exec", "reload", "mark", "unmark",
+                "marks", "add", "tags", "files", "help",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
             suggestions: Vec::new(),
             suggestion_index: 0,
         };
@@ -84,6 +112,7 @@ impl InteractiveSession {
 
     fn reset(&mut self) {
         self.filter_str.clear();
+        self.filter_input.clear();
         self.filtered_indices.clear();
         self.filtered_indices.extend(0..self.num_files());
         self.update_lists();
@@ -91,10 +120,91 @@ impl InteractiveSession {
         self.state = State::Default;
         self.tag_active.fill(true);
         self.state = State::ListsUpdated;
+        if !self.marks.is_empty() {
+            // Forget the marked files that are no longer tracked.
+            let files: HashSet<&Path> = self.table.files().iter().map(Path::new).collect();
+            self.marks.retain(|path| files.contains(path.as_path()));
+        }
     }
 
-    fn parse_index_to_filepath(&self, numstr: &str) -> Result<PathBuf, Error> {
-        let index = match numstr.parse::<usize>() {
+    /// Reload all the data from the disk, for example after the .ftag files
+    /// were modified. This resets the current filter.
+    pub fn reload(&mut self) -> Result<(), core::Error> {
+        self.table = Arc::new(TagTable::from_dir(
+            self.table.path().to_path_buf(),
+            &self.config,
+        )?);
+        self.tag_active = vec![true; self.table.tags().len()];
+        self.reset();
+        Ok(())
+    }
+
+    fn apply_filter(&mut self, filter: Filter, input: String) {
+        let filter = filter.simplify();
+        self.filtered_indices = self.table.matching_files(&filter);
+        self.update_lists();
+        self.filter_str = filter.text(self.table.tags());
+        self.filter_input = input;
+        self.state = State::ListsUpdated;
+    }
+
+    /// Same as `reload`, but only the directories that changed since they were
+    /// loaded are read again, and the current filter is applied again after
+    /// reloading, instead of being reset.
+    pub fn refresh(&mut self) -> Result<(), core::Error> {
+        Arc::make_mut(&mut self.table).refresh(&self.config)?;
+        self.table_changed()
+    }
+
+    /// Replace the table with `table`, such as a newer snapshot of a table that
+    /// is loaded in the background. The current filter is applied again, and
+    /// the suggestions being cycled through, if any, are kept.
+    pub fn set_table(&mut self, table: Arc<TagTable>) -> Result<(), core::Error> {
+        self.table = table;
+        let autocomplete = matches!(self.state, State::Autocomplete);
+        let echo = std::mem::take(&mut self.echo);
+        let result = self.table_changed();
+        self.echo = echo;
+        if autocomplete && result.is_ok() {
+            self.state = State::Autocomplete;
+        }
+        result
+    }
+
+    /// Same as `refresh`, but only the files in the directory `dir` and its
+    /// subdirectories are reloaded from the disk. This is much faster than
+    /// reloading everything when only the .ftag files in `dir` were modified.
+    pub fn refresh_dir(&mut self, dir: &Path) -> Result<(), core::Error> {
+        let dir = dir.strip_prefix(self.table.path()).unwrap_or(dir);
+        let dir: PathBuf = dir
+            .components()
+            .filter(|c| !matches!(c, std::path::Component::CurDir))
+            .collect();
+        Arc::make_mut(&mut self.table).update_dir(&dir, &self.config)?;
+        self.table_changed()
+    }
+
+    /// Update the lists after the table changed, keeping the current filter.
+    fn table_changed(&mut self) -> Result<(), core::Error> {
+        let input = std::mem::take(&mut self.filter_input);
+        self.tag_active = vec![true; self.table.tags().len()];
+        self.reset();
+        self.reapply_filter(input)
+    }
+
+    fn reapply_filter(&mut self, input: String) -> Result<(), core::Error> {
+        if !input.is_empty() {
+            let filter = self
+                .table
+                .parse_filter(&input)
+                .map_err(core::Error::InvalidFilter)?;
+            self.apply_filter(filter, input);
+        }
+        Ok(())
+    }
+
+    fn parse_index(&self, numstr: &str) -> Result<usize, Error> {
+        match numstr.trim().parse::<usize>() {
             Ok(num) if num < self.filtered_indices.len() => Ok(num),
             Ok(num) => Err(Error::InvalidCommand(format!(
                 "{num} is not a valid choice. Please choose an index between 0 and {}",
@@ -103,10 +213,77 @@ impl InteractiveSession {
             Err(_) => Err(Error::InvalidCommand(format!(
                 "Unable to parse '{numstr}' to an index."
             ))),
-        }?;
-        let mut path = self.table.path().to_path_buf();
-        path.push(&self.table.files()[self.filtered_indices[index]]);
-        Ok(path)
+        }
+    }
+
+    fn parse_index_to_filepath(&self, numstr: &str) -> Result<PathBuf, Error> {
+        let index = self.parse_index(numstr)?;
+        Ok(self
+            .table
+            .abs_path(&self.table.files()[self.filtered_indices[index]]))
+    }
+
+    /// Parse a selection of files such as '3-7' or '1,4,6-8' into the paths of
+    /// the files, relative to the root of the table. The selection 'marks'
+    /// refers to the marked files.
+    fn parse_selection(&self, selection: &str) -> Result<Vec<PathBuf>, Error> {
+        if selection.trim() == "marks" {
+            return match self.marks.is_empty() {
+                true => Err(Error::InvalidCommand(String::from("No files are marked."))),
+                false => Ok(self.marks.iter().cloned().collect()),
+            };
+        }
+        let mut paths = Vec::new();
+        for part in selection.split(',') {
+            let range = match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (self.parse_index(first)?, self.parse_index(last)?);
+                    if first > last {
+                        return Err(Error::InvalidCommand(format!(
+                            "'{part}' is not a valid range of indices."
+                        )));
+                    }
+                    first..=last
+                }
+                None => {
+                    let index = self.parse_index(part)?;
+                    index..=index
+                }
+            };
+            paths.extend(
+                self.filtered_indices[range]
+                    .iter()
+                    .map(|fi| PathBuf::from(&self.table.files()[*fi])),
+            );
+        }
+        Ok(paths)
+    }
+
+    /// Run the command template once for each of the files, from the root of
+    /// the table, and return the combined output.
+    pub fn exec(&self, paths: &[PathBuf], template: &str) -> String {
+        let mut echo = String::new();
+        for path in paths {
+            let output = match core::build_command(template, path, None) {
+                Some(mut cmd) => cmd.current_dir(self.table.path()).output(),
+                None => return String::from("The command is empty."),
+            };
+            match output {
+                Ok(output) => {
+                    echo.push_str(&String::from_utf8_lossy(&output.stdout));
+                    echo.push_str(&String::from_utf8_lossy(&output.stderr));
+                    if !output.status.success() {
+                        echo.push_str(&format!(
+                            "Command failed for '{}' with {}\n",
+                            path.display(),
+                            output.status
+                        ));
+                    }
+                }
+                Err(e) => return format!("Unable to run the command: {}", e),
+            }
+        }
+        echo
     }
 
     fn parse_command(&mut self) -> Result<Command, Error> {
@@ -115,23 +292,122 @@ impl InteractiveSession {
             Some("exit") => Ok(Command::Exit),
             Some("quit") => Ok(Command::Exit),
             Some("reset") => Ok(Command::Reset),
+            Some("reload") => Ok(Command::Reload(None)),
+            Some("unmark") => Ok(Command::Unmark(None)),
+            Some("marks") => Ok(Command::Marks),
             Some(cmd) => match cmd.split_once(char::is_whitespace) {
                 Some(("whatis", numstr)) => {
                     Ok(Command::WhatIs(self.parse_index_to_filepath(numstr)?))
                 }
-                Some(("open", numstr)) => Ok(Command::Open(self.parse_index_to_filepath(numstr)?)),
+                Some(("open", selection)) => Ok(Command::Open(
+                    self.parse_selection(selection)?
+                        .iter()
+                        .map(|path| self.table.abs_path(&path.to_string_lossy()))
+                        .collect(),
+                )),
+                Some(("mark", selection)) => Ok(Command::Mark(self.parse_selection(selection)?)),
+                Some(("unmark", selection)) => {
+                    Ok(Command::Unmark(Some(self.parse_selection(selection)?)))
+                }
+                Some(("add", args)) => match args.trim().split_once(char::is_whitespace) {
+                    Some((selection, tags)) => Ok(Command::AddTags(
+                        self.parse_selection(selection)?,
+                        split_tags(tags).map(|t| t.to_string()).collect(),
+                    )),
+                    None => Err(Error::InvalidCommand(String::from(
+                        "Expected a selection of files followed by tags, such as '/add marks vacation'.",
+                    ))),
+                },
+                Some(("reload", dir)) => Ok(Command::Reload(Some(PathBuf::from(dir.trim())))),
+                Some(("exec", args)) => match args.trim().split_once(char::is_whitespace) {
+                    Some((selection, template)) => Ok(Command::Exec(
+                        self.parse_selection(selection)?,
+                        template.trim().to_string(),
+                    )),
+                    None => Err(Error::InvalidCommand(String::from(
+                        "Expected a selection of files followed by a command, such as '/exec 3-7 cp {} backup/'.",
+                    ))),
+                },
                 _ => Err(Error::InvalidCommand(cmd.to_string())),
             },
-            None => Ok(Command::Filter(
-                Filter::parse(
-                    &format!("{} {cmd}", self.filter_str),
-                    self.table.tag_parse_fn(),
-                )
-                .map_err(Error::InvalidFilter)?,
-            )),
+            None => {
+                let input = self.refine_filter(cmd);
+                Ok(Command::Filter(
+                    self.table
+                        .parse_filter(&input)
+                        .map_err(Error::InvalidFilter)?,
+                    input,
+                ))
+            }
+        }
+    }
+
+    /// Refine the current filter with the input `cmd`. Input that begins with
+    /// a binary operator continues the current filter, otherwise it is combined
+    /// with the current filter as a whole.
+    fn refine_filter(&self, cmd: &str) -> String {
+        if self.filter_input.is_empty() || cmd.starts_with(['&', '|', '^']) {
+            format!("{} {cmd}", self.filter_input)
+        } else {
+            format!("({}) & ({cmd})", self.filter_input)
         }
     }
 
+    /// Show the files that match the filter being typed in the command line,
+    /// as it would be applied, without applying it. The files of the current
+    /// filter are shown while the command line is empty, or its filter can't
+    /// be parsed yet. Commands starting with '/' are left alone.
+    pub fn preview_filter(&mut self) {
+        let cmd = self.command.trim();
+        if cmd.starts_with('/') {
+            return;
+        }
+        let parse = |input: &str| self.table.parse_filter(input).ok();
+        let filter = match cmd.is_empty() {
+            true => None,
+            false => parse(&self.refine_filter(cmd)),
+        }
+        .or_else(|| parse(&self.filter_input));
+        self.filtered_indices = match filter {
+            Some(filter) => self.table.matching_files(&filter.simplify()),
+            None => (0..self.num_files()).collect(),
+        };
+        self.update_lists();
+        self.state = State::ListsUpdated;
+    }
+
+    /// Absolute paths of the marked files, one per line, so they can be
+    /// copied.
+    fn list_marks(&self) -> String {
+        match self.marks.is_empty() {
+            true => String::from("No files are marked."),
+            false => self
+                .marks
+                .iter()
+                .map(|path| {
+                    self.table
+                        .abs_path(&path.to_string_lossy())
+                        .display()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Add `tags` to the files at `paths`, and reload the table.
+    fn add_tags(&mut self, paths: &[PathBuf], tags: &[String]) -> Result<(), core::Error> {
+        if let Some(url) = paths.iter().find(|p| is_url(&p.to_string_lossy())) {
+            return Err(core::Error::InvalidPath(url.clone()));
+        }
+        core::add_tags(self.table.path(), paths, tags)?.apply(&self.config)?;
+        self.refresh()
+    }
+
+    pub fn is_marked(&self, file: &str) -> bool {
+        self.marks.contains(Path::new(file))
+    }
+
     fn num_files(&self) -> usize {
         self.table.files().len()
     }
@@ -149,13 +425,7 @@ impl InteractiveSession {
         active: &mut [bool],
         dst: &mut Vec<String>,
     ) {
-        active.fill(false);
-        for flags in indices.iter().map(|i| table.flags(*i)) {
-            active
-                .iter_mut()
-                .zip(flags.iter())
-                .for_each(|(dst, src)| *dst = *dst || *src);
-        }
+        table.tags_of_files(indices, active);
         dst.clear();
         dst.extend(tags.iter().zip(0..table.tags().len()).filter_map(|(t, i)| {
             if active[i] {
@@ -164,6 +434,8 @@ impl InteractiveSession {
                 None
             }
         }));
+        // Keep the tags that share a namespace together.
+        dst.sort_unstable_by(|a, b| namespace_order(a).cmp(&namespace_order(b)));
     }
 
     fn update_lists(&mut self) {
@@ -182,7 +454,7 @@ impl InteractiveSession {
     }
 
     fn last_word_start(&self) -> usize {
-        const DELIMS: &str = " ()&|!/";
+        const DELIMS: &str = " ()&|^!/";
         DELIMS
             .chars()
             .map(|ch| match self.command.rfind(ch) {
@@ -197,6 +469,15 @@ impl InteractiveSession {
         &self.table
     }
 
+    /// The table, to share it with another session.
+    pub fn shared_table(&self) -> &Arc<TagTable> {
+        &self.table
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
     pub fn taglist(&self) -> &[String] {
         &self.taglist
     }
@@ -233,34 +514,69 @@ impl InteractiveSession {
         &self.filter_str
     }
 
+    /// Absolute path of the file at `index` in the list of filtered files.
+    pub fn file_path(&self, index: usize) -> Option<PathBuf> {
+        self.filtered_indices
+            .get(index)
+            .map(|fi| self.table.abs_path(&self.table.files()[*fi]))
+    }
+
+    /// Show the description of the file at `path` in the echo area.
+    pub fn describe(&mut self, path: &Path) {
+        self.echo = what_is(path, &self.config)
+            .map(|d| d.to_string())
+            .unwrap_or(String::from(
+                "Unable to fetch the description of this file.",
+            ));
+    }
+
+    /// Open the files at `paths` with their default applications.
+    pub fn open(&mut self, paths: &[PathBuf]) {
+        if paths.iter().any(|p| open_file(p, &self.config).is_err()) {
+            self.echo = String::from("Unable to open the file.");
+        }
+    }
+
     pub fn process_input(&mut self) {
         match self.state {
             State::ListsUpdated | State::Default => {
                 match self.parse_command() {
                     Ok(cmd) => match cmd {
                         Command::Exit => self.state = State::Exit,
-                        Command::WhatIs(path) => {
-                            self.echo = what_is(&path)
-                                .unwrap_or(String::from(
-                                    "Unable to fetch the description of this file.",
-                                ))
-                                .to_string();
+                        Command::WhatIs(path) => self.describe(&path),
+                        Command::Filter(filter, input) => self.apply_filter(filter, input),
+                        Command::Reset => self.reset(),
+                        Command::Open(paths) => self.open(&paths),
+                        Command::Exec(paths, template) => self.echo = self.exec(&paths, &template),
+                        Command::Reload(dir) => {
+                            if let Err(e) = match dir {
+                                Some(dir) => self.refresh_dir(&dir),
+                                None => self.refresh(),
+                            } {
+                                self.echo = format!("{:?}", e);
+                            }
                         }
-                        Command::Filter(filter) => {
-                            self.filtered_indices.clear();
-                            self.filtered_indices.extend(
-                                (0..self.num_files())
-                                    .filter(|fi| filter.eval(|ti| self.table.flags(*fi)[ti])),
-                            );
-                            self.update_lists();
-                            self.filter_str = filter.text(self.table.tags());
-                            self.state = State::ListsUpdated;
+                        Command::Mark(paths) => {
+                            self.marks.extend(paths);
+                            self.echo = format!("{} files marked.", self.marks.len());
+                        }
+                        Command::Unmark(Some(paths)) => {
+                            paths.iter().for_each(|path| {
+                                self.marks.remove(path);
+                            });
+                            self.echo = format!("{} files marked.", self.marks.len());
+                        }
+                        Command::Unmark(None) => {
+                            self.marks.clear();
+                            self.echo = String::from("0 files marked.");
+                        }
+                        Command::Marks => self.echo = self.list_marks(),
+                        Command::AddTags(paths, tags) => {
+                            self.echo = match self.add_tags(&paths, &tags) {
+                                Ok(_) => format!("Added tags to {} files.", paths.len()),
+                                Err(e) => format!("{:?}", e),
+                            }
                         }
-                        Command::Reset => self.reset(),
-                        Command::Open(path) => match opener::open(path) {
-                            Ok(_) => {} // Do nothing.
-                            Err(_) => self.echo = String::from("Unable to open the file."),
-                        },
                     },
                     Err(e) => self.echo = format!("{:?}", e),
                 }
@@ -291,6 +607,15 @@ impl InteractiveSession {
                 self.echo.push_str(&format!(" {} ", suggestion));
             }
         }
+        if !self.command.starts_with('/') {
+            if let Some(desc) = self
+                .suggestions
+                .get(self.suggestion_index)
+                .and_then(|tag| self.config.describe(tag))
+            {
+                self.echo.push_str(&format!("\n{}", desc));
+            }
+        }
     }
 
     pub fn autocomplete(&mut self) {
@@ -310,9 +635,11 @@ impl InteractiveSession {
                             }
                         }));
                 } else {
+                    // Aliases are completed to their canonical form.
+                    let config = &self.config;
                     self.suggestions
                         .extend(self.table.tags().iter().filter_map(|t| {
-                            if t.starts_with(word) {
+                            if t.starts_with(word) || config.alias_matches(t, word) {
                                 Some(t.to_string())
                             } else {
                                 None
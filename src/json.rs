@@ -0,0 +1,347 @@
+use std::fmt::{Display, Formatter, Write};
+
+/*
+The output of the CLI can be formatted as JSON, to be consumed by scripts and
+editors. The output is small and made of strings, numbers and lists, so it is
+built as a tree of values and written out, instead of pulling in a
+serialization library.
+ */
+
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(usize),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    pub fn string(text: impl Into<String>) -> Json {
+        Json::String(text.into())
+    }
+
+    /// Array of strings.
+    pub fn strings<T: AsRef<str>>(items: impl IntoIterator<Item = T>) -> Json {
+        Json::Array(
+            items
+                .into_iter()
+                .map(|s| Json::string(s.as_ref()))
+                .collect(),
+        )
+    }
+}
+
+fn write_str(text: &str, f: &mut Formatter<'_>) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in text.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl Display for Json {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::Float(value) => write!(f, "{}", value),
+            Json::String(text) => write_str(text, f),
+            Json::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(fields) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_str(key, f)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+/// A JSON value read from text. Unlike `Json`, which is only written, the keys
+/// of its objects are owned.
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Parse a single JSON value from `text`. The error says what was expected,
+    /// and at which byte.
+    pub fn parse(text: &str) -> Result<Value, String> {
+        let mut parser = Parser { text, pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.pos == text.len() {
+            true => Ok(value),
+            false => Err(parser.error("the end of the text")),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Value of the field `key`, if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+/// Read the 4 hex digits of a unicode escape.
+fn hex_code(chars: &mut std::str::CharIndices) -> Option<u32> {
+    let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+    match hex.len() == 4 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        true => u32::from_str_radix(&hex, 16).ok(),
+        false => None,
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, expected: &str) -> String {
+        format!("Expected {} at byte {}.", expected, self.pos)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    /// Consume `token` if the text continues with it.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.text[self.pos..].starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        let rest = &self.text[self.pos..];
+        match rest.chars().next() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Value::String),
+            Some('-' | '0'..='9') => self.number(),
+            _ if self.eat("null") => Ok(Value::Null),
+            _ if self.eat("true") => Ok(Value::Bool(true)),
+            _ if self.eat("false") => Ok(Value::Bool(false)),
+            _ => Err(self.error("a value")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.eat("{");
+        let mut fields = Vec::new();
+        if self.eat("}") {
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            if !self.eat(":") {
+                return Err(self.error("':'"));
+            }
+            fields.push((key, self.value()?));
+            if self.eat("}") {
+                return Ok(Value::Object(fields));
+            } else if !self.eat(",") {
+                return Err(self.error("',' or '}'"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.eat("[");
+        let mut items = Vec::new();
+        if self.eat("]") {
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            if self.eat("]") {
+                return Ok(Value::Array(items));
+            } else if !self.eat(",") {
+                return Err(self.error("',' or ']'"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if !self.text[self.pos..].starts_with('"') {
+            return Err(self.error("a string"));
+        }
+        self.pos += 1;
+        let mut out = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(out);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('/') => out.push('/'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('n') => out.push('\n'),
+                    Some('r') => out.push('\r'),
+                    Some('t') => out.push('\t'),
+                    Some('u') => {
+                        let c = match hex_code(&mut chars) {
+                            // A surrogate pair.
+                            Some(high @ 0xd800..=0xdbff) => match (chars.next(), chars.next()) {
+                                (Some((_, '\\')), Some((_, 'u'))) => hex_code(&mut chars)
+                                    .filter(|low| (0xdc00..=0xdfff).contains(low))
+                                    .and_then(|low| {
+                                        char::from_u32(
+                                            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                                        )
+                                    }),
+                                _ => None,
+                            },
+                            Some(code) => char::from_u32(code),
+                            None => None,
+                        };
+                        match c {
+                            Some(c) => out.push(c),
+                            None => {
+                                self.pos += i;
+                                return Err(self.error("a unicode escape"));
+                            }
+                        }
+                    }
+                    _ => {
+                        self.pos += i;
+                        return Err(self.error("an escape sequence"));
+                    }
+                },
+                c if c.is_control() => {
+                    self.pos += i;
+                    return Err(self.error("an escaped control character"));
+                }
+                c => out.push(c),
+            }
+        }
+        self.pos = self.text.len();
+        Err(self.error("'\"'"))
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+            .unwrap_or(rest.len());
+        match rest[..len].parse::<f64>() {
+            Ok(number) if number.is_finite() => {
+                self.pos += len;
+                Ok(Value::Number(number))
+            }
+            _ => Err(self.error("a number")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_json_display() {
+        let value = Json::Object(vec![
+            ("path", Json::string("dir/a \"b\"\\c\n.txt")),
+            ("tags", Json::strings(["x", "y"])),
+            ("count", Json::Number(3)),
+            ("average", Json::Float(2.5)),
+            ("virtual", Json::Bool(false)),
+            ("desc", Json::Null),
+            ("empty", Json::Array(Vec::new())),
+            ("bell", Json::string("\u{7}")),
+        ]);
+        assert_eq!(
+            value.to_string(),
+            r#"{"path":"dir/a \"b\"\\c\n.txt","tags":["x","y"],"count":3,"average":2.5,"virtual":false,"desc":null,"empty":[],"bell":"\u0007"}"#
+        );
+        // What is written is read back.
+        let parsed = Value::parse(&value.to_string()).unwrap();
+        assert_eq!(
+            parsed.get("path"),
+            Some(&Value::String(String::from("dir/a \"b\"\\c\n.txt")))
+        );
+        assert_eq!(parsed.get("count"), Some(&Value::Number(3.0)));
+        assert_eq!(parsed.get("desc"), Some(&Value::Null));
+        assert_eq!(
+            parsed.get("bell"),
+            Some(&Value::String(String::from("\u{7}")))
+        );
+    }
+
+    #[test]
+    fn t_json_parse() {
+        assert_eq!(
+            Value::parse(" [1, -2.5e1, true, {\"a\": [] }, \"\\ud83d\\ude00\\/\"] ").unwrap(),
+            Value::Array(vec![
+                Value::Number(1.0),
+                Value::Number(-25.0),
+                Value::Bool(true),
+                Value::Object(vec![(String::from("a"), Value::Array(Vec::new()))]),
+                Value::String(String::from("\u{1f600}/")),
+            ])
+        );
+        for text in [
+            "",
+            "[1,]",
+            "{\"a\" 1}",
+            "\"open",
+            "\"\\ud83d\"",
+            "[1] 2",
+            "nul",
+        ] {
+            assert!(Value::parse(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -1,8 +1,49 @@
+//! Tag files with the .ftag files in their directories, and query them.
+//!
+//! [`Repo`] is the interface for other programs that want to use ftag as a
+//! library, instead of running the `ftag` binary and parsing its output:
+//!
+//! ```no_run
+//! let repo = ftag::Repo::open("/path/to/archive")?;
+//! for path in repo.query("photo & 2023 & !blurry")? {
+//!     println!("{}", path.display());
+//! }
+//! println!("{}", repo.what_is("notes.txt")?);
+//! # Ok::<(), ftag::Error>(())
+//! ```
+//!
+//! The other public modules are shared by the `ftag` and `ftagui` binaries,
+//! and may change between releases.
+
+pub mod archive;
+pub mod audio;
+pub mod bench;
+pub mod cache;
+pub mod config;
 pub mod core;
+pub mod diff;
+pub mod doctor;
 pub mod filter;
+pub mod index;
 pub mod interactive;
+pub mod json;
 pub mod load;
 pub mod query;
+pub mod relink;
+pub mod repo;
+pub mod schema;
+#[cfg(feature = "tui")]
+pub mod triage;
+#[cfg(feature = "tui")]
 pub mod tui;
+mod util;
+pub mod vfs;
+pub mod watch;
+
+pub mod walk;
 
-mod walk;
+pub use config::Config;
+pub use core::{Description, Error, Problem};
+pub use load::{parse_ftag, DirData, GlobData, OwnedDirData, OwnedGlobData};
+pub use repo::Repo;
+pub use walk::{Walk, WalkLimits, WalkedDir};
@@ -1,16 +1,17 @@
 use crate::{
     core::{Error, FTAG_BACKUP_FILE, FTAG_FILE},
+    vfs::FileSystem,
     walk::DirEntry,
 };
 use aho_corasick::{AhoCorasick, Match};
 use fast_glob::glob_match;
 use smallvec::SmallVec;
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     ffi::OsStr,
     fmt::Display,
-    fs::File,
-    io::Read,
-    ops::Range,
+    ops::{Range, RangeInclusive},
     path::{Path, PathBuf},
     sync::LazyLock,
 };
@@ -18,6 +19,8 @@ use std::{
 pub(crate) enum Tag<'a> {
     Text(&'a str),
     Year(u16),
+    Month(u8),
+    Date(u16, u8, u8),
     Format(&'a str),
 }
 
@@ -26,6 +29,8 @@ impl Display for Tag<'_> {
         match self {
             Tag::Text(t) | Tag::Format(t) => write!(f, "{}", t),
             Tag::Year(y) => write!(f, "{}", y),
+            Tag::Month(m) => write!(f, "month:{:02}", m),
+            Tag::Date(y, m, d) => write!(f, "date:{:04}-{:02}-{:02}", y, m, d),
         }
     }
 }
@@ -66,33 +71,147 @@ fn infer_year_range(mut input: &str) -> Option<Range<u16>> {
     Some(first..(first + 1))
 }
 
-/// Get an iterator over tags inferred from the format of the file. The input is
-/// expected to be the path / name of the file.
-fn infer_format_tag(input: &str) -> impl Iterator<Item = Tag> + use<'_> {
-    const EXT_TAG_MAP: &[(&[&str], &str)] = &[
-        (&[".mov", ".flv", ".mp4", ".3gp"], "video"),
-        (&[".png", ".jpg", ".jpeg", ".bmp", ".webp", ".gif"], "image"),
-    ];
-    EXT_TAG_MAP.iter().filter_map(|(exts, tag)| {
-        if exts
-            .iter()
-            .any(|ext| input[input.len().saturating_sub(ext.len())..].eq_ignore_ascii_case(ext))
-        {
-            Some(Tag::Format(tag))
-        } else {
-            None
+/// Get the number of days in the month of the year.
+pub(crate) fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
         }
-    })
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Try to infer a date such as '2021-07-14' or '2021_07_14' from the start of
+/// the name of a document or file.
+fn infer_date(input: &str) -> Option<(u16, u8, u8)> {
+    let bytes = input.as_bytes();
+    let sep = *bytes.get(4)?;
+    if !(sep == b'-' || sep == b'_') || bytes.get(7) != Some(&sep) {
+        return None;
+    }
+    if bytes.get(10).is_some_and(u8::is_ascii_digit) {
+        return None;
+    }
+    let number = |range: Range<usize>| -> Option<u16> {
+        let word = input.get(range)?;
+        match word.bytes().all(|b| b.is_ascii_digit()) {
+            true => word.parse().ok(),
+            false => None,
+        }
+    };
+    let (year, month, day) = (number(0..4)?, number(5..7)? as u8, number(8..10)? as u8);
+    match (1..=12).contains(&month) && (1..=days_in_month(year, month)).contains(&day) {
+        true => Some((year, month, day)),
+        false => None,
+    }
+}
+
+/// File extensions, and the format tags inferred from them by default.
+const FORMAT_EXTENSIONS: &[(&[&str], &str)] = &[
+    (&[".mov", ".flv", ".mp4", ".3gp"], "video"),
+    (&[".png", ".jpg", ".jpeg", ".bmp", ".webp", ".gif"], "image"),
+];
+
+/// Check if the file name ends with the extension, ignoring case.
+fn has_extension(name: &[u8], ext: &str) -> bool {
+    name[name.len().saturating_sub(ext.len())..].eq_ignore_ascii_case(ext.as_bytes())
 }
 
-/// Get an iterator over all the implicit tags that can be inferred
-/// from the name of the file or directory.
-pub(crate) fn infer_implicit_tags(name: &str) -> impl Iterator<Item = Tag> + use<'_> {
-    infer_year_range(name)
-        .into_iter()
-        .flatten()
-        .map(Tag::Year)
-        .chain(infer_format_tag(name))
+/// Check if the path of an entry in a .ftag file is a URL, such as a bookmark or
+/// a document in the cloud, rather than a glob of files on the disk.
+pub fn is_url(path: &str) -> bool {
+    match path.split_once("://") {
+        Some((scheme, rest)) => {
+            !rest.is_empty()
+                && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        }
+        None => false,
+    }
+}
+
+/// Check if the file name has any of the extensions.
+pub(crate) fn has_any_extension<T: AsRef<str>>(name: &OsStr, exts: &[T]) -> bool {
+    exts.iter()
+        .any(|ext| has_extension(name.as_encoded_bytes(), ext.as_ref()))
+}
+
+/// Rules for inferring the implicit tags from the names of files and
+/// directories.
+#[derive(Clone)]
+pub(crate) struct Inference {
+    /// Years outside this range are not inferred. `None` disables the years.
+    pub years: Option<RangeInclusive<u16>>,
+    /// Infer the month and date tags from names that start with a date. Dates
+    /// outside the range of years are not inferred.
+    pub dates: bool,
+    /// Format tags, and the extensions they're inferred from.
+    pub formats: Vec<(String, Vec<String>)>,
+}
+
+impl Default for Inference {
+    fn default() -> Self {
+        Inference {
+            years: Some(0..=u16::MAX),
+            dates: true,
+            formats: FORMAT_EXTENSIONS
+                .iter()
+                .map(|(exts, tag)| {
+                    (
+                        tag.to_string(),
+                        exts.iter().map(|e| e.to_string()).collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Inference {
+    /// Get an iterator over the tags inferred from the format of the file. The
+    /// input is expected to be the path / name of the file.
+    fn format_tags<'a>(&'a self, input: &'a str) -> impl Iterator<Item = Tag<'a>> {
+        self.formats.iter().filter_map(|(tag, exts)| {
+            if exts.iter().any(|ext| has_extension(input.as_bytes(), ext)) {
+                Some(Tag::Format(tag))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Get an iterator over all the implicit tags that can be inferred from
+    /// the name of the file or directory.
+    pub fn tags<'a>(&'a self, name: &'a str) -> impl Iterator<Item = Tag<'a>> {
+        let date = match self.dates {
+            true => infer_date(name)
+                .filter(|(year, ..)| self.years.as_ref().is_none_or(|years| years.contains(year))),
+            false => None,
+        };
+        self.years
+            .as_ref()
+            .and_then(|years| Some((years, infer_year_range(name)?)))
+            .into_iter()
+            .flat_map(|(years, range)| range.filter(|y| years.contains(y)))
+            .map(Tag::Year)
+            .chain(
+                date.into_iter()
+                    .flat_map(|(y, m, d)| [Tag::Month(m), Tag::Date(y, m, d)]),
+            )
+            .chain(self.format_tags(name))
+    }
+
+    /// Get the extensions of the files from which the format tag `tag` is
+    /// inferred. This is `None` if `tag` is not a format tag.
+    pub fn format_extensions(&self, tag: &str) -> Option<&[String]> {
+        self.formats
+            .iter()
+            .find_map(|(t, exts)| (t == tag).then_some(exts.as_slice()))
+    }
 }
 
 /// Get the filename from the path as a string. If the path cannot be a valid
@@ -107,6 +226,10 @@ pub(crate) fn get_filename_str(path: &Path) -> Result<&str, Error> {
     })
 }
 
+/// Directories with at least these many files are matched with globs on
+/// multiple threads.
+const PARALLEL_MATCH_THRESHOLD: usize = 4096;
+
 /// This datastructure is responsible for finding matches between the
 /// files on disk, and globs listed in the ftag file. This can be
 /// reused for multiple folders to avoid reallocations.
@@ -133,11 +256,103 @@ impl GlobMatches {
         files: &[DirEntry],
         globs: &[GlobData],
         short_circuit_globs: bool,
+    ) {
+        self.find_matches_where(files, globs, short_circuit_globs, &|_| true);
+    }
+
+    /// Same as `find_matches`, but only the files for which `keep` returns
+    /// true are matched with the globs. The other files are treated as if they
+    /// don't match any glob.
+    pub fn find_matches_where(
+        &mut self,
+        files: &[DirEntry],
+        globs: &[GlobData],
+        short_circuit_globs: bool,
+        keep: &(dyn Fn(&DirEntry) -> bool + Sync),
+    ) {
+        self.find_matches_on_threads(
+            files,
+            globs,
+            short_circuit_globs,
+            keep,
+            std::thread::available_parallelism().map_or(1, |n| n.get()),
+        );
+    }
+
+    /// Same as `find_matches_where`, but using at most `nthreads` threads.
+    fn find_matches_on_threads(
+        &mut self,
+        files: &[DirEntry],
+        globs: &[GlobData],
+        short_circuit_globs: bool,
+        keep: &(dyn Fn(&DirEntry) -> bool + Sync),
+        nthreads: usize,
     ) {
         self.file_matches.clear();
         self.file_matches.resize(files.len(), SmallVec::new());
         self.glob_matches.clear();
         self.glob_matches.resize(globs.len(), false);
+        if files.len() < PARALLEL_MATCH_THRESHOLD || nthreads < 2 {
+            Self::match_chunk(
+                files,
+                0,
+                &mut self.file_matches,
+                globs,
+                &mut self.glob_matches,
+                short_circuit_globs,
+                keep,
+            );
+            return;
+        }
+        /* Large directories are split into chunks of files that are matched
+         * on separate threads. Each chunk writes the matches of its own files,
+         * so the results are the same as matching them on a single thread.
+         */
+        let chunk_size = files.len().div_ceil(nthreads);
+        let glob_matches = &mut self.glob_matches;
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .file_matches
+                .chunks_mut(chunk_size)
+                .enumerate()
+                .map(|(ci, file_matches)| {
+                    scope.spawn(move || {
+                        let mut glob_matches = vec![false; globs.len()];
+                        Self::match_chunk(
+                            files,
+                            ci * chunk_size,
+                            file_matches,
+                            globs,
+                            &mut glob_matches,
+                            short_circuit_globs,
+                            keep,
+                        );
+                        glob_matches
+                    })
+                })
+                .collect();
+            for handle in handles {
+                let matched = handle.join().expect("FATAL: Glob matching thread panicked");
+                for (dst, src) in glob_matches.iter_mut().zip(matched) {
+                    *dst |= src;
+                }
+            }
+        });
+    }
+
+    /// Match `globs` with the chunk of `files` starting at `offset`. The matches
+    /// of the files in the chunk are written to `file_matches`, and the globs
+    /// that match any of the files in the chunk are flagged in `glob_matches`.
+    fn match_chunk(
+        files: &[DirEntry],
+        offset: usize,
+        file_matches: &mut [SmallVec<[usize; 4]>],
+        globs: &[GlobData],
+        glob_matches: &mut [bool],
+        short_circuit_globs: bool,
+        keep: &(dyn Fn(&DirEntry) -> bool + Sync),
+    ) {
+        let chunk = &files[offset..(offset + file_matches.len())];
         'globs: for (gi, g) in globs.iter().enumerate() {
             /* A glob can either directly be a filename or a glob that matches
              * one or more files. Checking for glob matches is MUCH more
@@ -146,18 +361,26 @@ impl GlobMatches {
              * check the remaining files, and move on to the next glob. If and
              * ONLY IF we don't find a diret match with any of the files, we try
              * to match it as a glob. I have tested with and without this
-             * optimization, and it makes a significant difference.
+             * optimization, and it makes a significant difference. The direct
+             * match is searched among all the files, not just the chunk, so
+             * that every chunk agrees on which globs are matched directly.
              */
             let gpath = OsStr::new(g.path);
             if let Ok(fi) = files.binary_search_by(move |f| f.name().cmp(gpath)) {
-                self.file_matches[fi].push(gi);
-                self.glob_matches[gi] = true;
+                if let Some(dst) = fi
+                    .checked_sub(offset)
+                    .and_then(|i| file_matches.get_mut(i))
+                    .filter(|_| keep(&files[fi]))
+                {
+                    dst.push(gi);
+                    glob_matches[gi] = true;
+                }
                 continue 'globs;
             }
-            for (fi, f) in files.iter().enumerate() {
+            for (fi, f) in chunk.iter().enumerate().filter(|(_, f)| keep(f)) {
                 if glob_match(g.path.as_bytes(), f.name().as_encoded_bytes()) {
-                    self.file_matches[fi].push(gi);
-                    self.glob_matches[gi] = true;
+                    file_matches[fi].push(gi);
+                    glob_matches[gi] = true;
                     if short_circuit_globs {
                         break;
                     }
@@ -219,50 +442,90 @@ pub fn get_ftag_backup_path(path: &Path) -> PathBuf {
     dirpath
 }
 
-/// Loads and parses an ftag file. Reuse this to avoid allocations.
+/// Loads and parses an ftag file. Reuse this to avoid allocating a new buffer
+/// for the text of every file.
 pub(crate) struct Loader {
-    // IMPORTANT: This MUST be the first member of the struct, because it holds
-    // references to `raw_text`. Members are dropped in the order they are
-    // listed here, so this ensures the references are dropped before the actual
-    // data.
-    parsed: DirData<'static>,
     raw_text: String,
     options: LoaderOptions,
 }
 
 /// Data in an ftag file, corresponding to one file / glob.
 #[derive(Clone)]
-pub(crate) struct GlobData<'a> {
-    pub desc: Option<&'a str>,
+pub struct GlobData<'a> {
+    /// The glob, relative to the directory of the .ftag file.
     pub path: &'a str,
     tags: Range<usize>,
+    desc: Option<usize>,
 }
 
-/// Data from an ftag file.
+/// Data from an ftag file. It borrows the text it was parsed from.
 #[derive(Default)]
-pub(crate) struct DirData<'a> {
+pub struct DirData<'a> {
+    /// The tags of the directory, followed by the tags of all the entries. Use
+    /// `DirData::tags` and `DirData::glob_tags` to get the tags of each.
     pub alltags: Vec<&'a str>,
-    pub desc: Option<&'a str>,
+    /// Description of the directory.
+    pub desc: Option<Cow<'a, str>>,
     tags: Range<usize>,
+    /// The descriptions of the entries, shared by all the globs of each entry.
+    descs: Vec<Cow<'a, str>>,
+    /// The globs of all the entries, in the order they appear in the file.
     pub globs: Vec<GlobData<'a>>,
 }
 
-impl<'a> GlobData<'a> {
-    pub fn tags(&'a self, alltags: &'a [&'a str]) -> &'a [&'a str] {
-        &alltags[self.tags.start..self.tags.end]
-    }
-}
-
 impl<'a> DirData<'a> {
+    /// Tags of the directory.
     pub fn tags(&'a self) -> &'a [&'a str] {
         &self.alltags[self.tags.start..self.tags.end]
     }
 
-    pub fn reset(&mut self) {
-        self.alltags.clear();
-        self.desc = None;
-        self.tags = 0..0;
-        self.globs.clear();
+    /// Tags of the entry `glob` belongs to. Globs of other directories have no
+    /// tags here.
+    pub fn glob_tags(&self, glob: &GlobData) -> &[&'a str] {
+        self.alltags.get(glob.tags.clone()).unwrap_or_default()
+    }
+
+    /// Description of the entry `glob` belongs to. Globs of other directories
+    /// have no description here.
+    pub fn glob_desc(&self, glob: &GlobData) -> Option<&str> {
+        glob.desc
+            .and_then(|i| self.descs.get(i))
+            .map(|desc| desc.as_ref())
+    }
+}
+
+/// Same as `GlobData`, except it owns its data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedGlobData {
+    pub path: String,
+    pub tags: Vec<String>,
+    pub desc: Option<String>,
+}
+
+/// Same as `DirData`, except it owns its data, so it can outlive the text it
+/// was parsed from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OwnedDirData {
+    pub tags: Vec<String>,
+    pub desc: Option<String>,
+    pub globs: Vec<OwnedGlobData>,
+}
+
+impl From<&DirData<'_>> for OwnedDirData {
+    fn from(data: &DirData<'_>) -> Self {
+        OwnedDirData {
+            tags: data.tags().iter().map(|t| t.to_string()).collect(),
+            desc: data.desc.as_deref().map(str::to_string),
+            globs: data
+                .globs
+                .iter()
+                .map(|g| OwnedGlobData {
+                    path: g.path.to_string(),
+                    tags: data.glob_tags(g).iter().map(|t| t.to_string()).collect(),
+                    desc: data.glob_desc(g).map(str::to_string),
+                })
+                .collect(),
+        }
     }
 }
 
@@ -356,33 +619,183 @@ impl Header {
     }
 }
 
+/// Check if `line` is a comment, i.e. it starts with a `#`, optionally after
+/// some whitespace.
+fn is_comment(line: &str) -> bool {
+    line.trim_start().starts_with('#')
+}
+
+/// Comments found in the text of a .ftag file.
+#[derive(Default)]
+pub(crate) struct Comments<'a> {
+    /// Comments before the first entry, that belong to the directory.
+    pub dir: Vec<&'a str>,
+    /// Comments in the entries, for each of the globs of the entries.
+    pub globs: HashMap<&'a str, Vec<&'a str>>,
+}
+
+/// Check if `mat` is a path header.
+fn is_path_header(mat: &Match) -> bool {
+    matches!(
+        HeaderType::from_u32(mat.pattern().as_u32()),
+        Some(HeaderType::Path)
+    )
+}
+
+/// Globs in the `content` of a path header, except the ones in comments.
+fn content_globs(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|g| !g.is_empty() && !is_comment(g))
+}
+
+/// Find the entries in the text of a .ftag file, as the index of the path
+/// header of each entry in `headers`, and the byte range of the entry. The
+/// comments directly before a path header belong to its entry, so the entry
+/// starts with them, and ends where the next entry starts. The comments before
+/// the first header belong to the directory.
+fn find_entries(input: &str, headers: &[Match]) -> Vec<(usize, Range<usize>)> {
+    let starts: Vec<(usize, usize)> = headers
+        .iter()
+        .enumerate()
+        .filter(|(_, mat)| is_path_header(mat))
+        .map(|(i, mat)| {
+            let before = i.checked_sub(1).map_or(mat.start(), |p| headers[p].end());
+            let mut offset = before;
+            let mut start = None;
+            for line in input[before..mat.start()].split_inclusive('\n') {
+                if is_comment(line) {
+                    start.get_or_insert(offset);
+                } else if !line.trim().is_empty() {
+                    start = None;
+                }
+                offset += line.len();
+            }
+            (i, start.unwrap_or(mat.start()))
+        })
+        .collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(k, &(i, start))| {
+            let end = starts.get(k + 1).map_or(input.len(), |&(_, s)| s);
+            (i, start..end)
+        })
+        .collect()
+}
+
+/// Find the comments in the text of a .ftag file, so that they can be written
+/// back when the file is rewritten from its data. The comments are trimmed.
+pub(crate) fn find_comments(input: &str) -> Comments<'_> {
+    let mut comments = Comments::default();
+    if !input.lines().any(is_comment) {
+        return comments;
+    }
+    let comments_in = |text| {
+        str::lines(text)
+            .filter(|l| is_comment(l))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+    let headers: Vec<Match> = find_headers(input).collect();
+    let entries = find_entries(input, &headers);
+    let dir_end = entries.first().map_or(input.len(), |(_, r)| r.start);
+    comments.dir = comments_in(&input[..dir_end]);
+    for (i, range) in entries {
+        let end = headers.get(i + 1).map_or(input.len(), |n| n.start());
+        let found = comments_in(&input[range]);
+        for glob in content_globs(&input[headers[i].end()..end]) {
+            comments.globs.entry(glob).or_default().extend(&found);
+        }
+    }
+    comments
+}
+
+/// Get the globs of all the entries in the text of a .ftag file, without
+/// loading the rest of it.
+pub(crate) fn text_globs(input: &str) -> Vec<&str> {
+    let headers: Vec<Match> = find_headers(input).collect();
+    headers
+        .iter()
+        .enumerate()
+        .filter(|(_, mat)| is_path_header(mat))
+        .flat_map(|(i, mat)| {
+            let end = headers.get(i + 1).map_or(input.len(), |n| n.start());
+            content_globs(&input[mat.end()..end])
+        })
+        .collect()
+}
+
+/// Get the line number of the byte at `offset` in `input`, starting from 1.
+fn line_number(input: &str, offset: usize) -> usize {
+    input[..offset].matches('\n').count() + 1
+}
+
+/// Find the headers in `input`, except the ones in comments. Everything that
+/// reads the text of a .ftag file finds the headers with this, so that they all
+/// agree on what is a comment.
+fn find_headers(input: &str) -> impl Iterator<Item = Match> + use<'_> {
+    AC_PARSER.find_iter(input).filter(|mat| {
+        let line_start = input[..mat.start()].rfind('\n').map_or(0, |i| i + 1);
+        !is_comment(&input[line_start..mat.start()])
+    })
+}
+
+/// Tags in the `content` of a tags header, except the ones in comments.
+fn content_tags(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .lines()
+        .filter(|line| !is_comment(line))
+        .flat_map(split_tags)
+}
+
+/// Description in the `content` of a desc header, without the comments, and
+/// with LF line endings.
+fn content_desc(content: &str) -> Cow<'_, str> {
+    if !content.contains('\r') && !content.lines().any(is_comment) {
+        return Cow::Borrowed(content);
+    }
+    Cow::Owned(
+        content
+            .lines()
+            .filter(|line| !is_comment(line))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_string(),
+    )
+}
+
+/// Data of the entry currently being parsed, as:
+/// (list of globs, list of tags, optional index of the description).
+type UnitData<'a> = (&'a str, Range<usize>, Option<usize>);
+
 fn load_impl<'text>(
     input: &'text str,
     filepath: &Path,
     options: &LoaderOptions,
-    dst: &mut DirData<'text>,
-) -> Result<(), Error> {
-    let DirData {
-        alltags,
-        desc,
-        tags: dirtags,
-        globs: files,
-    } = dst;
-    let mut headers = AC_PARSER.find_iter(input);
-    // We store the data of the file we're currently parsing as:
-    // (list of globs, list of tags, optional description).
-    let mut current_unit: Option<(&str, Range<usize>, Option<&str>)> = None;
+) -> Result<DirData<'text>, Error> {
+    let mut alltags = Vec::new();
+    let mut desc = None;
+    let mut dirtags = 0..0;
+    let mut descs = Vec::new();
+    let mut files = Vec::new();
+    let mut headers = find_headers(input);
+    let mut current_unit: Option<UnitData> = None;
     // Begin parsing.
     let (mut header, mut content, mut next_header) = match headers.next() {
         Some(mat) => {
             let h = Header::from_match(mat).ok_or(Error::CannotParseFtagFile(
                 filepath.to_path_buf(),
+                line_number(input, mat.start()),
                 "FATAL: Error when searching for headers in the file.".into(),
             ))?;
             let (c, n) = match headers.next() {
                 Some(mat) => {
                     let n = Header::from_match(mat).ok_or(Error::CannotParseFtagFile(
                         filepath.to_path_buf(),
+                        line_number(input, mat.start()),
                         "FATAL: Error when searching for headers in the file.".into(),
                     ))?;
                     let c = input[h.end..n.start].trim();
@@ -395,6 +808,7 @@ fn load_impl<'text>(
         None => {
             return Err(Error::CannotParseFtagFile(
                 filepath.to_path_buf(),
+                1,
                 "File does not contain any headers.".into(),
             ))
         }
@@ -411,30 +825,33 @@ fn load_impl<'text>(
                         let desc = desc.take();
                         let tags = std::mem::replace(tags, 0..0);
                         let lines = std::mem::replace(globs, content).lines();
-                        files.extend(lines.map(|g| GlobData {
-                            desc,
-                            path: g.trim(),
-                            tags: tags.clone(),
-                        }));
+                        files.extend(
+                            lines
+                                .map(str::trim)
+                                .filter(|g| !g.is_empty() && !is_comment(g))
+                                .map(|g| GlobData {
+                                    path: g,
+                                    tags: tags.clone(),
+                                    desc,
+                                }),
+                        );
                     }
                     None => current_unit = Some((content, 0..0, None)),
                 }
             }
             HeaderType::Tags => {
-                if let Some((globs, tags, _desc)) = current_unit.as_mut() {
+                if let Some((_globs, tags, _desc)) = current_unit.as_mut() {
                     if options.include_file_tags() {
                         if tags.start == tags.end {
                             // No tags found for the current unit.
                             let before = alltags.len();
-                            alltags.extend(content.split_whitespace());
+                            alltags.extend(content_tags(content));
                             *tags = before..alltags.len();
                         } else {
                             return Err(Error::CannotParseFtagFile(
                                 filepath.to_path_buf(),
-                                format!(
-                                    "The following globs have more than one 'tags' header:\n{}.",
-                                    globs
-                                ),
+                                line_number(input, header.start),
+                                "The entry has more than one 'tags' header.".into(),
                             ));
                         }
                     }
@@ -442,11 +859,12 @@ fn load_impl<'text>(
                     if dirtags.start == dirtags.end {
                         // No directory tags found.
                         let before = alltags.len();
-                        alltags.extend(content.split_whitespace());
-                        *dirtags = before..alltags.len();
+                        alltags.extend(content_tags(content));
+                        dirtags = before..alltags.len();
                     } else {
                         return Err(Error::CannotParseFtagFile(
                             filepath.to_path_buf(),
+                            line_number(input, header.start),
                             "The directory has more than one 'tags' header.".into(),
                         ));
                     }
@@ -455,27 +873,27 @@ fn load_impl<'text>(
             HeaderType::Desc => {
                 if let Some(file) = &mut current_unit {
                     if options.include_file_desc() {
-                        let (globs, _tags, desc) = file;
+                        let (_globs, _tags, desc) = file;
                         if desc.is_some() {
                             return Err(Error::CannotParseFtagFile(
                                 filepath.to_path_buf(),
-                                format!(
-                                    "Following globs have more than one description:\n{}.",
-                                    globs
-                                ),
+                                line_number(input, header.start),
+                                "The entry has more than one description.".into(),
                             ));
                         } else {
-                            *desc = Some(content);
+                            *desc = Some(descs.len());
+                            descs.push(content_desc(content));
                         }
                     }
                 } else if options.dir_desc {
                     if desc.is_some() {
                         return Err(Error::CannotParseFtagFile(
                             filepath.to_path_buf(),
+                            line_number(input, header.start),
                             "The directory has more than one description.".into(),
                         ));
                     } else {
-                        *desc = Some(content);
+                        desc = Some(content_desc(content));
                     }
                 }
             }
@@ -487,6 +905,7 @@ fn load_impl<'text>(
                     Some(mat) => {
                         let n = Header::from_match(mat).ok_or(Error::CannotParseFtagFile(
                             filepath.to_path_buf(),
+                            line_number(input, mat.start()),
                             "FATAL: Error when searching for headers in the file.".into(),
                         ))?;
                         content = input[header.end..n.start].trim();
@@ -499,13 +918,364 @@ fn load_impl<'text>(
         }
     }
     if let Some((globs, tags, desc)) = current_unit {
-        files.extend(globs.lines().map(|g| GlobData {
-            desc,
-            path: g.trim(),
-            tags: tags.clone(),
-        }));
+        files.extend(
+            globs
+                .lines()
+                .map(str::trim)
+                .filter(|g| !g.is_empty() && !is_comment(g))
+                .map(|g| GlobData {
+                    path: g,
+                    tags: tags.clone(),
+                    desc,
+                }),
+        );
+    }
+    Ok(DirData {
+        alltags,
+        desc,
+        tags: dirtags,
+        descs,
+        globs: files,
+    })
+}
+
+/// Parse the `text` of a .ftag file, with all the tags and descriptions. The
+/// returned data borrows from `text`.
+pub fn parse_ftag(text: &str) -> Result<DirData<'_>, Error> {
+    load_impl(
+        text.trim_start_matches('\u{feff}').trim(),
+        Path::new(FTAG_FILE),
+        &LoaderOptions::new(
+            true,
+            true,
+            FileLoadingOptions::Load {
+                file_tags: true,
+                file_desc: true,
+            },
+        ),
+    )
+}
+
+/// Find all the errors in the text of a .ftag file that would make it fail to
+/// load, instead of stopping at the first one. Each error is reported with the
+/// line number of the header it was found at.
+pub fn validate_text(input: &str) -> Vec<(usize, String)> {
+    let mut errors = Vec::new();
+    let mut headers = find_headers(input).peekable();
+    if headers.peek().is_none() {
+        errors.push((1, String::from("File does not contain any headers.")));
+    }
+    // Whether the directory, or the current entry, has tags and a description.
+    let (mut dir_tags, mut dir_desc) = (false, false);
+    let mut entry: Option<(bool, bool)> = None;
+    while let Some(mat) = headers.next() {
+        let end = headers.peek().map(|n| n.start()).unwrap_or(input.len());
+        let content = &input[mat.end()..end];
+        let line = line_number(input, mat.start());
+        match (HeaderType::from_u32(mat.pattern().as_u32()), entry.as_mut()) {
+            (Some(HeaderType::Path), _) => entry = Some((false, false)),
+            (Some(HeaderType::Tags), Some((tags, _desc))) => {
+                if *tags {
+                    errors.push((
+                        line,
+                        String::from("The entry has more than one 'tags' header."),
+                    ));
+                }
+                *tags |= content_tags(content).next().is_some();
+            }
+            (Some(HeaderType::Tags), None) => {
+                if dir_tags {
+                    errors.push((
+                        line,
+                        String::from("The directory has more than one 'tags' header."),
+                    ));
+                }
+                dir_tags |= content_tags(content).next().is_some();
+            }
+            (Some(HeaderType::Desc), Some((_tags, desc))) => {
+                if *desc {
+                    errors.push((
+                        line,
+                        String::from("The entry has more than one description."),
+                    ));
+                }
+                *desc = true;
+            }
+            (Some(HeaderType::Desc), None) => {
+                if dir_desc {
+                    errors.push((
+                        line,
+                        String::from("The directory has more than one description."),
+                    ));
+                }
+                dir_desc = true;
+            }
+            (None, _) => continue,
+        }
+    }
+    errors
+}
+
+/// Split the content of a tags header into tags. Tags are separated by
+/// whitespace, except when they're enclosed in double quotes, such as
+/// `"new york"`. A quote that is not closed ends at the end of the line.
+pub fn split_tags(content: &str) -> impl Iterator<Item = &str> {
+    let mut rest = content;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let (tag, after) = match rest.strip_prefix('"') {
+            Some(quoted) => match quoted.find(['"', '\n']) {
+                Some(i) if quoted[i..].starts_with('"') => (&quoted[..i], &quoted[i + 1..]),
+                Some(i) => quoted.split_at(i),
+                None => (quoted, ""),
+            },
+            None => rest.split_at(
+                rest.find(|c: char| c.is_whitespace() || c == '"')
+                    .unwrap_or(rest.len()),
+            ),
+        };
+        rest = after;
+        Some(tag.trim())
+    })
+    .filter(|tag| !tag.is_empty())
+}
+
+/// Enclose `tag` in double quotes if it contains whitespace, so that it is
+/// read back as a single tag by `split_tags`.
+pub fn quote_tag(tag: &str) -> Cow<'_, str> {
+    if tag.contains(char::is_whitespace) {
+        Cow::Owned(format!("\"{}\"", tag))
+    } else {
+        Cow::Borrowed(tag)
+    }
+}
+
+/// Append the `tags` to `out`, wrapped to lines of about 80 characters, and
+/// optionally sorted. `tags` is left empty.
+fn push_tags(out: &mut String, tags: &mut Vec<&str>, sort_tags: bool) {
+    if sort_tags {
+        tags.sort_unstable();
+    }
+    let mut len = 0usize;
+    for tag in tags.drain(..).map(quote_tag) {
+        if len > 0 && len + tag.len() > 80 {
+            out.push('\n');
+            len = 0;
+        } else if len > 0 {
+            out.push(' ');
+            len += 1;
+        }
+        out.push_str(&tag);
+        len += tag.len();
+    }
+    if len > 0 {
+        out.push('\n');
+    }
+}
+
+/// Reformat the text of a .ftag file, without changing what it means. Every
+/// header is put on its own line, the globs are trimmed, the tags are wrapped
+/// and optionally sorted, and trailing whitespace is removed from the
+/// descriptions. Unlike `clean`, the entries keep their order and nothing is
+/// removed. The text before the first header is kept as is, because it is
+/// ignored when loading. Comments stay where they are, except that the comments
+/// at the end of a header are moved below the blank line before the next
+/// entry, to stay with the entry they're likely about. Comments in the tags
+/// split them into groups that are wrapped and sorted separately.
+pub(crate) fn format_text(input: &str, sort_tags: bool) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut headers = find_headers(input).peekable();
+    if let Some(first) = headers.peek() {
+        let preamble = input[..first.start()].trim();
+        if !preamble.is_empty() {
+            out.push_str(preamble);
+            out.push('\n');
+        }
+    }
+    // Comments at the end of the previous header.
+    let mut pending: Vec<&str> = Vec::new();
+    let mut tags: Vec<&str> = Vec::new();
+    while let Some(mat) = headers.next() {
+        let end = headers.peek().map(|n| n.start()).unwrap_or(input.len());
+        let mut lines: Vec<&str> = input[mat.end()..end].trim().lines().collect();
+        let kind = HeaderType::from_u32(mat.pattern().as_u32());
+        if let (Some(HeaderType::Path), false) = (&kind, out.is_empty()) {
+            out.push('\n');
+        }
+        for comment in pending.drain(..) {
+            out.push_str(comment);
+            out.push('\n');
+        }
+        let nbody = lines.len() - lines.iter().rev().take_while(|l| is_comment(l)).count();
+        pending.extend(lines.drain(nbody..).map(str::trim));
+        match kind {
+            Some(HeaderType::Path) => {
+                out.push_str("[path]\n");
+                for glob in lines.into_iter().map(str::trim).filter(|g| !g.is_empty()) {
+                    out.push_str(glob);
+                    out.push('\n');
+                }
+            }
+            Some(HeaderType::Tags) => {
+                out.push_str("[tags]\n");
+                for line in lines {
+                    if is_comment(line) {
+                        push_tags(&mut out, &mut tags, sort_tags);
+                        out.push_str(line.trim());
+                        out.push('\n');
+                    } else {
+                        tags.extend(split_tags(line));
+                    }
+                }
+                push_tags(&mut out, &mut tags, sort_tags);
+            }
+            Some(HeaderType::Desc) => {
+                out.push_str("[desc]\n");
+                for line in lines {
+                    out.push_str(line.trim_end());
+                    out.push('\n');
+                }
+            }
+            None => continue,
+        }
+    }
+    for comment in pending {
+        out.push_str(comment);
+        out.push('\n');
+    }
+    out
+}
+
+/// Replace every occurrence of the tag `from` with `to` in the text of a .ftag
+/// file. Only the lines that contain the tag are rewritten, everything else is
+/// preserved as is. If `to` is already present in the same tags header, the tag
+/// is removed instead of being duplicated. Returns the new text, and the number
+/// of tags headers that were changed.
+pub(crate) fn rename_tag_in_text(input: &str, from: &str, to: &str) -> (String, usize) {
+    let mut out = String::with_capacity(input.len());
+    let mut changed = 0usize;
+    let mut last = 0usize;
+    let mut headers = find_headers(input).peekable();
+    while let Some(mat) = headers.next() {
+        if !matches!(
+            HeaderType::from_u32(mat.pattern().as_u32()),
+            Some(HeaderType::Tags)
+        ) {
+            continue;
+        }
+        let end = headers.peek().map(|n| n.start()).unwrap_or(input.len());
+        let content = &input[mat.end()..end];
+        if !content_tags(content).any(|t| t == from) {
+            continue;
+        }
+        changed += 1;
+        out.push_str(&input[last..mat.end()]);
+        let mut has_target = content_tags(content).any(|t| t == to);
+        for line in content.split_inclusive('\n') {
+            if is_comment(line) || !split_tags(line).any(|t| t == from) {
+                out.push_str(line);
+                continue;
+            }
+            let mut tags = Vec::new();
+            for tag in split_tags(line) {
+                if tag != from {
+                    tags.push(quote_tag(tag));
+                } else if !has_target {
+                    tags.push(quote_tag(to));
+                    has_target = true;
+                }
+            }
+            out.push_str(&tags.join(" "));
+            if line.ends_with('\n') {
+                out.push('\n');
+            }
+        }
+        last = end;
+    }
+    out.push_str(&input[last..]);
+    (out, changed)
+}
+
+/// Remove the glob `glob` from the text of a .ftag file. Entries that are left
+/// without any globs are removed along with their tags, description and
+/// comments. Other lines are preserved as is. Returns the new text, and the
+/// number of entries the glob was removed from.
+pub(crate) fn remove_glob_in_text(input: &str, glob: &str) -> (String, usize) {
+    let headers: Vec<Match> = find_headers(input).collect();
+    let mut out = String::with_capacity(input.len());
+    let mut removed = 0usize;
+    let mut last = 0usize;
+    for (i, range) in find_entries(input, &headers) {
+        let mat = &headers[i];
+        let end = headers.get(i + 1).map(|n| n.start()).unwrap_or(input.len());
+        let content = &input[mat.end()..end];
+        if !content.lines().any(|g| g.trim() == glob) {
+            continue;
+        }
+        removed += 1;
+        if content_globs(content).all(|g| g == glob) {
+            // Nothing left in the entry, so remove all of it.
+            out.push_str(&input[last..range.start]);
+            last = range.end;
+        } else {
+            out.push_str(&input[last..mat.end()]);
+            for line in content.split_inclusive('\n') {
+                if line.trim() != glob {
+                    out.push_str(line);
+                }
+            }
+            last = end;
+        }
+    }
+    out.push_str(&input[last..]);
+    (out, removed)
+}
+
+/// Replace the glob `from` with `to` in the text of a .ftag file. Other lines
+/// are preserved as is. Returns the new text, and the number of entries the
+/// glob was replaced in.
+pub(crate) fn rename_glob_in_text(input: &str, from: &str, to: &str) -> (String, usize) {
+    let headers: Vec<Match> = find_headers(input).collect();
+    let mut out = String::with_capacity(input.len());
+    let mut renamed = 0usize;
+    let mut last = 0usize;
+    for (i, mat) in headers.iter().enumerate().filter(|(_, m)| {
+        matches!(
+            HeaderType::from_u32(m.pattern().as_u32()),
+            Some(HeaderType::Path)
+        )
+    }) {
+        let end = headers.get(i + 1).map(|n| n.start()).unwrap_or(input.len());
+        out.push_str(&input[last..mat.end()]);
+        let mut found = false;
+        for line in input[mat.end()..end].split_inclusive('\n') {
+            if line.trim() == from {
+                out.push_str(&line.replacen(from, to, 1));
+                found = true;
+            } else {
+                out.push_str(line);
+            }
+        }
+        renamed += usize::from(found);
+        last = end;
+    }
+    out.push_str(&input[last..]);
+    (out, renamed)
+}
+
+/// Strip the UTF-8 byte order mark, and convert CRLF line endings to LF, so
+/// that files edited on Windows are parsed the same as everywhere else.
+pub(crate) fn normalize_text(text: &mut String) {
+    const BOM: char = '\u{feff}';
+    if text.starts_with(BOM) {
+        text.drain(..BOM.len_utf8());
+    }
+    if text.contains("\r\n") {
+        *text = text.replace("\r\n", "\n");
     }
-    Ok(())
 }
 
 impl Loader {
@@ -513,28 +1283,32 @@ impl Loader {
         Loader {
             raw_text: String::new(),
             options,
-            parsed: Default::default(),
         }
     }
 
-    /// Load the data from a .ftag file specified by the filepath.
-    pub fn load<'a>(&'a mut self, filepath: &Path) -> Result<&'a DirData<'a>, Error> {
+    /// Load the data from a .ftag file at `filepath` in the file system `fs`.
+    pub fn load_from<'a>(
+        &'a mut self,
+        fs: &impl FileSystem,
+        filepath: &Path,
+    ) -> Result<DirData<'a>, Error> {
         self.raw_text.clear();
-        File::open(filepath)
-            .map_err(|_| Error::CannotReadStoreFile(filepath.to_path_buf()))?
-            .read_to_string(&mut self.raw_text)
+        fs.read_to_string(filepath, &mut self.raw_text)
             .map_err(|_| Error::CannotReadStoreFile(filepath.to_path_buf()))?;
-        self.parsed.reset();
-        let borrowed = unsafe {
-            /*
-             * This is safe because the returned `DirData` borrows `self`, and
-             * won't let anyone modify or borrow `self` until that `DirData` is
-             * dropped.
-             */
-            std::mem::transmute::<&'a mut DirData<'static>, &'a mut DirData<'a>>(&mut self.parsed)
-        };
-        load_impl(self.raw_text.trim(), filepath, &self.options, borrowed)?;
-        Ok(borrowed)
+        self.parse(filepath)
+    }
+
+    /// Load the data from the `text` of a .ftag file, such as one from an
+    /// archive. `filepath` is only used to report errors.
+    pub fn load_text<'a>(&'a mut self, text: &str, filepath: &Path) -> Result<DirData<'a>, Error> {
+        self.raw_text.clear();
+        self.raw_text.push_str(text);
+        self.parse(filepath)
+    }
+
+    fn parse(&mut self, filepath: &Path) -> Result<DirData<'_>, Error> {
+        normalize_text(&mut self.raw_text);
+        load_impl(self.raw_text.trim(), filepath, &self.options)
     }
 }
 
@@ -542,29 +1316,319 @@ impl Loader {
 mod test {
     use super::*;
 
+    #[test]
+    fn t_parallel_glob_matches() {
+        let mut names: Vec<String> = (0..(PARALLEL_MATCH_THRESHOLD * 2))
+            .map(|i| format!("f{}.txt", i))
+            .collect();
+        names.sort();
+        let files: Vec<DirEntry> = names.iter().map(|n| DirEntry::file(n)).collect();
+        let globs: Vec<GlobData> = ["*1.txt", "f5*.txt", "f77.txt", "f3.txt", "*.jpg"]
+            .iter()
+            .map(|path| GlobData {
+                path,
+                tags: 0..0,
+                desc: None,
+            })
+            .collect();
+        for short_circuit in [false, true] {
+            let mut expected = GlobMatches::new();
+            expected.find_matches_on_threads(&files, &globs, short_circuit, &|_| true, 1);
+            let mut actual = GlobMatches::new();
+            actual.find_matches_on_threads(&files, &globs, short_circuit, &|_| true, 3);
+            assert_eq!(expected.glob_matches, actual.glob_matches);
+            if !short_circuit {
+                assert_eq!(expected.file_matches, actual.file_matches);
+            }
+        }
+    }
+
+    #[test]
+    fn t_parse_ftag() {
+        let text = "\u{feff}[tags]\r\ndir\r\n[desc]\r\nfirst\r\nsecond\r\n[path]\r\na.txt\r\nb.txt\r\n[tags]\r\nfoo bar\r\n";
+        let data = parse_ftag(text).unwrap();
+        assert_eq!(data.tags(), &["dir"]);
+        assert_eq!(data.desc.as_deref(), Some("first\nsecond"));
+        assert_eq!(data.globs.len(), 2);
+        assert_eq!(data.globs[0].path, "a.txt");
+        assert_eq!(data.globs[1].path, "b.txt");
+        assert_eq!(data.glob_tags(&data.globs[1]), &["foo", "bar"]);
+        // Comments are skipped, even when they look like headers.
+        let text = "# [path] x.txt\n[tags]\ndir\n# old\n[desc]\nfirst\n  # not this\nsecond\n[path]\na.txt\n# b.txt\n[tags]\nfoo # bar\n# baz\n";
+        let data = parse_ftag(text).unwrap();
+        assert_eq!(data.tags(), &["dir"]);
+        assert_eq!(data.desc.as_deref(), Some("first\nsecond"));
+        assert_eq!(data.globs.len(), 1);
+        assert_eq!(data.globs[0].path, "a.txt");
+        assert_eq!(data.glob_tags(&data.globs[0]), &["foo", "#", "bar"]);
+        assert!(parse_ftag("no headers").is_err());
+        // The globs of an entry share its description.
+        let data = parse_ftag("[path]\na.txt\nb.txt\n[desc]\n# c\nabout\n").unwrap();
+        assert_eq!(data.glob_desc(&data.globs[0]), Some("about"));
+        assert_eq!(data.glob_desc(&data.globs[1]), Some("about"));
+    }
+
+    #[test]
+    fn t_load_crlf_bom() {
+        let mut loader = Loader::new(LoaderOptions::new(
+            true,
+            true,
+            FileLoadingOptions::Load {
+                file_tags: true,
+                file_desc: true,
+            },
+        ));
+        let text = "\u{feff}[tags]\r\ndir\r\n[desc]\r\nfirst\r\nsecond\r\n[path]\r\na.txt\r\nb.txt\r\n[tags]\r\nfoo bar\r\n";
+        let data = loader.load_text(text, Path::new(".ftag")).unwrap();
+        assert_eq!(data.tags(), &["dir"]);
+        assert_eq!(data.desc.as_deref(), Some("first\nsecond"));
+        assert_eq!(data.globs.len(), 2);
+        assert_eq!(data.globs[0].path, "a.txt");
+        assert_eq!(data.globs[1].path, "b.txt");
+        assert_eq!(data.glob_tags(&data.globs[1]), &["foo", "bar"]);
+    }
+
+    #[test]
+    fn t_rename_tag_in_text() {
+        let input = "[tags]\nfoo bar\n\n[path]\nfoo.txt\n[desc]\nfoo is here\n[tags]\nbaz\n  foo qux\n[path]\nx\n[tags]\nfoo\n";
+        let (out, changed) = rename_tag_in_text(input, "foo", "baz");
+        assert_eq!(changed, 3);
+        assert_eq!(
+            out,
+            "[tags]\nbaz bar\n\n[path]\nfoo.txt\n[desc]\nfoo is here\n[tags]\nbaz\nqux\n[path]\nx\n[tags]\nbaz\n"
+        );
+        let (out, changed) = rename_tag_in_text(input, "nope", "baz");
+        assert_eq!(changed, 0);
+        assert_eq!(out, input);
+    }
+
+    #[test]
+    fn t_split_tags() {
+        assert_eq!(
+            split_tags(" \"new york\" photo\"san  francisco\"\n\"\" \"open\nx").collect::<Vec<_>>(),
+            vec!["new york", "photo", "san  francisco", "open", "x"]
+        );
+        assert_eq!(quote_tag("photo"), "photo");
+        assert_eq!(quote_tag("new york"), "\"new york\"");
+        let (out, changed) = rename_tag_in_text("[tags]\n\"new york\" photo\n", "new york", "nyc");
+        assert_eq!((out.as_str(), changed), ("[tags]\nnyc photo\n", 1));
+        let (out, _) = rename_tag_in_text("[tags]\nnyc photo\n", "nyc", "new york");
+        assert_eq!(out, "[tags]\n\"new york\" photo\n");
+    }
+
+    #[test]
+    fn t_remove_glob_in_text() {
+        let input = "[tags]\nroot\n\n[path]\na.txt\nb.txt\n[tags]\nfoo\n\n[path]\nb.txt\n[desc]\nabout b\n[tags]\nbar\n\n[path]\nc.txt\n[tags]\nbaz\n";
+        let (out, removed) = remove_glob_in_text(input, "b.txt");
+        assert_eq!(removed, 2);
+        assert_eq!(
+            out,
+            "[tags]\nroot\n\n[path]\na.txt\n[tags]\nfoo\n\n[path]\nc.txt\n[tags]\nbaz\n"
+        );
+        let (out, removed) = remove_glob_in_text(input, "c.txt");
+        assert_eq!(removed, 1);
+        assert_eq!(
+            out,
+            &input[..(input.len() - "[path]\nc.txt\n[tags]\nbaz\n".len())]
+        );
+        let (out, removed) = remove_glob_in_text(input, "*.txt");
+        assert_eq!(removed, 0);
+        assert_eq!(out, input);
+        // The comments of an entry are removed with it, and the ones directly
+        // before the next entry belong to that entry.
+        let input = "# dir\n[tags]\nroot\n\n# a\n[path]\na.txt\n# in a\n[tags]\nfoo\n\n# b\n[path]\nb.txt\n# in b\n";
+        let (out, removed) = remove_glob_in_text(input, "a.txt");
+        assert_eq!(
+            (out.as_str(), removed),
+            ("# dir\n[tags]\nroot\n\n# b\n[path]\nb.txt\n# in b\n", 1)
+        );
+        let (out, removed) = remove_glob_in_text(input, "b.txt");
+        assert_eq!(
+            (out.as_str(), removed),
+            (
+                "# dir\n[tags]\nroot\n\n# a\n[path]\na.txt\n# in a\n[tags]\nfoo\n\n",
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn t_rename_glob_in_text() {
+        let input =
+            "[tags]\na.txt\n\n[path]\na.txt\n  b.txt\n[tags]\nfoo\n# b.txt\n[path]\nb.txt\n";
+        let (out, renamed) = rename_glob_in_text(input, "b.txt", "c.txt");
+        assert_eq!(renamed, 2);
+        assert_eq!(
+            out,
+            "[tags]\na.txt\n\n[path]\na.txt\n  c.txt\n[tags]\nfoo\n# b.txt\n[path]\nc.txt\n"
+        );
+        // Tags and descriptions are not globs.
+        let (out, renamed) = rename_glob_in_text(input, "foo", "bar");
+        assert_eq!((out.as_str(), renamed), (input, 0));
+    }
+
     #[test]
     fn t_infer_year_range() {
         let inputs = vec!["2021_to_2023", "2021_2023"];
         let expected = vec!["2021", "2022", "2023"];
         for input in inputs {
-            let actual: Vec<_> = infer_implicit_tags(input).map(|t| t.to_string()).collect();
+            let actual: Vec<_> = Inference::default()
+                .tags(input)
+                .map(|t| t.to_string())
+                .collect();
             assert_eq!(actual, expected);
         }
         let inputs = vec!["1998_MyDirectory", "1998_MyFile.pdf"];
         let expected = vec!["1998"];
         for input in inputs {
-            let actual: Vec<_> = infer_implicit_tags(input).map(|t| t.to_string()).collect();
+            let actual: Vec<_> = Inference::default()
+                .tags(input)
+                .map(|t| t.to_string())
+                .collect();
             assert_eq!(actual, expected);
         }
     }
 
+    #[test]
+    fn t_infer_dates() {
+        let expected: &[(&str, &[&str])] = &[
+            (
+                "2021-07-14_trip.jpg",
+                &["2021", "month:07", "date:2021-07-14", "image"],
+            ),
+            ("2021_07_14", &["2021", "month:07", "date:2021-07-14"]),
+            ("2020-02-29.txt", &["2020", "month:02", "date:2020-02-29"]),
+            ("2021-02-29.txt", &["2021"]),
+            ("2021-13-01.txt", &["2021"]),
+            ("2021-07_14.txt", &["2021"]),
+            ("2021-07-143.txt", &["2021"]),
+        ];
+        for (input, expected) in expected {
+            let actual: Vec<_> = Inference::default()
+                .tags(input)
+                .map(|t| t.to_string())
+                .collect();
+            assert_eq!(&actual, expected);
+        }
+    }
+
     #[test]
     fn t_infer_format_tags() {
         let inputs = &["test.gif", "ex", "test2.png", "myvid.mov"];
         let expected: &[&[&str]] = &[&["image"], &[], &["image"], &["video"]];
         for (input, expected) in inputs.iter().zip(expected.iter()) {
-            let actual: Vec<_> = infer_format_tag(input).map(|t| t.to_string()).collect();
+            let actual: Vec<_> = Inference::default()
+                .format_tags(input)
+                .map(|t| t.to_string())
+                .collect();
             assert_eq!(&actual, expected);
         }
     }
+
+    #[test]
+    fn t_is_url() {
+        assert!(is_url("https://example.com/doc"));
+        assert!(is_url("s3://bucket/key.pdf"));
+        assert!(is_url("git+ssh://host/repo"));
+        assert!(!is_url("*.txt"));
+        assert!(!is_url("https://"));
+        assert!(!is_url("3d://model"));
+        assert!(!is_url("dir/file://name"));
+    }
+
+    #[test]
+    fn t_format_text() {
+        let input = "notes\n[tags]  b a\n  c\n[desc] Dir.  \n\n[path]\n  y.txt \n\nx.txt\n[tags] z y\n[path]\nmissing.txt\n[desc]\nLine one.  \n\nLine two.\n";
+        assert_eq!(
+            format_text(input, false),
+            "notes\n[tags]\nb a c\n[desc]\nDir.\n\n[path]\ny.txt\nx.txt\n[tags]\nz y\n\n[path]\nmissing.txt\n[desc]\nLine one.\n\nLine two.\n"
+        );
+        assert_eq!(
+            format_text(input, true),
+            "notes\n[tags]\na b c\n[desc]\nDir.\n\n[path]\ny.txt\nx.txt\n[tags]\ny z\n\n[path]\nmissing.txt\n[desc]\nLine one.\n\nLine two.\n"
+        );
+        let formatted = format_text(input, true);
+        assert_eq!(format_text(&formatted, true), formatted);
+        // Comments are kept, and the ones at the end of a header stay with the
+        // next entry.
+        let input = "# Dir comment\n[tags] b a\n# group\n d c\n[path]\nx.txt\n# [path] not a header\n  # about y\n[path]\ny.txt\n[desc]\nText\n";
+        assert_eq!(
+            format_text(input, true),
+            "# Dir comment\n[tags]\na b\n# group\nc d\n\n[path]\nx.txt\n\n# [path] not a header\n# about y\n[path]\ny.txt\n[desc]\nText\n"
+        );
+    }
+
+    #[test]
+    fn t_comments() {
+        let text =
+            "# comment\n[tags]\na # b\n# [tags] c\n[path]\nx.txt\n#y.txt\nz.txt\n[tags]\nd\n";
+        let mut loader = Loader::new(LoaderOptions::new(
+            true,
+            false,
+            FileLoadingOptions::Load {
+                file_tags: true,
+                file_desc: false,
+            },
+        ));
+        let data = loader.load_text(text, Path::new(".ftag")).unwrap();
+        assert_eq!(
+            data.tags()
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>(),
+            ["a", "#", "b"]
+        );
+        assert_eq!(
+            data.globs.iter().map(|g| g.path).collect::<Vec<_>>(),
+            ["x.txt", "z.txt"]
+        );
+        assert!(validate_text(text).is_empty());
+        let (renamed, count) = rename_tag_in_text(text, "c", "e");
+        assert_eq!((renamed.as_str(), count), (text, 0));
+        let comments = find_comments(text);
+        // The comments directly before a path header belong to its entry.
+        assert_eq!(comments.dir, ["# comment"]);
+        assert_eq!(comments.globs["x.txt"], ["# [tags] c", "#y.txt"]);
+        assert_eq!(comments.globs["z.txt"], ["# [tags] c", "#y.txt"]);
+    }
+
+    #[test]
+    fn t_validate_text() {
+        let cases: [(&str, &[usize]); 8] = [
+            ("[tags]\na\n[path]\nx.txt\n[tags]\nb\n[desc]\nd\n", &[]),
+            ("no headers", &[1]),
+            ("[tags]\na\n[tags]\nb\n", &[3]),
+            ("[tags]\n[tags]\nb\n", &[]),
+            (
+                "[desc]\nd\n[path]\nx\n[desc]\n1\n[desc]\n2\n[tags]\na\n[tags]\nb\n",
+                &[7, 11],
+            ),
+            ("[path]\nx\n[tags]\na\n[path]\ny\n[tags]\nb\n", &[]),
+            ("[tags]\n# a\n[tags]\nb\n", &[]),
+            (
+                "[tags]\na\n  # [tags]\n[desc]\nd\n# [desc]\n[tags]\nb\n",
+                &[7],
+            ),
+        ];
+        let mut loader = Loader::new(LoaderOptions::new(
+            true,
+            true,
+            FileLoadingOptions::Load {
+                file_tags: true,
+                file_desc: true,
+            },
+        ));
+        for (input, expected) in cases {
+            let lines: Vec<usize> = validate_text(input).iter().map(|(l, _)| *l).collect();
+            assert_eq!(&lines, expected, "{}", input);
+            // The loader stops at the first error.
+            match loader.load_text(input, Path::new(".ftag")) {
+                Ok(_) => assert!(expected.is_empty()),
+                Err(Error::CannotParseFtagFile(_, line, _)) => {
+                    assert_eq!(Some(&line), expected.first())
+                }
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+    }
 }
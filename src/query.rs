@@ -1,16 +1,387 @@
 use crate::{
-    core::Error,
-    filter::Filter,
+    config::Config,
+    core::{Error, FTAG_FILE},
+    filter::{Filter, FilterParseError},
+    index::indexed_file_system,
     load::{
-        get_filename_str, infer_implicit_tags, FileLoadingOptions, GlobMatches, LoaderOptions, Tag,
+        days_in_month, get_filename_str, has_any_extension, is_url, DirData, FileLoadingOptions,
+        GlobMatches, Loader, LoaderOptions, Tag,
     },
-    walk::{DirTree, MetaData, VisitedDir},
+    vfs::{ArchiveFileSystem, FileSystem},
+    walk::{walk_parallel, DirEntry, DirTree, MetaData, VisitedDir, WalkLimits, WorkQueue},
 };
+use fast_glob::glob_match;
+use smallvec::SmallVec;
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
-    path::{Path, PathBuf},
+    ffi::OsStr,
+    ops::RangeInclusive,
+    path::{Component, Path, PathBuf},
+    sync::{mpsc, Arc},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+/*
+Tags can be namespaced with a ':' separator, such as 'person:alice' or
+'project:ftag'. Namespaces can be nested, such as 'place:france:paris'. A
+wildcard such as 'person:*' in a query matches every tag in that namespace,
+including the tags in nested namespaces. Other glob patterns in a namespace,
+such as 'camera:fuji*', match the tags in that namespace whose names match the
+pattern.
+
+Years are compared as numbers, rather than tags. 'year>=2015' or '2010..2015'
+in a query match every tag that is a year in that range, whether it is
+inferred from the name of a file, or read from a .ftag file. Dates are
+compared the same way: 'date>=2021-01-01' or '2021-01-01..2021-06-30' match
+every tag such as 'date:2021-07-14' whose date is in that range.
+
+'size' and 'mtime' compare the size and the modification time of the files
+instead of their tags, such as 'size>10M' or 'mtime<30d'. The modification time
+can be compared with an age such as '30d', or a date such as '2021-01-01'. The
+walk only reads the metadata of the files if the query has such comparisons.
+Tag tables don't have the metadata, so their filters can't use them.
+
+'untagged' matches the tracked files that have no explicit tags, neither from
+the entries in the .ftag files that match them, nor from their directories or
+the ancestors of their directories. Implicit tags don't count.
+ */
+const NAMESPACE_SEPARATOR: char = ':';
+const NAMESPACE_WILDCARD: &str = ":*";
+const YEAR_PREFIX: &str = "year";
+const YEAR_RANGE_SEPARATOR: &str = "..";
+const DATE_PREFIX: &str = "date";
+const SIZE_PREFIX: &str = "size";
+const MTIME_PREFIX: &str = "mtime";
+pub(crate) const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const UNTAGGED: &str = "untagged";
+
+/// Get the namespace of a tag, i.e. 'place:france' for 'place:france:paris'.
+pub fn tag_namespace(tag: &str) -> Option<&str> {
+    tag.rsplit_once(NAMESPACE_SEPARATOR)
+        .map(|(ns, _)| ns)
+        .filter(|ns| !ns.is_empty())
+}
+
+/// If the tag is a wildcard such as 'person:*', get the namespace it matches.
+fn namespace_wildcard(tag: &str) -> Option<&str> {
+    tag.strip_suffix(NAMESPACE_WILDCARD)
+        .filter(|ns| !ns.is_empty())
+}
+
+/// If the tag is a glob pattern in a namespace, such as 'camera:fuji*', get the
+/// pattern. Plain wildcards such as 'person:*' are not patterns, they're
+/// handled by `namespace_wildcard`. Only namespaced tags can be patterns, so
+/// the meaning of the tags without a namespace doesn't change.
+fn namespace_pattern(tag: &str) -> Option<&str> {
+    match tag.split_once(NAMESPACE_SEPARATOR) {
+        Some((ns, rest))
+            if !ns.is_empty()
+                && rest.contains(['*', '?', '[', '{'])
+                && namespace_wildcard(tag).is_none() =>
+        {
+            Some(tag)
+        }
+        _ => None,
+    }
+}
+
+/// If the tag is a wildcard or a pattern in a namespace, get the predicate
+/// that tells whether another tag matches it.
+fn tag_wildcard(tag: &str) -> Option<impl Fn(&str) -> bool + '_> {
+    let (namespace, pattern) = (namespace_wildcard(tag), namespace_pattern(tag));
+    (namespace.is_some() || pattern.is_some()).then_some(move |t: &str| match namespace {
+        Some(ns) => in_namespace(t, ns),
+        None => pattern.is_some_and(|p| glob_match(p, t)),
+    })
+}
+
+/// If the tag is a comparison such as 'year>=2015' or 'year<2000', or a range
+/// such as '2010..2015', get the range of years it matches. Both ends of a
+/// range are included.
+fn year_range(tag: &str) -> Option<RangeInclusive<u16>> {
+    if let Some((first, last)) = tag.split_once(YEAR_RANGE_SEPARATOR) {
+        return Some(tag_year(first)?..=tag_year(last)?);
+    }
+    let rest = tag.strip_prefix(YEAR_PREFIX)?;
+    let (op, year) = ["<=", ">=", "<", ">"]
+        .iter()
+        .find_map(|op| Some((*op, rest.strip_prefix(op)?.parse::<u16>().ok()?)))?;
+    Some(match op {
+        "<=" => 0..=year,
+        ">=" => year..=u16::MAX,
+        "<" => 0..=year.checked_sub(1)?,
+        _ => year.checked_add(1)?..=u16::MAX,
+    })
+}
+
+/// Get the year of a tag made of exactly four digits, such as the years
+/// inferred from the names of the files.
+fn tag_year(tag: &str) -> Option<u16> {
+    match tag.len() == 4 && tag.bytes().all(|b| b.is_ascii_digit()) {
+        true => tag.parse().ok(),
+        false => None,
+    }
+}
+
+/// Parse a date such as '2021-07-14' into a number such as 20210714, so that
+/// the dates can be compared as numbers.
+fn parse_date(text: &str) -> Option<u32> {
+    let mut parts = text.splitn(3, '-');
+    let mut number = |len: usize| -> Option<u32> {
+        let word = parts.next()?;
+        match word.len() == len && word.bytes().all(|b| b.is_ascii_digit()) {
+            true => word.parse().ok(),
+            false => None,
+        }
+    };
+    let (year, month, day) = (number(4)?, number(2)?, number(2)?);
+    match (1..=12).contains(&month)
+        && (1..=days_in_month(year as u16, month as u8) as u32).contains(&day)
+    {
+        true => Some(year * 10000 + month * 100 + day),
+        false => None,
+    }
+}
+
+/// If the tag is a comparison such as 'date>=2021-01-01', or a range such as
+/// '2021-01-01..2021-06-30', get the range of dates it matches, as parsed by
+/// `parse_date`. Both ends of a range are included.
+fn date_range(tag: &str) -> Option<RangeInclusive<u32>> {
+    if let Some((first, last)) = tag.split_once(YEAR_RANGE_SEPARATOR) {
+        return Some(parse_date(first)?..=parse_date(last)?);
+    }
+    let rest = tag.strip_prefix(DATE_PREFIX)?;
+    let (op, date) = ["<=", ">=", "<", ">"]
+        .iter()
+        .find_map(|op| Some((*op, parse_date(rest.strip_prefix(op)?)?)))?;
+    Some(match op {
+        "<=" => 0..=date,
+        ">=" => date..=u32::MAX,
+        "<" => 0..=date - 1,
+        _ => date + 1..=u32::MAX,
+    })
+}
+
+/// Get the date of a tag such as 'date:2021-07-14', such as the dates inferred
+/// from the names of the files.
+fn tag_date(tag: &str) -> Option<u32> {
+    parse_date(
+        tag.strip_prefix(DATE_PREFIX)?
+            .strip_prefix(NAMESPACE_SEPARATOR)?,
+    )
+}
+
+/// A comparison of the years or the dates of tags.
+#[derive(PartialEq)]
+enum Comparison {
+    Years(RangeInclusive<u16>),
+    Dates(RangeInclusive<u32>),
+}
+
+impl Comparison {
+    fn parse(tag: &str) -> Option<Comparison> {
+        year_range(tag)
+            .map(Comparison::Years)
+            .or_else(|| date_range(tag).map(Comparison::Dates))
+    }
+
+    fn matches(&self, tag: &str) -> bool {
+        match self {
+            Comparison::Years(years) => tag_year(tag).is_some_and(|y| years.contains(&y)),
+            Comparison::Dates(dates) => tag_date(tag).is_some_and(|d| dates.contains(&d)),
+        }
+    }
+}
+
+/// Get the range of values to the left of the comparison `op` with `value`.
+fn comparison_bounds(op: &str, value: u64) -> Option<RangeInclusive<u64>> {
+    Some(match op {
+        "<=" => 0..=value,
+        ">=" => value..=u64::MAX,
+        "<" => 0..=value.checked_sub(1)?,
+        _ => value.checked_add(1)?..=u64::MAX,
+    })
+}
+
+/// Split a comparison such as '>=10M' into the operator and the value.
+fn split_comparison(text: &str) -> Option<(&'static str, &str)> {
+    ["<=", ">=", "<", ">"]
+        .iter()
+        .find_map(|op| Some((*op, text.strip_prefix(op)?)))
+}
+
+/// Parse a number followed by an optional suffix, such as '10M' or '30d',
+/// where `unit` gets the multiple of each suffix.
+fn parse_quantity(text: &str, unit: impl Fn(&str) -> Option<u64>) -> Option<u64> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let number: u64 = text[..split].parse().ok()?;
+    number.checked_mul(unit(&text[split..])?)
+}
+
+/// Get the number of seconds since the unix epoch, at the start of a date
+/// parsed by `parse_date`, in UTC.
+fn date_to_unix(date: u32) -> u64 {
+    let (y, m, d) = (
+        (date / 10000) as i64,
+        (date / 100 % 100) as i64,
+        (date % 100) as i64,
+    );
+    // Days since the epoch, from the algorithm of Howard Hinnant.
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let days = era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468;
+    (days.max(0) as u64) * SECONDS_PER_DAY
+}
+
+/// A comparison of the size or the modification time of files, rather than
+/// their tags.
+#[derive(PartialEq, Debug)]
+enum FileComparison {
+    /// Range of sizes in bytes.
+    Size(RangeInclusive<u64>),
+    /// Range of modification times, in seconds since the unix epoch.
+    Modified(RangeInclusive<u64>),
+}
+
+impl FileComparison {
+    /// Parse comparisons such as 'size>10M', 'mtime<30d' or
+    /// 'mtime>=2021-01-01'. Ages are relative to `now`, which is in seconds
+    /// since the unix epoch.
+    fn parse(tag: &str, now: u64) -> Option<FileComparison> {
+        if let Some(rest) = tag.strip_prefix(SIZE_PREFIX) {
+            let (op, size) = split_comparison(rest)?;
+            let size = parse_quantity(size, |unit| {
+                match unit.to_ascii_lowercase().trim_end_matches('b') {
+                    "" => Some(1),
+                    "k" => Some(1 << 10),
+                    "m" => Some(1 << 20),
+                    "g" => Some(1 << 30),
+                    "t" => Some(1 << 40),
+                    _ => None,
+                }
+            })?;
+            return Some(FileComparison::Size(comparison_bounds(op, size)?));
+        }
+        let (op, value) = split_comparison(tag.strip_prefix(MTIME_PREFIX)?)?;
+        if let Some(date) = parse_date(value) {
+            // The whole day is included, or excluded.
+            let start = date_to_unix(date);
+            let end = start + SECONDS_PER_DAY - 1;
+            return Some(FileComparison::Modified(match op {
+                "<=" => 0..=end,
+                ">=" => start..=u64::MAX,
+                "<" => 0..=start.checked_sub(1)?,
+                _ => end + 1..=u64::MAX,
+            }));
+        }
+        let age = parse_quantity(value, |unit| match unit {
+            "s" => Some(1),
+            "m" => Some(60),
+            "h" => Some(60 * 60),
+            "d" => Some(SECONDS_PER_DAY),
+            "w" => Some(7 * SECONDS_PER_DAY),
+            "y" => Some(365 * SECONDS_PER_DAY),
+            _ => None,
+        })?;
+        let ages = comparison_bounds(op, age)?;
+        // Files modified in the future are as young as they can be.
+        let latest = match *ages.start() {
+            0 => u64::MAX,
+            age => now.saturating_sub(age),
+        };
+        Some(FileComparison::Modified(
+            now.saturating_sub(*ages.end())..=latest,
+        ))
+    }
+
+    /// Check if a file with the size in bytes, and the modification time in
+    /// seconds since the unix epoch, matches the comparison.
+    fn matches(&self, size: u64, mtime: u64) -> bool {
+        match self {
+            FileComparison::Size(sizes) => sizes.contains(&size),
+            FileComparison::Modified(times) => times.contains(&mtime),
+        }
+    }
+}
+
+/// Check if the tag belongs to the namespace, or one of its nested namespaces.
+fn in_namespace(tag: &str, namespace: &str) -> bool {
+    tag.strip_prefix(namespace)
+        .is_some_and(|rest| rest.starts_with(NAMESPACE_SEPARATOR))
+}
+
+/// An item in a list of tags grouped by their namespaces.
+pub enum TagGroupItem<'a> {
+    /// The start of a group of tags that share a namespace.
+    Namespace(&'a str),
+    /// A tag, and the text to display for it. Namespaced tags are displayed
+    /// without their namespace.
+    Tag(&'a str, &'a str),
+}
+
+/// Group the tags by their namespaces. Tags without a namespace come first,
+/// followed by the groups of namespaced tags. The tags are expected to be
+/// sorted by `namespace_order`.
+pub fn group_by_namespace<T: AsRef<str>>(tags: &[T]) -> impl Iterator<Item = TagGroupItem<'_>> {
+    let mut current = None;
+    tags.iter().flat_map(move |tag| {
+        let tag = tag.as_ref();
+        let namespace = tag_namespace(tag);
+        let header = match namespace {
+            Some(ns) if current != Some(ns) => Some(TagGroupItem::Namespace(ns)),
+            _ => None,
+        };
+        current = namespace;
+        let text = match namespace {
+            Some(ns) => &tag[(ns.len() + 1)..],
+            None => tag,
+        };
+        header.into_iter().chain(Some(TagGroupItem::Tag(tag, text)))
+    })
+}
+
+/// Key for sorting tags, such that tags without a namespace come first, and
+/// tags that share a namespace are next to each other.
+pub fn namespace_order(tag: &str) -> (Option<&str>, &str) {
+    (tag_namespace(tag), tag)
+}
+
+/// Parse the filter, and replace the virtual tags in it with the filters parsed
+/// from their queries. The query of a virtual tag can only use the virtual tags
+/// declared before it, so there are no cycles.
+fn parse_with_virtual_tags<F>(
+    input: &str,
+    virtual_tags: &[(&str, &str)],
+    tagmaker: &mut F,
+) -> Result<Filter, FilterParseError>
+where
+    F: FnMut(&str, bool) -> Filter,
+{
+    // The queries were validated when the config was loaded.
+    let expand = |i: usize, tagmaker: &mut F| {
+        parse_with_virtual_tags(virtual_tags[i].1, &virtual_tags[..i], tagmaker)
+            .unwrap_or(Filter::FalseTag)
+    };
+    Filter::parse(input, |tag, quoted| {
+        match tag_wildcard(tag).filter(|_| !quoted) {
+            // The wildcard matches the virtual tags in the namespace too.
+            Some(matches) => (0..virtual_tags.len())
+                .filter(|i| matches(virtual_tags[*i].0))
+                .fold(tagmaker(tag, quoted), |lhs, i| {
+                    Filter::Or(Box::new(lhs), Box::new(expand(i, tagmaker)))
+                }),
+            None => match virtual_tags.iter().position(|(vtag, _)| *vtag == tag) {
+                Some(i) => expand(i, tagmaker),
+                None => tagmaker(tag, quoted),
+            },
+        }
+    })
+}
+
 /*
 When a tags are specified for a folder, it's subfolders and all their subfolders
 inherit those tags. This inheritance follows the directory tree. When
@@ -19,6 +390,7 @@ track of inherited tags. We do this by pushing all the tags into a vector, and
 by storing the offsets that separate contiguous chunks of this vector across the
 depth-first chain of directories currently being traversed.
  */
+#[derive(Clone)]
 struct InheritedTags {
     /// Indices of currently loaded tags.
     tag_indices: Vec<usize>,
@@ -53,11 +425,11 @@ impl InheritedTags {
 }
 
 /// Returns the number of files and the number of tags.
-pub fn count_files_tags(path: PathBuf) -> Result<(usize, usize), Error> {
+pub fn count_files_tags(path: PathBuf, config: &Config) -> Result<(usize, usize), Error> {
     let mut matcher = GlobMatches::new();
     let mut alltags = HashSet::new();
     let mut numfiles = 0usize;
-    let mut dir = DirTree::new(
+    let mut dir = DirTree::with_archives(
         path,
         LoaderOptions::new(
             true,
@@ -67,8 +439,10 @@ pub fn count_files_tags(path: PathBuf) -> Result<(usize, usize), Error> {
                 file_desc: false,
             },
         ),
+        config,
     )?;
     while let Some(VisitedDir {
+        abs_dir_path,
         rel_dir_path,
         files,
         metadata,
@@ -80,16 +454,21 @@ pub fn count_files_tags(path: PathBuf) -> Result<(usize, usize), Error> {
             MetaData::NotFound => continue,
             MetaData::Ok(data) => {
                 // Collect all tags.
-                alltags.extend(data.alltags.iter().map(|t| t.to_string()).chain(
-                    infer_implicit_tags(get_filename_str(rel_dir_path)?).map(|t| t.to_string()),
-                ));
+                alltags.extend(
+                    data.alltags.iter().map(|t| t.to_string()).chain(
+                        config
+                            .implicit_tags(get_filename_str(rel_dir_path)?)
+                            .map(|t| t.to_string()),
+                    ),
+                );
                 // Collect all tracked files.
                 matcher.find_matches(files, &data.globs, false);
                 files.iter().enumerate().fold(0usize, |numfiles, (fi, f)| {
                     match matcher.is_file_matched(fi) {
                         true => {
                             if let Some(name) = f.name().to_str() {
-                                alltags.extend(infer_implicit_tags(name).map(|t| t.to_string()));
+                                alltags.extend(config.implicit_tags(name).map(|t| t.to_string()));
+                                alltags.extend(config.audio_tags(&abs_dir_path.join(name)));
                             }
                             numfiles + 1
                         }
@@ -104,151 +483,1081 @@ pub fn count_files_tags(path: PathBuf) -> Result<(usize, usize), Error> {
             }
         }
     }
-    Ok((numfiles, alltags.len()))
+    // Aliases of the same tag are only counted once, and implied tags are
+    // counted too.
+    let ntags = alltags
+        .iter()
+        .flat_map(|t| config.expand(t))
+        .collect::<HashSet<_>>()
+        .len();
+    Ok((numfiles, ntags))
 }
 
-pub fn run_query(dirpath: PathBuf, filter: &str) -> Result<(), Error> {
-    let mut tag_index = BTreeMap::<String, usize>::new();
-    let filter = Filter::parse(filter, |tag| {
-        let size = tag_index.len();
-        let index = *tag_index.entry(tag.to_string()).or_insert(size);
-        Filter::Tag(index)
-    })
-    .map_err(Error::InvalidFilter)?;
-    let tag_index = tag_index; // Immutable.
-    let mut inherited = InheritedTags {
-        tag_indices: Vec::new(),
-        offsets: Vec::new(),
-        depth: 0,
-    };
+/// Summary of the files and tags in a directory tree.
+pub struct Stats {
+    /// Number of tracked files, including the entries with URLs.
+    pub tracked: usize,
+    pub untracked: usize,
+    /// Number of tracked files that have each tag, in descending order of the
+    /// counts. Inherited and implicit tags are included.
+    pub tag_counts: Vec<(String, usize)>,
+    /// Directories without a .ftag file, relative to the root.
+    pub dirs_without_ftag: Vec<PathBuf>,
+}
+
+impl Stats {
+    /// Average number of tags of a tracked file.
+    pub fn avg_tags_per_file(&self) -> f64 {
+        match self.tracked {
+            0 => 0.0,
+            n => {
+                self.tag_counts
+                    .iter()
+                    .map(|(_tag, count)| count)
+                    .sum::<usize>() as f64
+                    / n as f64
+            }
+        }
+    }
+}
+
+/// Recursively traverse the directories from `root` and gather the statistics
+/// of the files and tags.
+pub fn stats(root: PathBuf, config: &Config) -> Result<Stats, Error> {
     let mut matcher = GlobMatches::new();
-    let mut dir = DirTree::new(
-        dirpath,
+    let mut untracked = 0usize;
+    let mut dirs_without_ftag = Vec::new();
+    let mut dir = DirTree::with_archives(
+        root.clone(),
         LoaderOptions::new(
-            true,
+            false,
             false,
             FileLoadingOptions::Load {
-                file_tags: true,
+                file_tags: false,
                 file_desc: false,
             },
         ),
+        config,
     )?;
-    let mut filetags = vec![false; tag_index.len()].into_boxed_slice();
     while let Some(VisitedDir {
-        traverse_depth,
         rel_dir_path,
         files,
         metadata,
         ..
     }) = dir.walk()
     {
-        inherited.update(traverse_depth)?;
-        let data = match metadata {
-            MetaData::Ok(d) => d,
-            MetaData::NotFound => continue,
+        untracked += match metadata {
             MetaData::FailedToLoad(e) => return Err(e),
+            MetaData::Ok(data) => {
+                matcher.find_matches(files, &data.globs, false);
+                (0..files.len())
+                    .filter(|fi| !matcher.is_file_matched(*fi))
+                    .count()
+            }
+            MetaData::NotFound => {
+                dirs_without_ftag.push(rel_dir_path.to_path_buf());
+                files.len()
+            }
         };
+    }
+    let table = TagTable::from_dir(root, config)?;
+    Ok(Stats {
+        tracked: table.files().len(),
+        untracked,
+        tag_counts: table
+            .tag_counts()
+            .into_iter()
+            .map(|(tag, count)| (tag.to_string(), count))
+            .collect(),
+        dirs_without_ftag,
+    })
+}
+
+/// The parts of a query that don't change while walking the directories, and
+/// are shared by all the threads.
+struct Query {
+    filter: Filter,
+    tag_index: BTreeMap<String, usize>,
+    namespace_index: BTreeMap<String, usize>,
+    pattern_index: Vec<(String, usize)>,
+    comparison_index: Vec<(Comparison, usize)>,
+    metadata_index: Vec<(FileComparison, usize)>,
+    // Index of 'untagged' in the filter. The next index is set for the files
+    // and directories that have explicit tags.
+    untagged: Option<usize>,
+    // Format tags required by the filter, and the extensions they're inferred
+    // from. Where these can't come from anything other than the extensions,
+    // files with other extensions can be skipped.
+    formats: Vec<(usize, String, Vec<String>)>,
+}
+
+/// The path of a directory relative to the root of a query, and the paths of
+/// the files in it that match the query.
+type QueryResults = (PathBuf, Vec<PathBuf>);
+
+/// The state of one thread walking the directories for a query.
+struct QueryState {
+    inherited: InheritedTags,
+    matcher: GlobMatches,
+    filetags: Box<[bool]>,
+    /// Indices of the `formats` the files must have to pass the filter.
+    pruned: Vec<usize>,
+}
+
+impl Query {
+    fn new(filter: &str, config: &Config) -> Result<Self, Error> {
+        let mut tag_index = BTreeMap::<String, usize>::new();
+        let mut namespace_index = BTreeMap::<String, usize>::new();
+        let mut pattern_index = Vec::<(String, usize)>::new();
+        let mut comparison_index = Vec::<(Comparison, usize)>::new();
+        let mut metadata_index = Vec::<(FileComparison, usize)>::new();
+        let mut untagged: Option<usize> = None;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let virtual_tags: Vec<(&str, &str)> = config.virtual_tags().collect();
+        let filter = parse_with_virtual_tags(filter, &virtual_tags, &mut |tag, quoted| {
+            let size = tag_index.len()
+                + namespace_index.len()
+                + pattern_index.len()
+                + comparison_index.len()
+                + metadata_index.len()
+                + if untagged.is_some() { 2 } else { 0 };
+            if quoted {
+                return Filter::Tag(
+                    *tag_index
+                        .entry(config.canonical(tag).to_string())
+                        .or_insert(size),
+                );
+            }
+            if tag == UNTAGGED {
+                return Filter::Tag(*untagged.get_or_insert(size));
+            }
+            let index = match (
+                namespace_wildcard(tag),
+                Comparison::parse(tag),
+                FileComparison::parse(tag, now),
+            ) {
+                (Some(ns), ..) => *namespace_index.entry(ns.to_string()).or_insert(size),
+                (None, None, None) if namespace_pattern(tag).is_some() => {
+                    match pattern_index.iter().find(|(p, _)| p == tag) {
+                        Some((_, index)) => *index,
+                        None => {
+                            pattern_index.push((tag.to_string(), size));
+                            size
+                        }
+                    }
+                }
+                (None, None, Some(comparison)) => {
+                    match metadata_index.iter().find(|(c, _)| *c == comparison) {
+                        Some((_, index)) => *index,
+                        None => {
+                            metadata_index.push((comparison, size));
+                            size
+                        }
+                    }
+                }
+                (None, Some(comparison), _) => {
+                    match comparison_index.iter().find(|(c, _)| *c == comparison) {
+                        Some((_, index)) => *index,
+                        None => {
+                            comparison_index.push((comparison, size));
+                            size
+                        }
+                    }
+                }
+                (None, None, None) => *tag_index
+                    .entry(config.canonical(tag).to_string())
+                    .or_insert(size),
+            };
+            Filter::Tag(index)
+        })
+        .map_err(Error::InvalidFilter)?
+        .simplify();
+        let required = filter.required_tags();
+        let formats = tag_index
+            .iter()
+            .filter(|(_tag, index)| required.binary_search(index).is_ok())
+            .filter_map(|(tag, index)| {
+                Some((*index, tag.clone(), config.format_extensions(tag)?.to_vec()))
+            })
+            .collect();
+        Ok(Query {
+            filter,
+            tag_index,
+            namespace_index,
+            pattern_index,
+            comparison_index,
+            metadata_index,
+            untagged,
+            formats,
+        })
+    }
+
+    /// Get the indices of the tag, and the namespace wildcards, the patterns and
+    /// the comparisons of years and dates that match the tag.
+    fn indices(&self, tag: &str) -> SmallVec<[usize; 2]> {
+        self.tag_index
+            .get(tag)
+            .copied()
+            .into_iter()
+            .chain(
+                tag.match_indices(NAMESPACE_SEPARATOR)
+                    .filter_map(|(i, _)| self.namespace_index.get(&tag[..i]).copied()),
+            )
+            .chain(
+                self.pattern_index
+                    .iter()
+                    .filter(|(pattern, _)| glob_match(pattern, tag))
+                    .map(|(_, index)| *index),
+            )
+            .chain(
+                self.comparison_index
+                    .iter()
+                    .filter(|(comparison, _)| comparison.matches(tag))
+                    .map(|(_, index)| *index),
+            )
+            .collect()
+    }
+
+    fn state(&self, inherited: InheritedTags) -> QueryState {
+        QueryState {
+            inherited,
+            matcher: GlobMatches::new(),
+            filetags: vec![
+                false;
+                self.tag_index.len()
+                    + self.namespace_index.len()
+                    + self.pattern_index.len()
+                    + self.comparison_index.len()
+                    + self.metadata_index.len()
+                    + if self.untagged.is_some() { 2 } else { 0 }
+            ]
+            .into_boxed_slice(),
+            pruned: Vec::with_capacity(self.formats.len()),
+        }
+    }
+
+    /// Walk the directories in `dir`, and call `visit` with the path of every
+    /// file that matches the filter, and `visit_dir` with the path of every
+    /// directory. `prefix` is the path of the root of `dir` relative to the
+    /// root of the query, and is prepended to the paths.
+    fn walk<F: FileSystem>(
+        &self,
+        dir: &mut DirTree<F>,
+        prefix: &Path,
+        state: &mut QueryState,
+        config: &Config,
+        queue: &WorkQueue<InheritedTags>,
+        send: &mut dyn FnMut(QueryResults),
+    ) -> Result<(), Error> {
+        let depth = prefix.components().count();
+        while let Some(VisitedDir {
+            traverse_depth,
+            abs_dir_path,
+            rel_dir_path,
+            files,
+            metadata,
+            ancestor,
+        }) = dir.walk()
+        {
+            state.inherited.update(depth + traverse_depth)?;
+            let rel_dir_path = prefix.join(rel_dir_path);
+            let mut paths = Vec::new();
+            let descend = match metadata {
+                MetaData::Ok(data) => self.visit_dir(
+                    &rel_dir_path,
+                    abs_dir_path,
+                    files,
+                    &data,
+                    ancestor,
+                    state,
+                    config,
+                    &mut |path| paths.push(path.to_path_buf()),
+                )?,
+                MetaData::NotFound => true,
+                MetaData::FailedToLoad(e) => return Err(e),
+            };
+            send((rel_dir_path, paths));
+            if !descend {
+                dir.take_subdirs(); // Nothing below can match.
+            } else if !queue.share(dir, prefix, &state.inherited) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Walk the directories from `dirpath` within `limits`, in the file system
+    /// `fs`. The sizes and the modification times of the files are only read
+    /// if the query compares them.
+    fn walker<FS: FileSystem>(
+        &self,
+        dirpath: PathBuf,
+        limits: &WalkLimits,
+        config: &Config,
+        fs: FS,
+    ) -> Result<DirTree<FS>, Error> {
+        let dir = DirTree::with_fs(dirpath, query_options(), config, fs)?.limit(limits);
+        Ok(match self.metadata_index.is_empty() {
+            true => dir,
+            false => dir.with_stats(),
+        })
+    }
+
+    /// Check if the tags inherited from the directories make the filter false
+    /// for every file below them, regardless of the tags of the files.
+    fn rules_out(&self, state: &mut QueryState) -> bool {
+        let QueryState {
+            inherited,
+            filetags,
+            ..
+        } = state;
+        filetags.fill(false);
+        for index in &inherited.tag_indices {
+            filetags[*index] = true;
+        }
+        self.filter.eval_partial(|ti| match self.untagged {
+            // Files below a directory with explicit tags are never untagged.
+            Some(untagged) if ti == untagged => filetags[untagged + 1].then_some(false),
+            _ => filetags[ti].then_some(true),
+        }) == Some(false)
+    }
+
+    /// Call `visit` with the path of every file in the directory that matches
+    /// the filter. The tags of an `ancestor` of the subtree the walk is limited
+    /// to are inherited, but its entries are not visited. Returns false if no
+    /// file in the directory or its subdirectories can match the filter,
+    /// because of the tags they inherit, in which case the files are skipped.
+    #[allow(clippy::too_many_arguments)]
+    fn visit_dir(
+        &self,
+        rel_dir_path: &Path,
+        abs_dir_path: &Path,
+        files: &[DirEntry],
+        data: &DirData,
+        ancestor: bool,
+        state: &mut QueryState,
+        config: &Config,
+        visit: &mut impl FnMut(&Path),
+    ) -> Result<bool, Error> {
         // Push directory tags.
+        let inherited = &mut state.inherited;
         inherited.tag_indices.extend(
             data.tags()
                 .iter()
                 .map(|t| Tag::Text(t))
-                .chain(infer_implicit_tags(get_filename_str(rel_dir_path)?))
-                .filter_map(|tag| match tag {
-                    Tag::Text(t) | Tag::Format(t) => tag_index.get(t).copied(),
-                    Tag::Year(y) => tag_index.get(&y.to_string()).copied(),
-                }),
+                .chain(config.implicit_tags(get_filename_str(rel_dir_path)?))
+                .flat_map(|tag| config.expand_tag(tag))
+                .flat_map(|tag| self.indices(&tag)),
         );
+        if let Some(untagged) = self.untagged.filter(|_| !data.tags().is_empty()) {
+            inherited.tag_indices.push(untagged + 1);
+        }
+        if self.rules_out(state) {
+            return Ok(false);
+        }
+        if ancestor {
+            return Ok(true);
+        }
+        let QueryState {
+            inherited,
+            matcher,
+            filetags,
+            pruned,
+        } = state;
         // Process all files in the directory.
-        matcher.find_matches(files, &data.globs, false);
+        pruned.clear();
+        pruned.extend(
+            self.formats
+                .iter()
+                .enumerate()
+                .filter(|(_, (index, tag, _exts))| {
+                    !inherited.tag_indices.contains(index)
+                        && !data
+                            .alltags
+                            .iter()
+                            .any(|t| config.expand(t).any(|t| t == *tag))
+                })
+                .map(|(fi, _)| fi),
+        );
+        matcher.find_matches_where(files, &data.globs, false, &|file| {
+            pruned
+                .iter()
+                .all(|fi| has_any_extension(file.name(), &self.formats[*fi].2))
+        });
         for (fi, file) in files
             .iter()
             .enumerate()
             .filter(|(fi, _)| matcher.is_file_matched(*fi))
         {
             filetags.fill(false);
+            let name = file
+                .name()
+                .to_str()
+                .ok_or(Error::InvalidPath(file.name().into()))?;
+            let audio = config.audio_tags(&abs_dir_path.join(name));
             for index in matcher
                 .matched_globs(fi) // Tags associated with matching globs.
-                .flat_map(|gi| {
-                    data.globs[gi]
-                        .tags(&data.alltags)
-                        .iter()
-                        .map(|t| Tag::Text(t))
-                })
-                // Implicit tags.
-                .chain(infer_implicit_tags(
-                    file.name()
-                        .to_str()
-                        .ok_or(Error::InvalidPath(file.name().into()))?,
-                ))
-                .filter_map(|tag| match tag {
-                    Tag::Text(t) | Tag::Format(t) => tag_index.get(t).copied(),
-                    Tag::Year(y) => tag_index.get(&y.to_string()).copied(),
-                })
+                .flat_map(|gi| data.glob_tags(&data.globs[gi]).iter().map(|t| Tag::Text(t)))
+                .chain(config.implicit_tags(name)) // Implicit tags.
+                .chain(audio.iter().map(|t| Tag::Text(t)))
+                .flat_map(|tag| config.expand_tag(tag))
+                .flat_map(|tag| self.indices(&tag))
                 .chain(inherited.tag_indices.iter().copied())
             {
                 filetags[index] = true;
             }
-            if filter.eval(|ti| filetags[ti]) {
+            if let Some(untagged) = self.untagged {
+                let tagged = filetags[untagged + 1]
+                    || matcher
+                        .matched_globs(fi)
+                        .any(|gi| !data.glob_tags(&data.globs[gi]).is_empty());
+                filetags[untagged] = !tagged;
+            }
+            if let Some(stats) = file.stats() {
+                for (comparison, index) in &self.metadata_index {
+                    filetags[*index] = comparison.matches(stats.size, stats.mtime);
+                }
+            }
+            if self.filter.eval(name, |ti| filetags[ti]) {
                 let mut path = rel_dir_path.to_path_buf();
                 path.push(file.name());
-                println!("{}", path.display());
+                visit(&path);
             }
         }
+        // Entries with URLs instead of files. These only have the tags of
+        // their entries, and the inherited tags.
+        for (gi, url) in data
+            .globs
+            .iter()
+            .enumerate()
+            .filter(|(_, g)| is_url(g.path))
+        {
+            if data.globs[..gi].iter().any(|g| g.path == url.path) {
+                continue; // Already visited.
+            }
+            filetags.fill(false);
+            for index in data
+                .globs
+                .iter()
+                .filter(|g| g.path == url.path)
+                .flat_map(|g| data.glob_tags(g).iter().map(|t| Tag::Text(t)))
+                .flat_map(|tag| config.expand_tag(tag))
+                .flat_map(|tag| self.indices(&tag))
+                .chain(inherited.tag_indices.iter().copied())
+            {
+                filetags[index] = true;
+            }
+            if let Some(untagged) = self.untagged {
+                let tagged = filetags[untagged + 1]
+                    || data
+                        .globs
+                        .iter()
+                        .any(|g| g.path == url.path && !data.glob_tags(g).is_empty());
+                filetags[untagged] = !tagged;
+            }
+            if self.filter.eval(url.path, |ti| filetags[ti]) {
+                visit(Path::new(url.path));
+            }
+        }
+        Ok(true)
     }
-    Ok(())
 }
 
-/// 2d array of bools.
-pub(crate) struct BoolTable {
-    data: Box<[bool]>, // Boxed, so that it cannot be resized by accident.
-    ncols: usize,
+fn query_options() -> LoaderOptions {
+    LoaderOptions::new(
+        true,
+        false,
+        FileLoadingOptions::Load {
+            file_tags: true,
+            file_desc: false,
+        },
+    )
+}
+
+/// Recursively traverse the directories from `dirpath` within `limits`, and
+/// call `visit` with the path of every file that matches `filter`, relative to
+/// `dirpath`. The subdirectories of `dirpath` are walked on multiple threads,
+/// but `visit` is called on the calling thread, in the same order as a single
+/// threaded walk. If `dirpath` has an up to date index, the directories are
+/// walked from the snapshot in the index.
+pub fn run_query<F>(
+    dirpath: PathBuf,
+    filter: &str,
+    limits: &WalkLimits,
+    config: &Config,
+    visit: F,
+) -> Result<(), Error>
+where
+    F: FnMut(&Path),
+{
+    let query = Query::new(filter, config)?;
+    run_parsed_query(&dirpath, &query, limits, config, visit, |_| {})
 }
 
-impl BoolTable {
+/// Run `query` on the directories from `dirpath`, and call `visit` with the
+/// paths of the matching files, and `visit_dir` with the paths of the walked
+/// directories.
+fn run_parsed_query<F, D>(
+    dirpath: &Path,
+    query: &Query,
+    limits: &WalkLimits,
+    config: &Config,
+    visit: F,
+    visit_dir: D,
+) -> Result<(), Error>
+where
+    F: FnMut(&Path),
+    D: FnMut(&Path),
+{
+    match indexed_file_system(dirpath, config) {
+        Some(fs) => walk_query(dirpath, query, limits, config, || &fs, visit, visit_dir),
+        None => walk_query(
+            dirpath,
+            query,
+            limits,
+            config,
+            || ArchiveFileSystem::new(config.archives()),
+            visit,
+            visit_dir,
+        ),
+    }
+}
+
+/// Run `query` on the directories from `dirpath`, using the file systems
+/// created by `make_fs`. Every thread walks its own file system.
+fn walk_query<FS, M, F, D>(
+    dirpath: &Path,
+    query: &Query,
+    limits: &WalkLimits,
+    config: &Config,
+    make_fs: M,
+    mut visit: F,
+    mut visit_dir: D,
+) -> Result<(), Error>
+where
+    FS: FileSystem,
+    M: Fn() -> FS + Sync,
+    F: FnMut(&Path),
+    D: FnMut(&Path),
+{
+    let mut state = query.state(InheritedTags {
+        tag_indices: Vec::new(),
+        offsets: Vec::new(),
+        depth: 0,
+    });
+    // Visit the root directory, then walk its subdirectories on other threads.
+    let mut dir = query.walker(dirpath.to_path_buf(), limits, config, make_fs())?;
+    let mut descend = true;
+    if let Some(VisitedDir {
+        traverse_depth,
+        abs_dir_path,
+        rel_dir_path,
+        files,
+        metadata,
+        ancestor,
+    }) = dir.walk()
+    {
+        state.inherited.update(traverse_depth)?;
+        visit_dir(rel_dir_path);
+        descend = match metadata {
+            MetaData::Ok(data) => query.visit_dir(
+                rel_dir_path,
+                abs_dir_path,
+                files,
+                &data,
+                ancestor,
+                &mut state,
+                config,
+                &mut visit,
+            )?,
+            // Filters such as 'none' can't match anything, even without tags.
+            MetaData::NotFound => !query.rules_out(&mut state),
+            MetaData::FailedToLoad(e) => return Err(e),
+        };
+    }
+    let subdirs = match descend {
+        true => dir.take_subdirs(),
+        false => Vec::new(),
+    };
+    let tasks = subdirs
+        .into_iter()
+        .map(|subdir| (subdir, state.inherited.clone()))
+        .collect();
+    walk_parallel(
+        tasks,
+        |subdir, inherited, queue, send| {
+            let mut state = query.state(inherited);
+            let mut dir = query.walker(
+                dirpath.join(subdir),
+                &limits.below(subdir),
+                config,
+                make_fs(),
+            )?;
+            query.walk(&mut dir, subdir, &mut state, config, queue, send)
+        },
+        |(dir, paths)| {
+            visit_dir(&dir);
+            paths.iter().for_each(|path| visit(path));
+        },
+    )
+}
+
+/// Same as `run_query`, except the paths of the files that match `filter` are
+/// collected and returned, instead of being visited one by one.
+pub fn query_files(
+    dirpath: PathBuf,
+    filter: &str,
+    limits: &WalkLimits,
+    config: &Config,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = Vec::new();
+    run_query(dirpath, filter, limits, config, |path| {
+        paths.push(path.to_path_buf())
+    })?;
+    Ok(paths)
+}
+
+/// Same as `query_files`, but the paths of the walked directories, relative
+/// to `dirpath`, are also returned. The directories are `None` if the results
+/// also depend on the sizes or the modification times of the files, and not
+/// just on the names of the files and the .ftag files.
+pub(crate) fn query_files_and_dirs(
+    dirpath: &Path,
+    filter: &str,
+    limits: &WalkLimits,
+    config: &Config,
+) -> Result<(Vec<PathBuf>, Option<Vec<PathBuf>>), Error> {
+    let query = Query::new(filter, config)?;
+    let mut paths = Vec::new();
+    let mut dirs = Vec::new();
+    run_parsed_query(
+        dirpath,
+        &query,
+        limits,
+        config,
+        |path| paths.push(path.to_path_buf()),
+        |dir| dirs.push(dir.to_path_buf()),
+    )?;
+    Ok((paths, query.metadata_index.is_empty().then_some(dirs)))
+}
+
+/// Get the indices of the bits that are set in the bitset.
+fn ones(words: &[u64]) -> impl Iterator<Item = usize> + '_ {
+    words.iter().enumerate().flat_map(|(wi, word)| {
+        let mut word = *word;
+        std::iter::from_fn(move || match word {
+            0 => None,
+            _ => {
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(wi * 64 + bit)
+            }
+        })
+    })
+}
+
+/// 2d array of bits, stored one column after another. Each column is a bitset
+/// of the rows, packed into 64 bit words, so that filters can be evaluated for
+/// all the rows at once, with bitwise operations on whole columns. The bits
+/// past the last row are always zero.
+#[derive(Clone)]
+pub(crate) struct BitTable {
+    words: Box<[u64]>, // Boxed, so that it cannot be resized by accident.
+    nrows: usize,
+    stride: usize, // Words per column.
+}
+
+impl BitTable {
     pub fn new(nrows: usize, ncols: usize) -> Self {
-        BoolTable {
-            data: vec![false; nrows * ncols].into_boxed_slice(),
-            ncols,
+        let stride = nrows.div_ceil(64);
+        BitTable {
+            words: vec![0u64; stride * ncols].into_boxed_slice(),
+            nrows,
+            stride,
         }
     }
 
-    pub fn row(&self, r: usize) -> &[bool] {
-        let start = r * self.ncols;
-        &self.data[start..(start + self.ncols)]
+    pub fn get(&self, row: usize, col: usize) -> bool {
+        (self.words[col * self.stride + row / 64] >> (row % 64)) & 1 == 1
+    }
+
+    fn set(&mut self, row: usize, col: usize, value: bool) {
+        let word = &mut self.words[col * self.stride + row / 64];
+        match value {
+            true => *word |= 1 << (row % 64),
+            false => *word &= !(1 << (row % 64)),
+        }
     }
+
+    fn column(&self, col: usize) -> &[u64] {
+        &self.words[col * self.stride..(col + 1) * self.stride]
+    }
+
+    fn column_mut(&mut self, col: usize) -> &mut [u64] {
+        &mut self.words[col * self.stride..(col + 1) * self.stride]
+    }
+
+    /// Clear the bits past the last row.
+    fn mask(&self, bits: &mut [u64]) {
+        if let (Some(last), rem @ 1..) = (bits.last_mut(), self.nrows % 64) {
+            *last &= (1u64 << rem) - 1;
+        }
+    }
+
+    /// Copy the first `ncols` columns of `src` into this table, such that the
+    /// row `r` of `src` becomes the row `rows[r]` of this table, or is dropped
+    /// if that's `None`.
+    fn copy_rows(&mut self, src: &BitTable, rows: &[Option<usize>], ncols: usize) {
+        for col in 0..ncols {
+            for row in ones(src.column(col)) {
+                if let Some(dst) = rows[row] {
+                    self.set(dst, col, true);
+                }
+            }
+        }
+    }
+
+    /// Get the bitset of the rows that pass the filter, where the columns are
+    /// the tags, and `name` gets the name of the file in a row.
+    fn eval<'a>(&self, filter: &Filter, name: &impl Fn(usize) -> &'a str) -> Vec<u64> {
+        let mut out = vec![0u64; self.stride];
+        self.eval_into(filter, name, &mut out);
+        out
+    }
+
+    fn eval_into<'a>(&self, filter: &Filter, name: &impl Fn(usize) -> &'a str, out: &mut [u64]) {
+        let combine = |out: &mut [u64], rhs: &Filter, op: fn(u64, u64) -> u64| {
+            let mut buf = Vec::new();
+            let rhs = match rhs {
+                Filter::Tag(ti) => self.column(*ti),
+                _ => {
+                    buf.resize(self.stride, 0);
+                    self.eval_into(rhs, name, &mut buf);
+                    &buf
+                }
+            };
+            out.iter_mut().zip(rhs).for_each(|(l, r)| *l = op(*l, *r));
+        };
+        match filter {
+            Filter::Tag(ti) => out.copy_from_slice(self.column(*ti)),
+            Filter::Name(glob) => {
+                out.fill(0);
+                for row in (0..self.nrows).filter(|row| glob_match(glob, name(*row))) {
+                    out[row / 64] |= 1 << (row % 64);
+                }
+            }
+            Filter::And(lhs, rhs) => {
+                self.eval_into(lhs, name, out);
+                combine(out, rhs, |l, r| l & r);
+            }
+            Filter::Or(lhs, rhs) => {
+                self.eval_into(lhs, name, out);
+                combine(out, rhs, |l, r| l | r);
+            }
+            Filter::Xor(lhs, rhs) => {
+                self.eval_into(lhs, name, out);
+                combine(out, rhs, |l, r| l ^ r);
+            }
+            Filter::Not(inner) => {
+                self.eval_into(inner, name, out);
+                out.iter_mut().for_each(|w| *w = !*w);
+                self.mask(out);
+            }
+            Filter::FalseTag => out.fill(0),
+            Filter::TrueTag => {
+                out.fill(u64::MAX);
+                self.mask(out);
+            }
+        }
+    }
+}
+
+/// Modification times of a directory and its .ftag file, and the size of the
+/// .ftag file, to tell whether the directory changed since it was loaded.
+type DirStamp = (Option<SystemTime>, Option<SystemTime>, Option<u64>);
+
+fn dir_stamp(dir: &Path) -> DirStamp {
+    let ftag = std::fs::metadata(dir.join(FTAG_FILE)).ok();
+    (
+        std::fs::metadata(dir).and_then(|m| m.modified()).ok(),
+        ftag.as_ref().and_then(|m| m.modified().ok()),
+        ftag.map(|m| m.len()),
+    )
 }
 
 /// This is similar to a `TagTable`, but the flags indicating in which
 /// file has which tags are stored in a dense 2d array rather than a
 /// sparse hash-map of vectors.
+#[derive(Clone)]
 pub struct TagTable {
     root: PathBuf,
-    flags: BoolTable,
+    flags: BitTable,
     files: Box<[String]>,
     tags: Box<[String]>,
     tag_index: HashMap<String, usize>,
+    // Entries with URLs instead of files, and the directories of the .ftag
+    // files they're in.
+    url_dirs: HashMap<usize, PathBuf>,
+    // Stamps of the directories when they were loaded, by their paths
+    // relative to the root.
+    stamps: HashMap<PathBuf, DirStamp>,
 }
 
 impl TagTable {
-    fn get_tag_index(tag: String, map: &mut HashMap<String, usize>) -> usize {
-        let size = map.len();
-        *(map.entry(tag).or_insert(size))
+    fn get_tag_index(
+        tag: String,
+        map: &mut HashMap<String, usize>,
+        tags: &mut Vec<String>,
+    ) -> usize {
+        *(map.entry(tag).or_insert_with_key(|tag| {
+            tags.push(tag.clone());
+            tags.len() - 1
+        }))
+    }
+
+    /// Table of the directory `root`, without any files or tags.
+    fn empty(root: PathBuf) -> TagTable {
+        TagTable {
+            root,
+            flags: BitTable::new(0, 0),
+            files: Box::new([]),
+            tags: Box::new([]),
+            tag_index: HashMap::new(),
+            url_dirs: HashMap::new(),
+            stamps: HashMap::new(),
+        }
+    }
+
+    pub fn from_dir(dirpath: PathBuf, config: &Config) -> Result<TagTable, Error> {
+        let mut table = TagTable::empty(dirpath);
+        table.update_dir(Path::new(""), config)?;
+        Ok(table)
+    }
+
+    /// Reload the directories that changed since they were loaded, along with
+    /// their subdirectories, without reloading the rest of the table. A
+    /// directory has changed if its .ftag file was modified, or files were
+    /// added to or removed from it. Returns the paths of the reloaded
+    /// directories, relative to the root of the table.
+    pub fn refresh(&mut self, config: &Config) -> Result<Vec<PathBuf>, Error> {
+        let mut changed: Vec<PathBuf> = self
+            .stamps
+            .iter()
+            .filter(|(dir, stamp)| dir_stamp(&self.root.join(dir)) != **stamp)
+            .map(|(dir, _)| dir.clone())
+            .collect();
+        // Subdirectories are reloaded along with their changed ancestors.
+        changed.sort();
+        changed.dedup_by(|dir, ancestor| dir.starts_with(ancestor));
+        for dir in &changed {
+            self.update_dir(dir, config)?;
+        }
+        Ok(changed)
+    }
+
+    /// Reload the tags of the files in the directory `reldir`, and its
+    /// subdirectories, without reloading the rest of the table. `reldir` is
+    /// relative to the root of the table. The files keep their place in the
+    /// table, and the indices of the existing tags remain valid.
+    pub fn update_dir(&mut self, reldir: &Path, config: &Config) -> Result<(), Error> {
+        self.load_subtree(reldir, None, config)
+    }
+
+    /// Same as `update_dir`, but only the files up to `max_depth` levels of
+    /// subdirectories below `reldir` are loaded, if `max_depth` is given.
+    fn load_subtree(
+        &mut self,
+        reldir: &Path,
+        max_depth: Option<usize>,
+        config: &Config,
+    ) -> Result<(), Error> {
+        if !reldir
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(Error::InvalidPath(reldir.to_path_buf()));
+        }
+        let mut tags = std::mem::take(&mut self.tags).into_vec();
+        let oldntags = tags.len();
+        let mut newfiles = Vec::new();
+        let mut newurls = Vec::new();
+        let mut pairs = HashSet::<(usize, usize)>::new();
+        let mut stamps = Vec::new();
+        // Only a full load is worth reading the index for.
+        let index = match reldir.as_os_str().is_empty() && max_depth.is_none() {
+            true => indexed_file_system(&self.root, config),
+            false => None,
+        };
+        let result = match index {
+            Some(fs) => Self::load_dir(
+                &self.root,
+                reldir,
+                max_depth,
+                config,
+                &fs,
+                &mut self.tag_index,
+                &mut tags,
+                &mut newfiles,
+                &mut newurls,
+                &mut pairs,
+                &mut stamps,
+            ),
+            None => Self::load_dir(
+                &self.root,
+                reldir,
+                max_depth,
+                config,
+                ArchiveFileSystem::new(config.archives()),
+                &mut self.tag_index,
+                &mut tags,
+                &mut newfiles,
+                &mut newurls,
+                &mut pairs,
+                &mut stamps,
+            ),
+        };
+        if let Err(e) = result {
+            // Forget the tags that were discovered before the failure.
+            tags.truncate(oldntags);
+            self.tag_index.retain(|_, i| *i < oldntags);
+            self.tags = tags.into_boxed_slice();
+            return Err(e);
+        }
+        self.stamps.retain(|dir, _| !dir.starts_with(reldir));
+        self.stamps.extend(stamps);
+        // Every virtual tag gets a column, whose flags are computed at the end.
+        for (tag, _query) in config.virtual_tags() {
+            Self::get_tag_index(tag.to_string(), &mut self.tag_index, &mut tags);
+        }
+        // Replace the rows of the files in the directory with the new rows.
+        let mut oldfiles = std::mem::take(&mut self.files).into_vec();
+        let oldurls = std::mem::take(&mut self.url_dirs);
+        let inside = |fi: usize| match oldurls.get(&fi) {
+            Some(dir) => dir.starts_with(reldir),
+            None => Path::new(&oldfiles[fi]).starts_with(reldir),
+        };
+        let start = (0..oldfiles.len())
+            .position(inside)
+            .unwrap_or(oldfiles.len());
+        let kept: Vec<usize> = (0..oldfiles.len()).filter(|fi| !inside(*fi)).collect();
+        let (before, after) = kept.split_at(kept.partition_point(|fi| *fi < start));
+        let ntags = tags.len();
+        let mut flags = BitTable::new(kept.len() + newfiles.len(), ntags);
+        let offset = before.len() + newfiles.len();
+        let mut rows = vec![None; oldfiles.len()];
+        for (row, fi) in before.iter().enumerate() {
+            rows[*fi] = Some(row);
+        }
+        for (row, fi) in after.iter().enumerate() {
+            rows[*fi] = Some(offset + row);
+        }
+        flags.copy_rows(&self.flags, &rows, oldntags);
+        for (fi, ti) in pairs {
+            flags.set(before.len() + fi, ti, true);
+        }
+        let oldrows = before
+            .iter()
+            .enumerate()
+            .chain(after.iter().enumerate().map(|(row, fi)| (offset + row, fi)));
+        self.url_dirs = oldrows
+            .filter_map(|(row, fi)| Some((row, oldurls.get(fi)?.clone())))
+            .chain(
+                newurls
+                    .into_iter()
+                    .map(|(fi, dir)| (before.len() + fi, dir)),
+            )
+            .collect();
+        let mut files = Vec::with_capacity(kept.len() + newfiles.len());
+        files.extend(before.iter().map(|fi| std::mem::take(&mut oldfiles[*fi])));
+        files.extend(newfiles);
+        files.extend(after.iter().map(|fi| std::mem::take(&mut oldfiles[*fi])));
+        self.files = files.into_boxed_slice();
+        self.flags = flags;
+        // Aliases are only used for parsing filters, so they are not listed
+        // in the tags.
+        let aliases: Vec<_> = config
+            .aliases()
+            .filter_map(|(alias, canonical)| {
+                self.tag_index
+                    .get(canonical)
+                    .map(|i| (alias.to_string(), *i))
+            })
+            .collect();
+        self.tag_index.extend(aliases);
+        self.tags = tags.into_boxed_slice();
+        self.update_virtual_tags(config);
+        Ok(())
     }
 
-    pub fn from_dir(dirpath: PathBuf) -> Result<TagTable, Error> {
-        let mut tag_index = HashMap::new();
-        let mut allfiles = Vec::new();
-        let mut table = HashSet::<(usize, usize)>::new();
+    /// Compute the flags of the virtual tags from the flags of the other
+    /// tags. The virtual tags are computed in the order they're declared, so
+    /// that each of them can use the ones declared before it.
+    fn update_virtual_tags(&mut self, config: &Config) {
+        for (tag, query) in config.virtual_tags() {
+            let ti = match self.tag_index.get(tag) {
+                Some(ti) => *ti,
+                None => continue,
+            };
+            let filter = match self.parse_filter(query) {
+                Ok(filter) => filter,
+                Err(_) => continue, // Validated when the config was loaded.
+            };
+            let flags = self.flags.eval(&filter, &|fi| self.file_name(fi));
+            self.flags.column_mut(ti).copy_from_slice(&flags);
+        }
+    }
+
+    /// Walk the directory `reldir` and its subdirectories in `fs`, up to
+    /// `max_depth` levels below `reldir` if given, and collect the tracked
+    /// files and the (file, tag) pairs of their tags. The indices of the files
+    /// are relative to the start of `files`. Entries with URLs
+    /// are included in `files`, and their indices and directories are added to
+    /// `urls`. The stamps of the walked directories are added to `stamps`.
+    #[allow(clippy::too_many_arguments)]
+    fn load_dir<F: FileSystem>(
+        root: &Path,
+        reldir: &Path,
+        max_depth: Option<usize>,
+        config: &Config,
+        fs: F,
+        tag_index: &mut HashMap<String, usize>,
+        tags: &mut Vec<String>,
+        files: &mut Vec<String>,
+        urls: &mut Vec<(usize, PathBuf)>,
+        pairs: &mut HashSet<(usize, usize)>,
+        stamps: &mut Vec<(PathBuf, DirStamp)>,
+    ) -> Result<(), Error> {
         let mut inherited = InheritedTags {
             tag_indices: Vec::new(),
             offsets: Vec::new(),
             depth: 0,
         };
+        // The directory inherits the tags of all its ancestors.
+        if let Some(parent) = reldir.parent() {
+            let mut loader = Loader::new(LoaderOptions::new(true, false, FileLoadingOptions::Skip));
+            let mut relpath = PathBuf::new();
+            for name in std::iter::once(OsStr::new("")).chain(parent.iter()) {
+                relpath.push(name);
+                let fpath = root.join(&relpath).join(FTAG_FILE);
+                let data = match fs.is_file(&fpath) {
+                    true => Some(loader.load_from(&fs, &fpath)?),
+                    false => None,
+                };
+                inherited.tag_indices.extend(
+                    data.iter()
+                        .flat_map(|d| d.tags().iter().map(|t| Tag::Text(t)))
+                        .chain(config.implicit_tags(get_filename_str(&relpath)?))
+                        .flat_map(|tag| config.expand_tag(tag))
+                        .map(|tag| Self::get_tag_index(tag.into_owned(), tag_index, tags)),
+                );
+            }
+        }
         let mut matcher = GlobMatches::new();
         let mut filetags: Vec<String> = Vec::new();
-        let mut dir = DirTree::new(
-            dirpath.clone(),
+        let mut dir = DirTree::with_fs(
+            root.join(reldir),
             LoaderOptions::new(
                 true,
                 false,
@@ -257,7 +1566,10 @@ impl TagTable {
                     file_desc: false,
                 },
             ),
-        )?;
+            config,
+            fs,
+        )?
+        .limit(&WalkLimits::depth(max_depth));
         while let Some(VisitedDir {
             traverse_depth,
             rel_dir_path,
@@ -267,6 +1579,10 @@ impl TagTable {
         }) = dir.walk()
         {
             inherited.update(traverse_depth)?;
+            let dirpath = reldir.join(rel_dir_path);
+            // Directories without .ftag files are stamped too, so adding one
+            // is noticed when refreshing.
+            stamps.push((dirpath.clone(), dir_stamp(&root.join(&dirpath))));
             let data = match metadata {
                 MetaData::Ok(d) => d,
                 MetaData::NotFound => continue,
@@ -277,87 +1593,115 @@ impl TagTable {
                 data.tags()
                     .iter()
                     .map(|t| Tag::Text(t))
-                    .chain(infer_implicit_tags(get_filename_str(rel_dir_path)?))
-                    .map(|tag| match tag {
-                        Tag::Text(t) | Tag::Format(t) => {
-                            Self::get_tag_index(t.to_string(), &mut tag_index)
-                        }
-                        Tag::Year(y) => Self::get_tag_index(y.to_string(), &mut tag_index),
-                    }),
+                    .chain(config.implicit_tags(get_filename_str(&dirpath)?))
+                    .flat_map(|tag| config.expand_tag(tag))
+                    .map(|tag| Self::get_tag_index(tag.into_owned(), tag_index, tags)),
             );
             // Process all files in the directory.
             matcher.find_matches(dirfiles, &data.globs, false);
-            allfiles.reserve(dirfiles.len());
+            files.reserve(dirfiles.len());
             for (fi, file) in dirfiles
                 .iter()
                 .enumerate()
                 // Only interested in tracked files.
                 .filter(|(fi, _)| matcher.is_file_matched(*fi))
             {
+                let audio = config.audio_tags(&root.join(&dirpath).join(file.name()));
                 filetags.clear();
                 filetags.extend(
                     matcher
                         .matched_globs(fi) // Tags associated with matching globs.
-                        .flat_map(|gi| {
-                            data.globs[gi]
-                                .tags(&data.alltags)
-                                .iter()
-                                .map(|t| t.to_string())
-                        })
+                        .flat_map(|gi| data.glob_tags(&data.globs[gi]).iter().map(|t| Tag::Text(t)))
                         // Implicit tags.
                         .chain(
-                            infer_implicit_tags(
+                            config.implicit_tags(
                                 file.name()
                                     .to_str()
                                     .ok_or(Error::InvalidPath(file.name().into()))?,
-                            )
-                            .map(|t| t.to_string()),
-                        ),
+                            ),
+                        )
+                        .chain(audio.iter().map(|t| Tag::Text(t)))
+                        .flat_map(|tag| config.expand_tag(tag))
+                        .map(|tag| tag.into_owned()),
                 );
-                let file_index = allfiles.len();
-                allfiles.push(format!(
-                    "{}",
-                    {
-                        let mut relpath = rel_dir_path.to_path_buf();
-                        relpath.push(file.name());
-                        relpath
-                    }
-                    .display()
-                ));
-                table.extend(
+                let file_index = files.len();
+                files.push(format!("{}", dirpath.join(file.name()).display()));
+                pairs.extend(
                     filetags
                         .drain(..)
-                        .map(|tag| (file_index, Self::get_tag_index(tag, &mut tag_index))) // This file's explicit tags.
+                        .map(|tag| (file_index, Self::get_tag_index(tag, tag_index, tags))) // This file's explicit tags.
                         .chain(inherited.tag_indices.iter().map(|ti| (file_index, *ti))), // Inherited tags.
                 );
             }
+            // Entries with URLs instead of files.
+            for (gi, url) in data
+                .globs
+                .iter()
+                .enumerate()
+                .filter(|(_, g)| is_url(g.path))
+            {
+                if data.globs[..gi].iter().any(|g| g.path == url.path) {
+                    continue; // Already added.
+                }
+                let file_index = files.len();
+                files.push(url.path.to_string());
+                urls.push((file_index, dirpath.clone()));
+                filetags.extend(
+                    data.globs
+                        .iter()
+                        .filter(|g| g.path == url.path)
+                        .flat_map(|g| data.glob_tags(g).iter().map(|t| Tag::Text(t)))
+                        .flat_map(|tag| config.expand_tag(tag))
+                        .map(|tag| tag.into_owned()),
+                );
+                pairs.extend(
+                    filetags
+                        .drain(..)
+                        .map(|tag| (file_index, Self::get_tag_index(tag, tag_index, tags)))
+                        .chain(inherited.tag_indices.iter().map(|ti| (file_index, *ti))),
+                );
+            }
         }
-        // Construct the bool-table.
-        let ntags = tag_index.len();
-        let mut flags = BoolTable::new(allfiles.len(), ntags);
-        for i in table.into_iter().map(move |(fi, ti)| fi * ntags + ti) {
-            flags.data[i] = true;
-        }
-        Ok(TagTable {
-            root: dirpath,
-            flags,
-            files: allfiles.into_boxed_slice(),
-            tags: {
-                // Vec of tags sorted by their indices.
-                let mut pairs: Vec<_> = tag_index.iter().collect();
-                pairs.sort_unstable_by(|(_t1, i1), (_t2, i2)| i1.cmp(i2));
-                pairs.into_iter().map(|(t, _i)| t.clone()).collect()
-            },
-            tag_index,
-        })
+        Ok(())
     }
 
     pub fn path(&self) -> &Path {
         &self.root
     }
 
-    pub fn flags(&self, file: usize) -> &[bool] {
-        self.flags.row(file)
+    /// Get the path of a file in the table, which is relative to the root of
+    /// the table, as an absolute path. URLs are returned as they are.
+    pub fn abs_path(&self, file: &str) -> PathBuf {
+        match is_url(file) {
+            true => PathBuf::from(file),
+            false => self.root.join(file),
+        }
+    }
+
+    /// Check if the file has the tag.
+    pub fn has_tag(&self, file: usize, tag: usize) -> bool {
+        self.flags.get(file, tag)
+    }
+
+    /// Get the indices of the files that pass the filter, in ascending order.
+    pub fn matching_files(&self, filter: &Filter) -> Vec<usize> {
+        ones(&self.flags.eval(filter, &|fi| self.file_name(fi))).collect()
+    }
+
+    /// Set the flags in `active` for the tags that any of the `files` have.
+    pub fn tags_of_files(&self, files: &[usize], active: &mut [bool]) {
+        let mut selected = vec![0u64; self.flags.stride];
+        for fi in files {
+            selected[fi / 64] |= 1 << (fi % 64);
+        }
+        for (ti, flag) in active.iter_mut().enumerate() {
+            *flag = self
+                .flags
+                .column(ti)
+                .iter()
+                .zip(&selected)
+                .any(|(tags, selected)| tags & selected != 0);
+        }
     }
 
     pub fn tags(&self) -> &[String] {
@@ -368,10 +1712,627 @@ impl TagTable {
         &self.files
     }
 
-    pub fn tag_parse_fn(&self) -> impl Fn(&str) -> Filter + use<'_> {
-        |tag| match self.tag_index.get(tag) {
-            Some(i) => Filter::Tag(*i),
-            None => Filter::FalseTag,
+    /// Get the name of a file in the table, without its directory. URLs are
+    /// returned as they are.
+    pub fn file_name(&self, file: usize) -> &str {
+        let path = &self.files[file];
+        match is_url(path) {
+            true => path,
+            false => Path::new(path)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or(path),
+        }
+    }
+
+    /// Get the number of files that have each tag, for the tags that at least
+    /// one file has. The output is sorted in descending order of the counts.
+    pub fn tag_counts(&self) -> Vec<(&str, usize)> {
+        let mut counts: Vec<(&str, usize)> = self
+            .tags
+            .iter()
+            .enumerate()
+            .map(|(ti, tag)| {
+                (
+                    tag.as_str(),
+                    self.flags
+                        .column(ti)
+                        .iter()
+                        .map(|w| w.count_ones() as usize)
+                        .sum(),
+                )
+            })
+            .filter(|(_tag, count)| *count > 0)
+            .collect();
+        counts.sort_by(|(atag, acount), (btag, bcount)| bcount.cmp(acount).then(atag.cmp(btag)));
+        counts
+    }
+
+    /// Parse `input` as a filter of the files in the table. Comparisons of the
+    /// sizes and the modification times of files are rejected, because the
+    /// table only has the tags of the files.
+    pub fn parse_filter(&self, input: &str) -> Result<Filter, FilterParseError> {
+        let mut unsupported = None;
+        let parse = self.tag_parse_fn();
+        let filter = Filter::parse(input, |tag, quoted| {
+            if !quoted && unsupported.is_none() && FileComparison::parse(tag, 0).is_some() {
+                unsupported = Some(tag.to_string());
+            }
+            parse(tag, quoted)
+        })?;
+        match unsupported {
+            Some(tag) => Err(FilterParseError::Unsupported(tag)),
+            None => Ok(filter),
+        }
+    }
+
+    fn tag_parse_fn(&self) -> impl Fn(&str, bool) -> Filter + use<'_> {
+        let any_of = |pred: &dyn Fn(&str) -> bool| {
+            self.tags
+                .iter()
+                .enumerate()
+                .filter(|(_i, t)| pred(t))
+                .map(|(i, _t)| Filter::Tag(i))
+                .reduce(|lhs, rhs| Filter::Or(Box::new(lhs), Box::new(rhs)))
+                .unwrap_or(Filter::FalseTag)
+        };
+        move |tag, quoted| match (tag_wildcard(tag), Comparison::parse(tag)) {
+            (Some(matches), _) if !quoted => any_of(&matches),
+            (None, Some(comparison)) if !quoted => any_of(&|t| comparison.matches(t)),
+            _ => match self.tag_index.get(tag) {
+                Some(i) => Filter::Tag(*i),
+                None => Filter::FalseTag,
+            },
+        }
+    }
+}
+
+/// Minimum time between two snapshots of a table that is loaded in the
+/// background, so that copying the table doesn't slow down the loading.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Snapshot of a `TagTable` that is being loaded in the background.
+pub struct LoadProgress {
+    /// The snapshot is shared, so that it can be used in several places
+    /// without copying it.
+    pub table: Arc<TagTable>,
+    /// Number of top level directories loaded so far.
+    pub loaded: usize,
+    /// Number of top level directories to load.
+    pub total: usize,
+}
+
+impl LoadProgress {
+    pub fn is_done(&self) -> bool {
+        self.loaded == self.total
+    }
+}
+
+/// Loads a `TagTable` on a background thread, so that it can be used before
+/// all of it is loaded. The files in the root directory and its immediate
+/// subdirectories are loaded first, followed by the rest of the tree, one top
+/// level directory at a time. Snapshots of the table are sent as it grows.
+pub struct TableLoader {
+    receiver: mpsc::Receiver<Result<LoadProgress, Error>>,
+}
+
+impl TableLoader {
+    /// Load the files in `dirpath` and its immediate subdirectories, and start
+    /// loading the rest of the tree in the background. Returns the first
+    /// snapshot of the table, and the loader to receive the following ones.
+    pub fn start(dirpath: PathBuf, config: &Config) -> Result<(LoadProgress, TableLoader), Error> {
+        let mut table = TagTable::empty(dirpath);
+        table.load_subtree(Path::new(""), Some(1), config)?;
+        let mut topdirs: Vec<PathBuf> = table
+            .stamps
+            .keys()
+            .filter(|dir| dir.components().count() == 1)
+            .cloned()
+            .collect();
+        topdirs.sort();
+        let table = Arc::new(table);
+        let first = LoadProgress {
+            table: Arc::clone(&table),
+            loaded: 0,
+            total: topdirs.len(),
+        };
+        let (sender, receiver) = mpsc::channel();
+        let config = config.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = Self::load(table, &topdirs, &config, &sender) {
+                // Nobody is listening if the receiver was dropped.
+                let _ = sender.send(Err(e));
+            }
+        });
+        Ok((first, TableLoader { receiver }))
+    }
+
+    fn load(
+        mut table: Arc<TagTable>,
+        topdirs: &[PathBuf],
+        config: &Config,
+        sender: &mpsc::Sender<Result<LoadProgress, Error>>,
+    ) -> Result<(), Error> {
+        let total = topdirs.len();
+        let mut last = Instant::now();
+        for (loaded, dir) in topdirs.iter().enumerate() {
+            if loaded > 0 && last.elapsed() >= SNAPSHOT_INTERVAL {
+                let progress = LoadProgress {
+                    table: Arc::clone(&table),
+                    loaded,
+                    total,
+                };
+                if sender.send(Ok(progress)).is_err() {
+                    return Ok(()); // The table is no longer needed.
+                }
+                last = Instant::now();
+            }
+            // This only copies the table if the last snapshot is still in use.
+            Arc::make_mut(&mut table).update_dir(dir, config)?;
+        }
+        let _ = sender.send(Ok(LoadProgress {
+            table,
+            loaded: total,
+            total,
+        }));
+        Ok(())
+    }
+
+    /// The latest snapshot of the table sent since the last call, if any. This
+    /// doesn't wait for the loading to make progress.
+    pub fn poll(&self) -> Option<Result<LoadProgress, Error>> {
+        self.receiver.try_iter().last()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+    use crate::vfs::MemFileSystem;
+
+    #[test]
+    fn t_namespaces() {
+        assert_eq!(tag_namespace("person:alice"), Some("person"));
+        assert_eq!(tag_namespace("place:france:paris"), Some("place:france"));
+        assert_eq!(tag_namespace("flat"), None);
+        assert_eq!(tag_namespace(":odd"), None);
+        assert_eq!(namespace_wildcard("person:*"), Some("person"));
+        assert_eq!(namespace_wildcard("person"), None);
+        assert_eq!(namespace_pattern("camera:fuji*"), Some("camera:fuji*"));
+        assert_eq!(namespace_pattern("person:*"), None);
+        assert_eq!(namespace_pattern("fuji*"), None);
+        assert_eq!(namespace_pattern("camera:fuji"), None);
+        let matches = tag_wildcard("camera:fuji*").unwrap();
+        assert!(matches("camera:fujifilm") && matches("camera:fuji:x100"));
+        assert!(!matches("camera:canon") && !matches("lens:fuji"));
+        assert!(in_namespace("place:france:paris", "place"));
+        assert!(in_namespace("place:france:paris", "place:france"));
+        assert!(!in_namespace("placebo:x", "place"));
+        let mut tags = vec!["person:bob", "flat", "place:spain", "person:alice"];
+        tags.sort_unstable_by(|a, b| namespace_order(a).cmp(&namespace_order(b)));
+        let lines: Vec<String> = group_by_namespace(&tags)
+            .map(|item| match item {
+                TagGroupItem::Namespace(ns) => format!("{}:", ns),
+                TagGroupItem::Tag(_tag, text) => text.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            ["flat", "person:", "alice", "bob", "place:", "spain"]
+        );
+    }
+
+    #[test]
+    fn t_year_range() {
+        assert_eq!(year_range("year>=2015"), Some(2015..=u16::MAX));
+        assert_eq!(year_range("year>2015"), Some(2016..=u16::MAX));
+        assert_eq!(year_range("year<=2000"), Some(0..=2000));
+        assert_eq!(year_range("year<2000"), Some(0..=1999));
+        assert_eq!(year_range("2010..2015"), Some(2010..=2015));
+        assert_eq!(year_range("year<0"), None);
+        assert_eq!(year_range("year"), None);
+        assert_eq!(year_range("10..2015"), None);
+        assert_eq!(tag_year("2015"), Some(2015));
+        assert_eq!(tag_year("201"), None);
+        assert_eq!(tag_year("20a5"), None);
+    }
+
+    #[test]
+    fn t_date_range() {
+        assert_eq!(date_range("date>=2021-01-01"), Some(20210101..=u32::MAX));
+        assert_eq!(
+            date_range("2021-01-01..2021-06-30"),
+            Some(20210101..=20210630)
+        );
+        assert_eq!(date_range("date>=2021-02-30"), None);
+        assert_eq!(date_range("date>=2021-1-1"), None);
+        assert_eq!(date_range("2010..2015"), None);
+        assert_eq!(tag_date("date:2021-07-14"), Some(20210714));
+        assert_eq!(tag_date("2021-07-14"), None);
+        let dates = Comparison::parse("date<=2021-07-14").unwrap();
+        assert!(dates.matches("date:2020-12-31"));
+        assert!(!dates.matches("date:2021-07-15"));
+        assert!(!dates.matches("2020"));
+        let dates = Comparison::parse("date<2021-07-01").unwrap();
+        assert!(dates.matches("date:2021-06-30"));
+        assert!(!dates.matches("date:2021-07-01"));
+    }
+
+    #[test]
+    fn t_file_comparison() {
+        let now = date_to_unix(20240301) + 12 * 60 * 60;
+        let parse = |tag| FileComparison::parse(tag, now);
+        assert_eq!(
+            parse("size>10M"),
+            Some(FileComparison::Size((10 << 20) + 1..=u64::MAX))
+        );
+        assert_eq!(parse("size<=2kb"), Some(FileComparison::Size(0..=2048)));
+        assert_eq!(parse("size<0"), None);
+        assert_eq!(parse("size>10X"), None);
+        assert_eq!(parse("mtime<30"), None);
+        assert_eq!(date_to_unix(19700101), 0);
+        assert_eq!(date_to_unix(20000301), 951868800);
+        let recent = parse("mtime<30d").unwrap();
+        assert!(recent.matches(0, now - 29 * SECONDS_PER_DAY));
+        assert!(recent.matches(0, now + 60));
+        assert!(!recent.matches(0, now - 30 * SECONDS_PER_DAY));
+        let old = parse("mtime>1w").unwrap();
+        assert!(old.matches(0, now - 8 * SECONDS_PER_DAY));
+        assert!(!old.matches(0, now - 6 * SECONDS_PER_DAY));
+        let before = parse("mtime<=2024-02-29").unwrap();
+        assert!(before.matches(0, now - 13 * 60 * 60));
+        assert!(!before.matches(0, now - 11 * 60 * 60));
+        let after = parse("mtime>2024-02-29").unwrap();
+        assert!(after.matches(0, now));
+        assert!(!after.matches(0, now - SECONDS_PER_DAY));
+    }
+
+    #[test]
+    fn t_query_file_stats() {
+        let mut fs = MemFileSystem::new();
+        fs.add_file("/root/.ftag", "[path]\n*.txt\n[tags]\nfoo\n");
+        fs.add_file("/root/small.txt", "ab");
+        fs.add_file("/root/sub/.ftag", "[path]\nbig.txt\n[tags]\nfoo\n");
+        fs.add_file("/root/sub/big.txt", "abcdefgh");
+        let config = Config::default();
+        let query = |filter| {
+            let mut files = Vec::new();
+            walk_query(
+                Path::new("/root"),
+                &Query::new(filter, &config).unwrap(),
+                &WalkLimits::default(),
+                &config,
+                || &fs,
+                |path| files.push(path.display().to_string()),
+                |_| {},
+            )
+            .unwrap();
+            files.sort();
+            files
+        };
+        assert_eq!(query("foo & size>4"), ["sub/big.txt"]);
+        assert_eq!(query("foo & size<=2"), ["small.txt"]);
+        // The files in memory were last modified at the Unix epoch.
+        assert!(query("foo & mtime<1d").is_empty());
+        assert_eq!(query("mtime<=1970-01-01"), ["small.txt", "sub/big.txt"]);
+    }
+
+    #[test]
+    fn t_parse_with_virtual_tags() {
+        let virtual_tags = [("inbox", "photo & !reviewed"), ("later", "inbox | todo")];
+        let mut names = Vec::new();
+        let filter = parse_with_virtual_tags("later & !beach", &virtual_tags, &mut |tag, _| {
+            names.push(tag.to_string());
+            Filter::Tag(names.len() - 1)
+        })
+        .unwrap();
+        assert_eq!(filter.text(&names), "((photo & !reviewed) | todo) & !beach");
+    }
+
+    #[test]
+    fn t_bit_table() {
+        // Enough rows to span several words, and a partial last word.
+        let (nrows, ncols) = (150, 3);
+        let mut table = BitTable::new(nrows, ncols);
+        for row in 0..nrows {
+            table.set(row, 0, row % 2 == 0);
+            table.set(row, 1, row % 3 == 0);
+            table.set(row, 2, row % 5 == 0);
         }
+        let names: Vec<String> = (0..nrows).map(|row| format!("{}.txt", row)).collect();
+        for query in [
+            "a & b",
+            "a | !c",
+            "a ^ (b | c)",
+            "!(a & b & c)",
+            "* & !a",
+            "none | c",
+            "!*",
+            "b & name:1*",
+        ] {
+            let filter = Filter::parse(query, |tag, _| {
+                Filter::Tag(["a", "b", "c"].iter().position(|t| *t == tag).unwrap())
+            })
+            .unwrap();
+            let expected: Vec<usize> = (0..nrows)
+                .filter(|row| filter.eval(&names[*row], |col| table.get(*row, col)))
+                .collect();
+            let actual: Vec<usize> = ones(&table.eval(&filter, &|row| &names[row])).collect();
+            assert_eq!(actual, expected, "{}", query);
+        }
+        // Rows are moved or dropped when copying.
+        let mut copy = BitTable::new(2, ncols + 1);
+        let mut rows = vec![None; nrows];
+        (rows[3], rows[10]) = (Some(1), Some(0));
+        copy.copy_rows(&table, &rows, ncols);
+        assert_eq!(
+            (0..ncols).map(|col| copy.get(0, col)).collect::<Vec<_>>(),
+            [true, false, true]
+        );
+        assert_eq!(
+            (0..ncols).map(|col| copy.get(1, col)).collect::<Vec<_>>(),
+            [false, true, false]
+        );
+        assert!(!copy.get(1, ncols));
+    }
+
+    #[test]
+    fn t_query_files() {
+        let dir = TempDir::new(
+            "query",
+            &[
+                (
+                    FTAG_FILE,
+                    "[tags]\ntop\n[path]\na.txt\n[tags]\nfoo camera:fujifilm\n",
+                ),
+                ("a.txt", ""),
+                ("sub/.ftag", "[tags]\nfoo\n[path]\nb.txt\n[tags]\nbar\n"),
+                ("sub/b.txt", ""),
+            ],
+        );
+        let config = Config::default();
+        let mut paths =
+            query_files(dir.to_path_buf(), "foo", &WalkLimits::default(), &config).unwrap();
+        paths.sort();
+        assert_eq!(paths, [PathBuf::from("a.txt"), PathBuf::from("sub/b.txt")]);
+        assert_eq!(
+            query_files(
+                dir.to_path_buf(),
+                "foo & bar",
+                &WalkLimits::default(),
+                &config
+            )
+            .unwrap(),
+            [PathBuf::from("sub/b.txt")]
+        );
+        // Tags are inherited by the subdirectories walked on other threads.
+        assert_eq!(
+            query_files(
+                dir.to_path_buf(),
+                "top & bar",
+                &WalkLimits::default(),
+                &config
+            )
+            .unwrap(),
+            [PathBuf::from("sub/b.txt")]
+        );
+        assert!(query_files(
+            dir.to_path_buf(),
+            "foo & (",
+            &WalkLimits::default(),
+            &config
+        )
+        .is_err());
+        assert_eq!(
+            query_files(
+                dir.to_path_buf(),
+                "camera:fuji*",
+                &WalkLimits::default(),
+                &config
+            )
+            .unwrap(),
+            [PathBuf::from("a.txt")]
+        );
+        assert!(query_files(
+            dir.to_path_buf(),
+            "camera:can*",
+            &WalkLimits::default(),
+            &config
+        )
+        .unwrap()
+        .is_empty());
+        // A quoted wildcard is a plain tag.
+        assert!(query_files(
+            dir.to_path_buf(),
+            "\"camera:fuji*\"",
+            &WalkLimits::default(),
+            &config
+        )
+        .unwrap()
+        .is_empty());
+        // Only the files in the subtree are listed, but they inherit the tags of
+        // its ancestors.
+        let limits = WalkLimits {
+            subtree: PathBuf::from("sub"),
+            max_depth: None,
+        };
+        assert_eq!(
+            query_files(dir.to_path_buf(), "top & foo", &limits, &config).unwrap(),
+            [PathBuf::from("sub/b.txt")]
+        );
+        let limits = WalkLimits {
+            subtree: PathBuf::new(),
+            max_depth: Some(0),
+        };
+        assert_eq!(
+            query_files(dir.to_path_buf(), "foo", &limits, &config).unwrap(),
+            [PathBuf::from("a.txt")]
+        );
+    }
+
+    #[test]
+    fn t_query_untagged() {
+        let dir = TempDir::new(
+            "untagged",
+            &[
+                (FTAG_FILE, "[path]\na.txt\n[path]\nb.txt\n[tags]\nfoo\n"),
+                ("sub/.ftag", "[tags]\nbar\n[path]\nc.txt\n"),
+                (
+                    "other/.ftag",
+                    "[path]\n2019_d.jpg\n[path]\nhttps://example.com\n",
+                ),
+                ("a.txt", ""),
+                ("b.txt", ""),
+                ("sub/c.txt", ""),
+                ("other/2019_d.jpg", ""),
+            ],
+        );
+        let config = Config::default();
+        let query = |filter| {
+            let mut paths = query_files(dir.to_path_buf(), filter, &WalkLimits::default(), &config)
+                .unwrap()
+                .into_iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+        assert_eq!(
+            query("untagged"),
+            ["a.txt", "https://example.com", "other/2019_d.jpg"]
+        );
+        assert_eq!(query("untagged & 2019"), ["other/2019_d.jpg"]);
+        assert_eq!(query("!untagged"), ["b.txt", "sub/c.txt"]);
+    }
+
+    #[test]
+    fn t_query_prunes_subtrees() {
+        let dir = TempDir::new(
+            "prune",
+            &[
+                ("project/.ftag", "[tags]\nproject\n[path]\na.txt\n"),
+                ("project/sub/.ftag", "[path]\nb.txt\n"),
+                ("other/.ftag", "[path]\nc.txt\n[tags]\nfoo\n"),
+                ("project/a.txt", ""),
+                ("project/sub/b.txt", ""),
+                ("other/c.txt", ""),
+            ],
+        );
+        let config = Config::default();
+        let query = |filter| {
+            let (files, dirs) =
+                query_files_and_dirs(&dir, filter, &WalkLimits::default(), &config).unwrap();
+            let mut files = files
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>();
+            files.sort();
+            (files, dirs.unwrap().contains(&PathBuf::from("project/sub")))
+        };
+        assert_eq!(query("!project"), (vec!["other/c.txt".to_string()], false));
+        assert_eq!(query("untagged"), (vec![], false));
+        assert_eq!(query("none"), (vec![], false));
+        assert_eq!(
+            query("project & !foo"),
+            (
+                vec!["project/a.txt".to_string(), "project/sub/b.txt".to_string()],
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn t_refresh() {
+        let dir = TempDir::new(
+            "refresh",
+            &[
+                (FTAG_FILE, "[path]\na.txt\n[tags]\nfoo\n"),
+                ("sub/.ftag", "[path]\nb.txt\n[tags]\nbar\n"),
+                ("sub/deep/.ftag", "[path]\nc.txt\n[tags]\nbar\n"),
+                ("a.txt", ""),
+                ("sub/b.txt", ""),
+                ("sub/deep/c.txt", ""),
+            ],
+        );
+        let config = Config::default();
+        let mut table = TagTable::from_dir(dir.to_path_buf(), &config).unwrap();
+        let query = |table: &TagTable, filter: &str| {
+            let filter = table.parse_filter(filter).unwrap();
+            let mut files = table
+                .matching_files(&filter)
+                .into_iter()
+                .map(|f| table.files()[f].clone())
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+        assert!(table.refresh(&config).unwrap().is_empty());
+        // The table doesn't know the sizes of the files.
+        assert!(table.parse_filter("foo & size>1").is_err());
+        std::fs::write(
+            dir.join("sub/deep/.ftag"),
+            "[path]\nc.txt\n[tags]\nbar baz\n",
+        )
+        .unwrap();
+        assert_eq!(table.refresh(&config).unwrap(), [Path::new("sub/deep")]);
+        assert_eq!(query(&table, "baz"), ["sub/deep/c.txt"]);
+        assert_eq!(query(&table, "bar"), ["sub/b.txt", "sub/deep/c.txt"]);
+        // A new file changes the stamp of its directory.
+        std::fs::write(dir.join("sub/d.txt"), "").unwrap();
+        std::fs::write(
+            dir.join("sub/.ftag"),
+            "[path]\nb.txt\n[tags]\nbar\n[path]\nd.txt\n[tags]\nbar\n",
+        )
+        .unwrap();
+        assert_eq!(table.refresh(&config).unwrap(), [Path::new("sub")]);
+        assert_eq!(
+            query(&table, "bar"),
+            ["sub/b.txt", "sub/d.txt", "sub/deep/c.txt"]
+        );
+        assert_eq!(query(&table, "foo"), ["a.txt"]);
+    }
+
+    #[test]
+    fn t_table_loader() {
+        let dir = TempDir::new(
+            "loader",
+            &[
+                (FTAG_FILE, "[tags]\nroot\n[path]\na.txt\n"),
+                ("x/.ftag", "[path]\nb.txt\n[tags]\nfoo\n"),
+                ("x/deep/.ftag", "[tags]\nbar\n[path]\nc.txt\n"),
+                ("y/.ftag", "[path]\nd.txt\n[tags]\nfoo\n"),
+                ("a.txt", ""),
+                ("x/b.txt", ""),
+                ("x/deep/c.txt", ""),
+                ("y/d.txt", ""),
+            ],
+        );
+        let config = Config::default();
+        let sorted = |table: &TagTable| {
+            let mut files = table.files().to_vec();
+            files.sort();
+            let mut counts = table
+                .tag_counts()
+                .into_iter()
+                .map(|(tag, count)| (tag.to_string(), count))
+                .collect::<Vec<_>>();
+            counts.sort();
+            (files, counts)
+        };
+        let (first, loader) = TableLoader::start(dir.to_path_buf(), &config).unwrap();
+        assert_eq!((first.loaded, first.total), (0, 2));
+        // The deeper directories are loaded in the background.
+        assert_eq!(sorted(&first.table).0, ["a.txt", "x/b.txt", "y/d.txt"]);
+        let last = loop {
+            match loader.poll() {
+                Some(progress) if progress.as_ref().unwrap().is_done() => break progress.unwrap(),
+                _ => std::thread::sleep(Duration::from_millis(5)),
+            }
+        };
+        assert_eq!(
+            sorted(&last.table),
+            sorted(&TagTable::from_dir(dir.to_path_buf(), &config).unwrap())
+        );
     }
 }
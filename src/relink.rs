@@ -0,0 +1,367 @@
+use crate::{
+    config::Config,
+    core::{file_entry, write_entry, Error, WritePlan, FTAG_FILE, FTAG_HASH_FILE},
+    load::{normalize_text, remove_glob_in_text, FileLoadingOptions, GlobMatches, LoaderOptions},
+    util::checksum,
+    walk::{DirTree, MetaData, VisitedDir},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+/*
+The size and a checksum of every tracked file are recorded in a .ftaghash file
+next to the .ftag file, so that files that were moved or renamed outside of
+ftag can be found again by their contents. Each line of the .ftaghash file has
+the checksum in hex, the size in bytes and the name of a file, separated by
+single spaces. The checksum is 64 bit FNV-1a, which is not cryptographic, but
+together with the size it is more than enough to tell the files in a
+collection apart. Recording the checksums is optional, and nothing else reads
+the .ftaghash files.
+ */
+
+/// Size and checksum of a file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Record {
+    size: u64,
+    hash: u64,
+}
+
+/// Get the size and the checksum of the file at `path`.
+fn record_file(path: &Path) -> Option<Record> {
+    let file = std::fs::File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let hash = checksum(file).ok()?;
+    Some(Record { size, hash })
+}
+
+/// Parse the text of a .ftaghash file. Lines that can't be parsed are skipped.
+fn parse_records(text: &str) -> BTreeMap<String, Record> {
+    text.lines()
+        .filter_map(|line| {
+            let (hash, rest) = line.split_once(' ')?;
+            let (size, name) = rest.split_once(' ')?;
+            Some((
+                name.to_string(),
+                Record {
+                    size: size.parse().ok()?,
+                    hash: u64::from_str_radix(hash, 16).ok()?,
+                },
+            ))
+        })
+        .collect()
+}
+
+fn format_records(records: &BTreeMap<String, Record>) -> String {
+    let mut out = String::new();
+    for (name, Record { size, hash }) in records {
+        let _ = writeln!(out, "{:016x} {} {}", hash, size, name);
+    }
+    out
+}
+
+/// Read the records in the .ftaghash file in the directory `dirpath`.
+fn read_records(dirpath: &Path) -> BTreeMap<String, Record> {
+    std::fs::read_to_string(dirpath.join(FTAG_HASH_FILE))
+        .map(|text| parse_records(&text))
+        .unwrap_or_default()
+}
+
+/// Plan to write the `records` to the .ftaghash file in `dirpath`, or to
+/// remove the file if there are no records.
+fn write_records(dirpath: &Path, records: &BTreeMap<String, Record>, plan: &mut WritePlan) {
+    let path = dirpath.join(FTAG_HASH_FILE);
+    match records.is_empty() {
+        true if path.exists() => plan.remove(path),
+        true => {}
+        false => plan.write(path, format_records(records)),
+    }
+}
+
+fn walk_options() -> LoaderOptions {
+    LoaderOptions::new(
+        false,
+        false,
+        FileLoadingOptions::Load {
+            file_tags: false,
+            file_desc: false,
+        },
+    )
+}
+
+/// Plan to record the size and checksum of every tracked file in the directory
+/// tree at `root`, in the .ftaghash files. Files that haven't changed since
+/// the .ftaghash file was written are not read again. Returns the plan, and
+/// the number of files recorded.
+pub fn record(root: PathBuf, config: &Config) -> Result<(WritePlan, usize), Error> {
+    let mut matcher = GlobMatches::new();
+    let mut plan = WritePlan::default();
+    let mut count = 0usize;
+    let mut dir = DirTree::new(root, walk_options(), config)?;
+    while let Some(VisitedDir {
+        abs_dir_path,
+        files,
+        metadata,
+        ..
+    }) = dir.walk()
+    {
+        let mut records = BTreeMap::new();
+        match metadata {
+            MetaData::FailedToLoad(e) => return Err(e),
+            MetaData::NotFound => {}
+            MetaData::Ok(data) => {
+                let old = read_records(abs_dir_path);
+                let written = std::fs::metadata(abs_dir_path.join(FTAG_HASH_FILE))
+                    .and_then(|m| m.modified())
+                    .ok();
+                matcher.find_matches(files, &data.globs, false);
+                for name in files
+                    .iter()
+                    .enumerate()
+                    .filter(|(fi, _)| matcher.is_file_matched(*fi))
+                    .filter_map(|(_, f)| f.name().to_str())
+                {
+                    let path = abs_dir_path.join(name);
+                    let meta = match std::fs::metadata(&path) {
+                        Ok(meta) => meta,
+                        Err(_) => continue,
+                    };
+                    let unchanged = match (old.get(name), written, meta.modified()) {
+                        (Some(rec), Some(written), Ok(modified)) => {
+                            rec.size == meta.len() && modified < written
+                        }
+                        _ => false,
+                    };
+                    let rec = match unchanged {
+                        true => old.get(name).copied(),
+                        false => record_file(&path),
+                    };
+                    if let Some(rec) = rec {
+                        records.insert(name.to_string(), rec);
+                    }
+                }
+            }
+        }
+        count += records.len();
+        write_records(abs_dir_path, &records, &mut plan);
+    }
+    Ok((plan, count))
+}
+
+/// A glob that doesn't match any files, and the record of the file it used to
+/// match.
+struct Orphan {
+    dir: PathBuf,
+    glob: String,
+    record: Record,
+}
+
+/// A file that is not tracked, with its size.
+struct Untracked {
+    dir: PathBuf,
+    name: String,
+    size: u64,
+}
+
+/// Find the files that were moved or renamed within the directory tree at
+/// `root` after their checksums were recorded, and plan to move their entries
+/// to the .ftag files in their new directories, along with their tags and
+/// descriptions. A file is matched to an entry when the entry's glob doesn't
+/// match any files, and the file is the only untracked file with the recorded
+/// size and checksum. Empty files are never matched, because they all have the
+/// same checksum. Returns the plan, and the old and new paths of the files
+/// relative to `root`.
+pub fn relink(
+    root: PathBuf,
+    config: &Config,
+) -> Result<(WritePlan, Vec<(PathBuf, PathBuf)>), Error> {
+    let mut matcher = GlobMatches::new();
+    let mut orphans: Vec<Orphan> = Vec::new();
+    let mut untracked: Vec<Untracked> = Vec::new();
+    let mut dir = DirTree::new(root.clone(), walk_options(), config)?;
+    while let Some(VisitedDir {
+        abs_dir_path,
+        rel_dir_path,
+        files,
+        metadata,
+        ..
+    }) = dir.walk()
+    {
+        let matched: Vec<bool> = match metadata {
+            MetaData::FailedToLoad(e) => return Err(e),
+            MetaData::NotFound => vec![false; files.len()],
+            MetaData::Ok(data) => {
+                matcher.find_matches(files, &data.globs, false);
+                let records = read_records(abs_dir_path);
+                orphans.extend(
+                    data.globs
+                        .iter()
+                        .enumerate()
+                        .filter(|(gi, _)| !matcher.is_glob_matched(*gi))
+                        .filter_map(|(_, g)| {
+                            Some(Orphan {
+                                dir: rel_dir_path.to_path_buf(),
+                                glob: g.path.to_string(),
+                                record: *records.get(g.path)?,
+                            })
+                        })
+                        // Empty files all have the same checksum, so they
+                        // can't be told apart.
+                        .filter(|orphan| orphan.record.size > 0),
+                );
+                (0..files.len())
+                    .map(|fi| matcher.is_file_matched(fi))
+                    .collect()
+            }
+        };
+        untracked.extend(
+            files
+                .iter()
+                .zip(matched)
+                .filter(|(_, matched)| !matched)
+                .filter_map(|(f, _)| {
+                    let name = f.name().to_str()?;
+                    Some(Untracked {
+                        dir: rel_dir_path.to_path_buf(),
+                        name: name.to_string(),
+                        size: std::fs::metadata(abs_dir_path.join(name)).ok()?.len(),
+                    })
+                }),
+        );
+    }
+    // Only the untracked files with the size of an orphan are read.
+    let mut hashes: HashMap<usize, Option<u64>> = HashMap::new();
+    let candidates: Vec<Vec<usize>> = orphans
+        .iter()
+        .map(|orphan| {
+            (0..untracked.len())
+                .filter(|ui| {
+                    let file = &untracked[*ui];
+                    file.size == orphan.record.size
+                        && *hashes.entry(*ui).or_insert_with(|| {
+                            record_file(&root.join(&file.dir).join(&file.name)).map(|r| r.hash)
+                        }) == Some(orphan.record.hash)
+                })
+                .collect()
+        })
+        .collect();
+    let moves: Vec<(&Orphan, &Untracked)> = orphans
+        .iter()
+        .zip(candidates.iter())
+        .filter_map(|(orphan, found)| match found.as_slice() {
+            [ui] if candidates.iter().filter(|c| c.contains(ui)).count() == 1 => {
+                Some((orphan, &untracked[*ui]))
+            }
+            _ => None,
+        })
+        .collect();
+    // Texts of the .ftag files and the records of the directories as they're
+    // changed by the moves.
+    let mut texts: BTreeMap<PathBuf, String> = BTreeMap::new();
+    let mut records: BTreeMap<PathBuf, BTreeMap<String, Record>> = BTreeMap::new();
+    let mut out = Vec::with_capacity(moves.len());
+    for (orphan, file) in moves {
+        let (srcdir, dstdir) = (root.join(&orphan.dir), root.join(&file.dir));
+        let (srcstore, dststore) = (srcdir.join(FTAG_FILE), dstdir.join(FTAG_FILE));
+        let (tags, desc) = file_entry(&srcstore, &orphan.glob)?;
+        for store in [&srcstore, &dststore] {
+            if !texts.contains_key(store) {
+                let mut text = match store.exists() {
+                    true => std::fs::read_to_string(store)
+                        .map_err(|_| Error::CannotReadStoreFile(store.clone()))?,
+                    false => String::new(),
+                };
+                normalize_text(&mut text);
+                texts.insert(store.clone(), text);
+            }
+        }
+        let text = texts.entry(srcstore.clone()).or_default();
+        *text = remove_glob_in_text(text, &orphan.glob).0;
+        let text = texts.entry(dststore.clone()).or_default();
+        let mut writer = match text.trim_end() {
+            "" => Vec::new(),
+            trimmed => format!("{}\n", trimmed).into_bytes(),
+        };
+        write_entry(&file.name, &tags, desc.as_ref(), &mut writer)
+            .map_err(|_| Error::CannotWriteFile(dststore.clone()))?;
+        *text = String::from_utf8(writer).map_err(|_| Error::CannotWriteFile(dststore))?;
+        records
+            .entry(srcdir.clone())
+            .or_insert_with(|| read_records(&srcdir))
+            .remove(&orphan.glob);
+        records
+            .entry(dstdir.clone())
+            .or_insert_with(|| read_records(&dstdir))
+            .insert(file.name.clone(), orphan.record);
+        out.push((orphan.dir.join(&orphan.glob), file.dir.join(&file.name)));
+    }
+    let mut plan = WritePlan::default();
+    for (path, text) in texts {
+        match text.trim().is_empty() {
+            true => plan.remove(path),
+            false => plan.write(path, text),
+        }
+    }
+    for (dirpath, records) in records {
+        write_records(&dirpath, &records, &mut plan);
+    }
+    Ok((plan, out))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn t_records() {
+        let text = "af63dc4c8601ec8c 1 my file.txt\nnot a record\n00000000000000ff 20 b.jpg\n";
+        let records = parse_records(text);
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records.get("my file.txt"),
+            Some(&Record {
+                size: 1,
+                hash: 0xaf63dc4c8601ec8c
+            })
+        );
+        assert_eq!(
+            format_records(&records),
+            "00000000000000ff 20 b.jpg\naf63dc4c8601ec8c 1 my file.txt\n"
+        );
+    }
+
+    #[test]
+    fn t_relink() {
+        let dir = TempDir::new(
+            "relink",
+            &[
+                (
+                    "a/.ftag",
+                    "[path]\nx.txt\n[tags]\nx\n\n[path]\ne.txt\n[tags]\ne\n",
+                ),
+                ("a/x.txt", "some text"),
+                ("a/e.txt", ""),
+                ("b/y.txt", "other text"),
+            ],
+        );
+        let config = Config::default();
+        let (plan, count) = record(dir.to_path_buf(), &config).unwrap();
+        assert_eq!(count, 2);
+        plan.apply(&config).unwrap();
+        for name in ["x.txt", "e.txt"] {
+            std::fs::rename(dir.join("a").join(name), dir.join("b").join(name)).unwrap();
+        }
+        let (plan, moved) = relink(dir.to_path_buf(), &config).unwrap();
+        assert_eq!(
+            moved,
+            [(PathBuf::from("a/x.txt"), PathBuf::from("b/x.txt"))]
+        );
+        plan.apply(&config).unwrap();
+        let text = std::fs::read_to_string(dir.join("b/.ftag")).unwrap();
+        assert!(text.contains("x.txt"));
+        assert!(!text.contains("e.txt"));
+    }
+}
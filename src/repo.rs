@@ -0,0 +1,134 @@
+use crate::{
+    cache::cached_query_files,
+    config::Config,
+    core::{self, Description, Error, Problem},
+    walk::{Walk, WalkLimits},
+};
+use std::path::{Path, PathBuf};
+
+/// A directory tree tagged with ftag, together with its configuration. This
+/// is the entry point for other programs that use ftag as a library. The
+/// results are the same as those of the corresponding subcommands of the
+/// `ftag` binary, in the form of typed values instead of text.
+pub struct Repo {
+    root: PathBuf,
+    config: Config,
+}
+
+impl Repo {
+    /// Open the tree rooted at `root`, with the configuration from the
+    /// .ftagconfig file of the tree and the config file of the user, just like
+    /// the `ftag` binary does.
+    pub fn open(root: impl AsRef<Path>) -> Result<Repo, Error> {
+        let root = root_dir(root.as_ref())?;
+        let config = Config::load(&root)?;
+        Ok(Repo { root, config })
+    }
+
+    /// Open the tree rooted at `root` with the given `config`, instead of
+    /// loading it from the config files.
+    pub fn with_config(root: impl AsRef<Path>, config: Config) -> Result<Repo, Error> {
+        Ok(Repo {
+            root: root_dir(root.as_ref())?,
+            config,
+        })
+    }
+
+    /// Absolute path of the root directory of the tree.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The configuration used by all the queries on this tree.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Paths of the files that match `filter`, relative to the root, in no
+    /// particular order. The filter uses the same syntax as `ftag query`. The
+    /// results are cached if the `query-cache` setting is enabled.
+    pub fn query(&self, filter: &str) -> Result<Vec<PathBuf>, Error> {
+        cached_query_files(
+            self.root.clone(),
+            filter,
+            &WalkLimits::default(),
+            &self.config,
+        )
+    }
+
+    /// All the tags used in the tree, including the implicit tags, sorted
+    /// and without duplicates.
+    pub fn tags(&self) -> Result<Vec<String>, Error> {
+        let mut tags: Vec<_> = core::get_all_tags(self.root.clone(), None, &self.config)?.collect();
+        tags.sort_unstable();
+        Ok(tags)
+    }
+
+    /// Paths of the files that are not tracked by any .ftag file, relative to
+    /// the root.
+    pub fn untracked(&self) -> Result<Vec<PathBuf>, Error> {
+        core::untracked_files(self.root.clone(), None, &self.config)
+    }
+
+    /// The tags and the description of a file or a directory. A relative
+    /// `path` is resolved against the root of the tree.
+    pub fn what_is(&self, path: impl AsRef<Path>) -> Result<Description, Error> {
+        core::what_is(&self.root.join(path), &self.config)
+    }
+
+    /// Walk all the directories of the tree, with the data from their .ftag
+    /// files.
+    pub fn walk(&self) -> Result<Walk, Error> {
+        Walk::new(self.root.clone(), &self.config)
+    }
+
+    /// All the problems in the metadata of the tree. The tree passes `ftag
+    /// check` if and only if this is empty.
+    pub fn check(&self) -> Result<Vec<Problem>, Error> {
+        core::find_problems(self.root.clone(), None, &self.config)
+    }
+}
+
+/// Absolute path of the directory `root`.
+fn root_dir(root: &Path) -> Result<PathBuf, Error> {
+    root.canonicalize()
+        .ok()
+        .filter(|path| path.is_dir())
+        .ok_or_else(|| Error::InvalidPath(root.to_path_buf()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn t_repo() {
+        let dir = TempDir::new(
+            "repo",
+            &[
+                (
+                    "a/.ftag",
+                    "[path]\nx.txt\n[tags]\nfoo bar\n[path]\ngone.txt\n[tags]\nfoo\n",
+                ),
+                ("b/.ftag", "[tags]\nbaz\n[path]\ny.txt\n[tags]\nfoo\n"),
+                ("a/x.txt", ""),
+                ("b/y.txt", ""),
+                ("b/z.txt", ""),
+            ],
+        );
+        let repo = Repo::with_config(&*dir, Config::default()).unwrap();
+        let mut files = repo.query("foo & !baz").unwrap();
+        files.sort();
+        assert_eq!(files, [PathBuf::from("a/x.txt")]);
+        assert_eq!(repo.tags().unwrap(), ["bar", "baz", "foo"]);
+        assert_eq!(repo.untracked().unwrap(), [PathBuf::from("b/z.txt")]);
+        let desc = repo.what_is("b/y.txt").unwrap();
+        assert_eq!(desc.explicit, ["foo"]);
+        assert_eq!(desc.inherited, ["baz"]);
+        let problems = repo.check().unwrap();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].code(), "unmatched-glob");
+        assert!(Repo::with_config(dir.join("missing"), Config::default()).is_err());
+    }
+}
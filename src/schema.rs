@@ -0,0 +1,211 @@
+use crate::json::Value;
+
+/*
+The JSON output of the commands is described by a JSON schema that is embedded
+in the binary, so that programs that read the output can check it against the
+version they were written for. The version of the schema is bumped whenever the
+output changes in a way that could break them. Only the keywords the schema
+uses are supported when validating: `$ref` to the definitions in the same
+schema, `anyOf`, `type`, `minimum`, `items`, `properties`, `required` and
+`additionalProperties`.
+ */
+
+/// The JSON schema of the output of the commands with `--format json`.
+pub const SCHEMA: &str = include_str!("assets/output.schema.json");
+/// Version of `SCHEMA`.
+pub const SCHEMA_VERSION: usize = 1;
+
+const REF_PREFIX: &str = "#/$defs/";
+
+struct Schema {
+    root: Value,
+}
+
+impl Schema {
+    fn load() -> Result<Schema, String> {
+        Value::parse(SCHEMA)
+            .map(|root| Schema { root })
+            .map_err(|e| format!("The embedded schema is invalid: {}", e))
+    }
+
+    fn definition(&self, name: &str) -> Option<&Value> {
+        self.root.get("$defs")?.get(name)
+    }
+
+    /// Names of the outputs, i.e. the definitions the root of the schema refers
+    /// to.
+    fn outputs(&self) -> Vec<&str> {
+        match self.root.get("anyOf") {
+            Some(Value::Array(items)) => items
+                .iter()
+                .filter_map(|item| item.get("$ref")?.as_str()?.strip_prefix(REF_PREFIX))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Check `value` against `schema`, and push the reasons it doesn't match to
+    /// `errors`. `path` is where the value is in the document, for the errors.
+    fn check(&self, schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+        if let Some(r) = schema.get("$ref").and_then(Value::as_str) {
+            match r
+                .strip_prefix(REF_PREFIX)
+                .and_then(|name| self.definition(name))
+            {
+                Some(def) => self.check(def, value, path, errors),
+                None => errors.push(format!("{}: Unknown reference '{}'.", path, r)),
+            }
+        }
+        if let Some(Value::Array(options)) = schema.get("anyOf") {
+            if !options.iter().any(|option| {
+                let mut errors = Vec::new();
+                self.check(option, value, path, &mut errors);
+                errors.is_empty()
+            }) {
+                errors.push(format!(
+                    "{}: Doesn't match any of the allowed values.",
+                    path
+                ));
+            }
+        }
+        if let Some(expected) = schema.get("type") {
+            let names: Vec<&str> = match expected {
+                Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+                name => name.as_str().into_iter().collect(),
+            };
+            if !names.iter().any(|name| match (*name, value) {
+                ("null", Value::Null)
+                | ("boolean", Value::Bool(_))
+                | ("number", Value::Number(_))
+                | ("string", Value::String(_))
+                | ("array", Value::Array(_))
+                | ("object", Value::Object(_)) => true,
+                ("integer", Value::Number(n)) => n.fract() == 0.0,
+                _ => false,
+            }) {
+                errors.push(format!(
+                    "{}: Expected a value of type {}.",
+                    path,
+                    names.join(" or ")
+                ));
+                return;
+            }
+        }
+        match (schema.get("minimum"), value) {
+            (Some(Value::Number(min)), Value::Number(n)) if n < min => {
+                errors.push(format!("{}: Expected at least {}.", path, min))
+            }
+            _ => {}
+        }
+        if let (Some(items), Value::Array(values)) = (schema.get("items"), value) {
+            for (i, item) in values.iter().enumerate() {
+                self.check(items, item, &format!("{}[{}]", path, i), errors);
+            }
+        }
+        if let Value::Object(fields) = value {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if value.get(key).is_none() {
+                        errors.push(format!("{}: The field '{}' is missing.", path, key));
+                    }
+                }
+            }
+            let properties = schema.get("properties");
+            for (key, field) in fields {
+                match properties.and_then(|p| p.get(key)) {
+                    Some(property) => {
+                        self.check(property, field, &format!("{}.{}", path, key), errors)
+                    }
+                    None if matches!(
+                        schema.get("additionalProperties"),
+                        Some(Value::Bool(false))
+                    ) =>
+                    {
+                        errors.push(format!("{}: Unexpected field '{}'.", path, key))
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
+/// Check the JSON `text` against the schema of the output named `kind`, or of
+/// any of the outputs if `kind` is `None`. Returns the names of the outputs it
+/// matches, or the reasons it doesn't match.
+pub fn validate_output(text: &str, kind: Option<&str>) -> Result<Vec<String>, Vec<String>> {
+    let schema = Schema::load().map_err(|e| vec![e])?;
+    let value = Value::parse(text).map_err(|e| vec![e])?;
+    let outputs = schema.outputs();
+    let candidates: Vec<&str> = match kind {
+        Some(kind) if outputs.contains(&kind) => vec![kind],
+        Some(kind) => {
+            return Err(vec![format!(
+                "'{}' is not one of the outputs: {}.",
+                kind,
+                outputs.join(", ")
+            )])
+        }
+        None => outputs,
+    };
+    let mut errors = Vec::new();
+    let mut matched = Vec::new();
+    for name in candidates {
+        let before = errors.len();
+        if let Some(def) = schema.definition(name) {
+            schema.check(def, &value, "$", &mut errors);
+        }
+        if errors.len() == before {
+            matched.push(name.to_string());
+        }
+    }
+    match (matched.is_empty(), kind) {
+        (false, _) => Ok(matched),
+        (true, Some(_)) => Err(errors),
+        (true, None) => Err(vec![String::from(
+            "It doesn't match the output of any command. Check it against a single output to see why.",
+        )]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::json::Json;
+
+    #[test]
+    fn t_schema() {
+        let schema = Schema::load().unwrap();
+        assert_eq!(
+            schema.root.get("version"),
+            Some(&Value::Number(SCHEMA_VERSION as f64))
+        );
+        // Every output refers to a definition.
+        for name in schema.outputs() {
+            assert!(schema.definition(name).is_some(), "{}", name);
+        }
+        let tags = Json::Array(vec![Json::Object(vec![
+            ("tag", Json::string("new york")),
+            ("virtual", Json::Bool(false)),
+        ])])
+        .to_string();
+        assert_eq!(validate_output(&tags, None).unwrap(), ["tags"]);
+        let paths = Json::strings(["a.txt", "b/c.txt"]).to_string();
+        assert_eq!(validate_output(&paths, None).unwrap(), ["paths"]);
+        // An empty list could be the output of any command that prints a list.
+        assert!(validate_output("[]", None).unwrap().len() > 1);
+        assert_eq!(validate_output("[]", Some("tags")).unwrap(), ["tags"]);
+        let problems = r#"[{"code":"untracked","dir":"a","subject":"x.txt","suggestion":null,"message":"m","extra":1}]"#;
+        assert!(validate_output(problems, None).is_err());
+        assert_eq!(
+            validate_output(problems, Some("problems")).unwrap_err(),
+            ["$[0]: Unexpected field 'extra'."]
+        );
+        assert_eq!(
+            validate_output(r#"{"added":["a"],"removed":[1]}"#, Some("watch_diff")).unwrap_err(),
+            ["$.removed[0]: Expected a value of type string."]
+        );
+        assert!(validate_output(&paths, Some("nope")).is_err());
+        assert!(validate_output("[", None).is_err());
+    }
+}
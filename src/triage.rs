@@ -0,0 +1,308 @@
+use crate::{
+    config::Config,
+    core::{add_entry, Error},
+    load::split_tags,
+};
+use crossterm::{
+    event::{self, KeyCode, KeyEvent, KeyEventKind},
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    ExecutableCommand,
+};
+use ratatui::{
+    prelude::{Backend, Constraint, CrosstermBackend, Direction, Layout, Terminal},
+    style::{Modifier, Style},
+    text::{Line, Text},
+    widgets::{Block, Borders, Padding, Paragraph},
+    Frame,
+};
+use std::{
+    io::{stdout, Read},
+    path::{Path, PathBuf},
+};
+
+/*
+Triage walks through the untracked files one at a time, so that they can be
+tagged and described without editing the .ftag files by hand. Each file is
+shown with the tags that are inferred from its name, which don't need to be
+typed, and a preview of its contents if it is a text file. The entry is
+appended to the .ftag file in the directory of the file as soon as it is
+entered, so quitting half way through doesn't lose any work.
+ */
+
+/// Number of bytes read from the start of a file to preview it.
+const PREVIEW_BYTES: u64 = 4096;
+
+/// Get the text to preview from the first few bytes of a file. Returns `None`
+/// if the bytes don't look like text. A multi-byte character that is cut off
+/// at the end of `bytes` is dropped.
+fn preview_text(bytes: &[u8]) -> Option<&str> {
+    if bytes.contains(&0) {
+        return None;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Some(text),
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&bytes[..e.valid_up_to()]).ok(),
+        Err(_) => None,
+    }
+}
+
+/// Read the preview of the file at `path`.
+fn read_preview(path: &Path) -> String {
+    let mut bytes = Vec::new();
+    match std::fs::File::open(path).and_then(|f| f.take(PREVIEW_BYTES).read_to_end(&mut bytes)) {
+        Ok(_) => match preview_text(&bytes) {
+            Some(text) => text.to_string(),
+            None => String::from("(Binary file)"),
+        },
+        Err(_) => String::from("(Cannot read the file)"),
+    }
+}
+
+/// The input field that is being typed into.
+#[derive(Clone, Copy, PartialEq)]
+enum Field {
+    Tags,
+    Desc,
+}
+
+struct TriageApp {
+    root: PathBuf,
+    config: Config,
+    /// Untracked files relative to `root`. Files are removed from this list
+    /// once they're added to a .ftag file.
+    files: Vec<PathBuf>,
+    current: usize,
+    show_preview: bool,
+    preview: String,
+    field: Field,
+    tags: String,
+    desc: String,
+    echo: String,
+    num_added: usize,
+    exit: bool,
+}
+
+impl TriageApp {
+    fn init(root: PathBuf, files: Vec<PathBuf>, show_preview: bool, config: Config) -> Self {
+        let mut app = TriageApp {
+            root,
+            config,
+            files,
+            current: 0,
+            show_preview,
+            preview: String::new(),
+            field: Field::Tags,
+            tags: String::new(),
+            desc: String::new(),
+            echo: String::from(
+                "Type the tags, and press Enter to type the description. Press Enter again to add the entry.\nUp / Down to go to the previous / next file without adding it. Esc to quit.",
+            ),
+            num_added: 0,
+            exit: false,
+        };
+        app.select(0);
+        app
+    }
+
+    /// Show the file at `index`, and clear the inputs.
+    fn select(&mut self, index: usize) {
+        self.current = index.min(self.files.len().saturating_sub(1));
+        self.field = Field::Tags;
+        self.tags.clear();
+        self.desc.clear();
+        self.preview = match (self.show_preview, self.files.get(self.current)) {
+            (true, Some(path)) => read_preview(&self.root.join(path)),
+            _ => String::new(),
+        };
+    }
+
+    fn input_mut(&mut self) -> &mut String {
+        match self.field {
+            Field::Tags => &mut self.tags,
+            Field::Desc => &mut self.desc,
+        }
+    }
+
+    /// Append the entry for the current file to the .ftag file, and move on to
+    /// the next file.
+    fn add_current(&mut self) -> Result<(), Error> {
+        let path = match self.files.get(self.current) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let tags: Vec<String> = split_tags(&self.tags).map(|t| t.to_string()).collect();
+        let desc = self.desc.trim();
+        add_entry(
+            &self.root,
+            path,
+            &tags,
+            if desc.is_empty() { None } else { Some(desc) },
+        )?
+        .apply(&self.config)?;
+        self.echo = format!("Added '{}'.", path.display());
+        for tag in tags.iter().filter(|t| self.config.is_deprecated(t)) {
+            self.echo.push_str(&match self.config.replacement(tag) {
+                Some(r) => format!(
+                    "\nWarning: The tag '{}' is deprecated, use '{}' instead.",
+                    tag, r
+                ),
+                None => format!("\nWarning: The tag '{}' is deprecated.", tag),
+            });
+        }
+        self.files.remove(self.current);
+        self.num_added += 1;
+        if self.files.is_empty() {
+            self.exit = true;
+        } else {
+            self.select(self.current);
+        }
+        Ok(())
+    }
+
+    fn keyevent(&mut self, evt: KeyEvent) {
+        if let KeyEventKind::Release = evt.kind {
+            return;
+        }
+        match evt.code {
+            KeyCode::Char(c) => self.input_mut().push(c),
+            KeyCode::Backspace => {
+                self.input_mut().pop();
+            }
+            KeyCode::Tab | KeyCode::BackTab => {
+                self.field = match self.field {
+                    Field::Tags => Field::Desc,
+                    Field::Desc => Field::Tags,
+                }
+            }
+            KeyCode::Enter if self.field == Field::Tags => self.field = Field::Desc,
+            KeyCode::Enter => {
+                if let Err(e) = self.add_current() {
+                    self.echo = format!("{:?}", e);
+                }
+            }
+            KeyCode::Up => self.select(self.current.saturating_sub(1)),
+            KeyCode::Down => self.select(self.current + 1),
+            KeyCode::Esc => self.exit = true,
+            _ => {}
+        }
+    }
+}
+
+/// Start triaging the untracked `files`, which are relative to `root`, in a
+/// TUI. If `show_preview` is true, the start of text files is shown. Returns
+/// the number of files that were added to the .ftag files.
+pub fn start(
+    root: PathBuf,
+    files: Vec<PathBuf>,
+    show_preview: bool,
+    config: Config,
+) -> std::io::Result<usize> {
+    if files.is_empty() {
+        return Ok(0);
+    }
+    stdout().execute(EnterAlternateScreen)?;
+    enable_raw_mode()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    terminal.clear()?;
+    let mut app = TriageApp::init(root, files, show_preview, config);
+    run_app(&mut terminal, &mut app)?;
+    // Clean up.
+    stdout().execute(LeaveAlternateScreen)?;
+    disable_raw_mode()?;
+    Ok(app.num_added)
+}
+
+fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut TriageApp) -> std::io::Result<()> {
+    terminal.draw(|f| render(f, app))?;
+    while !app.exit {
+        if let event::Event::Key(key) = event::read()? {
+            app.keyevent(key);
+        }
+        terminal.draw(|f| render(f, app))?;
+    }
+    Ok(())
+}
+
+fn render(f: &mut Frame, app: &TriageApp) {
+    let blocks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![
+            Constraint::Length(4),
+            Constraint::Min(3),
+            Constraint::Length(4),
+            Constraint::Length(2),
+            Constraint::Length(2),
+        ])
+        .split(f.area());
+    let (fileblock, previewblock, echoblock, tagsblock, descblock) =
+        (blocks[0], blocks[1], blocks[2], blocks[3], blocks[4]);
+    let path = app.files.get(app.current);
+    let name = path
+        .and_then(|p| p.file_name())
+        .and_then(|n| n.to_str())
+        .unwrap_or("");
+    f.render_widget(
+        Paragraph::new(vec![
+            Line::from(format!(
+                "[{}/{}] {}",
+                app.current + 1,
+                app.files.len(),
+                path.map(|p| p.display().to_string()).unwrap_or_default()
+            ))
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+            Line::from(format!(
+                "Inferred tags: {}",
+                app.config
+                    .implicit_tags(name)
+                    .map(|t| t.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )),
+            Line::from(format!("Added: {}", app.num_added)),
+        ])
+        .block(Block::new().padding(Padding::horizontal(2))),
+        fileblock,
+    );
+    f.render_widget(
+        Paragraph::new(Text::from(app.preview.as_str())).block(
+            Block::new()
+                .borders(Borders::TOP)
+                .padding(Padding::horizontal(2)),
+        ),
+        previewblock,
+    );
+    f.render_widget(
+        Paragraph::new(Text::from(app.echo.as_str())).block(
+            Block::new()
+                .borders(Borders::TOP)
+                .padding(Padding::horizontal(2)),
+        ),
+        echoblock,
+    );
+    for (field, label, text, area) in [
+        (Field::Tags, "tags", &app.tags, tagsblock),
+        (Field::Desc, "desc", &app.desc, descblock),
+    ] {
+        let cursor = if app.field == field { "█" } else { "" };
+        f.render_widget(
+            Paragraph::new(Text::from(format!("{} >>> {}{}", label, text, cursor)))
+                .block(Block::new().borders(Borders::TOP)),
+            area,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_preview_text() {
+        assert_eq!(preview_text(b"hello\nworld"), Some("hello\nworld"));
+        assert_eq!(preview_text(b"\x89PNG\r\n\x1a\n\0\0"), None);
+        // A character cut off at the end is dropped.
+        let text = "naïve".as_bytes();
+        assert_eq!(preview_text(&text[..3]), Some("na"));
+        assert_eq!(preview_text(b"a\xffb"), None);
+    }
+}
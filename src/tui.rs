@@ -1,21 +1,31 @@
 use crate::{
+    config::Config,
+    core::{plan_tag_rename, TagRename},
     interactive::{InteractiveSession, State},
-    query::TagTable,
+    query::{group_by_namespace, LoadProgress, TableLoader, TagGroupItem},
 };
 use crossterm::{
-    event::{self, KeyCode, KeyEvent, KeyEventKind},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, KeyCode, KeyEvent, KeyEventKind,
+        MouseButton, MouseEvent, MouseEventKind,
+    },
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
 use ratatui::{
-    prelude::{Backend, Constraint, CrosstermBackend, Direction, Layout, Terminal},
+    prelude::{Backend, Constraint, CrosstermBackend, Direction, Layout, Position, Rect, Terminal},
+    style::{Modifier, Style},
     text::{Line, Text},
     widgets::{
-        Block, Borders, Padding, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState,
+        Block, Borders, Clear, Padding, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState,
     },
     Frame,
 };
-use std::io::stdout;
+use std::{
+    io::stdout,
+    time::{Duration, Instant},
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 /// Count digits in the integer as written in base 10.
 fn count_digits(mut num: usize) -> u8 {
@@ -30,63 +40,533 @@ fn count_digits(mut num: usize) -> u8 {
     digits
 }
 
+/// Number of columns the list of files is panned by a single key press.
+const HSCROLL_STEP: usize = 8;
+
+/// If `line` is wider than `width` terminal cells, replace the middle of it
+/// with an ellipsis, so that the line fits in `width` and the file name at the
+/// end is still visible.
+fn elide_middle(line: &str, width: usize) -> String {
+    if line.width() <= width || width < 3 {
+        return line.to_string();
+    }
+    let head = width / 3;
+    let tail = width - head - 1;
+    // Wide characters, such as CJK, take up two cells each.
+    let mut used = 0usize;
+    let head_end = line
+        .char_indices()
+        .find(|(_, c)| {
+            used += c.width().unwrap_or(0);
+            used > head
+        })
+        .map_or(line.len(), |(i, _)| i);
+    used = 0;
+    let tail_start = line
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| {
+            used += c.width().unwrap_or(0);
+            used <= tail
+        })
+        .last()
+        .map_or(line.len(), |(i, _)| i);
+    format!("{}…{}", &line[..head_end], &line[tail_start..])
+}
+
+/// Time to wait after the last edit of the command line, before showing the
+/// files that match the filter being typed.
+const PREVIEW_DELAY: Duration = Duration::from_millis(150);
+
+/// Longest time between two clicks on the same file, for them to count as a
+/// double click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// Command that switches the TUI to the tag management mode.
+const TAGS_COMMAND: &str = "/tags";
+
+/// Command that switches the TUI to the file selection mode.
+const FILES_COMMAND: &str = "/files";
+
+/// Command that shows the help.
+const HELP_COMMAND: &str = "/help";
+
+/// Text of the help overlay.
+const HELP: &str = "\
+Type a filter and press Enter to narrow down the list of files. The next filter
+refines it, or continues it if it starts with &, | or ^.
+
+Filters
+  tag1 & tag2   AND        tag1 | tag2   OR         tag1 ^ tag2   XOR
+  !tag          NOT        tag1 tag2     AND        (...)         nesting
+  *             all files  none          no files   name:*.rs     file names
+  person:*      every tag in a namespace            \"new york\"    quoted tag
+
+Commands
+  /reset                   Remove the current filter.
+  /whatis <index>          Show the tags and description of a file.
+  /open <indices>          Open files, such as /open 3-7 or /open 1,4,6-8.
+  /exec <indices> <cmd>    Run a command on files, {} is replaced by the path.
+  /mark <indices>          Mark files across filters. /unmark [indices].
+  /marks                   List the marked files. Use 'marks' as the indices.
+  /add <indices> <tags>    Add tags to files.
+  /reload [dir]            Reload the .ftag files that changed.
+  /tags                    Rename or merge tags.
+  /files                   Select files with a cursor.
+  /help                    Show this help.
+  /quit                    Exit.
+
+Keys
+  Tab                      Autocomplete tags and commands. With an empty
+                           command line, select files with a cursor.
+  Up / Down                Scroll the list of tags.
+  Left / Right             Pan the list of files.
+  Esc                      Clear the command line, or go back.
+  j / k                    Move the cursor in /files. Enter opens, d describes.
+  Mouse                    The wheel scrolls the tags or the files. Clicking a
+                           file selects it, and double clicking opens it.
+  ?                        Show this help, when the command line is empty.
+
+Press any key to close this help.";
+
+enum Mode {
+    /// Querying files.
+    Query,
+    /// Managing tags. `selected` is the index of the selected tag in the list
+    /// of tags.
+    Tags { selected: usize },
+    /// Waiting for the user to confirm renaming a tag.
+    Confirm { selected: usize, rename: TagRename },
+    /// Selecting files. `cursor` is the index of the selected file in the list
+    /// of files.
+    Files { cursor: usize },
+}
+
 struct TuiApp {
     session: InteractiveSession,
+    mode: Mode,
     scroll: usize,
     scrollstate: ScrollbarState,
     frameheight: usize,
+    tagheight: usize,
     file_index_width: u8,
+    /// Number of lines the list of files is scrolled down.
+    filescroll: usize,
+    /// Number of lines of the list of files that fit on the screen.
+    fileheight: usize,
+    /// Number of columns the list of files is panned to the right.
+    hscroll: usize,
+    /// Loader of the rest of the table, until it is fully loaded.
+    loader: Option<TableLoader>,
+    /// Number of top level directories loaded, and the total number.
+    progress: (usize, usize),
+    /// When the filter in the command line was last edited, if the files that
+    /// match it are not shown yet.
+    edited: Option<Instant>,
+    /// The help is shown over everything else.
+    help: bool,
+    /// Where the list of tags and the list of files were last drawn, to find
+    /// what the mouse points at.
+    tagrect: Rect,
+    filerect: Rect,
+    /// The file that was last clicked, and when.
+    last_click: Option<(usize, Instant)>,
 }
 
 impl TuiApp {
-    fn init(table: TagTable) -> Self {
-        let ntags = table.tags().len();
-        let nfiles = table.files().len();
+    fn init(first: LoadProgress, loader: TableLoader, config: Config) -> Self {
+        let nfiles = first.table.files().len();
+        let progress = (first.loaded, first.total);
+        let loader = (!first.is_done()).then_some(loader);
+        let session = InteractiveSession::init(first.table, config);
+        let nlines = group_by_namespace(session.taglist()).count();
         TuiApp {
-            session: InteractiveSession::init(table),
+            session,
+            mode: Mode::Query,
             scroll: 0,
-            scrollstate: ScrollbarState::new(ntags),
+            scrollstate: ScrollbarState::new(nlines),
             frameheight: 0,
-            file_index_width: count_digits(nfiles - 1),
+            tagheight: 0,
+            file_index_width: count_digits(nfiles.saturating_sub(1)),
+            filescroll: 0,
+            fileheight: 0,
+            hscroll: 0,
+            loader,
+            progress,
+            edited: None,
+            help: false,
+            tagrect: Rect::default(),
+            filerect: Rect::default(),
+            last_click: None,
+        }
+    }
+
+    /// Show the files that match the filter being typed, once it hasn't been
+    /// edited for a while. Nothing happens while cycling through the
+    /// autocomplete suggestions. Returns true if the view changed.
+    fn poll_preview(&mut self) -> bool {
+        if let State::Autocomplete = self.session.state() {
+            return false;
+        }
+        match self.edited {
+            Some(time) if time.elapsed() >= PREVIEW_DELAY => {
+                self.edited = None;
+                self.session.preview_filter();
+                self.session.set_state(State::Default);
+                self.refresh_lists();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Note that the command line was edited, so that the files that match the
+    /// filter being typed are shown, if live filtering is enabled.
+    fn command_edited(&mut self) {
+        if self.session.config().live_filter() {
+            self.edited = Some(Instant::now());
+        }
+    }
+
+    /// Show the latest snapshot of the table that is loaded in the background,
+    /// if there is a new one. Returns true if the view changed.
+    fn poll_loader(&mut self) -> bool {
+        let result = match self.loader.as_ref().and_then(TableLoader::poll) {
+            None => return false,
+            Some(Ok(progress)) if progress.is_done() => {
+                self.loader = None;
+                self.progress = (progress.loaded, progress.total);
+                // Catch the changes made to the files while they were loading.
+                self.session
+                    .set_table(progress.table)
+                    .and_then(|_| self.session.refresh())
+            }
+            Some(Ok(progress)) => {
+                self.progress = (progress.loaded, progress.total);
+                self.session.set_table(progress.table)
+            }
+            Some(Err(e)) => {
+                self.loader = None;
+                Err(e)
+            }
+        };
+        if let Err(e) = result {
+            self.session.set_echo(&format!("{:?}", e));
+        }
+        if let State::ListsUpdated = self.session.state() {
+            self.session.set_state(State::Default);
+        }
+        if matches!(self.mode, Mode::Query) && !self.session.command().trim().is_empty() {
+            // Show the files of the new table that match the filter being typed.
+            self.command_edited();
         }
+        self.file_index_width = count_digits(self.session.table().files().len().saturating_sub(1));
+        self.scrollstate = self.scrollstate.content_length(self.num_tag_lines());
+        true
+    }
+
+    /// Number of lines in the list of tags, including the namespace headers.
+    fn num_tag_lines(&self) -> usize {
+        group_by_namespace(self.session.taglist()).count()
     }
 
     fn can_scroll(&self) -> bool {
-        self.session.taglist().len() + 1 > self.frameheight
+        self.num_tag_lines() + 1 > self.frameheight
+    }
+
+    /// Index of the line in the list of tags, that shows the tag at `index`.
+    fn tag_line(&self, index: usize) -> usize {
+        group_by_namespace(self.session.taglist())
+            .enumerate()
+            .filter(|(_line, item)| matches!(item, TagGroupItem::Tag(..)))
+            .nth(index)
+            .map(|(line, _item)| line)
+            .unwrap_or(0)
+    }
+
+    /// Select the tag at `index` and scroll to make it visible.
+    fn select_tag(&mut self, index: usize) {
+        let line = self.tag_line(index);
+        if line < self.scroll {
+            self.scroll = line;
+        } else if line >= self.scroll + self.tagheight.max(1) {
+            self.scroll = line + 1 - self.tagheight.max(1);
+        }
+        self.scrollstate = self.scrollstate.position(self.scroll);
+        self.mode = Mode::Tags { selected: index };
+    }
+
+    /// Scroll the list of tags up or down by a line, if it doesn't fit.
+    fn scroll_tags(&mut self, down: bool) {
+        if self.can_scroll() {
+            self.scroll = match down {
+                true => self.scroll.saturating_add(1),
+                false => self.scroll.saturating_sub(1),
+            };
+            self.scrollstate = self.scrollstate.position(self.scroll);
+        }
+    }
+
+    /// Start selecting files, with the cursor on the file at `index`.
+    fn begin_file_selection(&mut self, index: usize) {
+        self.session.set_echo(
+            "Select a file with j / k or the arrow keys.\nPress Enter to open it, or d to describe it.\nPress Esc or Tab to go back.",
+        );
+        self.select_file(index);
+    }
+
+    /// Select the file at `index` and scroll to make it visible.
+    fn select_file(&mut self, index: usize) {
+        if index < self.filescroll {
+            self.filescroll = index;
+        } else if index >= self.filescroll + self.fileheight.max(1) {
+            self.filescroll = index + 1 - self.fileheight.max(1);
+        }
+        self.mode = Mode::Files { cursor: index };
+    }
+
+    /// Scroll the lists of tags and files back to the top, after they're
+    /// rebuilt.
+    fn refresh_lists(&mut self) {
+        self.scroll = 0;
+        self.filescroll = 0;
+        self.scrollstate = self.scrollstate.content_length(self.num_tag_lines());
     }
 
     fn keyevent(&mut self, evt: KeyEvent) {
+        if self.help {
+            if let KeyEventKind::Press = evt.kind {
+                self.help = false;
+            }
+            return;
+        }
+        match std::mem::replace(&mut self.mode, Mode::Query) {
+            Mode::Query => self.query_keyevent(evt),
+            Mode::Tags { selected } => self.tags_keyevent(evt, selected),
+            Mode::Confirm { selected, rename } => self.confirm_keyevent(evt, selected, rename),
+            Mode::Files { cursor } => self.files_keyevent(evt, cursor),
+        }
+    }
+
+    fn mouseevent(&mut self, evt: MouseEvent) {
+        if self.help {
+            if let MouseEventKind::Down(_) = evt.kind {
+                self.help = false;
+            }
+            return;
+        }
+        let pos = Position::new(evt.column, evt.row);
+        match evt.kind {
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown if self.tagrect.contains(pos) => {
+                self.scroll_tags(evt.kind == MouseEventKind::ScrollDown)
+            }
+            MouseEventKind::ScrollUp if self.filerect.contains(pos) => {
+                self.filescroll = self.filescroll.saturating_sub(1)
+            }
+            MouseEventKind::ScrollDown if self.filerect.contains(pos) => {
+                self.filescroll = self.filescroll.saturating_add(1)
+            }
+            MouseEventKind::Down(MouseButton::Left) if self.filerect.contains(pos) => {
+                // Clicks don't interrupt managing the tags.
+                let index = match (&self.mode, evt.row - self.filerect.y) {
+                    (Mode::Query | Mode::Files { .. }, row) if row > 0 => {
+                        self.filescroll + row as usize - 1 // Top border.
+                    }
+                    _ => return,
+                };
+                let Some(path) = self.session.file_path(index) else {
+                    return;
+                };
+                let double = self
+                    .last_click
+                    .is_some_and(|(last, time)| last == index && time.elapsed() <= DOUBLE_CLICK);
+                match self.mode {
+                    Mode::Files { .. } => self.select_file(index),
+                    _ => self.begin_file_selection(index),
+                }
+                self.last_click = match double {
+                    true => {
+                        self.session.open(&[path]);
+                        None
+                    }
+                    false => Some((index, Instant::now())),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn files_keyevent(&mut self, evt: KeyEvent, cursor: usize) {
+        // The list of files can shrink while the table is loading.
+        let cursor = cursor.min(self.session.filelist().len().saturating_sub(1));
+        self.mode = Mode::Files { cursor };
+        if let KeyEventKind::Release = evt.kind {
+            return;
+        }
+        match evt.code {
+            KeyCode::Char('k') | KeyCode::Up => self.select_file(cursor.saturating_sub(1)),
+            KeyCode::Char('j') | KeyCode::Down if cursor + 1 < self.session.filelist().len() => {
+                self.select_file(cursor + 1)
+            }
+            KeyCode::Enter => {
+                if let Some(path) = self.session.file_path(cursor) {
+                    self.session.open(&[path]);
+                }
+            }
+            KeyCode::Char('d') => {
+                if let Some(path) = self.session.file_path(cursor) {
+                    self.session.describe(&path);
+                }
+            }
+            KeyCode::Char('?') => self.help = true,
+            KeyCode::Left => self.hscroll = self.hscroll.saturating_sub(HSCROLL_STEP),
+            KeyCode::Right => self.hscroll = self.hscroll.saturating_add(HSCROLL_STEP),
+            KeyCode::Esc | KeyCode::Tab => {
+                self.mode = Mode::Query;
+                self.filescroll = 0;
+                self.session.set_echo("");
+            }
+            _ => {}
+        }
+    }
+
+    fn tags_keyevent(&mut self, evt: KeyEvent, selected: usize) {
+        self.mode = Mode::Tags { selected };
+        if let KeyEventKind::Release = evt.kind {
+            return;
+        }
+        match evt.code {
+            KeyCode::Char(c) => self.session.command_mut().push(c),
+            KeyCode::Backspace => {
+                self.session.command_mut().pop();
+            }
+            KeyCode::Up => self.select_tag(selected.saturating_sub(1)),
+            KeyCode::Down if selected + 1 < self.session.taglist().len() => {
+                self.select_tag(selected + 1)
+            }
+            KeyCode::Esc if self.session.command().is_empty() => {
+                self.mode = Mode::Query;
+                self.session.set_echo("");
+            }
+            KeyCode::Esc => self.session.command_mut().clear(),
+            KeyCode::Enter => {
+                let to = self.session.command().trim().to_string();
+                let from = match self.session.taglist().get(selected) {
+                    Some(from) => from.clone(),
+                    None => return,
+                };
+                if to.is_empty() || to.contains(char::is_whitespace) {
+                    self.session
+                        .set_echo("Type a new name for the tag, without any whitespace.");
+                    return;
+                }
+                let verb = if self.session.table().tags().contains(&to) {
+                    "Merge"
+                } else {
+                    "Rename"
+                };
+                match plan_tag_rename(
+                    self.session.table().path().to_path_buf(),
+                    &from,
+                    &to,
+                    self.session.config(),
+                ) {
+                    Ok(rename) => {
+                        self.session.set_echo(&format!(
+                            "{} '{}' into '{}'? This changes {} entries in {} files. (y/n)",
+                            verb,
+                            from,
+                            to,
+                            rename.num_entries(),
+                            rename.num_files()
+                        ));
+                        self.mode = Mode::Confirm { selected, rename };
+                    }
+                    Err(e) => self.session.set_echo(&format!("{:?}", e)),
+                }
+                self.session.command_mut().clear();
+            }
+            _ => {}
+        }
+    }
+
+    fn confirm_keyevent(&mut self, evt: KeyEvent, selected: usize, rename: TagRename) {
+        match (evt.kind, evt.code) {
+            (KeyEventKind::Release, _) => self.mode = Mode::Confirm { selected, rename },
+            (_, KeyCode::Char('y')) => {
+                // The reloaded table is complete, so the table being loaded
+                // in the background is no longer needed.
+                self.loader = None;
+                match rename
+                    .apply(self.session.config())
+                    .and_then(|_| self.session.reload())
+                {
+                    Ok(_) => self.session.set_echo("Done."),
+                    Err(e) => self.session.set_echo(&format!("{:?}", e)),
+                }
+                self.refresh_lists();
+                let ntags = self.session.taglist().len();
+                self.select_tag(selected.min(ntags.saturating_sub(1)));
+            }
+            (_, KeyCode::Char('n')) | (_, KeyCode::Esc) => {
+                self.session.set_echo("Cancelled.");
+                self.mode = Mode::Tags { selected };
+            }
+            _ => self.mode = Mode::Confirm { selected, rename },
+        }
+    }
+
+    fn query_keyevent(&mut self, evt: KeyEvent) {
         match evt.kind {
             KeyEventKind::Press | KeyEventKind::Repeat => match evt.code {
+                KeyCode::Char('?') if self.session.command().is_empty() => self.help = true,
                 KeyCode::Char(c) => {
                     self.session.command_mut().push(c);
                     self.session.stop_autocomplete();
+                    self.command_edited();
                 }
                 KeyCode::Backspace => {
                     self.session.command_mut().pop();
                     self.session.stop_autocomplete();
+                    self.command_edited();
+                }
+                KeyCode::Enter if self.session.command().trim() == TAGS_COMMAND => {
+                    self.session.command_mut().clear();
+                    self.session.set_echo(
+                        "Select a tag with the arrow keys.\nType a new name to rename it, or an existing tag to merge it.\nPress Esc to go back.",
+                    );
+                    self.select_tag(0);
+                }
+                KeyCode::Enter if self.session.command().trim() == HELP_COMMAND => {
+                    self.session.command_mut().clear();
+                    self.help = true;
+                }
+                KeyCode::Enter if self.session.command().trim() == FILES_COMMAND => {
+                    self.session.command_mut().clear();
+                    self.begin_file_selection(0);
                 }
                 KeyCode::Enter => {
+                    self.edited = None;
                     self.session.process_input();
                     if let State::ListsUpdated = self.session.state() {
-                        self.scroll = 0;
-                        self.scrollstate = self
-                            .scrollstate
-                            .content_length(self.session.taglist().len());
+                        self.refresh_lists();
                         self.session.set_state(State::Default);
                     }
                 }
                 KeyCode::Esc => {
                     self.session.command_mut().clear();
                     self.session.stop_autocomplete();
+                    self.command_edited();
                 }
-                KeyCode::Up if self.can_scroll() => {
-                    self.scroll = self.scroll.saturating_sub(1);
-                    self.scrollstate = self.scrollstate.position(self.scroll);
-                }
-                KeyCode::Down if self.can_scroll() => {
-                    self.scroll = self.scroll.saturating_add(1);
-                    self.scrollstate = self.scrollstate.position(self.scroll);
+                KeyCode::Up => self.scroll_tags(false),
+                KeyCode::Down => self.scroll_tags(true),
+                KeyCode::Left => self.hscroll = self.hscroll.saturating_sub(HSCROLL_STEP),
+                KeyCode::Right => self.hscroll = self.hscroll.saturating_add(HSCROLL_STEP),
+                // Focus the list of files, starting at the first visible file.
+                KeyCode::Tab
+                    if self.session.command().is_empty() && !self.session.filelist().is_empty() =>
+                {
+                    self.begin_file_selection(self.filescroll)
                 }
                 KeyCode::Tab => self.session.autocomplete(),
                 _ => {}
@@ -96,18 +576,45 @@ impl TuiApp {
     }
 }
 
-/// Start the interactive TUI mode of ftag.
-pub fn start(table: TagTable) -> std::io::Result<()> {
-    stdout().execute(EnterAlternateScreen)?;
-    enable_raw_mode()?;
+/// Start the interactive TUI mode of ftag, with the first snapshot of a table
+/// whose remaining directories are loaded by `loader`.
+pub fn start(first: LoadProgress, loader: TableLoader, config: Config) -> std::io::Result<()> {
+    let _guard = TerminalGuard::enter(config.tui_mouse())?;
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
     terminal.clear()?;
-    let mut app = TuiApp::init(table);
-    run_app(&mut terminal, &mut app)?;
-    // Clean up.
-    stdout().execute(LeaveAlternateScreen)?;
-    disable_raw_mode()?;
-    Ok(())
+    let mut app = TuiApp::init(first, loader, config);
+    run_app(&mut terminal, &mut app)
+}
+
+/// Puts the terminal in the alternate screen and raw mode, and restores it when
+/// dropped, even if the TUI fails with an error.
+struct TerminalGuard {
+    mouse: bool,
+}
+
+impl TerminalGuard {
+    fn enter(mouse: bool) -> std::io::Result<Self> {
+        stdout().execute(EnterAlternateScreen)?;
+        // Restore whatever was set up so far, if anything after this fails.
+        let mut guard = TerminalGuard { mouse: false };
+        if mouse {
+            stdout().execute(EnableMouseCapture)?;
+            guard.mouse = true;
+        }
+        enable_raw_mode()?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        // Clean up.
+        if self.mouse {
+            let _ = stdout().execute(DisableMouseCapture);
+        }
+        let _ = stdout().execute(LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
 }
 
 fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut TuiApp) -> std::io::Result<()> {
@@ -120,11 +627,16 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut TuiApp) -> std::io:
         // Poll events to see if redraw needed.
         if event::poll(std::time::Duration::from_millis(DELAY))? {
             // If a key event occurs, handle it
-            if let event::Event::Key(key) = crossterm::event::read()? {
-                app.keyevent(key);
+            match crossterm::event::read()? {
+                event::Event::Key(key) => app.keyevent(key),
+                event::Event::Mouse(mouse) => app.mouseevent(mouse),
+                _ => {}
             }
             terminal.draw(|f| render(f, app))?;
         }
+        if app.poll_loader() || app.poll_preview() {
+            terminal.draw(|f| render(f, app))?;
+        }
         if let State::Exit = app.session.state() {
             break;
         };
@@ -134,20 +646,19 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut TuiApp) -> std::io:
 
 /// Given `prev` and `curr`, this function removes the common prefix
 /// from `curr` and returns the resulting string as part of a
-/// tuple. The first element of the tuple is the length of the prefix
-/// that was trimmed.
+/// tuple. The first element of the tuple is the width of the prefix
+/// that was trimmed, in terminal cells.
 fn remove_common_prefix<'a>(prev: &str, curr: &'a str) -> (usize, &'a str) {
-    let stop = usize::min(prev.len(), curr.len());
     let mut start = 0usize;
-    for (i, (l, r)) in prev.chars().zip(curr.chars()).enumerate() {
-        if !(i < stop && l == r) {
+    for ((i, l), r) in curr.char_indices().zip(prev.chars()) {
+        if l != r {
             break;
         }
         if l == std::path::MAIN_SEPARATOR {
             start = i;
         }
     }
-    (start, &curr[start..])
+    (curr[..start].width(), &curr[start..])
 }
 
 fn render(f: &mut Frame, app: &mut TuiApp) {
@@ -173,17 +684,40 @@ fn render(f: &mut Frame, app: &mut TuiApp) {
         .constraints(vec![Constraint::Max(1000), Constraint::Length(6)])
         .split(hlayout[0]);
     let tagblock = lblocks[0];
+    app.tagheight = tagblock.height.saturating_sub(1) as usize; // Top border.
+    app.tagrect = tagblock;
     let filterblock = lblocks[1];
     let fileblock = rblocks[0];
     let echoblock = rblocks[1];
     let cmdblock = rblocks[2];
     // Tags.
+    let selected = match &app.mode {
+        Mode::Query | Mode::Files { .. } => None,
+        Mode::Tags { selected } | Mode::Confirm { selected, .. } => Some(*selected),
+    };
+    let mut tagcounter = 0usize;
     f.render_widget(
         Paragraph::new(
-            app.session
-                .taglist()
-                .iter()
-                .map(|t| Line::from(t.clone()))
+            group_by_namespace(app.session.taglist())
+                .map(|item| match item {
+                    TagGroupItem::Namespace(ns) => Line::from(format!("{}:", ns)),
+                    TagGroupItem::Tag(tag, text) => {
+                        let mut line = if text.len() < tag.len() {
+                            Line::from(format!("  {}", text))
+                        } else {
+                            Line::from(text.to_string())
+                        };
+                        if app.session.config().is_virtual(tag) {
+                            line = line.style(Style::new().add_modifier(Modifier::ITALIC));
+                        }
+                        tagcounter += 1;
+                        if selected == Some(tagcounter - 1) {
+                            line.style(Style::new().add_modifier(Modifier::REVERSED))
+                        } else {
+                            line
+                        }
+                    }
+                })
                 .collect::<Vec<_>>(),
         )
         .block(
@@ -204,35 +738,76 @@ fn render(f: &mut Frame, app: &mut TuiApp) {
         &mut app.scrollstate,
     );
     {
+        // Long lines are elided in the middle, unless the list is panned to
+        // see them in full.
+        let width = fileblock.width.saturating_sub(4) as usize; // Padding.
+        app.fileheight = fileblock.height.saturating_sub(1) as usize; // Top border.
+        app.filerect = fileblock;
+        let cursor = match app.mode {
+            Mode::Files { cursor } => Some(cursor),
+            _ => None,
+        };
         let mut prevfile: &str = "";
+        let mut maxlen = 0usize;
+        let lines: Vec<String> = app
+            .session
+            .filelist()
+            .iter()
+            .enumerate()
+            .map(|(filecounter, file)| {
+                let out = format!(
+                    "[{}]{}{}",
+                    {
+                        let nspaces = app.file_index_width - count_digits(filecounter);
+                        format!("{}{filecounter}", " ".repeat(nspaces as usize))
+                    },
+                    match app.session.is_marked(file) {
+                        true => '*',
+                        false => ' ',
+                    },
+                    {
+                        let (space, trimmed) = remove_common_prefix(prevfile, file);
+                        format!("{}{}", ".".repeat(space), trimmed)
+                    }
+                );
+                prevfile = file;
+                maxlen = maxlen.max(out.width());
+                out
+            })
+            .collect();
+        app.hscroll = app.hscroll.min(maxlen.saturating_sub(width));
+        app.filescroll = app.filescroll.min(lines.len().saturating_sub(1));
         f.render_widget(
             Paragraph::new(
-                app.session
-                    .filelist()
+                lines
                     .iter()
                     .enumerate()
-                    .map(|(filecounter, file)| {
-                        let out = format!(
-                            "[{}] {}",
-                            {
-                                let nspaces = app.file_index_width - count_digits(filecounter);
-                                format!("{}{filecounter}", " ".repeat(nspaces as usize))
-                            },
-                            {
-                                let (space, trimmed) = remove_common_prefix(prevfile, file);
-                                format!("{}{}", ".".repeat(space), trimmed)
-                            }
-                        );
-                        prevfile = file;
-                        Line::from(out)
+                    .map(|(i, line)| {
+                        let line = match app.hscroll {
+                            0 => Line::from(elide_middle(line, width)),
+                            _ => Line::from(line.as_str()),
+                        };
+                        if cursor == Some(i) {
+                            line.style(Style::new().add_modifier(Modifier::REVERSED))
+                        } else {
+                            line
+                        }
                     })
                     .collect::<Vec<_>>(),
             )
-            .block(
-                Block::new()
+            .block({
+                let block = Block::new()
                     .borders(Borders::TOP)
-                    .padding(Padding::horizontal(2)),
-            ),
+                    .padding(Padding::horizontal(2));
+                match app.loader {
+                    Some(_) => block.title(format!(
+                        " Loading {} of {} directories... ",
+                        app.progress.0, app.progress.1
+                    )),
+                    None => block,
+                }
+            })
+            .scroll((app.filescroll as u16, app.hscroll as u16)),
             fileblock,
         );
     }
@@ -253,8 +828,59 @@ fn render(f: &mut Frame, app: &mut TuiApp) {
         filterblock,
     );
     f.render_widget(
-        Paragraph::new(Text::from(format!(">>> {}█", app.session.command())))
-            .block(Block::new().borders(Borders::TOP)),
+        Paragraph::new(Text::from(format!(
+            "{}>>> {}█",
+            match app.mode {
+                Mode::Query => "",
+                Mode::Tags { .. } => "new name ",
+                Mode::Confirm { .. } => "(y/n) ",
+                Mode::Files { .. } => "(j/k, Enter, d) ",
+            },
+            app.session.command()
+        )))
+        .block(Block::new().borders(Borders::TOP)),
         cmdblock,
     );
+    if app.help {
+        // Centered over everything else, and cut short if it doesn't fit.
+        let area = f.area();
+        let width = HELP.lines().map(|l| l.width()).max().unwrap_or(0) + 6; // Borders and padding.
+        let height = HELP.lines().count() + 2; // Borders.
+        let width = (width as u16).min(area.width);
+        let height = (height as u16).min(area.height);
+        let rect = Rect::new(
+            (area.width - width) / 2,
+            (area.height - height) / 2,
+            width,
+            height,
+        );
+        f.render_widget(Clear, rect);
+        f.render_widget(
+            Paragraph::new(HELP).block(
+                Block::new()
+                    .borders(Borders::ALL)
+                    .title(" Help ")
+                    .padding(Padding::horizontal(2)),
+            ),
+            rect,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_unicode_lines() {
+        let sep = std::path::MAIN_SEPARATOR;
+        let prev = format!("写真{sep}夏{sep}a.jpg");
+        let curr = format!("写真{sep}冬{sep}b.jpg");
+        assert_eq!(remove_common_prefix(&prev, &curr), (4, &curr[6..]));
+        assert_eq!(remove_common_prefix("", &curr), (0, curr.as_str()));
+        assert_eq!(elide_middle("ab写真cd.jpg", 20), "ab写真cd.jpg");
+        let elided = elide_middle("写真写真写真写真.jpg", 10);
+        assert_eq!(elided, "写…真.jpg");
+        assert!(elided.width() <= 10);
+    }
 }
@@ -0,0 +1,78 @@
+use std::io::Read;
+#[cfg(test)]
+use std::path::{Path, PathBuf};
+
+/// Get the 64 bit FNV-1a checksum of everything read from `reader`. This is
+/// not cryptographic, but fast, and good enough to tell files and cached
+/// results apart.
+pub(crate) fn checksum(mut reader: impl Read) -> std::io::Result<u64> {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    let mut hash = OFFSET;
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hash);
+        }
+        for byte in &buf[..n] {
+            hash = (hash ^ *byte as u64).wrapping_mul(PRIME);
+        }
+    }
+}
+
+/// A directory with the files of a test, which is removed along with everything
+/// in it when dropped, even if the test fails.
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    /// Create an empty directory for the test `name`, and write the `files` in
+    /// it. Each file is a path relative to the directory, and its contents.
+    pub(crate) fn new(name: &str, files: &[(&str, &str)]) -> TempDir {
+        let dir =
+            TempDir(std::env::temp_dir().join(format!("ftag_{}_{}", name, std::process::id())));
+        let _ = std::fs::remove_dir_all(&dir.0);
+        std::fs::create_dir_all(&dir.0).unwrap();
+        for (path, text) in files {
+            dir.write(path, text);
+        }
+        dir
+    }
+
+    /// Write `text` to the file at `path` relative to the directory, creating
+    /// the directories it is in.
+    pub(crate) fn write(&self, path: &str, text: &str) {
+        let path = self.0.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, text).unwrap();
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_checksum() {
+        assert_eq!(checksum(&b""[..]).unwrap(), 0xcbf29ce484222325);
+        assert_eq!(checksum(&b"a"[..]).unwrap(), 0xaf63dc4c8601ec8c);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::archive::TarIndex;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    ffi::{OsStr, OsString},
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::UNIX_EPOCH,
+};
+
+/*
+The directory walker and the loader only need to list directories, check
+whether paths are files or directories, and read the text of .ftag files. These
+operations go through the `FileSystem` trait, so that the same code can run
+against the real file system, or an in-memory tree of files in tests. Other
+backends can implement the same trait, such as `ArchiveFileSystem`, which
+lets tar archives be walked as if they were directories.
+ */
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum EntryKind {
+    File,
+    Dir,
+}
+
+/// Size in bytes, and modification time in seconds since the Unix epoch, of a
+/// file.
+#[derive(PartialEq, Eq, Copy, Clone, Default, Debug)]
+pub struct FileStats {
+    pub size: u64,
+    pub mtime: u64,
+}
+
+pub trait FileSystem {
+    /// Call `visit` with the name and kind of every entry in the directory at
+    /// `path`. Entries that are neither files nor directories are skipped.
+    fn read_dir(&self, path: &Path, visit: &mut dyn FnMut(OsString, EntryKind)) -> io::Result<()>;
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Append the contents of the file at `path` to `buf`.
+    fn read_to_string(&self, path: &Path, buf: &mut String) -> io::Result<()>;
+
+    /// Get the size and the modification time of the file at `path`.
+    fn stats(&self, path: &Path) -> io::Result<FileStats>;
+}
+
+impl<T: FileSystem + ?Sized> FileSystem for &T {
+    fn read_dir(&self, path: &Path, visit: &mut dyn FnMut(OsString, EntryKind)) -> io::Result<()> {
+        (**self).read_dir(path, visit)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        (**self).is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        (**self).is_file(path)
+    }
+
+    fn read_to_string(&self, path: &Path, buf: &mut String) -> io::Result<()> {
+        (**self).read_to_string(path, buf)
+    }
+
+    fn stats(&self, path: &Path) -> io::Result<FileStats> {
+        (**self).stats(path)
+    }
+}
+
+/// The file system of the operating system.
+#[derive(Default, Copy, Clone)]
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn read_dir(&self, path: &Path, visit: &mut dyn FnMut(OsString, EntryKind)) -> io::Result<()> {
+        for child in std::fs::read_dir(path)?.flatten() {
+            match child.file_type() {
+                Ok(ctype) if ctype.is_dir() => visit(child.file_name(), EntryKind::Dir),
+                Ok(ctype) if ctype.is_file() => visit(child.file_name(), EntryKind::File),
+                _ => continue,
+            }
+        }
+        Ok(())
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn read_to_string(&self, path: &Path, buf: &mut String) -> io::Result<()> {
+        File::open(path)?.read_to_string(buf).map(|_| ())
+    }
+
+    fn stats(&self, path: &Path) -> io::Result<FileStats> {
+        let meta = std::fs::metadata(path)?;
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        Ok(FileStats {
+            size: meta.len(),
+            mtime,
+        })
+    }
+}
+
+/// Same as the file system of the operating system, except tar archives can be
+/// browsed like directories, when enabled. The contents of an archive are read
+/// from the archive as needed, without extracting it.
+pub struct ArchiveFileSystem {
+    enabled: bool,
+    // Archives that were opened, and `None` for the files that turned out not
+    // to be valid archives.
+    archives: RefCell<HashMap<PathBuf, Option<Rc<TarIndex>>>>,
+}
+
+fn is_tar(name: &OsStr) -> bool {
+    name.to_str()
+        .is_some_and(|n| n.len() > 4 && n[(n.len() - 4)..].eq_ignore_ascii_case(".tar"))
+}
+
+impl ArchiveFileSystem {
+    pub fn new(enabled: bool) -> Self {
+        ArchiveFileSystem {
+            enabled,
+            archives: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Get the index of the archive at `path`, if it is a valid archive.
+    fn index(&self, path: &Path) -> Option<Rc<TarIndex>> {
+        self.archives
+            .borrow_mut()
+            .entry(path.to_path_buf())
+            .or_insert_with(|| TarIndex::read(path).ok().map(Rc::new))
+            .clone()
+    }
+
+    /// If `path` is inside an archive, or is an archive, get the path of the
+    /// archive, the index of the archive, and the path relative to the root of
+    /// the archive.
+    fn find_archive<'a>(&self, path: &'a Path) -> Option<(PathBuf, Rc<TarIndex>, &'a Path)> {
+        if !self.enabled {
+            return None;
+        }
+        let mut archive = PathBuf::new();
+        for c in path.components() {
+            archive.push(c);
+            if is_tar(c.as_os_str()) && archive.is_file() {
+                let index = self.index(&archive)?;
+                let inner = path.strip_prefix(&archive).ok()?;
+                return Some((archive, index, inner));
+            }
+        }
+        None
+    }
+}
+
+impl FileSystem for ArchiveFileSystem {
+    fn read_dir(&self, path: &Path, visit: &mut dyn FnMut(OsString, EntryKind)) -> io::Result<()> {
+        if let Some((_archive, index, inner)) = self.find_archive(path) {
+            if !index.is_dir(inner) {
+                return Err(io::ErrorKind::NotFound.into());
+            }
+            index.read_dir(inner, |name, is_file| {
+                visit(
+                    name.to_os_string(),
+                    if is_file {
+                        EntryKind::File
+                    } else {
+                        EntryKind::Dir
+                    },
+                )
+            });
+            return Ok(());
+        }
+        OsFileSystem.read_dir(path, &mut |name, kind| {
+            let kind = match kind {
+                EntryKind::File
+                    if self.enabled && is_tar(&name) && self.index(&path.join(&name)).is_some() =>
+                {
+                    EntryKind::Dir
+                }
+                kind => kind,
+            };
+            visit(name, kind)
+        })
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        match self.find_archive(path) {
+            Some((_archive, index, inner)) => index.is_dir(inner),
+            None => path.is_dir(),
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        match self.find_archive(path) {
+            Some((_archive, index, inner)) => index.is_file(inner),
+            None => path.is_file(),
+        }
+    }
+
+    fn read_to_string(&self, path: &Path, buf: &mut String) -> io::Result<()> {
+        match self.find_archive(path) {
+            Some((archive, index, inner)) => index.read_to_string(&archive, inner, buf),
+            None => OsFileSystem.read_to_string(path, buf),
+        }
+    }
+
+    fn stats(&self, path: &Path) -> io::Result<FileStats> {
+        match self.find_archive(path) {
+            Some((_archive, index, inner)) => index.stats(inner),
+            None => OsFileSystem.stats(path),
+        }
+    }
+}
+
+/// A tree of files held in memory. Files are added along with their contents,
+/// and their parent directories are created implicitly.
+#[derive(Default)]
+pub struct MemFileSystem {
+    // Contents of the files, and `None` for directories.
+    entries: BTreeMap<PathBuf, Option<String>>,
+}
+
+impl MemFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a directory at `path`, along with all its ancestors.
+    pub fn add_dir(&mut self, path: impl Into<PathBuf>) {
+        let path: PathBuf = path.into();
+        for dir in path.ancestors() {
+            if dir.as_os_str().is_empty() {
+                break;
+            }
+            self.entries.insert(dir.to_path_buf(), None);
+        }
+    }
+
+    /// Add a file at `path` with the given contents. Any existing file at the
+    /// same path is replaced.
+    pub fn add_file(&mut self, path: impl Into<PathBuf>, contents: impl Into<String>) {
+        let path: PathBuf = path.into();
+        if let Some(parent) = path.parent() {
+            self.add_dir(parent);
+        }
+        self.entries.insert(path, Some(contents.into()));
+    }
+}
+
+impl FileSystem for MemFileSystem {
+    fn read_dir(&self, path: &Path, visit: &mut dyn FnMut(OsString, EntryKind)) -> io::Result<()> {
+        if !self.is_dir(path) {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        for (child, contents) in self
+            .entries
+            .range(path.to_path_buf()..)
+            .skip(1)
+            .take_while(|(child, _)| child.starts_with(path))
+        {
+            if child.parent() != Some(path) {
+                continue; // Not an immediate child.
+            }
+            if let Some(name) = child.file_name() {
+                visit(
+                    name.to_os_string(),
+                    match contents {
+                        Some(_) => EntryKind::File,
+                        None => EntryKind::Dir,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        matches!(self.entries.get(path), Some(None))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        matches!(self.entries.get(path), Some(Some(_)))
+    }
+
+    fn read_to_string(&self, path: &Path, buf: &mut String) -> io::Result<()> {
+        match self.entries.get(path) {
+            Some(Some(contents)) => {
+                buf.push_str(contents);
+                Ok(())
+            }
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    /// The files in memory are never modified, so their modification time is
+    /// the Unix epoch.
+    fn stats(&self, path: &Path) -> io::Result<FileStats> {
+        match self.entries.get(path) {
+            Some(Some(contents)) => Ok(FileStats {
+                size: contents.len() as u64,
+                mtime: 0,
+            }),
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::Config,
+        load::{FileLoadingOptions, LoaderOptions},
+        walk::{DirTree, MetaData, VisitedDir},
+    };
+
+    #[test]
+    fn t_walk_mem_file_system() {
+        let mut fs = MemFileSystem::new();
+        fs.add_file("/root/.ftag", "[tags]\nroot\n");
+        fs.add_file("/root/a.txt", "");
+        fs.add_file("/root/sub/.ftag", "[path]\nb.txt\n[tags]\nfoo\n");
+        fs.add_file("/root/sub/b.txt", "");
+        fs.add_file("/root/sub/c.txt", "");
+        fs.add_dir("/root/empty");
+        let mut dir = DirTree::with_fs(
+            PathBuf::from("/root"),
+            LoaderOptions::new(
+                true,
+                false,
+                FileLoadingOptions::Load {
+                    file_tags: true,
+                    file_desc: false,
+                },
+            ),
+            &Config::default(),
+            fs,
+        )
+        .unwrap();
+        let mut visited = Vec::new();
+        while let Some(VisitedDir {
+            rel_dir_path,
+            files,
+            metadata,
+            ..
+        }) = dir.walk()
+        {
+            let names: Vec<String> = files
+                .iter()
+                .map(|f| f.name().to_string_lossy().to_string())
+                .collect();
+            let tags: Vec<String> = match metadata {
+                MetaData::Ok(data) => data
+                    .tags()
+                    .iter()
+                    .chain(data.globs.iter().flat_map(|g| data.glob_tags(g)))
+                    .map(|t| t.to_string())
+                    .collect(),
+                MetaData::NotFound => Vec::new(),
+                MetaData::FailedToLoad(e) => panic!("{:?}", e),
+            };
+            visited.push((rel_dir_path.to_path_buf(), names, tags));
+        }
+        visited.sort();
+        assert_eq!(
+            visited,
+            vec![
+                (
+                    PathBuf::from(""),
+                    vec!["a.txt".to_string()],
+                    vec!["root".to_string()]
+                ),
+                (PathBuf::from("empty"), vec![], vec![]),
+                (
+                    PathBuf::from("sub"),
+                    vec!["b.txt".to_string(), "c.txt".to_string()],
+                    vec!["foo".to_string()]
+                ),
+            ]
+        );
+    }
+}
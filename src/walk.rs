@@ -1,11 +1,25 @@
 use std::{
     ffi::{OsStr, OsString},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
 };
 
 use crate::{
-    core::{Error, FTAG_BACKUP_FILE, FTAG_FILE},
-    load::{get_ftag_path, DirData, Loader, LoaderOptions},
+    config::Config,
+    core::{
+        Error, FTAG_BACKUP_FILE, FTAG_CACHE_FILE, FTAG_CONFIG_FILE, FTAG_FILE, FTAG_HASH_FILE,
+        FTAG_INDEX_FILE, FTAG_JOURNAL_FILE, FTAG_LOCK_FILE,
+    },
+    load::{text_globs, DirData, FileLoadingOptions, Loader, LoaderOptions, OwnedDirData},
+    vfs::{ArchiveFileSystem, EntryKind, FileStats, FileSystem, OsFileSystem},
+};
+use fast_glob::glob_match;
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
 };
 
 #[derive(PartialEq, Eq, Copy, Clone)]
@@ -21,27 +35,104 @@ pub(crate) struct DirEntry {
     depth: usize,
     entry_type: DirEntryType,
     name: OsString,
+    stats: Option<FileStats>,
 }
 
 impl DirEntry {
     pub fn name(&self) -> &OsStr {
         &self.name
     }
+
+    /// Size and modification time of the file, if the walk reads them. See
+    /// `DirTree::with_stats`.
+    pub fn stats(&self) -> Option<FileStats> {
+        self.stats
+    }
+
+    #[cfg(test)]
+    pub(crate) fn file(name: &str) -> DirEntry {
+        DirEntry {
+            depth: 1,
+            entry_type: DirEntryType::File,
+            name: OsString::from(name),
+            stats: None,
+        }
+    }
 }
 
 /// Recursively walk directories, while caching useful information
 /// about the contents of the directory. The traversal is depth first.
-pub(crate) struct DirTree {
+pub(crate) struct DirTree<F: FileSystem = OsFileSystem> {
+    fs: F,
     abs_dir_path: PathBuf,
     rel_dir_path: PathBuf,
     stack: Vec<DirEntry>,
     cur_depth: usize,
     num_children: usize,
     loader: Loader,
+    exclude: Vec<String>,
+    ignore_files: bool,
+    ignores: Vec<IgnoreRules>,
+    skipped: Option<SkippedFiles>, // Only in keep-going mode.
+    subtree: Vec<OsString>,
+    max_level: Option<usize>,
+    stats: bool,
+}
+
+/// Limits of a walk. Only the directories in `subtree`, which is relative to the
+/// root of the walk, are walked, up to `max_depth` levels of subdirectories below
+/// `subtree`. The ancestors of `subtree` are still visited for their metadata,
+/// so that their tags are inherited, but their files are not listed.
+#[derive(Clone, Default)]
+pub struct WalkLimits {
+    pub subtree: PathBuf,
+    pub max_depth: Option<usize>,
+}
+
+impl WalkLimits {
+    /// Limits of a walk of the whole tree, up to `max_depth` levels of
+    /// subdirectories.
+    pub fn depth(max_depth: Option<usize>) -> WalkLimits {
+        WalkLimits {
+            subtree: PathBuf::new(),
+            max_depth,
+        }
+    }
+
+    /// Limits of a walk of `subdir`, such that it visits the same directories
+    /// as the walk of the root. `subdir` is relative to the root, and must be
+    /// visited by the walk of the root.
+    pub(crate) fn below(&self, subdir: &Path) -> WalkLimits {
+        match self.subtree.strip_prefix(subdir) {
+            Ok(rest) => WalkLimits {
+                subtree: rest.to_path_buf(),
+                max_depth: self.max_depth,
+            },
+            Err(_) => WalkLimits {
+                subtree: PathBuf::new(),
+                max_depth: self.max_depth.map(|depth| {
+                    depth.saturating_sub(
+                        subdir.components().count() - self.subtree.components().count(),
+                    )
+                }),
+            },
+        }
+    }
+}
+
+/// Names of the files with gitignore patterns, that are honored when walking
+/// the directories. The patterns in the latter take precedence.
+pub(crate) const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// The patterns from the ignore files of a directory, and the depth of the
+/// directory in the walk.
+struct IgnoreRules {
+    depth: usize,
+    rules: Gitignore,
 }
 
 pub(crate) enum MetaData<'a> {
-    Ok(&'a DirData<'a>),
+    Ok(DirData<'a>),
     NotFound,
     FailedToLoad(Error),
 }
@@ -52,39 +143,224 @@ pub(crate) struct VisitedDir<'a> {
     pub(crate) rel_dir_path: &'a Path,
     pub(crate) files: &'a [DirEntry],
     pub(crate) metadata: MetaData<'a>,
+    /// The directory is an ancestor of the subtree the walk is limited to. It
+    /// is only visited for its metadata, and `files` is empty.
+    pub(crate) ancestor: bool,
+}
+
+/// The .ftag files that failed to load, and were skipped by the walks in
+/// keep-going mode, with the errors. Clones share the same list, so the walks
+/// of a command, including those on other threads, all record the files they
+/// skip in the list the caller passed to `Config::set_keep_going`.
+#[derive(Clone, Default)]
+pub struct SkippedFiles(Arc<Mutex<Vec<(PathBuf, Error)>>>);
+
+impl SkippedFiles {
+    /// Record that the .ftag file at `path` was skipped because of
+    /// `error`. Each file is only recorded once, even if it is walked many
+    /// times.
+    fn record(&self, path: PathBuf, error: Error) {
+        let mut skipped = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if !skipped.iter().any(|(p, _)| *p == path) {
+            skipped.push((path, error));
+        }
+    }
+
+    /// Take the errors of the .ftag files that were skipped so far, in the
+    /// order they were found.
+    pub fn take(&self) -> Vec<Error> {
+        let mut skipped = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        std::mem::take(&mut *skipped)
+            .into_iter()
+            .map(|(_path, e)| e)
+            .collect()
+    }
 }
 
 fn is_ftag_file(file: &OsStr) -> bool {
-    file == OsStr::new(FTAG_FILE) || file == OsStr::new(FTAG_BACKUP_FILE)
+    file == OsStr::new(FTAG_FILE)
+        || file == OsStr::new(FTAG_BACKUP_FILE)
+        || file == OsStr::new(FTAG_INDEX_FILE)
+        || file == OsStr::new(FTAG_CONFIG_FILE)
+        || file == OsStr::new(FTAG_LOCK_FILE)
+        || file == OsStr::new(FTAG_HASH_FILE)
+        || file == OsStr::new(FTAG_CACHE_FILE)
+        || file == OsStr::new(FTAG_JOURNAL_FILE)
+}
+
+/// Check if the file or directory with the given name should be skipped.
+fn is_excluded(name: &OsStr, exclude: &[String]) -> bool {
+    !exclude.is_empty()
+        && exclude
+            .iter()
+            .any(|g| glob_match(g.as_bytes(), name.as_encoded_bytes()))
+}
+
+/// Read the patterns from the ignore files in `dir`. Invalid patterns are
+/// skipped, like git does.
+fn load_ignore_files(fs: &impl FileSystem, dir: &Path) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+    let mut text = String::new();
+    for path in IGNORE_FILES.iter().map(|name| dir.join(name)) {
+        text.clear();
+        if fs.is_file(&path) && fs.read_to_string(&path, &mut text).is_ok() {
+            found = true;
+            for line in text.lines() {
+                let _ = builder.add_line(Some(path.clone()), line);
+            }
+        }
+    }
+    if found {
+        builder.build().ok()
+    } else {
+        None
+    }
+}
+
+/// Get the directories above `root` whose ignore files apply to a walk of
+/// `root`, starting from the outermost. These stop at the root of the archive,
+/// i.e. the closest directory with a .ftagconfig file or a git repository, so
+/// that ignore files elsewhere, such as in the home directory, don't hide the
+/// files of the archive. There are none if `root` is not inside such a
+/// directory.
+pub(crate) fn ignore_ancestors(fs: &impl FileSystem, root: &Path) -> Vec<PathBuf> {
+    let is_archive_root = |dir: &Path| {
+        [FTAG_CONFIG_FILE, ".git"].iter().any(|name| {
+            let path = dir.join(name);
+            fs.is_file(&path) || fs.is_dir(&path)
+        })
+    };
+    if is_archive_root(root) {
+        return Vec::new();
+    }
+    let mut dirs = Vec::new();
+    for dir in root.ancestors().skip(1) {
+        dirs.push(dir.to_path_buf());
+        if is_archive_root(dir) {
+            dirs.reverse();
+            return dirs;
+        }
+    }
+    Vec::new()
+}
+
+/// Check if the file or directory at `path` is ignored by the patterns in
+/// `ignores`. The patterns of the deeper directories take precedence.
+fn is_ignored(ignores: &[IgnoreRules], path: &Path, is_dir: bool) -> bool {
+    for IgnoreRules { rules, .. } in ignores.iter().rev() {
+        match rules.matched(path, is_dir) {
+            Match::None => continue,
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+        }
+    }
+    false
 }
 
 impl DirTree {
-    pub fn new(rootdir: PathBuf, options: LoaderOptions) -> Result<Self, Error> {
-        if !rootdir.is_dir() {
+    pub fn new(rootdir: PathBuf, options: LoaderOptions, config: &Config) -> Result<Self, Error> {
+        Self::with_fs(rootdir, options, config, OsFileSystem)
+    }
+}
+
+impl DirTree<ArchiveFileSystem> {
+    /// Same as `new`, except tar archives are walked like directories if they
+    /// are enabled in the config. This is only meant for reading the metadata,
+    /// because nothing inside an archive can be changed.
+    pub fn with_archives(
+        rootdir: PathBuf,
+        options: LoaderOptions,
+        config: &Config,
+    ) -> Result<Self, Error> {
+        Self::with_fs(
+            rootdir,
+            options,
+            config,
+            ArchiveFileSystem::new(config.archives()),
+        )
+    }
+}
+
+impl<F: FileSystem> DirTree<F> {
+    /// Walk the directories in the file system `fs`, starting at `rootdir`.
+    pub fn with_fs(
+        rootdir: PathBuf,
+        options: LoaderOptions,
+        config: &Config,
+        fs: F,
+    ) -> Result<Self, Error> {
+        if !fs.is_dir(&rootdir) {
             return Err(Error::InvalidPath(rootdir));
         }
+        // The ignore files of the ancestors apply to the walk, so that walking a
+        // subdirectory finds the same files as walking its parent.
+        let ignores = match config.ignore_files() {
+            true => ignore_ancestors(&fs, &rootdir)
+                .iter()
+                .filter_map(|dir| load_ignore_files(&fs, dir))
+                .map(|rules| IgnoreRules { depth: 0, rules })
+                .collect(),
+            false => Vec::new(),
+        };
         Ok(DirTree {
+            fs,
             abs_dir_path: rootdir,
             rel_dir_path: PathBuf::new(),
             stack: vec![DirEntry {
                 depth: 1,
                 entry_type: DirEntryType::Dir,
                 name: OsString::new(),
+                stats: None,
             }],
             cur_depth: 0,
             num_children: 0,
             loader: Loader::new(options),
+            exclude: config.exclude().to_vec(),
+            ignore_files: config.ignore_files(),
+            ignores,
+            skipped: config.skipped_files().cloned(),
+            subtree: Vec::new(),
+            max_level: None,
+            stats: false,
         })
     }
 
+    /// Limit the walk to the directories within `limits`.
+    pub fn limit(mut self, limits: &WalkLimits) -> Self {
+        self.subtree = limits
+            .subtree
+            .iter()
+            .map(|name| name.to_os_string())
+            .collect();
+        self.max_level = limits.max_depth.map(|depth| depth + self.subtree.len());
+        self
+    }
+
+    /// Read the size and the modification time of every file that is walked,
+    /// from the file system being walked. This costs a call to the file system
+    /// per file, so it is off by default.
+    pub fn with_stats(mut self) -> Self {
+        self.stats = true;
+        self
+    }
+
+    /// Report every .ftag file that fails to load as `MetaData::FailedToLoad`,
+    /// even in keep-going mode. This is for walks that look for broken files.
+    pub fn report_failures(mut self) -> Self {
+        self.skipped = None;
+        self
+    }
+
     /// Move on to the next directory. Returns a tuple containing the depth of
     /// the directory, its absolute path, its path relative to the root of the
     /// walk, and a slice containing info about the files in this directory.
-    pub fn walk(&mut self) -> Option<VisitedDir> {
+    pub fn walk(&mut self) -> Option<VisitedDir<'_>> {
         while let Some(DirEntry {
             depth,
             entry_type,
             name,
+            ..
         }) = self.stack.pop()
         {
             match entry_type {
@@ -100,26 +376,80 @@ impl DirTree {
                     self.cur_depth += 1;
                     // Push all children.
                     let mut numfiles = 0;
+                    let mut has_ignore_files = false;
                     let before = self.stack.len();
-                    if let Ok(entries) = std::fs::read_dir(&self.abs_dir_path) {
-                        for child in entries.flatten() {
-                            match (child.file_name(), child.file_type()) {
-                                (cname, _) if is_ftag_file(&cname) => continue,
-                                (cname, Ok(ctype)) if ctype.is_dir() => self.stack.push(DirEntry {
-                                    depth: depth + 1,
-                                    entry_type: DirEntryType::Dir,
-                                    name: cname,
-                                }),
-                                (cname, Ok(ctype)) if ctype.is_file() => {
-                                    self.stack.push(DirEntry {
-                                        depth: depth + 1,
-                                        entry_type: DirEntryType::File,
-                                        name: cname,
-                                    });
-                                    numfiles += 1;
+                    let level = depth - 1; // Of the current directory.
+                    let ancestor = level < self.subtree.len();
+                    let descend = self.max_level.is_none_or(|max| level < max);
+                    let (stack, exclude, subtree) = (&mut self.stack, &self.exclude, &self.subtree);
+                    let _ = self.fs.read_dir(&self.abs_dir_path, &mut |cname, ctype| {
+                        if is_ftag_file(&cname) || is_excluded(&cname, exclude) {
+                            return;
+                        }
+                        let entry_type = match ctype {
+                            EntryKind::Dir if !descend || (ancestor && cname != subtree[level]) => {
+                                return;
+                            }
+                            EntryKind::Dir => DirEntryType::Dir,
+                            EntryKind::File => {
+                                has_ignore_files |= IGNORE_FILES.iter().any(|f| cname == *f);
+                                if ancestor {
+                                    return;
                                 }
-                                _ => continue,
+                                numfiles += 1;
+                                DirEntryType::File
                             }
+                        };
+                        stack.push(DirEntry {
+                            depth: depth + 1,
+                            entry_type,
+                            name: cname,
+                            stats: None,
+                        });
+                    });
+                    if self.ignore_files {
+                        // Forget the patterns of the directories that are not ancestors.
+                        while self.ignores.last().is_some_and(|r| r.depth >= depth) {
+                            self.ignores.pop();
+                        }
+                        if has_ignore_files {
+                            if let Some(rules) = load_ignore_files(&self.fs, &self.abs_dir_path) {
+                                self.ignores.push(IgnoreRules { depth, rules });
+                            }
+                        }
+                        if !self.ignores.is_empty() {
+                            let fpath = self.abs_dir_path.join(FTAG_FILE);
+                            let (ignores, dirpath, fs) =
+                                (&self.ignores, &mut self.abs_dir_path, &self.fs);
+                            let mut globs: Option<String> = None;
+                            let children = self.stack.split_off(before);
+                            self.stack.extend(children.into_iter().filter(|child| {
+                                dirpath.push(&child.name);
+                                let is_dir = child.entry_type == DirEntryType::Dir;
+                                // Tagged files are never ignored, so that their tags
+                                // aren't lost, and neither are directories with .ftag
+                                // files.
+                                let keep = !is_ignored(ignores, dirpath, is_dir)
+                                    || match child.entry_type {
+                                        DirEntryType::Dir => fs.is_file(&dirpath.join(FTAG_FILE)),
+                                        DirEntryType::File => {
+                                            let text = globs.get_or_insert_with(|| {
+                                                let mut text = String::new();
+                                                let _ = fs.read_to_string(&fpath, &mut text);
+                                                text
+                                            });
+                                            child.name.to_str().is_some_and(|name| {
+                                                text_globs(text).iter().any(|g| glob_match(g, name))
+                                            })
+                                        }
+                                    };
+                                dirpath.pop();
+                                keep
+                            }));
+                            numfiles = self.stack[before..]
+                                .iter()
+                                .filter(|c| c.entry_type == DirEntryType::File)
+                                .count();
                         }
                     }
                     self.num_children = self.stack.len() - before;
@@ -132,22 +462,489 @@ impl DirTree {
                             (DirEntryType::Dir, DirEntryType::Dir) => std::cmp::Ordering::Equal,
                         }
                     });
+                    let first_file = self.stack.len() - numfiles;
+                    if self.stats {
+                        for file in &mut self.stack[first_file..] {
+                            file.stats = self.fs.stats(&self.abs_dir_path.join(&file.name)).ok();
+                        }
+                    }
                     return Some(VisitedDir {
                         traverse_depth: depth,
                         abs_dir_path: &self.abs_dir_path,
                         rel_dir_path: &self.rel_dir_path,
-                        files: &self.stack[(self.stack.len() - numfiles)..], // Files are sorted to the end of the stack.
-                        metadata: match get_ftag_path::<true>(&self.abs_dir_path) {
-                            Some(fpath) => match self.loader.load(&fpath) {
-                                Ok(data) => MetaData::Ok(data),
-                                Err(e) => MetaData::FailedToLoad(e),
-                            },
-                            None => MetaData::NotFound,
+                        files: &self.stack[first_file..], // Files are sorted to the end of the stack.
+                        metadata: match self.abs_dir_path.join(FTAG_FILE) {
+                            fpath if self.fs.is_file(&fpath) => {
+                                match self.loader.load_from(&self.fs, &fpath) {
+                                    Ok(data) => MetaData::Ok(data),
+                                    Err(e) if self.skipped.is_some() => {
+                                        // Carry on as if the file doesn't exist.
+                                        if let Some(skipped) = &self.skipped {
+                                            skipped.record(fpath, e);
+                                        }
+                                        MetaData::NotFound
+                                    }
+                                    Err(e) => MetaData::FailedToLoad(e),
+                                }
+                            }
+                            _ => MetaData::NotFound,
                         },
+                        ancestor,
                     });
                 }
             }
         }
         None
     }
+
+    /// Remove the subdirectories of the directory that was visited last from
+    /// the walk, and return their paths relative to the root of the walk, in
+    /// the order they would have been visited. This lets the subdirectories be
+    /// walked separately, for example on other threads.
+    pub fn take_subdirs(&mut self) -> Vec<PathBuf> {
+        let children = self.stack.split_off(self.stack.len() - self.num_children);
+        self.num_children = 0;
+        children
+            .into_iter()
+            .rev() // The stack is popped from the end.
+            .filter(|child| child.entry_type == DirEntryType::Dir)
+            .map(|child| self.rel_dir_path.join(child.name))
+            .collect()
+    }
+}
+
+/// A directory visited by `Walk`.
+pub struct WalkedDir {
+    /// Number of levels of directories between this one and the root of the
+    /// walk. The root is at depth 0.
+    pub depth: usize,
+    pub abs_path: PathBuf,
+    /// Path relative to the root of the walk, which is empty for the root.
+    pub rel_path: PathBuf,
+    /// Names of the files directly inside the directory, sorted. The files
+    /// that ftag ignores are left out.
+    pub files: Vec<OsString>,
+    /// Data from the .ftag file of the directory, if it has one.
+    pub data: Option<OwnedDirData>,
+    /// The directory is an ancestor of the subtree the walk is limited to. It
+    /// is only visited for its data, and `files` is empty.
+    pub ancestor: bool,
+}
+
+/// Walk the directories of a tree depth first, the same way ftag does, and
+/// read their .ftag files. Each directory is yielded before its
+/// subdirectories. A .ftag file that fails to load is yielded as an error, and
+/// the walk carries on after it.
+pub struct Walk {
+    tree: DirTree<ArchiveFileSystem>,
+}
+
+impl Walk {
+    /// Walk the tree rooted at `root`, with the exclusions and the ignore files
+    /// from `config`.
+    pub fn new(root: impl Into<PathBuf>, config: &Config) -> Result<Walk, Error> {
+        Ok(Walk {
+            tree: DirTree::with_archives(
+                root.into(),
+                LoaderOptions::new(
+                    true,
+                    true,
+                    FileLoadingOptions::Load {
+                        file_tags: true,
+                        file_desc: true,
+                    },
+                ),
+                config,
+            )?,
+        })
+    }
+
+    /// Limit the walk to the directories within `limits`.
+    pub fn limit(self, limits: &WalkLimits) -> Walk {
+        Walk {
+            tree: self.tree.limit(limits),
+        }
+    }
+
+    /// Don't walk the subdirectories of the directory that was yielded last.
+    pub fn skip_subdirs(&mut self) {
+        self.tree.take_subdirs();
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<WalkedDir, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let VisitedDir {
+            traverse_depth,
+            abs_dir_path,
+            rel_dir_path,
+            files,
+            metadata,
+            ancestor,
+        } = self.tree.walk()?;
+        let data = match metadata {
+            MetaData::Ok(data) => Some(OwnedDirData::from(&data)),
+            MetaData::NotFound => None,
+            MetaData::FailedToLoad(e) => return Some(Err(e)),
+        };
+        Some(Ok(WalkedDir {
+            depth: traverse_depth - 1,
+            abs_path: abs_dir_path.to_path_buf(),
+            rel_path: rel_dir_path.to_path_buf(),
+            files: files.iter().map(|f| f.name().to_os_string()).collect(),
+            data,
+            ancestor,
+        }))
+    }
+}
+
+/// The subtrees left to walk in `walk_parallel`, with the state the walk of
+/// each of them starts with. The threads take subtrees from the queue, and
+/// share parts of their own subtrees when other threads run out of work, so
+/// the work is balanced at any depth, not just between the subdirectories of
+/// the root.
+pub(crate) struct WorkQueue<S> {
+    tasks: Mutex<Tasks<S>>,
+    ready: Condvar,
+    idle: AtomicUsize, // Number of threads waiting for work.
+    stop: AtomicBool,
+}
+
+struct Tasks<S> {
+    queue: Vec<(PathBuf, S)>,
+    idle: usize,
+}
+
+impl<S> WorkQueue<S> {
+    fn new(queue: Vec<(PathBuf, S)>) -> Self {
+        WorkQueue {
+            tasks: Mutex::new(Tasks { queue, idle: 0 }),
+            ready: Condvar::new(),
+            idle: AtomicUsize::new(0),
+            stop: AtomicBool::new(false),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Tasks<S>> {
+        self.tasks.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wait for the next subtree to walk. Returns `None` when the walk is
+    /// stopped, or when there is nothing left to walk, i.e. the queue is empty
+    /// and all `nthreads` threads are waiting.
+    fn pop(&self, nthreads: usize) -> Option<(PathBuf, S)> {
+        let mut tasks = self.lock();
+        loop {
+            if self.stop.load(Ordering::Relaxed) {
+                return None;
+            }
+            if let Some(task) = tasks.queue.pop() {
+                return Some(task);
+            }
+            if tasks.idle + 1 == nthreads {
+                // Nothing left to walk. The lock is held, so notify directly.
+                self.stop.store(true, Ordering::Relaxed);
+                self.ready.notify_all();
+                return None;
+            }
+            tasks.idle += 1;
+            self.idle.store(tasks.idle, Ordering::Relaxed);
+            tasks = self
+                .ready
+                .wait(tasks)
+                .unwrap_or_else(PoisonError::into_inner);
+            tasks.idle -= 1;
+            self.idle.store(tasks.idle, Ordering::Relaxed);
+        }
+    }
+
+    /// Stop all the threads, once the work is done or one of them failed.
+    fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _tasks = self.lock(); // So no thread misses the notification.
+        self.ready.notify_all();
+    }
+
+    /// Called by the walk of a subtree after visiting each directory. Returns
+    /// false if the walk was stopped, because another thread failed. Otherwise,
+    /// if any threads are waiting for work, the subdirectories of the
+    /// directory that was visited last are taken from `dir` and shared with
+    /// them, and the walk of each starts with a clone of `state`. `prefix` is
+    /// the path of the root of `dir`, relative to the root of the whole walk.
+    pub(crate) fn share<F: FileSystem>(
+        &self,
+        dir: &mut DirTree<F>,
+        prefix: &Path,
+        state: &S,
+    ) -> bool
+    where
+        S: Clone,
+    {
+        if self.stop.load(Ordering::Relaxed) {
+            return false;
+        }
+        if self.idle.load(Ordering::Relaxed) > 0 {
+            let subdirs = dir.take_subdirs();
+            if !subdirs.is_empty() {
+                let mut tasks = self.lock();
+                tasks.queue.extend(
+                    subdirs
+                        .into_iter()
+                        .map(|subdir| (prefix.join(subdir), state.clone())),
+                );
+                self.ready.notify_all();
+            }
+        }
+        true
+    }
+}
+
+/// Stops the walk if the thread that owns it panics, so the other threads
+/// don't wait forever for the work it would have shared.
+struct StopOnPanic<'a, S>(&'a WorkQueue<S>);
+
+impl<S> Drop for StopOnPanic<'_, S> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.0.stop();
+        }
+    }
+}
+
+/// Walk the subtrees in `tasks` on multiple threads. Each task is the path of
+/// a subtree relative to the root of the walk, and the state its walk starts
+/// with. `work` walks a subtree, sends the results with the function it is
+/// given, and shares parts of the subtree with the other threads through the
+/// queue. `visit` is called on this thread with the results as they arrive,
+/// in no particular order. The walk stops at the first error, and a thread
+/// that panics is reported as `Error::DirectoryTraversalFailed`.
+pub(crate) fn walk_parallel<S, T, W>(
+    tasks: Vec<(PathBuf, S)>,
+    work: W,
+    mut visit: impl FnMut(T),
+) -> Result<(), Error>
+where
+    S: Send,
+    T: Send,
+    W: Fn(&Path, S, &WorkQueue<S>, &mut dyn FnMut(T)) -> Result<(), Error> + Sync,
+{
+    if tasks.is_empty() {
+        return Ok(());
+    }
+    let nthreads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let queue = WorkQueue::new(tasks);
+    let (sender, receiver) = mpsc::channel();
+    let (queue, work) = (&queue, &work);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..nthreads)
+            .map(|_| {
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    let _guard = StopOnPanic(queue);
+                    let mut send = |result| {
+                        let _ = sender.send(result);
+                    };
+                    while let Some((subdir, state)) = queue.pop(nthreads) {
+                        work(&subdir, state, queue, &mut send).inspect_err(|_| queue.stop())?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        drop(sender); // The results stop when all the threads are done.
+        receiver.iter().for_each(&mut visit);
+        // Join every thread before looking at the results, or the scope panics.
+        let results: Vec<_> = handles.into_iter().map(|handle| handle.join()).collect();
+        results
+            .into_iter()
+            .try_for_each(|result| result.map_err(|_| Error::DirectoryTraversalFailed)?)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn t_walk_parallel() {
+        let mut fs = crate::vfs::MemFileSystem::new();
+        let mut expected = Vec::new();
+        for i in 0..4 {
+            for j in 0..20 {
+                for k in 0..3 {
+                    let path = format!("{}/{}/{}", i, j, k);
+                    fs.add_file(format!("/root/{}/x.txt", path), "");
+                    expected.extend([PathBuf::from(format!("{}/{}", i, j)), PathBuf::from(path)]);
+                }
+            }
+            expected.push(PathBuf::from(i.to_string()));
+        }
+        expected.sort();
+        expected.dedup();
+        let options = || LoaderOptions::new(false, false, crate::load::FileLoadingOptions::Skip);
+        let config = Config::default();
+        let tasks = ["0", "1", "2", "3"]
+            .iter()
+            .map(|d| (PathBuf::from(d), ()))
+            .collect();
+        let mut found = Vec::new();
+        walk_parallel(
+            tasks,
+            |subdir, (), queue, send| {
+                let root = Path::new("/root").join(subdir);
+                let mut dir = DirTree::with_fs(root, options(), &config, &fs)?;
+                while let Some(visited) = dir.walk() {
+                    send(subdir.join(visited.rel_dir_path));
+                    queue.share(&mut dir, subdir, &());
+                }
+                Ok(())
+            },
+            |path| found.push(path),
+        )
+        .unwrap();
+        found.sort();
+        assert_eq!(found, expected);
+        // Errors and panics in the threads are reported.
+        let tasks = || {
+            (0..100)
+                .map(|i| (PathBuf::from(i.to_string()), ()))
+                .collect()
+        };
+        let result = walk_parallel(
+            tasks(),
+            |_, (), _, _: &mut dyn FnMut(())| Err(Error::InvalidArgs),
+            |_| {},
+        );
+        assert!(matches!(result, Err(Error::InvalidArgs)));
+        let result = walk_parallel(tasks(), |_, (), _, _: &mut dyn FnMut(())| panic!(), |_| {});
+        assert!(matches!(result, Err(Error::DirectoryTraversalFailed)));
+    }
+
+    #[test]
+    fn t_ignore_files() {
+        let mut fs = crate::vfs::MemFileSystem::new();
+        fs.add_file("/.gitignore", "*\n");
+        fs.add_dir("/root/.git");
+        fs.add_file("/root/.gitignore", "target/\n*.log\nignored/\n");
+        fs.add_file("/root/.ftag", "[path]\ntagged.log\n[tags]\nfoo\n");
+        fs.add_file("/root/tagged.log", "");
+        fs.add_file("/root/ignored/.ftag", "[path]\nc.txt\n[tags]\nbar\n");
+        fs.add_file("/root/ignored/c.txt", "");
+        fs.add_file("/root/a.log", "");
+        fs.add_file("/root/a.txt", "");
+        fs.add_file("/root/target/b.txt", "");
+        fs.add_file("/root/sub/.ignore", "!keep.log\n");
+        fs.add_file("/root/sub/keep.log", "");
+        fs.add_file("/root/sub/other.log", "");
+        let walk = |root: &str| {
+            let options = LoaderOptions::new(false, false, crate::load::FileLoadingOptions::Skip);
+            let mut dir =
+                DirTree::with_fs(PathBuf::from(root), options, &Config::default(), &fs).unwrap();
+            let mut found = Vec::new();
+            while let Some(VisitedDir {
+                rel_dir_path,
+                files,
+                ..
+            }) = dir.walk()
+            {
+                found.extend(files.iter().map(|f| rel_dir_path.join(f.name())));
+            }
+            found.sort();
+            found
+        };
+        let paths = |items: &[&str]| -> Vec<PathBuf> { items.iter().map(PathBuf::from).collect() };
+        // Tagged files, and directories with .ftag files, are never ignored. The
+        // ignore files above the git repository don't apply.
+        assert_eq!(
+            walk("/root"),
+            paths(&[
+                ".gitignore",
+                "a.txt",
+                "ignored/c.txt",
+                "sub/.ignore",
+                "sub/keep.log",
+                "tagged.log"
+            ])
+        );
+        // The ignore files of the ancestors in the repository apply.
+        assert_eq!(walk("/root/sub"), paths(&[".ignore", "keep.log"]));
+    }
+
+    #[test]
+    fn t_walk() {
+        let dir = TempDir::new(
+            "walk",
+            &[
+                ("a/.ftag", "[tags]\nfoo\n[path]\nx.txt\n[tags]\nbar\n"),
+                ("a/x.txt", ""),
+                ("a/c/y.txt", ""),
+                ("b/.ftag", "[tags]\nfoo\n[tags]\nbar\n"),
+                ("z.txt", ""),
+            ],
+        );
+        let mut walk = Walk::new(dir.to_path_buf(), &Config::default()).unwrap();
+        let mut found = Vec::new();
+        while let Some(item) = walk.next() {
+            let Ok(visited) = item else {
+                found.push((String::from("error"), Vec::new()));
+                continue;
+            };
+            if visited.rel_path == Path::new("a") {
+                let data = visited.data.as_ref().unwrap();
+                assert_eq!(data.tags, ["foo"]);
+                assert_eq!(data.globs[0].path, "x.txt");
+                assert_eq!(data.globs[0].tags, ["bar"]);
+                walk.skip_subdirs();
+            }
+            found.push((visited.rel_path.display().to_string(), visited.files));
+        }
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                (String::new(), vec![OsString::from("z.txt")]),
+                (String::from("a"), vec![OsString::from("x.txt")]),
+                (String::from("error"), Vec::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn t_keep_going() {
+        let mut fs = crate::vfs::MemFileSystem::new();
+        fs.add_file("/root/.ftag", "[tags]\nroot\n");
+        fs.add_file("/root/broken/.ftag", "[tags]\na\n[tags]\nb\n");
+        fs.add_file("/root/broken/x.txt", "");
+        let mut config = Config::default();
+        let skipped = SkippedFiles::default();
+        config.set_keep_going(skipped.clone());
+        let options = || LoaderOptions::new(true, false, crate::load::FileLoadingOptions::Skip);
+        let mut dir = DirTree::with_fs(PathBuf::from("/root"), options(), &config, &fs).unwrap();
+        let mut found = Vec::new();
+        while let Some(VisitedDir { metadata, .. }) = dir.walk() {
+            found.push(match metadata {
+                MetaData::Ok(_) => "ok",
+                MetaData::NotFound => "not found",
+                MetaData::FailedToLoad(_) => "failed",
+            });
+        }
+        assert_eq!(found, vec!["ok", "not found"]);
+        let errors = skipped.take();
+        assert_eq!(errors.len(), 1);
+        assert!(
+            matches!(&errors[0], Error::CannotParseFtagFile(path, 3, _) if path == Path::new("/root/broken/.ftag"))
+        );
+        let mut dir = DirTree::with_fs(PathBuf::from("/root"), options(), &config, &fs)
+            .unwrap()
+            .report_failures();
+        dir.walk();
+        assert!(matches!(
+            dir.walk().map(|d| d.metadata),
+            Some(MetaData::FailedToLoad(_))
+        ));
+        assert!(skipped.take().is_empty());
+    }
 }
@@ -0,0 +1,111 @@
+use crate::{
+    config::Config,
+    core::{Error, FTAG_FILE},
+    query::query_files,
+    walk::WalkLimits,
+};
+use notify::{event::ModifyKind, Event, EventKind, RecursiveMode, Watcher};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::Duration,
+};
+
+/*
+Watching keeps the results of a query fresh, for scripts that act on them. The
+directories are watched with the notifications of the operating system, and
+the query is run again when a .ftag file changes, or when files are added,
+removed or renamed. Changes usually arrive in bursts, for example when an
+editor saves a file or a directory is copied, so the query is only run again
+once the changes settle down.
+ */
+
+/// Time to wait for more changes before running the query again.
+const SETTLE: Duration = Duration::from_millis(200);
+
+/// Check if `event` can change the results of a query.
+fn is_relevant(event: &Event) -> bool {
+    match event.kind {
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_)) => {
+            true
+        }
+        EventKind::Modify(_) => event
+            .paths
+            .iter()
+            .any(|path| path.file_name().is_some_and(|name| name == FTAG_FILE)),
+        _ => false,
+    }
+}
+
+/// Get the paths that were added to, and removed from `old` to get `new`.
+fn diff(old: &[PathBuf], new: &[PathBuf]) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let (old, new): (BTreeSet<&PathBuf>, BTreeSet<&PathBuf>) =
+        (old.iter().collect(), new.iter().collect());
+    (
+        new.difference(&old).map(|p| p.to_path_buf()).collect(),
+        old.difference(&new).map(|p| p.to_path_buf()).collect(),
+    )
+}
+
+/// Run the query `filter` on the directory tree at `root`, and run it again
+/// every time the directories change, until the process is interrupted.
+/// `report` is called with the results of the first run, and after every run
+/// that changes the results, with the new results, and the paths that were
+/// added and removed. Errors from running the query again, such as a .ftag
+/// file that fails to parse while it is being edited, are passed to
+/// `report_error`, and the directories are watched for the next change.
+pub fn watch<F, E>(
+    root: &Path,
+    filter: &str,
+    config: &Config,
+    mut report: F,
+    mut report_error: E,
+) -> Result<(), Error>
+where
+    F: FnMut(&[PathBuf], &[PathBuf], &[PathBuf]),
+    E: FnMut(Error),
+{
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(sender).map_err(|e| Error::WatchFailed(e.to_string()))?;
+    watcher
+        .watch(root, RecursiveMode::Recursive)
+        .map_err(|e| Error::WatchFailed(e.to_string()))?;
+    let mut results = query_files(root.to_path_buf(), filter, &WalkLimits::default(), config)?;
+    report(&results, &results, &[]);
+    loop {
+        match receiver.recv() {
+            Ok(Ok(event)) if is_relevant(&event) => {}
+            Ok(_) => continue, // Irrelevant events and errors about single events.
+            Err(_) => return Err(Error::WatchFailed(String::from("The watcher stopped."))),
+        }
+        // Wait for the changes to settle.
+        while receiver.recv_timeout(SETTLE).is_ok() {}
+        match query_files(root.to_path_buf(), filter, &WalkLimits::default(), config) {
+            Ok(newresults) => {
+                let (added, removed) = diff(&results, &newresults);
+                results = newresults;
+                if !added.is_empty() || !removed.is_empty() {
+                    report(&results, &added, &removed);
+                }
+            }
+            Err(e) => report_error(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_diff() {
+        let paths = |items: &[&str]| -> Vec<PathBuf> { items.iter().map(PathBuf::from).collect() };
+        assert_eq!(
+            diff(&paths(&["a", "b", "c"]), &paths(&["b", "d", "c"])),
+            (paths(&["d"]), paths(&["a"]))
+        );
+        assert_eq!(diff(&paths(&["a"]), &paths(&["a"])), (vec![], vec![]));
+    }
+}